@@ -0,0 +1,12 @@
+// Feeds arbitrary bytes to the parser, which has to return an error rather than panic. Hangs and
+// runaway allocations are caught by libFuzzer's -timeout and -rss_limit_mb, e.g.
+//
+//     cargo +nightly fuzz run parse_module -- -timeout=5 -rss_limit_mb=512
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use jump_jet::parser::ModuleParser;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = ModuleParser::default().parse_module(data);
+});
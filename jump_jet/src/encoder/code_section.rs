@@ -0,0 +1,41 @@
+use encoder::utils::write_bytes;
+use encoder::utils::write_varuint;
+
+use parse_tree::functions::FuncBody;
+use parse_tree::language_types::Operation;
+use parse_tree::language_types::ValueType;
+use parse_tree::ParseModule;
+
+pub fn encode(module: &ParseModule, out: &mut Vec<u8>) {
+    debug!("Encoding code section");
+    write_varuint(out, (module.function_bodies.len() + module.encoded_bodies.len()) as u64);
+    // Bodies a lazy parser left encoded are copied as they are, and come first
+    for body in &module.encoded_bodies {
+        write_bytes(out, &body.bytes);
+    }
+    for body in &module.function_bodies {
+        let mut encoded = vec![];
+        encode_body(body, &mut encoded);
+        write_bytes(out, &encoded);
+    }
+}
+
+fn encode_body(body: &FuncBody, out: &mut Vec<u8>) {
+    // Locals are declared in runs of the same type
+    let mut runs: Vec<(u64, ValueType)> = vec![];
+    for local in &body.locals {
+        if let Some(run) = runs.last_mut() {
+            if run.1 == *local {
+                run.0 += 1;
+                continue;
+            }
+        }
+        runs.push((1, local.clone()));
+    }
+    write_varuint(out, runs.len() as u64);
+    for &(count, ref value_type) in &runs {
+        write_varuint(out, count);
+        value_type.encode(out);
+    }
+    Operation::encode_multiple(&body.code, out);
+}
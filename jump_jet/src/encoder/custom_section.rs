@@ -0,0 +1,9 @@
+use encoder::utils::write_bytes;
+
+use parse_tree::custom::CustomSection;
+
+pub fn encode(section: &CustomSection, out: &mut Vec<u8>) {
+    debug!("Encoding custom section {}", section.name);
+    write_bytes(out, section.name.as_bytes());
+    out.extend_from_slice(&section.payload);
+}
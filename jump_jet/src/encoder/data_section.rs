@@ -0,0 +1,14 @@
+use encoder::utils::write_bytes;
+use encoder::utils::write_varuint;
+
+use parse_tree::ParseModule;
+
+pub fn encode(module: &ParseModule, out: &mut Vec<u8>) {
+    debug!("Encoding data section");
+    write_varuint(out, module.data.len() as u64);
+    for segment in &module.data {
+        write_varuint(out, segment.index as u64);
+        segment.offset.encode(out);
+        write_bytes(out, &segment.data);
+    }
+}
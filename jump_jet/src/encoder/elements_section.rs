@@ -0,0 +1,16 @@
+use encoder::utils::write_varuint;
+
+use parse_tree::ParseModule;
+
+pub fn encode(module: &ParseModule, out: &mut Vec<u8>) {
+    debug!("Encoding elements section");
+    write_varuint(out, module.elements.len() as u64);
+    for segment in &module.elements {
+        write_varuint(out, segment.index as u64);
+        segment.offset.encode(out);
+        write_varuint(out, segment.elements.len() as u64);
+        for function in &segment.elements {
+            write_varuint(out, *function as u64);
+        }
+    }
+}
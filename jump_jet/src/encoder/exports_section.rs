@@ -0,0 +1,13 @@
+use encoder::utils::write_bytes;
+use encoder::utils::write_varuint;
+
+use parse_tree::ParseModule;
+
+pub fn encode(module: &ParseModule, out: &mut Vec<u8>) {
+    debug!("Encoding exports section");
+    write_varuint(out, module.exports.len() as u64);
+    for export in &module.exports {
+        write_bytes(out, export.field.as_bytes());
+        export.kind.encode(out);
+    }
+}
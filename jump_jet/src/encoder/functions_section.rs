@@ -0,0 +1,11 @@
+use encoder::utils::write_varuint;
+
+use parse_tree::ParseModule;
+
+pub fn encode(module: &ParseModule, out: &mut Vec<u8>) {
+    debug!("Encoding functions section");
+    write_varuint(out, module.function_signatures.len() as u64);
+    for index in &module.function_signatures {
+        write_varuint(out, *index as u64);
+    }
+}
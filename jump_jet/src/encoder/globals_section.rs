@@ -0,0 +1,12 @@
+use encoder::utils::write_varuint;
+
+use parse_tree::ParseModule;
+
+pub fn encode(module: &ParseModule, out: &mut Vec<u8>) {
+    debug!("Encoding globals section");
+    write_varuint(out, module.globals.len() as u64);
+    for global in &module.globals {
+        global.constraints.encode(out);
+        global.value.encode(out);
+    }
+}
@@ -0,0 +1,14 @@
+use encoder::utils::write_bytes;
+use encoder::utils::write_varuint;
+
+use parse_tree::ParseModule;
+
+pub fn encode(module: &ParseModule, out: &mut Vec<u8>) {
+    debug!("Encoding imports section");
+    write_varuint(out, module.imports.len() as u64);
+    for import in &module.imports {
+        write_bytes(out, import.module.as_bytes());
+        write_bytes(out, import.field.as_bytes());
+        import.kind.encode(out);
+    }
+}
@@ -0,0 +1,491 @@
+use encoder::utils::write_varint;
+use encoder::utils::write_varuint;
+
+use parse_tree::language_types::Block;
+use parse_tree::language_types::BlockType;
+use parse_tree::language_types::BranchTable;
+use parse_tree::language_types::ExternalKind;
+use parse_tree::language_types::GlobalType;
+use parse_tree::language_types::ImportKind;
+use parse_tree::language_types::InitExpression;
+use parse_tree::language_types::LanguageType;
+use parse_tree::language_types::MemoryImmediate;
+use parse_tree::language_types::Operation;
+use parse_tree::language_types::ResizableLimits;
+use parse_tree::language_types::TableType;
+use parse_tree::language_types::ValueType;
+
+const END: u8 = 0x0b;
+
+impl ValueType {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        LanguageType::Value(self.clone()).encode(out);
+    }
+}
+
+impl LanguageType {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let key = match *self {
+            LanguageType::Value(ValueType::I32) => -0x01,
+            LanguageType::Value(ValueType::I64) => -0x02,
+            LanguageType::Value(ValueType::F32) => -0x03,
+            LanguageType::Value(ValueType::F64) => -0x04,
+            LanguageType::Anyfunc => -0x10,
+            LanguageType::Func => -0x20,
+            LanguageType::EmptyBlock => -0x40,
+        };
+        write_varint(out, key);
+    }
+}
+
+impl ExternalKind {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let (kind, index) = match *self {
+            ExternalKind::Function(index) => (0, index),
+            ExternalKind::Table(index) => (1, index),
+            ExternalKind::Memory(index) => (2, index),
+            ExternalKind::Global(index) => (3, index),
+        };
+        out.push(kind);
+        write_varuint(out, index as u64);
+    }
+}
+
+impl ImportKind {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match *self {
+            ImportKind::Function(index) => {
+                out.push(0);
+                write_varuint(out, index as u64);
+            },
+            ImportKind::Table(ref table) => {
+                out.push(1);
+                table.encode(out);
+            },
+            ImportKind::Memory(ref limits) => {
+                out.push(2);
+                limits.encode(out);
+            },
+            ImportKind::Global(ref global) => {
+                out.push(3);
+                global.encode(out);
+            },
+        }
+    }
+}
+
+impl ResizableLimits {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match self.maximum {
+            Some(maximum) => {
+                out.push(1);
+                write_varuint(out, self.initial);
+                write_varuint(out, maximum);
+            },
+            None => {
+                out.push(0);
+                write_varuint(out, self.initial);
+            }
+        }
+    }
+}
+
+impl GlobalType {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        self.content_type.encode(out);
+        out.push(self.mutability as u8);
+    }
+}
+
+impl TableType {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        self.elem_type.encode(out);
+        self.limits.encode(out);
+    }
+}
+
+impl InitExpression {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match *self {
+            InitExpression::I32Const(value) => Operation::I32Const(value).encode(out),
+            InitExpression::I64Const(value) => Operation::I64Const(value).encode(out),
+            InitExpression::F32Const(value) => Operation::F32Const(value).encode(out),
+            InitExpression::F64Const(value) => Operation::F64Const(value).encode(out),
+            InitExpression::GetGlobal(index) => Operation::GetGlobal(index).encode(out),
+        }
+        out.push(END);
+    }
+}
+
+impl Operation {
+    /// Writes `operations` followed by the `end` that closes them, which the tree leaves out.
+    pub fn encode_multiple(operations: &[Operation], out: &mut Vec<u8>) {
+        for operation in operations {
+            operation.encode(out);
+        }
+        out.push(END);
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.push(self.opcode());
+        match *self {
+            Operation::Block(ref block) | Operation::Loop(ref block) | Operation::If(ref block) => block.encode(out),
+            Operation::Branch(depth) | Operation::BranchIf(depth) => write_varuint(out, depth as u32 as u64),
+            Operation::BranchTable(ref table) => table.encode(out),
+            Operation::Call(index) | Operation::GetLocal(index) | Operation::SetLocal(index) |
+            Operation::TeeLocal(index) | Operation::GetGlobal(index) | Operation::SetGlobal(index) => write_varuint(out, index as u64),
+            Operation::CallIndirect(index, reserved) => {
+                write_varuint(out, index as u64);
+                out.push(reserved as u8);
+            },
+            Operation::I32Load(ref immediate) | Operation::I64Load(ref immediate) | Operation::F32Load(ref immediate) |
+            Operation::F64Load(ref immediate) | Operation::I32Load8S(ref immediate) | Operation::I32Load8U(ref immediate) |
+            Operation::I32Load16S(ref immediate) | Operation::I32Load16U(ref immediate) | Operation::I64Load8S(ref immediate) |
+            Operation::I64Load8U(ref immediate) | Operation::I64Load16S(ref immediate) | Operation::I64Load16U(ref immediate) |
+            Operation::I64Load32S(ref immediate) | Operation::I64Load32U(ref immediate) | Operation::I32Store(ref immediate) |
+            Operation::I64Store(ref immediate) | Operation::F32Store(ref immediate) | Operation::F64Store(ref immediate) |
+            Operation::I32Store8(ref immediate) | Operation::I32Store16(ref immediate) | Operation::I64Store8(ref immediate) |
+            Operation::I64Store16(ref immediate) | Operation::I64Store32(ref immediate) => immediate.encode(out),
+            Operation::CurrentMemory(reserved) | Operation::GrowMemory(reserved) => out.push(reserved as u8),
+            Operation::I32Const(value) => write_varint(out, value as i64),
+            Operation::I64Const(value) => write_varint(out, value),
+            // Floats are written bit for bit, so NaN payloads survive
+            Operation::F32Const(value) => out.extend_from_slice(&value.to_bits().to_le_bytes()),
+            Operation::F64Const(value) => out.extend_from_slice(&value.to_bits().to_le_bytes()),
+            _ => {}
+        }
+    }
+
+    fn opcode(&self) -> u8 {
+        match *self {
+            // Control flow operators
+            Operation::Unreachable => 0x00,
+            Operation::Nop => 0x01,
+            Operation::Block(_) => 0x02,
+            Operation::Loop(_) => 0x03,
+            Operation::If(_) => 0x04,
+            Operation::Else => 0x05,
+            Operation::End => 0x0b,
+            Operation::Branch(_) => 0x0c,
+            Operation::BranchIf(_) => 0x0d,
+            Operation::BranchTable(_) => 0x0e,
+            Operation::Return => 0x0f,
+            // Call operators
+            Operation::Call(_) => 0x10,
+            Operation::CallIndirect(..) => 0x11,
+            // Parametric operators
+            Operation::Drop => 0x1a,
+            Operation::Select => 0x1b,
+            // Variable access
+            Operation::GetLocal(_) => 0x20,
+            Operation::SetLocal(_) => 0x21,
+            Operation::TeeLocal(_) => 0x22,
+            Operation::GetGlobal(_) => 0x23,
+            Operation::SetGlobal(_) => 0x24,
+            // Memory-related operators
+            Operation::I32Load(_) => 0x28,
+            Operation::I64Load(_) => 0x29,
+            Operation::F32Load(_) => 0x2a,
+            Operation::F64Load(_) => 0x2b,
+            Operation::I32Load8S(_) => 0x2c,
+            Operation::I32Load8U(_) => 0x2d,
+            Operation::I32Load16S(_) => 0x2e,
+            Operation::I32Load16U(_) => 0x2f,
+            Operation::I64Load8S(_) => 0x30,
+            Operation::I64Load8U(_) => 0x31,
+            Operation::I64Load16S(_) => 0x32,
+            Operation::I64Load16U(_) => 0x33,
+            Operation::I64Load32S(_) => 0x34,
+            Operation::I64Load32U(_) => 0x35,
+            Operation::I32Store(_) => 0x36,
+            Operation::I64Store(_) => 0x37,
+            Operation::F32Store(_) => 0x38,
+            Operation::F64Store(_) => 0x39,
+            Operation::I32Store8(_) => 0x3a,
+            Operation::I32Store16(_) => 0x3b,
+            Operation::I64Store8(_) => 0x3c,
+            Operation::I64Store16(_) => 0x3d,
+            Operation::I64Store32(_) => 0x3e,
+            Operation::CurrentMemory(_) => 0x3f,
+            Operation::GrowMemory(_) => 0x40,
+            // Constants
+            Operation::I32Const(_) => 0x41,
+            Operation::I64Const(_) => 0x42,
+            Operation::F32Const(_) => 0x43,
+            Operation::F64Const(_) => 0x44,
+            // Comparison operators
+            Operation::I32Eqz => 0x45,
+            Operation::I32Eq => 0x46,
+            Operation::I32Ne => 0x47,
+            Operation::I32LtS => 0x48,
+            Operation::I32LtU => 0x49,
+            Operation::I32GtS => 0x4a,
+            Operation::I32GtU => 0x4b,
+            Operation::I32LeS => 0x4c,
+            Operation::I32LeU => 0x4d,
+            Operation::I32GeS => 0x4e,
+            Operation::I32GeU => 0x4f,
+            Operation::I64Eqz => 0x50,
+            Operation::I64Eq => 0x51,
+            Operation::I64Ne => 0x52,
+            Operation::I64LtS => 0x53,
+            Operation::I64LtU => 0x54,
+            Operation::I64GtS => 0x55,
+            Operation::I64GtU => 0x56,
+            Operation::I64LeS => 0x57,
+            Operation::I64LeU => 0x58,
+            Operation::I64GeS => 0x59,
+            Operation::I64GeU => 0x5a,
+            Operation::F32Eq => 0x5b,
+            Operation::F32Ne => 0x5c,
+            Operation::F32Lt => 0x5d,
+            Operation::F32Gt => 0x5e,
+            Operation::F32Le => 0x5f,
+            Operation::F32Ge => 0x60,
+            Operation::F64Eq => 0x61,
+            Operation::F64Ne => 0x62,
+            Operation::F64Lt => 0x63,
+            Operation::F64Gt => 0x64,
+            Operation::F64Le => 0x65,
+            Operation::F64Ge => 0x66,
+            // Numeric operators
+            Operation::I32Clz => 0x67,
+            Operation::I32Ctz => 0x68,
+            Operation::I32Popcnt => 0x69,
+            Operation::I32Add => 0x6a,
+            Operation::I32Sub => 0x6b,
+            Operation::I32Mul => 0x6c,
+            Operation::I32DivS => 0x6d,
+            Operation::I32DivU => 0x6e,
+            Operation::I32RemS => 0x6f,
+            Operation::I32RemU => 0x70,
+            Operation::I32And => 0x71,
+            Operation::I32Or => 0x72,
+            Operation::I32Xor => 0x73,
+            Operation::I32Shl => 0x74,
+            Operation::I32ShrS => 0x75,
+            Operation::I32ShrU => 0x76,
+            Operation::I32Rotl => 0x77,
+            Operation::I32Rotr => 0x78,
+            Operation::I64Clz => 0x79,
+            Operation::I64Ctz => 0x7a,
+            Operation::I64Popcnt => 0x7b,
+            Operation::I64Add => 0x7c,
+            Operation::I64Sub => 0x7d,
+            Operation::I64Mul => 0x7e,
+            Operation::I64DivS => 0x7f,
+            Operation::I64DivU => 0x80,
+            Operation::I64RemS => 0x81,
+            Operation::I64RemU => 0x82,
+            Operation::I64And => 0x83,
+            Operation::I64Or => 0x84,
+            Operation::I64Xor => 0x85,
+            Operation::I64Shl => 0x86,
+            Operation::I64ShrS => 0x87,
+            Operation::I64ShrU => 0x88,
+            Operation::I64Rotl => 0x89,
+            Operation::I64Rotr => 0x8a,
+            Operation::F32Abs => 0x8b,
+            Operation::F32Neg => 0x8c,
+            Operation::F32Ceil => 0x8d,
+            Operation::F32Floor => 0x8e,
+            Operation::F32Trunc => 0x8f,
+            Operation::F32Nearest => 0x90,
+            Operation::F32Sqrt => 0x91,
+            Operation::F32Add => 0x92,
+            Operation::F32Sub => 0x93,
+            Operation::F32Mul => 0x94,
+            Operation::F32Div => 0x95,
+            Operation::F32Min => 0x96,
+            Operation::F32Max => 0x97,
+            Operation::F32Copysign => 0x98,
+            Operation::F64Abs => 0x99,
+            Operation::F64Neg => 0x9a,
+            Operation::F64Ceil => 0x9b,
+            Operation::F64Floor => 0x9c,
+            Operation::F64Trunc => 0x9d,
+            Operation::F64Nearest => 0x9e,
+            Operation::F64Sqrt => 0x9f,
+            Operation::F64Add => 0xa0,
+            Operation::F64Sub => 0xa1,
+            Operation::F64Mul => 0xa2,
+            Operation::F64Div => 0xa3,
+            Operation::F64Min => 0xa4,
+            Operation::F64Max => 0xa5,
+            Operation::F64Copysign => 0xa6,
+            // Conversions
+            Operation::I32WrapI64 => 0xa7,
+            Operation::I32TruncSF32 => 0xa8,
+            Operation::I32TruncUF32 => 0xa9,
+            Operation::I32TruncSF64 => 0xaa,
+            Operation::I32TruncUF64 => 0xab,
+            Operation::I64ExtendSI32 => 0xac,
+            Operation::I64ExtendUI32 => 0xad,
+            Operation::I64TruncSF32 => 0xae,
+            Operation::I64TruncUF32 => 0xaf,
+            Operation::I64TruncSF64 => 0xb0,
+            Operation::I64TruncUF64 => 0xb1,
+            Operation::F32ConvertSI32 => 0xb2,
+            Operation::F32ConvertUI32 => 0xb3,
+            Operation::F32ConvertSI64 => 0xb4,
+            Operation::F32ConvertUI64 => 0xb5,
+            Operation::F32DemoteF64 => 0xb6,
+            Operation::F64ConvertSI32 => 0xb7,
+            Operation::F64ConvertUI32 => 0xb8,
+            Operation::F64ConvertSI64 => 0xb9,
+            Operation::F64ConvertUI64 => 0xba,
+            Operation::F64PromoteF32 => 0xbb,
+            // Reinterpretations
+            Operation::I32ReinterpretF32 => 0xbc,
+            Operation::I64ReinterpretF64 => 0xbd,
+            Operation::F32ReinterpretI32 => 0xbe,
+            Operation::F64ReinterpretI64 => 0xbf,
+        }
+    }
+}
+
+impl Block {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        self.block_type.encode(out);
+        Operation::encode_multiple(&self.operations, out);
+    }
+}
+
+impl BlockType {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match *self {
+            BlockType::Value(ref value_type) => value_type.encode(out),
+            BlockType::Empty => LanguageType::EmptyBlock.encode(out),
+            BlockType::Type(index) => write_varint(out, index as i64),
+        }
+    }
+}
+
+impl BranchTable {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        write_varuint(out, self.targets.len() as u64);
+        for target in &self.targets {
+            write_varuint(out, *target as u32 as u64);
+        }
+        write_varuint(out, self.default as u32 as u64);
+    }
+}
+
+impl MemoryImmediate {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        write_varuint(out, self.flags as u64);
+        write_varuint(out, self.offset as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parse_tree::builder::ModuleBuilder;
+    use parse_tree::ParseModule;
+
+    fn p() -> ParseModule<'static> {
+        ModuleBuilder::new().build()
+    }
+
+    fn encode(operations: &[Operation]) -> Vec<u8> {
+        let mut out = vec![];
+        Operation::encode_multiple(operations, &mut out);
+        out
+    }
+
+    #[test]
+    fn operations_without_immediates_are_their_opcode() {
+        let opcodes = [0x00, 0x01, 0x0f, 0x1a, 0x1b].iter().cloned().chain(0x45..0xc0);
+        for opcode in opcodes {
+            let operation = Operation::parse(&mut &[opcode][..], &p()).unwrap();
+            let mut out = vec![];
+            operation.encode(&mut out);
+            assert_eq!(out, vec![opcode], "{:?}", operation);
+        }
+    }
+
+    #[test]
+    fn immediates_read_back_the_same() {
+        let immediate = |flags, offset| MemoryImmediate { flags, offset };
+        let operations = vec![
+            Operation::Block(Block { block_type: BlockType::Value(ValueType::I64), operations: vec![
+                Operation::Loop(Block { block_type: BlockType::Empty, operations: vec![
+                    Operation::Branch(1),
+                    Operation::BranchIf(200),
+                    Operation::BranchTable(BranchTable { targets: vec![0, 1, 300], default: 2 }),
+                ] }),
+                Operation::If(Block { block_type: BlockType::Value(ValueType::F64), operations: vec![
+                    Operation::Nop,
+                    Operation::Else,
+                    Operation::Unreachable,
+                ] }),
+                Operation::Block(Block { block_type: BlockType::Type(100), operations: vec![] }),
+            ] }),
+            Operation::Call(128),
+            Operation::CallIndirect(3, false),
+            Operation::GetLocal(0),
+            Operation::SetLocal(70000),
+            Operation::TeeLocal(1),
+            Operation::GetGlobal(2),
+            Operation::SetGlobal(3),
+            Operation::I32Load(immediate(2, 0)),
+            Operation::I64Load(immediate(3, 1)),
+            Operation::F32Load(immediate(2, 127)),
+            Operation::F64Load(immediate(3, 128)),
+            Operation::I32Load8S(immediate(0, ::std::u32::MAX)),
+            Operation::I32Load8U(immediate(0, 4)),
+            Operation::I32Load16S(immediate(1, 4)),
+            Operation::I32Load16U(immediate(0, 4)),
+            Operation::I64Load8S(immediate(0, 4)),
+            Operation::I64Load8U(immediate(0, 4)),
+            Operation::I64Load16S(immediate(1, 4)),
+            Operation::I64Load16U(immediate(1, 4)),
+            Operation::I64Load32S(immediate(2, 4)),
+            Operation::I64Load32U(immediate(2, 4)),
+            Operation::I32Store(immediate(2, 8)),
+            Operation::I64Store(immediate(3, 8)),
+            Operation::F32Store(immediate(2, 8)),
+            Operation::F64Store(immediate(3, 8)),
+            Operation::I32Store8(immediate(0, 8)),
+            Operation::I32Store16(immediate(1, 8)),
+            Operation::I64Store8(immediate(0, 8)),
+            Operation::I64Store16(immediate(1, 8)),
+            Operation::I64Store32(immediate(2, 8)),
+            Operation::CurrentMemory(false),
+            Operation::GrowMemory(false),
+            Operation::I32Const(::std::i32::MIN),
+            Operation::I32Const(-1),
+            Operation::I32Const(::std::i32::MAX),
+            Operation::I64Const(::std::i64::MIN),
+            Operation::I64Const(64),
+            Operation::I64Const(::std::i64::MAX),
+            Operation::F32Const(-0.0),
+            Operation::F32Const(::std::f32::INFINITY),
+            Operation::F64Const(1.5e300),
+            Operation::F64Const(::std::f64::MIN_POSITIVE),
+        ];
+        let encoded = encode(&operations);
+        assert_eq!(Operation::parse_multiple(&mut &encoded[..], &p()).unwrap(), operations);
+    }
+
+    #[test]
+    fn nan_payloads_are_kept() {
+        let bits = 0x7ff4_0000_0000_0001;
+        let encoded = encode(&[Operation::F64Const(f64::from_bits(bits))]);
+        match Operation::parse_multiple(&mut &encoded[..], &p()).unwrap()[0] {
+            Operation::F64Const(value) => assert_eq!(value.to_bits(), bits),
+            ref operation => panic!("expected an f64.const, got {:?}", operation)
+        }
+    }
+
+    #[test]
+    fn init_expressions_end_with_end() {
+        let mut out = vec![];
+        InitExpression::I32Const(-2).encode(&mut out);
+        assert_eq!(out, vec![0x41, 0x7e, 0x0b]);
+        let mut out = vec![];
+        InitExpression::GetGlobal(1).encode(&mut out);
+        assert_eq!(InitExpression::parse(&mut &out[..]).unwrap(), InitExpression::GetGlobal(1));
+    }
+}
@@ -0,0 +1,11 @@
+use encoder::utils::write_varuint;
+
+use parse_tree::ParseModule;
+
+pub fn encode(module: &ParseModule, out: &mut Vec<u8>) {
+    debug!("Encoding memory section");
+    write_varuint(out, module.memories.len() as u64);
+    for memory in &module.memories {
+        memory.limits.encode(out);
+    }
+}
@@ -0,0 +1,187 @@
+use parse_tree::ParseModule;
+
+mod utils;
+use encoder::utils::write_section;
+
+mod language_types;
+
+mod custom_section;
+mod name_section;
+mod types_section;
+mod imports_section;
+mod functions_section;
+mod tables_section;
+mod memory_section;
+mod globals_section;
+mod exports_section;
+mod start_section;
+mod elements_section;
+mod code_section;
+mod data_section;
+
+const MAGIC_NUMBER: u32 = 0x6d736100;
+
+/// Writes `module` in the binary format, so that parsing the result gives back the same tree.
+/// Sections the module has nothing in are left out. Custom sections go back after the section
+/// they followed, except that the name section comes before any that followed the last one.
+pub fn encode_module(module: &ParseModule) -> Vec<u8> {
+    info!("Encoding module");
+    let mut out = vec![];
+    out.extend_from_slice(&MAGIC_NUMBER.to_le_bytes());
+    out.extend_from_slice(&module.version.to_le_bytes());
+
+    let sections: [(u8, bool, fn(&ParseModule, &mut Vec<u8>)); 11] = [
+        (1,  !module.types.is_empty(), types_section::encode),
+        (2,  !module.imports.is_empty(), imports_section::encode),
+        (3,  !module.function_signatures.is_empty(), functions_section::encode),
+        (4,  !module.tables.is_empty(), tables_section::encode),
+        (5,  !module.memories.is_empty(), memory_section::encode),
+        (6,  !module.globals.is_empty(), globals_section::encode),
+        (7,  !module.exports.is_empty(), exports_section::encode),
+        (8,  module.start_function.is_some(), start_section::encode),
+        (9,  !module.elements.is_empty(), elements_section::encode),
+        (10, !module.function_bodies.is_empty() || !module.encoded_bodies.is_empty(), code_section::encode),
+        (11, !module.data.is_empty(), data_section::encode),
+    ];
+    let last = module.last_section();
+    if last > 0 {
+        write_custom_sections(module, &mut out, |after| after == 0);
+    }
+    for &(id, present, encode) in &sections {
+        if present {
+            let mut contents = vec![];
+            encode(module, &mut contents);
+            write_section(&mut out, id, &contents);
+        }
+        if id < last {
+            write_custom_sections(module, &mut out, |after| after == id);
+        }
+    }
+
+    if name_section::has_names(&module.names) {
+        let mut contents = vec![];
+        name_section::encode(&module.names, &mut contents);
+        write_section(&mut out, 0, &contents);
+    }
+    write_custom_sections(module, &mut out, |after| after >= last);
+    info!("encoded module");
+    out
+}
+
+fn write_custom_sections<F: Fn(u8) -> bool>(module: &ParseModule, out: &mut Vec<u8>, wanted: F) {
+    for section in module.custom_sections.iter().filter(|section| wanted(section.after)) {
+        let mut contents = vec![];
+        custom_section::encode(section, &mut contents);
+        write_section(out, 0, &contents);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parse_tree::tests::assert_same;
+    use parser;
+    use parser::ModuleParser;
+    use runtime_tree::ValueTypeProvider;
+    use std::collections::HashMap;
+    use text;
+
+    const SAMPLE: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f,
+        0x03, 0x02, 0x01, 0x00,
+        0x07, 0x05, 0x01, 0x01, b'f', 0x00, 0x00,
+        0x0a, 0x0b, 0x01, 0x09, 0x01, 0x01, 0x7f, 0x02, 0x7f, 0x41, 0x2a, 0x0b, 0x0b,
+        0x00, 0x07, 0x04, b'm', b'e', b't', b'a', 0x01, 0x02,
+    ];
+
+    const TEXT: &str = r#"
+        (module $m
+          (import "env" "log" (func $log (param i32)))
+          (import "env" "table" (table 1 10 anyfunc))
+          (import "env" "g" (global f64))
+          (global $limit (mut i64) (i64.const -9223372036854775808))
+          (memory 1 2)
+          (func $main (export "main") (param $n i32) (result f32) (local $i i32) (local i32 f64 f64 i32)
+            (block $done
+              (loop $again
+                (br_if $done (i32.ge_u (get_local $i) (get_local $n)))
+                (call $log (i32.load8_u offset=4 align=1 (get_local $i)))
+                (set_local $i (i32.add (get_local $i) (i32.const 1)))
+                (br_table $again $done (get_local $i))))
+            (if (result f32) (get_local $n)
+              (then (f32.const -0x1.8p-3))
+              (else (f32.add (f32.const nan:0x200) (f32.const -inf)))))
+          (func (result f64) (f64.const 0.1) (f64.store (i32.const 0) (f64.const 1e300)) (memory.grow (i32.const 1)) drop)
+          (export "limit" (global 1))
+          (start $main)
+          (elem (i32.const 3) $main 2 $log)
+          (data (i32.const 8) "bytes\00\ff"))
+    "#;
+
+    #[test]
+    fn encoding_gives_back_the_bytes_that_were_parsed() {
+        let module = ModuleParser::default().parse_module(SAMPLE).unwrap();
+        assert_eq!(encode_module(&module), SAMPLE);
+        let lazy = ModuleParser::default().lazy(true).parse_module_from_slice(SAMPLE).unwrap();
+        assert_eq!(encode_module(&lazy), SAMPLE);
+    }
+
+    #[test]
+    fn parsing_what_was_encoded_gives_back_the_same_tree() {
+        let module = text::parse_module(TEXT).unwrap();
+        let encoded = encode_module(&module);
+        let parsed = ModuleParser::default().parse_module(&encoded[..]).unwrap();
+        assert_eq!(encode_module(&parsed), encoded);
+        assert_same(parsed, module);
+        let borrowed = ModuleParser::default().parse_module_from_slice(&encoded).unwrap();
+        assert_same(borrowed, text::parse_module(TEXT).unwrap());
+    }
+
+    #[test]
+    fn encoded_bodies_are_written_before_decoded_ones() {
+        let module = text::parse_module("(module (func (result i32) (i32.const 1)) (func (result i64) (i64.const 2)))").unwrap();
+        let encoded = encode_module(&module);
+        let mut mixed = ModuleParser::default().lazy(true).parse_module_from_slice(&encoded).unwrap();
+        let last = mixed.encoded_bodies.pop().unwrap();
+        mixed.function_bodies.push(parser::decode_body(&last, 1).unwrap());
+        assert_eq!(encode_module(&mixed), encoded);
+    }
+
+    #[test]
+    fn custom_sections_go_back_where_they_were() {
+        let bytes: &[u8] = &[
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+            0x00, 0x03, 0x01, b'a', 0x01,
+            0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+            0x00, 0x03, 0x01, b'b', 0x02,
+            0x05, 0x03, 0x01, 0x00, 0x01,
+            0x00, 0x03, 0x01, b'c', 0x03,
+        ];
+        let module = ModuleParser::default().parse_module(bytes).unwrap();
+        assert_eq!(module.custom_sections.iter().map(|section| section.after).collect::<Vec<_>>(), vec![0, 1, 5]);
+        assert_eq!(encode_module(&module), bytes);
+        let borrowed = ModuleParser::default().parse_module_from_slice(bytes).unwrap();
+        assert_eq!(encode_module(&borrowed), bytes);
+    }
+
+    #[test]
+    fn empty_modules_are_just_a_header() {
+        let module = text::parse_module("(module)").unwrap();
+        assert_eq!(encode_module(&module), &SAMPLE[..8]);
+    }
+
+    #[test]
+    fn encoded_modules_can_be_instantiated() {
+        let module = text::parse_module(r#"
+            (module
+              (func (export "add") (param i32 i32) (result i32)
+                (i32.add (get_local 0) (get_local 1))))
+        "#).unwrap();
+        let encoded = encode_module(&module);
+        let template = ::runtime::instantiate(&mut &encoded[..], HashMap::new()).unwrap();
+        let mut instance = template.instantiate().unwrap();
+        let result = instance.exports().call_fn("add", vec![ValueTypeProvider::I32(42), ValueTypeProvider::I32(81)]);
+        assert_eq!(result.unwrap(), vec![ValueTypeProvider::I32(123)]);
+    }
+}
@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+
+use encoder::utils::write_bytes;
+use encoder::utils::write_section;
+use encoder::utils::write_varuint;
+
+use parse_tree::names::Names;
+
+const MODULE_NAME: u8 = 0;
+const FUNCTION_NAMES: u8 = 1;
+const LOCAL_NAMES: u8 = 2;
+
+/// Whether there are any names to write.
+pub fn has_names(names: &Names) -> bool {
+    names.module.is_some() || !names.functions.is_empty() || !names.locals.is_empty()
+}
+
+pub fn encode(names: &Names, out: &mut Vec<u8>) {
+    debug!("Encoding name section");
+    write_bytes(out, b"name");
+    if let Some(ref name) = names.module {
+        let mut subsection = vec![];
+        write_bytes(&mut subsection, name.as_bytes());
+        write_section(out, MODULE_NAME, &subsection);
+    }
+    if !names.functions.is_empty() {
+        let mut subsection = vec![];
+        write_name_map(&mut subsection, &names.functions);
+        write_section(out, FUNCTION_NAMES, &subsection);
+    }
+    if !names.locals.is_empty() {
+        let mut subsection = vec![];
+        write_varuint(&mut subsection, names.locals.len() as u64);
+        for index in sorted(&names.locals) {
+            write_varuint(&mut subsection, index as u64);
+            write_name_map(&mut subsection, &names.locals[&index]);
+        }
+        write_section(out, LOCAL_NAMES, &subsection);
+    }
+}
+
+fn write_name_map(out: &mut Vec<u8>, names: &HashMap<usize, String>) {
+    write_varuint(out, names.len() as u64);
+    for index in sorted(names) {
+        write_varuint(out, index as u64);
+        write_bytes(out, names[&index].as_bytes());
+    }
+}
+
+// Name maps are written in order of index
+fn sorted<T>(names: &HashMap<usize, T>) -> Vec<usize> {
+    let mut indices: Vec<usize> = names.keys().cloned().collect();
+    indices.sort();
+    indices
+}
@@ -0,0 +1,10 @@
+use encoder::utils::write_varuint;
+
+use parse_tree::ParseModule;
+
+pub fn encode(module: &ParseModule, out: &mut Vec<u8>) {
+    debug!("Encoding start section");
+    if let Some(index) = module.start_function {
+        write_varuint(out, index as u64);
+    }
+}
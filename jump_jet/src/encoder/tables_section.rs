@@ -0,0 +1,15 @@
+use encoder::utils::write_varuint;
+
+use parse_tree::language_types::LanguageType;
+use parse_tree::ParseModule;
+use parse_tree::tables::Table;
+
+pub fn encode(module: &ParseModule, out: &mut Vec<u8>) {
+    debug!("Encoding tables section");
+    write_varuint(out, module.tables.len() as u64);
+    for table in &module.tables {
+        let Table::AnyFunc { ref limits, .. } = *table;
+        LanguageType::Anyfunc.encode(out);
+        limits.encode(out);
+    }
+}
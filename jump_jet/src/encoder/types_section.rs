@@ -0,0 +1,22 @@
+use encoder::utils::write_varuint;
+
+use parse_tree::language_types::LanguageType;
+use parse_tree::types::TypeDefinition;
+use parse_tree::ParseModule;
+
+pub fn encode(module: &ParseModule, out: &mut Vec<u8>) {
+    debug!("Encoding types section");
+    write_varuint(out, module.types.len() as u64);
+    for definition in &module.types {
+        let TypeDefinition::Func(ref signature) = *definition;
+        LanguageType::Func.encode(out);
+        write_varuint(out, signature.parameters.len() as u64);
+        for parameter in &signature.parameters {
+            parameter.encode(out);
+        }
+        write_varuint(out, signature.returns.len() as u64);
+        for value_type in &signature.returns {
+            value_type.encode(out);
+        }
+    }
+}
@@ -0,0 +1,73 @@
+/// Writes `value` as an unsigned LEB128 number, in as few bytes as it fits in.
+pub fn write_varuint(out: &mut Vec<u8>, value: u64) {
+    let mut value = value;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Writes `value` as a signed LEB128 number, in as few bytes as it fits in.
+pub fn write_varint(out: &mut Vec<u8>, value: i64) {
+    let mut value = value;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        // Done once what's left is just the sign, and the sign bit of this byte agrees with it
+        let sign = byte & 0x40 != 0;
+        if (value == 0 && !sign) || (value == -1 && sign) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Writes a length prefixed string or payload.
+pub fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_varuint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+/// Writes a section with its id and the size of its contents.
+pub fn write_section(out: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    out.push(id);
+    write_bytes(out, contents);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unsigned(value: u64) -> Vec<u8> {
+        let mut out = vec![];
+        write_varuint(&mut out, value);
+        out
+    }
+
+    fn signed(value: i64) -> Vec<u8> {
+        let mut out = vec![];
+        write_varint(&mut out, value);
+        out
+    }
+
+    #[test]
+    fn numbers_take_as_few_bytes_as_they_fit_in() {
+        assert_eq!(unsigned(0), vec![0x00]);
+        assert_eq!(unsigned(127), vec![0x7f]);
+        assert_eq!(unsigned(128), vec![0x80, 0x01]);
+        assert_eq!(unsigned(624485), vec![0xe5, 0x8e, 0x26]);
+        assert_eq!(signed(0), vec![0x00]);
+        assert_eq!(signed(-1), vec![0x7f]);
+        assert_eq!(signed(63), vec![0x3f]);
+        assert_eq!(signed(64), vec![0xc0, 0x00]);
+        assert_eq!(signed(-64), vec![0x40]);
+        assert_eq!(signed(-65), vec![0xbf, 0x7f]);
+        assert_eq!(signed(-123456), vec![0xc0, 0xbb, 0x78]);
+    }
+}
@@ -1,10 +1,17 @@
+#![cfg_attr(feature = "nightly", feature(test))]
+
 #[macro_use]
 extern crate log;
 
 pub use runtime::instantiate;
+pub use runtime::instantiate_wat;
+pub use runtime::LoadError;
 
+pub mod encoder;
 pub mod parse_tree;
 pub mod parser;
 #[macro_use]
 pub mod runtime;
 pub mod runtime_tree;
+pub mod text;
+pub mod validator;
@@ -0,0 +1,303 @@
+use parse_tree::functions::FuncBody;
+use parse_tree::functions::FuncSignature;
+use parse_tree::globals::Global;
+use parse_tree::language_types::ExportEntry;
+use parse_tree::language_types::ExternalKind;
+use parse_tree::language_types::GlobalType;
+use parse_tree::language_types::ImportEntry;
+use parse_tree::language_types::ImportKind;
+use parse_tree::language_types::InitExpression;
+use parse_tree::language_types::LanguageType;
+use parse_tree::language_types::Operation;
+use parse_tree::language_types::ResizableLimits;
+use parse_tree::language_types::TableType;
+use parse_tree::language_types::ValueType;
+use parse_tree::memory::DataSegment;
+use parse_tree::memory::Memory;
+use parse_tree::tables::ElementSegment;
+use parse_tree::tables::Table;
+use parse_tree::types::TypeDefinition;
+use parse_tree::ParseModule;
+
+/// Puts a `ParseModule` together piece by piece. Everything that's declared gets back its index,
+/// counting imports, for instructions, exports and segments to refer to it by.
+///
+/// Imports come first in their index space, so they have to be declared before anything of the
+/// same kind is defined, otherwise the indices already handed out would change.
+pub struct ModuleBuilder {
+    module: ParseModule<'static>,
+}
+
+impl ModuleBuilder {
+    pub fn new() -> ModuleBuilder {
+        ModuleBuilder {
+            module: ParseModule::new()
+        }
+    }
+
+    /// The index of the type for a function with this signature, which is added if there isn't
+    /// one already.
+    pub fn signature(&mut self, parameters: Vec<ValueType>, returns: Vec<ValueType>) -> usize {
+        let signature = FuncSignature { parameters, returns };
+        let existing = self.module.types.iter().position(|definition| {
+            let TypeDefinition::Func(ref existing) = *definition;
+            *existing == signature
+        });
+        match existing {
+            Some(index) => index,
+            None => {
+                self.module.types.push(TypeDefinition::Func(signature));
+                self.module.types.len() - 1
+            }
+        }
+    }
+
+    /// Panics if a function has already been defined.
+    pub fn import_function(&mut self, module: &str, field: &str, parameters: Vec<ValueType>, returns: Vec<ValueType>) -> usize {
+        assert!(self.module.function_signatures.is_empty(), "functions have to be imported before any are defined");
+        let signature = self.signature(parameters, returns);
+        self.import(module, field, ImportKind::Function(signature));
+        self.module.function_space().len() - 1
+    }
+
+    /// Panics if a table has already been defined.
+    pub fn import_table(&mut self, module: &str, field: &str, initial: u64, maximum: Option<u64>) -> usize {
+        assert!(self.module.tables.is_empty(), "tables have to be imported before any are defined");
+        self.import(module, field, ImportKind::Table(TableType {
+            elem_type: LanguageType::Anyfunc,
+            limits: ResizableLimits { initial, maximum }
+        }));
+        self.module.table_space().len() - 1
+    }
+
+    /// Panics if a memory has already been defined.
+    pub fn import_memory(&mut self, module: &str, field: &str, initial: u64, maximum: Option<u64>) -> usize {
+        assert!(self.module.memories.is_empty(), "memories have to be imported before any are defined");
+        self.import(module, field, ImportKind::Memory(ResizableLimits { initial, maximum }));
+        self.module.memory_space().len() - 1
+    }
+
+    /// Panics if a global has already been defined.
+    pub fn import_global(&mut self, module: &str, field: &str, content_type: ValueType, mutability: bool) -> usize {
+        assert!(self.module.globals.is_empty(), "globals have to be imported before any are defined");
+        self.import(module, field, ImportKind::Global(GlobalType { content_type, mutability }));
+        self.module.global_space().len() - 1
+    }
+
+    fn import(&mut self, module: &str, field: &str, kind: ImportKind) {
+        self.module.imports.push(ImportEntry {
+            module: module.to_string().into(),
+            field: field.to_string().into(),
+            kind
+        });
+    }
+
+    /// Starts defining a function, which is added when `FunctionBuilder::finish` is called.
+    pub fn function<'b>(&'b mut self, parameters: Vec<ValueType>, returns: Vec<ValueType>) -> FunctionBuilder<'b> {
+        let signature = self.signature(parameters, returns);
+        FunctionBuilder {
+            builder: self,
+            signature,
+            body: FuncBody::new(),
+            exports: vec![],
+            name: None
+        }
+    }
+
+    pub fn table(&mut self, initial: u64, maximum: Option<u64>) -> usize {
+        self.module.tables.push(Table::AnyFunc {
+            limits: ResizableLimits { initial, maximum },
+            values: vec![]
+        });
+        self.module.table_space().len() - 1
+    }
+
+    pub fn memory(&mut self, initial: u64, maximum: Option<u64>) -> usize {
+        self.module.memories.push(Memory {
+            limits: ResizableLimits { initial, maximum },
+            values: vec![]
+        });
+        self.module.memory_space().len() - 1
+    }
+
+    pub fn global(&mut self, content_type: ValueType, mutability: bool, value: InitExpression) -> usize {
+        self.module.globals.push(Global {
+            constraints: GlobalType { content_type, mutability },
+            value
+        });
+        self.module.global_space().len() - 1
+    }
+
+    pub fn export(&mut self, field: &str, kind: ExternalKind) -> &mut ModuleBuilder {
+        self.module.exports.push(ExportEntry {
+            field: field.to_string().into(),
+            kind
+        });
+        self
+    }
+
+    pub fn start(&mut self, function: usize) -> &mut ModuleBuilder {
+        self.module.start_function = Some(function);
+        self
+    }
+
+    /// Puts `functions` in table `table`, starting at `offset`.
+    pub fn elements(&mut self, table: usize, offset: InitExpression, functions: Vec<usize>) -> &mut ModuleBuilder {
+        self.module.elements.push(ElementSegment {
+            index: table,
+            offset,
+            elements: functions
+        });
+        self
+    }
+
+    /// Puts `data` in memory `memory`, starting at `offset`.
+    pub fn data(&mut self, memory: usize, offset: InitExpression, data: Vec<u8>) -> &mut ModuleBuilder {
+        self.module.data.push(DataSegment {
+            index: memory,
+            offset,
+            data: data.into()
+        });
+        self
+    }
+
+    /// Names the module in its name section.
+    pub fn name(&mut self, name: &str) -> &mut ModuleBuilder {
+        self.module.names.module = Some(name.to_string());
+        self
+    }
+
+    pub fn build(self) -> ParseModule<'static> {
+        self.module
+    }
+}
+
+/// A function that's being defined, see `ModuleBuilder::function`.
+pub struct FunctionBuilder<'b> {
+    builder: &'b mut ModuleBuilder,
+    signature: usize,
+    body: FuncBody,
+    exports: Vec<String>,
+    name: Option<String>,
+}
+
+impl<'b> FunctionBuilder<'b> {
+    /// Declares a local, after the parameters and any locals declared before it.
+    pub fn local(mut self, value_type: ValueType) -> FunctionBuilder<'b> {
+        self.body.locals.push(value_type);
+        self
+    }
+
+    /// Adds `operations` to the end of the body, which doesn't end with an `End`.
+    pub fn code(mut self, operations: Vec<Operation>) -> FunctionBuilder<'b> {
+        self.body.code.extend(operations);
+        self
+    }
+
+    pub fn export(mut self, field: &str) -> FunctionBuilder<'b> {
+        self.exports.push(field.to_string());
+        self
+    }
+
+    /// Names the function in the module's name section.
+    pub fn name(mut self, name: &str) -> FunctionBuilder<'b> {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// Adds the function to the module, returning its index.
+    pub fn finish(self) -> usize {
+        let module = &mut self.builder.module;
+        module.function_signatures.push(self.signature);
+        module.function_bodies.push(self.body);
+        let index = module.function_space().len() - 1;
+        for field in self.exports {
+            module.exports.push(ExportEntry {
+                field: field.into(),
+                kind: ExternalKind::Function(index)
+            });
+        }
+        if let Some(name) = self.name {
+            module.names.functions.insert(index, name);
+        }
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoder::encode_module;
+    use parser::ModuleParser;
+    use runtime_tree::ModuleTemplateBuilder;
+    use runtime_tree::ValueTypeProvider;
+    use std::collections::HashMap;
+    use validator;
+
+    #[test]
+    fn indices_count_imports_first() {
+        let mut builder = ModuleBuilder::new();
+        let log = builder.import_function("env", "log", vec![ValueType::I32], vec![]);
+        let imported = builder.import_global("env", "g", ValueType::I32, false);
+        let first = builder.function(vec![], vec![]).finish();
+        let second = builder.function(vec![ValueType::I32], vec![]).code(vec![Operation::Call(log)]).finish();
+        let global = builder.global(ValueType::I64, true, InitExpression::I64Const(0));
+        let memory = builder.memory(1, None);
+        let table = builder.table(2, Some(2));
+        let module = builder.build();
+        assert_eq!((log, first, second), (0, 1, 2));
+        assert_eq!((imported, global, memory, table), (0, 1, 0, 0));
+        // Functions with the same signature share a type
+        assert_eq!(module.types.len(), 2);
+        assert_eq!(module.function_space(), vec![0, 1, 0]);
+    }
+
+    #[test]
+    fn built_modules_validate_and_run() {
+        let mut builder = ModuleBuilder::new();
+        let memory = builder.memory(1, Some(1));
+        builder.data(memory, InitExpression::I32Const(8), vec![42, 0, 0, 0]);
+        builder.export("memory", ExternalKind::Memory(memory));
+        let load = builder.function(vec![], vec![ValueType::I32])
+            .local(ValueType::I32)
+            .code(vec![
+                Operation::I32Const(8),
+                Operation::SetLocal(0),
+                Operation::GetLocal(0),
+                Operation::I32Load(::parse_tree::language_types::MemoryImmediate { flags: 2, offset: 0 }),
+            ])
+            .name("load")
+            .finish();
+        builder.function(vec![ValueType::I32], vec![ValueType::I32])
+            .code(vec![Operation::Call(load), Operation::GetLocal(0), Operation::I32Add])
+            .export("add_loaded")
+            .finish();
+        let module = builder.build();
+        assert_eq!(validator::validate(&module), Ok(()));
+        assert_eq!(module.names.function(load), Some("load"));
+        let template = module.build(HashMap::new()).unwrap();
+        let mut instance = template.instantiate().unwrap();
+        let result = instance.exports().call_fn("add_loaded", vec![ValueTypeProvider::I32(81)]);
+        assert_eq!(result.unwrap(), vec![ValueTypeProvider::I32(123)]);
+    }
+
+    #[test]
+    fn built_modules_can_be_encoded() {
+        let mut builder = ModuleBuilder::new();
+        builder.name("built");
+        let table = builder.import_table("env", "table", 1, None);
+        let function = builder.function(vec![], vec![]).finish();
+        builder.elements(table, InitExpression::I32Const(0), vec![function]).start(function);
+        let module = builder.build();
+        let parsed = ModuleParser::default().parse_module(&encode_module(&module)[..]).unwrap();
+        assert_eq!(parsed, module);
+    }
+
+    #[test]
+    #[should_panic(expected = "functions have to be imported before any are defined")]
+    fn imports_cant_follow_definitions() {
+        let mut builder = ModuleBuilder::new();
+        builder.function(vec![], vec![]).finish();
+        builder.import_function("env", "f", vec![], vec![]);
+    }
+}
@@ -0,0 +1,12 @@
+use std::borrow::Cow;
+
+/// A custom section's contents, kept as they were found for any section the parser wasn't given a
+/// handler for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CustomSection<'a> {
+    pub name: Cow<'a, str>,
+    pub payload: Cow<'a, [u8]>,
+    /// The id of the last known section with anything in it before this one, 0 if there wasn't
+    /// one, which the encoder puts it back after.
+    pub after: u8,
+}
@@ -7,18 +7,26 @@ pub struct Function {
     pub body: FuncBody,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct FuncSignature {
     pub parameters: Vec<ValueType>,
     pub returns: Vec<ValueType>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct FuncBody {
     pub locals: Vec<ValueType>,
     pub code: Vec<Operation>,
 }
 
+/// A function body that was left encoded, to be decoded by `parser::decode_body` when it's needed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EncodedBody {
+    /// Where the body starts in the module, after its size.
+    pub offset: u64,
+    pub bytes: Vec<u8>,
+}
+
 impl FuncBody {
     pub fn new() -> Self {
         FuncBody {
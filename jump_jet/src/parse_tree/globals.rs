@@ -1,7 +1,7 @@
 use parse_tree::language_types::GlobalType;
 use parse_tree::language_types::InitExpression;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Global {
     pub constraints: GlobalType,
     pub value: InitExpression
@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+
 #[derive(Debug)]
 #[derive(Clone)]
 #[derive(PartialEq)]
@@ -18,7 +20,7 @@ pub enum LanguageType {
     EmptyBlock,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ExternalKind {
     Function(usize),
     Table(usize),
@@ -26,25 +28,48 @@ pub enum ExternalKind {
     Global(usize),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct TableType {
     pub elem_type: LanguageType,
     pub limits: ResizableLimits,
 }
 
-#[derive(Debug, Clone)]
+/// What an import expects the embedder to provide.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImportKind {
+    Function(usize), // index into the type section
+    Table(TableType),
+    Memory(ResizableLimits),
+    Global(GlobalType),
+}
+
+// Names are borrowed from the module's bytes when it's parsed from a slice
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportEntry<'a> {
+    pub module: Cow<'a, str>,
+    pub field: Cow<'a, str>,
+    pub kind: ImportKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportEntry<'a> {
+    pub field: Cow<'a, str>,
+    pub kind: ExternalKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct ResizableLimits {
     pub initial: u64,
     pub maximum: Option<u64>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct GlobalType {
     pub content_type: ValueType,
     pub mutability: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum InitExpression {
     I32Const(i32),
     I64Const(i64),
@@ -257,7 +282,7 @@ pub enum Operation {
 #[derive(Debug)]
 #[derive(PartialEq)]
 pub struct MemoryImmediate {
-    pub flags: u32, // varuint32 - i have no idea what this is
+    pub flags: u32, // varuint32, log2 of the alignment - only a hint
     pub offset: u32,
 }
 
@@ -266,7 +291,9 @@ pub struct MemoryImmediate {
 #[derive(PartialEq)]
 pub enum BlockType {
     Value(ValueType),
-    Empty
+    Empty,
+    // The index of a function type, whose parameters the block takes off the stack
+    Type(usize)
 }
 
 #[derive(Clone)]
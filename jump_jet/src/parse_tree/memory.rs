@@ -1,36 +1,78 @@
+use std::borrow::Cow;
 use std::fmt::Debug;
 use std::fmt::Error;
 use std::fmt::Formatter;
 
+use parse_tree::language_types::InitExpression;
 use parse_tree::language_types::ResizableLimits;
 
 pub const WASM_PAGE_SIZE: usize = 64*1024;
-const WASM_PAGE: [u8;WASM_PAGE_SIZE] = [0;WASM_PAGE_SIZE];
+// 4GiB, the most a 32 bit address can reach
+pub const MAX_PAGES: u32 = 65536;
 
-#[derive(Clone)]
+// Memories are only given their pages when a module is instantiated, until then `values` is empty
+#[derive(Clone, PartialEq)]
 pub struct Memory {
     pub limits: ResizableLimits,
     pub values: Vec<u8>
 }
 
+/// Bytes copied into a memory when a module is instantiated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataSegment<'a> {
+    pub index: usize,
+    pub offset: InitExpression,
+    pub data: Cow<'a, [u8]>,
+}
+
+impl<'a> DataSegment<'a> {
+    /// Copies the data if it's borrowed, so the segment can outlive the module's bytes.
+    pub fn into_owned(self) -> DataSegment<'static> {
+        DataSegment {
+            index: self.index,
+            offset: self.offset,
+            data: Cow::Owned(self.data.into_owned())
+        }
+    }
+}
+
 impl Debug for Memory {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
         f.debug_struct("Memory")
             .field("limits", &self.limits)
             // TODO it would be better if the below didn't have quotes around it, but I can't quite see how to achieve that...
-            .field("values", &format!("[{:?} * {:?}]", self.values.len() % WASM_PAGE_SIZE + 1, WASM_PAGE_SIZE))
+            .field("values", &format!("[{:?} * {:?}]", self.size(), WASM_PAGE_SIZE))
             .finish()
     }
 }
 
 impl Memory {
-    pub fn grow(&mut self) -> i32 {
-        let r = self.size();
-        self.values.extend(WASM_PAGE.iter());
-        return r;
+    /// Grows the memory by `delta` pages and returns its previous size in pages, or `None` if that
+    /// would take it past its maximum.
+    pub fn grow(&mut self, delta: u32) -> Option<u32> {
+        let previous = self.size();
+        let desired = match previous.checked_add(delta) {
+            Some(desired) if desired <= self.maximum() => desired,
+            _ => return None
+        };
+        let length = match (desired as usize).checked_mul(WASM_PAGE_SIZE) {
+            Some(length) => length,
+            None => return None
+        };
+        self.values.resize(length, 0);
+        Some(previous)
+    }
+
+    /// The size of the memory in pages.
+    pub fn size(&self) -> u32 {
+        (self.values.len() / WASM_PAGE_SIZE) as u32
     }
 
-    pub fn size(&self) -> i32 {
-        (self.values.len() % WASM_PAGE_SIZE) as i32
+    /// The most pages the memory can grow to, its declared maximum if it has one and 4GiB otherwise.
+    pub fn maximum(&self) -> u32 {
+        match self.limits.maximum {
+            Some(maximum) if maximum < MAX_PAGES as u64 => maximum as u32,
+            _ => MAX_PAGES
+        }
     }
-}
\ No newline at end of file
+}
@@ -1,22 +1,153 @@
-use std::collections::HashMap;
-
 pub mod types;
 pub mod language_types;
 pub mod memory;
 pub mod functions;
 pub mod tables;
 pub mod globals;
+pub mod custom;
+pub mod names;
+pub mod builder;
 
-#[derive(Debug)]
-pub struct ParseModule {
+/// A decoded module. Names, custom sections and data segments borrow from the module's bytes when
+/// it's parsed with `ModuleParser::parse_module_from_slice`, and own copies otherwise.
+#[derive(Debug, PartialEq)]
+pub struct ParseModule<'a> {
     pub version: u32,
     pub types: Vec<types::TypeDefinition>,
-    pub imports: HashMap<String, HashMap<String, language_types::ExternalKind>>,
+    pub imports: Vec<language_types::ImportEntry<'a>>, // in the order they're declared
     pub function_signatures: Vec<usize>,
     pub function_bodies: Vec<functions::FuncBody>,
+    pub encoded_bodies: Vec<functions::EncodedBody>, // when parsed lazily; these come before function_bodies
     pub tables: Vec<tables::Table>,
     pub memories: Vec<memory::Memory>,
     pub globals: Vec<globals::Global>,
-    pub exports: HashMap<String, language_types::ExternalKind>,
+    pub exports: Vec<language_types::ExportEntry<'a>>, // in the order they're declared
     pub start_function: Option<usize>,
+    pub elements: Vec<tables::ElementSegment>,
+    pub data: Vec<memory::DataSegment<'a>>,
+    pub custom_sections: Vec<custom::CustomSection<'a>>,
+    pub names: names::Names,
+}
+
+impl<'a> ParseModule<'a> {
+    /// An empty module of the current version.
+    pub fn new() -> ParseModule<'a> {
+        ParseModule {
+            version: 1,
+            types: vec![],
+            imports: vec![],
+            function_signatures: vec![],
+            function_bodies: vec![],
+            encoded_bodies: vec![],
+            tables: vec![],
+            memories: vec![],
+            globals: vec![],
+            exports: vec![],
+            start_function: None,
+            elements: vec![],
+            data: vec![],
+            custom_sections: vec![],
+            names: names::Names::default()
+        }
+    }
+}
+
+impl<'a> Default for ParseModule<'a> {
+    fn default() -> ParseModule<'a> {
+        ParseModule::new()
+    }
+}
+
+/// The index spaces instructions, exports and segments refer to. Imports come first in the order
+/// they're declared, followed by what the module defines itself.
+impl<'a> ParseModule<'a> {
+    /// The type index of every function.
+    pub fn function_space(&self) -> Vec<usize> {
+        let mut space: Vec<usize> = self.imports.iter().filter_map(|import| match import.kind {
+            language_types::ImportKind::Function(index) => Some(index),
+            _ => None
+        }).collect();
+        space.extend(self.function_signatures.iter().cloned());
+        space
+    }
+
+    pub fn table_space(&self) -> Vec<language_types::ResizableLimits> {
+        let mut space: Vec<language_types::ResizableLimits> = self.imports.iter().filter_map(|import| match import.kind {
+            language_types::ImportKind::Table(ref table) => Some(table.limits.clone()),
+            _ => None
+        }).collect();
+        space.extend(self.tables.iter().map(|table| {
+            let tables::Table::AnyFunc{ref limits, ..} = *table;
+            limits.clone()
+        }));
+        space
+    }
+
+    pub fn memory_space(&self) -> Vec<language_types::ResizableLimits> {
+        let mut space: Vec<language_types::ResizableLimits> = self.imports.iter().filter_map(|import| match import.kind {
+            language_types::ImportKind::Memory(ref limits) => Some(limits.clone()),
+            _ => None
+        }).collect();
+        space.extend(self.memories.iter().map(|memory| memory.limits.clone()));
+        space
+    }
+
+    /// The id of the last known section the module has anything in, 0 if it's empty.
+    pub fn last_section(&self) -> u8 {
+        let present = [
+            (11, !self.data.is_empty()),
+            (10, !self.function_bodies.is_empty() || !self.encoded_bodies.is_empty()),
+            (9, !self.elements.is_empty()),
+            (8, self.start_function.is_some()),
+            (7, !self.exports.is_empty()),
+            (6, !self.globals.is_empty()),
+            (5, !self.memories.is_empty()),
+            (4, !self.tables.is_empty()),
+            (3, !self.function_signatures.is_empty()),
+            (2, !self.imports.is_empty()),
+            (1, !self.types.is_empty()),
+        ];
+        present.iter().find(|&&(_, present)| present).map_or(0, |&(id, _)| id)
+    }
+
+    pub fn global_space(&self) -> Vec<language_types::GlobalType> {
+        let mut space: Vec<language_types::GlobalType> = self.imports.iter().filter_map(|import| match import.kind {
+            language_types::ImportKind::Global(ref global) => Some(global.clone()),
+            _ => None
+        }).collect();
+        space.extend(self.globals.iter().map(|global| global.constraints.clone()));
+        space
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use parse_tree::language_types::Operation;
+
+    // NaNs never equal themselves, so float constants are compared by their bits instead
+    fn same_code(left: &[Operation], right: &[Operation]) -> bool {
+        left.len() == right.len() && left.iter().zip(right).all(|pair| match pair {
+            (&Operation::F32Const(a), &Operation::F32Const(b)) => a.to_bits() == b.to_bits(),
+            (&Operation::F64Const(a), &Operation::F64Const(b)) => a.to_bits() == b.to_bits(),
+            (&Operation::Block(ref a), &Operation::Block(ref b)) |
+            (&Operation::Loop(ref a), &Operation::Loop(ref b)) |
+            (&Operation::If(ref a), &Operation::If(ref b)) =>
+                a.block_type == b.block_type && same_code(&a.operations, &b.operations),
+            (a, b) => a == b
+        })
+    }
+
+    /// Asserts two modules are equal, counting NaN constants in their code as equal when they
+    /// have the same payload.
+    pub fn assert_same(mut left: ParseModule, mut right: ParseModule) {
+        let left_bodies = left.function_bodies.split_off(0);
+        let right_bodies = right.function_bodies.split_off(0);
+        assert_eq!(left, right);
+        assert_eq!(left_bodies.len(), right_bodies.len());
+        for (left, right) in left_bodies.iter().zip(&right_bodies) {
+            assert_eq!(left.locals, right.locals);
+            assert!(same_code(&left.code, &right.code), "{:?} != {:?}", left.code, right.code);
+        }
+    }
 }
@@ -0,0 +1,36 @@
+use std::collections::HashMap;
+
+/// Names from the "name" custom section, used to describe things in errors and debug output.
+/// Indices are into the function index space, and locals are numbered from the first parameter.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Names {
+    pub module: Option<String>,
+    pub functions: HashMap<usize, String>,
+    pub locals: HashMap<usize, HashMap<usize, String>>,
+}
+
+impl Names {
+    pub fn function(&self, index: usize) -> Option<&str> {
+        self.functions.get(&index).map(|name| &name[..])
+    }
+
+    pub fn local(&self, function: usize, index: usize) -> Option<&str> {
+        self.locals.get(&function).and_then(|locals| locals.get(&index)).map(|name| &name[..])
+    }
+
+    /// `$name` if the function has one, otherwise its index.
+    pub fn describe_function(&self, index: usize) -> String {
+        match self.function(index) {
+            Some(name) => format!("${}", name),
+            None => format!("function {}", index)
+        }
+    }
+
+    /// `$name` if the local has one, otherwise its index.
+    pub fn describe_local(&self, function: usize, index: usize) -> String {
+        match self.local(function, index) {
+            Some(name) => format!("${}", name),
+            None => format!("local {}", index)
+        }
+    }
+}
@@ -1,9 +1,19 @@
+use parse_tree::language_types::InitExpression;
 use parse_tree::language_types::ResizableLimits;
 
-#[derive(Debug, Clone)]
+// Tables are only filled in when a module is instantiated, until then `values` is empty
+#[derive(Debug, Clone, PartialEq)]
 pub enum Table {
     AnyFunc {
         limits: ResizableLimits,
-        values: Vec<usize>,
+        values: Vec<Option<usize>>, // None for elements no segment has initialised
     }
+}
+
+/// Function indices copied into a table when a module is instantiated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElementSegment {
+    pub index: usize,
+    pub offset: InitExpression,
+    pub elements: Vec<usize>,
 }
\ No newline at end of file
@@ -1,6 +1,6 @@
 use parse_tree::functions::FuncSignature;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TypeDefinition {
     Func(FuncSignature)
 }
\ No newline at end of file
@@ -1,38 +1,192 @@
+use std::io;
+use std::io::ErrorKind;
 use std::io::Read;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::thread;
 
 use parser::leb::ReadLEB;
 use parser::ParseError;
+use parser::ParseErrorKind;
+use parser::utils::Position;
 
+use parse_tree::functions::EncodedBody;
 use parse_tree::functions::FuncBody;
 use parse_tree::language_types::Operation;
 use parse_tree::language_types::ValueType;
 use parse_tree::ParseModule;
 
+// The most locals a function can declare, they're allocated before the body is parsed
+const MAX_LOCALS: usize = 50_000;
+
 // TODO finish implementing.
 pub fn parse(reader: &mut Read, module: &mut ParseModule) -> Result<(), ParseError> {
     debug!("Parsing code section");
-    let s = "".to_string();
-    let count = reader.bytes().read_varuint(32).unwrap();
+    let count = parse_count(reader, module)?;
+    let imported_functions = module.function_space().len() - module.function_signatures.len();
+    for index in 0..count {
+        parse_body(reader, module, index).map_err(|e| e.in_function(imported_functions + index as usize))?;
+        debug!("ops parsed");
+    }
+    Ok(())
+}
+
+/// Reads how many bodies the section has, which must be one for each declared function.
+pub fn parse_count(reader: &mut Read, module: &ParseModule) -> Result<u64, ParseError> {
+    let count = reader.bytes().read_varuint(32)?;
+    if count != module.function_signatures.len() as u64 {
+        return Err(ParseErrorKind::FunctionCountMismatch {
+            signatures: module.function_signatures.len(),
+            bodies: count as usize
+        }.into());
+    }
+    Ok(count)
+}
+
+/// Reads one size-prefixed function body and adds it to `module`.
+pub fn parse_body(reader: &mut Read, module: &mut ParseModule, index: u64) -> Result<(), ParseError> {
+    let body_size = reader.bytes().read_varuint(32)?;
+    debug!("body {} has size {}", index, body_size);
+    let mut body = reader.take(body_size);
+    let decoded = decode_body(&mut body, module, index)?;
+    if body.limit() != 0 {
+        return Err(body_too_short(index));
+    }
+    module.function_bodies.push(decoded);
+    Ok(())
+}
+
+/// Like `parse`, but leaves the bodies encoded in `module.encoded_bodies`, only checking they
+/// have the size they say. `offset` is where the section's contents start in the module.
+pub fn parse_encoded(reader: &mut Read, module: &mut ParseModule, offset: u64) -> Result<(), ParseError> {
+    debug!("Parsing code section without decoding it");
+    let mut reader = Position::new(reader);
+    let count = parse_count(&mut reader, module)?;
+    let imported_functions = module.function_space().len() - module.function_signatures.len();
     for index in 0..count {
-        let _body_size = reader.bytes().read_varuint(32).unwrap();
-        println!("body {} has size {}", index, _body_size);
-//        if index == 4 {reader.bytes().take(_body_size as usize).for_each(|x|print!("{:X} ", x.unwrap()));println!();}
-        let local_count = reader.bytes().read_varuint(32).unwrap();
-        let mut locals = vec![];
-        debug!("about to parse {} locals", local_count);
-        for _ in 0..local_count {
-            let local_quantity = reader.bytes().read_varuint(32).unwrap() as usize;
-            let local_type = ValueType::parse(&mut reader.bytes())?;
-            let mut l = vec![local_type; local_quantity];
-            locals.append(&mut l);
+        let size = (&mut reader).bytes().read_varuint(32).map_err(|e| ParseError::from(e).in_function(imported_functions + index as usize))?;
+        let body_offset = offset + reader.offset;
+        let mut bytes = vec![];
+        (&mut reader).take(size).read_to_end(&mut bytes)?;
+        if (bytes.len() as u64) < size {
+            let error: ParseError = io::Error::new(ErrorKind::UnexpectedEof, "function body is cut short").into();
+            return Err(error.in_function(imported_functions + index as usize));
         }
-        match Operation::parse_multiple(reader, module) {
-            Ok(code) => {
-                module.function_bodies.push(FuncBody{locals, code});
-            },
-            Err(e) => {return Err(e)},
+        module.encoded_bodies.push(EncodedBody {
+            offset: body_offset,
+            bytes
+        });
+    }
+    Ok(())
+}
+
+/// Decodes a body `parse_encoded` left encoded. `index` is the function's index, for errors.
+pub fn decode_encoded(body: &EncodedBody, module: &ParseModule, index: usize) -> Result<FuncBody, ParseError> {
+    let mut reader = Position::new(&body.bytes[..]);
+    let decoded = decode_body(&mut reader, module, index as u64).and_then(|decoded| {
+        if reader.offset != body.bytes.len() as u64 {
+            return Err(body_too_short(index as u64));
         }
-        debug!("ops parsed");
+        Ok(decoded)
+    });
+    decoded.map_err(|e| e.in_section(10).in_function(index).at(body.offset + reader.offset))
+}
+
+/// Like `parse`, but decodes the bodies on up to `threads` threads. The section is read into
+/// memory first and split up by the body sizes. `offset` is where the section's contents start
+/// in the module, so errors can still say where they were found.
+pub fn parse_parallel(reader: &mut Read, module: &mut ParseModule, threads: usize, offset: u64) -> Result<(), ParseError> {
+    debug!("Parsing code section on {} threads", threads);
+    let mut reader = Position::new(reader);
+    let count = parse_count(&mut reader, module).map_err(|e| e.at(offset + reader.offset))?;
+    let start = offset + reader.offset;
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+
+    // Where each body is in `bytes`. A body that's cut short is decoded as far as it goes, so it
+    // fails the same way it would on one thread
+    let mut bodies = vec![];
+    let mut split_error = None;
+    let mut rest = &bytes[..];
+    while (bodies.len() as u64) < count {
+        let size = match (&mut rest).bytes().read_varuint(32) {
+            Ok(size) => size as usize,
+            Err(e) => {
+                split_error = Some(ParseError::from(e));
+                break;
+            }
+        };
+        let position = bytes.len() - rest.len();
+        let available = size.min(rest.len());
+        bodies.push((position, size, &rest[..available]));
+        rest = &rest[available..];
+    }
+
+    let imported_functions = module.function_space().len() - module.function_signatures.len();
+    let decoded = decode_bodies(&bodies, module, threads);
+    for (index, result) in decoded.into_iter().enumerate() {
+        match result {
+            Ok(body) => module.function_bodies.push(body),
+            Err((error, position)) => return Err(error.in_function(imported_functions + index).at(start + position))
+        }
+    }
+    let end = start + (bytes.len() - rest.len()) as u64;
+    if let Some(error) = split_error {
+        return Err(error.in_function(imported_functions + bodies.len()).at(end));
+    }
+    if !rest.is_empty() {
+        return Err(ParseError::from(ParseErrorKind::SectionLengthWrong).at(end));
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+// Decodes each body on whichever thread gets to it next, failures come with their position in
+// the section
+fn decode_bodies(bodies: &[(usize, usize, &[u8])], module: &ParseModule, threads: usize) -> Vec<Result<FuncBody, (ParseError, u64)>> {
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<_> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.min(bodies.len())).map(|_| scope.spawn(|| {
+            let mut decoded = vec![];
+            loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let (position, size, bytes) = match bodies.get(index) {
+                    Some(&body) => body,
+                    None => break
+                };
+                let mut reader = Position::new(bytes);
+                let result = decode_body(&mut reader, module, index as u64).and_then(|body| {
+                    if reader.offset != size as u64 {
+                        return Err(body_too_short(index as u64));
+                    }
+                    Ok(body)
+                });
+                decoded.push((index, result.map_err(|e| (e, position as u64 + reader.offset))));
+            }
+            decoded
+        })).collect();
+        workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect()
+    });
+    results.sort_by_key(|&(index, _)| index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+fn decode_body(reader: &mut Read, module: &ParseModule, index: u64) -> Result<FuncBody, ParseError> {
+    let local_count = reader.bytes().read_varuint(32)?;
+    let mut locals = vec![];
+    debug!("about to parse {} locals", local_count);
+    for _ in 0..local_count {
+        let local_quantity = reader.bytes().read_varuint(32)? as usize;
+        if local_quantity > MAX_LOCALS - locals.len() {
+            return Err(ParseErrorKind::CustomError(format!("Function body {} declares too many locals", index)).into());
+        }
+        let local_type = ValueType::parse(&mut reader.bytes())?;
+        let mut l = vec![local_type; local_quantity];
+        locals.append(&mut l);
+    }
+    let code = Operation::parse_multiple(reader, module)?;
+    Ok(FuncBody{locals, code})
+}
+
+fn body_too_short(index: u64) -> ParseError {
+    ParseErrorKind::CustomError(format!("Function body {} is shorter than its size", index)).into()
+}
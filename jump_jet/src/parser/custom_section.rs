@@ -0,0 +1,19 @@
+use std::borrow::Cow;
+
+use parser::ParseError;
+use parser::utils::Source;
+
+use parse_tree::custom::CustomSection;
+use parse_tree::ParseModule;
+
+pub fn parse<'a, S: Source<'a>>(reader: &mut S, name: Cow<'a, str>, module: &mut ParseModule<'a>) -> Result<(), ParseError> {
+    debug!("Preserving custom section {}", name);
+    let payload = reader.read_rest()?;
+    let after = module.last_section();
+    module.custom_sections.push(CustomSection {
+        name,
+        payload,
+        after
+    });
+    Ok(())
+}
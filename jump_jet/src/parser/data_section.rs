@@ -2,39 +2,39 @@ use std::io::Read;
 
 use parser::leb::ReadLEB;
 use parser::ParseError;
+use parser::ParseErrorKind;
+use parser::utils::Copying;
+use parser::utils::Source;
 
 use parse_tree::language_types::InitExpression;
+use parse_tree::memory::DataSegment;
 use parse_tree::ParseModule;
 
-// TODO finish implementing.
 pub fn parse(reader: &mut Read, module: &mut ParseModule) -> Result<(), ParseError> {
+    parse_from(&mut Copying::new(reader), module)
+}
+
+pub fn parse_from<'a, S: Source<'a>>(reader: &mut S, module: &mut ParseModule<'a>) -> Result<(), ParseError> {
     debug!("Parsing data section");
-    let count = reader.bytes().read_varuint(32).unwrap();
+    let count = (&mut *reader).bytes().read_varuint(32)?;
     for _ in 0..count {
-        let index = reader.bytes().read_varuint(32).unwrap() as usize;
+        let index = (&mut *reader).bytes().read_varuint(32)? as usize;
         if index != 0 {
-            return Err(ParseError::CustomError("Data index must be 0 in wasm 1.0".to_string()));
+            return Err(ParseErrorKind::CustomError("Data index must be 0 in wasm 1.0".to_string()).into());
         }
 
-        if let Ok(InitExpression::I32Const(init_expr)) = InitExpression::parse(reader, module) {
-            let offset = init_expr as usize;
-            let size = reader.bytes().read_varuint(32).unwrap();
-            let mut data = vec![];
-            if let Err(e) = reader.take(size).read_to_end(&mut data) {
-                return Err(ParseError::Io(e));
-            }
-
-            let memory = &mut module.memories[index];
-            if let Some(max) = memory.limits.maximum {
-                if offset + size as usize > max as usize {
-                    // TODO not sure of correct behaviour here...
-                    return Err(ParseError::CustomError("adding to big memory".to_string()));
-                }
-            }
-            memory.values.splice(offset..offset+size as usize-1, data);
-        } else {
-            return Err(ParseError::CustomError("init_expr for data section must be i32.const".to_string()));
-        }
+        let offset = match InitExpression::parse(reader)? {
+            offset @ InitExpression::I32Const(_) | offset @ InitExpression::GetGlobal(_) => offset,
+            _ => return Err(ParseErrorKind::CustomError("init_expr for data section must be an i32".to_string()).into())
+        };
+        let size = (&mut *reader).bytes().read_varuint(32)?;
+        let data = reader.read_bytes(size)?;
+        // Whether the data fits is only known once the memory has been allocated
+        module.data.push(DataSegment {
+            index,
+            offset,
+            data
+        });
     }
     Ok(())
-}
\ No newline at end of file
+}
@@ -2,42 +2,37 @@ use std::io::Read;
 
 use parser::leb::ReadLEB;
 use parser::ParseError;
+use parser::ParseErrorKind;
 
 use parse_tree::language_types::InitExpression;
 use parse_tree::ParseModule;
-use parse_tree::tables::Table;
+use parse_tree::tables::ElementSegment;
 
 pub fn parse(reader: &mut Read, module: &mut ParseModule) -> Result<(), ParseError> {
     debug!("Parsing elements section");
-    let count = reader.bytes().read_varuint(32).unwrap();
+    let count = reader.bytes().read_varuint(32)?;
     for _ in 0..count {
-        let index = reader.bytes().read_varuint(32).unwrap() as usize;
+        let index = reader.bytes().read_varuint(32)? as usize;
         if index != 0 {
-            return Err(ParseError::CustomError("WASM 1.0 only allows 1 table".to_string()));
+            return Err(ParseErrorKind::CustomError("WASM 1.0 only allows 1 table".to_string()).into());
         }
 
-        if let Ok(InitExpression::I32Const(init_expr)) = InitExpression::parse(reader, module) {
-            let init  = init_expr as usize;
-            let num_elem = reader.bytes().read_varuint(32).unwrap() as usize;
-            let mut elements = vec![];
-            for _ in 0..num_elem {
-                let item = reader.bytes().read_varuint(32).unwrap() as usize;
-                elements.push(item);
-            }
-            match module.tables[index] {
-                Table::AnyFunc {ref mut values, ref limits} => {
-                    if let Some(max) = limits.maximum {
-                        if init + num_elem > max as usize {
-                            // TODO check for the proper behaviour on what to do here... might want to keep adding items up to the end
-                            return Err(ParseError::CustomError("Attempted to add values to late into table... ".to_string()));
-                        }
-                    }
-                    values.splice(init..init+num_elem, elements);
-                },
-            }
-        } else {
-            return Err(ParseError::CustomError("init_expr for elements section must be i32.const".to_string()));
+        let offset = match InitExpression::parse(reader)? {
+            offset @ InitExpression::I32Const(_) | offset @ InitExpression::GetGlobal(_) => offset,
+            _ => return Err(ParseErrorKind::CustomError("init_expr for elements section must be an i32".to_string()).into())
+        };
+        let num_elem = reader.bytes().read_varuint(32)? as usize;
+        let mut elements = vec![];
+        for _ in 0..num_elem {
+            let item = reader.bytes().read_varuint(32)? as usize;
+            elements.push(item);
         }
+        // Whether the elements fit is only known once the table has been allocated
+        module.elements.push(ElementSegment {
+            index,
+            offset,
+            elements
+        });
     }
     Ok(())
-}
\ No newline at end of file
+}
@@ -2,18 +2,27 @@ use std::io::Read;
 
 use parser::leb::ReadLEB;
 use parser::ParseError;
-use parser::utils;
+use parser::utils::Copying;
+use parser::utils::Source;
 
+use parse_tree::language_types::ExportEntry;
 use parse_tree::language_types::ExternalKind;
 use parse_tree::ParseModule;
 
 pub fn parse(reader: &mut Read, module: &mut ParseModule) -> Result<(), ParseError> {
+    parse_from(&mut Copying::new(reader), module)
+}
+
+pub fn parse_from<'a, S: Source<'a>>(reader: &mut S, module: &mut ParseModule<'a>) -> Result<(), ParseError> {
     debug!("Parsing exports section");
-    let count = reader.bytes().read_varuint(32).unwrap();
+    let count = (&mut *reader).bytes().read_varuint(32)?;
     for _ in 0..count {
-        let field = utils::read_string(reader)?;
+        let field = reader.read_name()?;
         let kind = ExternalKind::parse(reader)?;
-        module.exports.insert(field, kind);
+        module.exports.push(ExportEntry {
+            field,
+            kind
+        });
     }
     Ok(())
 }
\ No newline at end of file
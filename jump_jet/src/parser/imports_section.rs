@@ -1,30 +1,35 @@
-use std::collections::HashMap;
-use std::io::Read;
-
 use parser::leb::ReadLEB;
 use parser::ParseError;
-use parser::utils::read_string;
+use parser::ParseErrorKind;
+use parser::utils::Copying;
+use parser::utils::Source;
 
-use parse_tree::language_types::ExternalKind;
+use std::io::Read;
+
+use parse_tree::language_types::ImportEntry;
+use parse_tree::language_types::ImportKind;
 use parse_tree::ParseModule;
 
 pub fn parse(reader: &mut Read, module: &mut ParseModule) -> Result<(), ParseError> {
+    parse_from(&mut Copying::new(reader), module)
+}
+
+pub fn parse_from<'a, S: Source<'a>>(reader: &mut S, module: &mut ParseModule<'a>) -> Result<(), ParseError> {
     debug!("Parsing imports section");
-    let count = reader.bytes().read_varuint(32).unwrap();
+    let count = (&mut *reader).bytes().read_varuint(32)?;
     for _ in 0..count {
-        let module_name = read_string(reader)?;
-        let field = read_string(reader)?;
-        let kind = ExternalKind::parse(reader)?;
+        let module_name = reader.read_name()?;
+        let field = reader.read_name()?;
+        let kind = ImportKind::parse(reader)?;
 
-        if !module.imports.contains_key(&module_name) {
-            let mut map = HashMap::new();
-            map.insert(field, kind);
-            module.imports.insert(module_name, map);
-        } else if !module.imports.get(&module_name).unwrap().contains_key(&field) {
-            module.imports.get_mut(&module_name).unwrap().insert(field, kind);
-        } else {
-            return Err(ParseError::CustomError("Tried to import an already imported field".to_string()));
+        if module.imports.iter().any(|import| import.module == module_name && import.field == field) {
+            return Err(ParseErrorKind::CustomError("Tried to import an already imported field".to_string()).into());
         }
+        module.imports.push(ImportEntry {
+            module: module_name,
+            field,
+            kind
+        });
     }
     Ok(())
 }
\ No newline at end of file
@@ -5,12 +5,14 @@ use parser::byteorder::LittleEndian;
 use parser::byteorder::ReadBytesExt;
 use parser::leb::ReadLEB;
 use parser::ParseError;
+use parser::ParseErrorKind;
 
 use parse_tree::language_types::Block;
 use parse_tree::language_types::BlockType;
 use parse_tree::language_types::BranchTable;
 use parse_tree::language_types::ExternalKind;
 use parse_tree::language_types::GlobalType;
+use parse_tree::language_types::ImportKind;
 use parse_tree::language_types::InitExpression;
 use parse_tree::language_types::LanguageType;
 use parse_tree::language_types::MemoryImmediate;
@@ -20,9 +22,13 @@ use parse_tree::language_types::TableType;
 use parse_tree::language_types::ValueType;
 use parse_tree::ParseModule;
 
+// Blocks are parsed recursively, so how deeply they can nest is limited to keep a hostile module from
+// overflowing the stack
+const MAX_NESTING: usize = 1024;
+
 impl ValueType {
 	pub fn parse<R: Read>(bytes: &mut Bytes<R>) -> Result<ValueType, ParseError> {
-		let read = bytes.read_varint(7).unwrap();
+		let read = bytes.read_varint(7)?;
 		debug!("Value type ");
 		ValueType::get(read)
 	}
@@ -33,14 +39,14 @@ impl ValueType {
 			-0x02 => Ok(ValueType::I64),
 			-0x03 => Ok(ValueType::F32),
 			-0x04 => Ok(ValueType::F64),
-			_    => Err(ParseError::InvalidValueType(key))
+			_    => Err(ParseErrorKind::InvalidValueType(key).into())
 		}
 	}
 }
 
 impl LanguageType {
 	pub fn parse<R: Read>(bytes: &mut Bytes<R>) -> Result<LanguageType, ParseError> {
-		let read = bytes.read_varint(7).unwrap();
+		let read = bytes.read_varint(7)?;
 		LanguageType::get(read)
 	}
 
@@ -53,7 +59,7 @@ impl LanguageType {
 			-0x10 => Ok(LanguageType::Anyfunc),
 			-0x20 => Ok(LanguageType::Func),
 			-0x40 => Ok(LanguageType::EmptyBlock),
-			_    => Err(ParseError::InvalidLanguageType(key))
+			_    => Err(ParseErrorKind::InvalidLanguageType(key).into())
 		}
 	}
 }
@@ -66,18 +72,30 @@ impl ExternalKind {
 			1 => ExternalKind::Table(reader.bytes().read_varuint(32)? as usize),
 			2 => ExternalKind::Memory(reader.bytes().read_varuint(32)? as usize),
 			3 => ExternalKind::Global(reader.bytes().read_varuint(32)? as usize),
-			_ => return Err(ParseError::InvalidExternalKind(external_kind))
+			_ => return Err(ParseErrorKind::InvalidExternalKind(external_kind).into())
+		})
+	}
+}
+
+impl ImportKind {
+	pub fn parse(reader: &mut Read) -> Result<ImportKind, ParseError> {
+		let external_kind = reader.read_u8()?;
+		Ok(match external_kind {
+			0 => ImportKind::Function(reader.bytes().read_varuint(32)? as usize),
+			1 => ImportKind::Table(TableType::parse(reader)?),
+			2 => ImportKind::Memory(ResizableLimits::parse(reader)?),
+			3 => ImportKind::Global(GlobalType::parse(reader)?),
+			_ => return Err(ParseErrorKind::InvalidExternalKind(external_kind).into())
 		})
 	}
 }
 
 impl ResizableLimits {
 	pub fn parse(reader: &mut Read) -> Result<ResizableLimits, ParseError> {
-		println!("attempting");
-		let flags = reader.bytes().read_varuint(1).unwrap();
-		let initial = reader.bytes().read_varuint(32).unwrap();
+		let flags = reader.bytes().read_varuint(1)?;
+		let initial = reader.bytes().read_varuint(32)?;
         let maximum = if flags == 1 {
-            Some(reader.bytes().read_varuint(32).unwrap())
+            Some(reader.bytes().read_varuint(32)?)
         } else {
             None
         };
@@ -88,7 +106,7 @@ impl ResizableLimits {
 impl GlobalType {
 	pub fn parse(reader: &mut Read) -> Result<GlobalType, ParseError> {
 		let value_type = ValueType::parse(&mut reader.bytes())?;
-		let mutable = reader.bytes().read_varuint(1).unwrap();
+		let mutable = reader.bytes().read_varuint(1)?;
 		Ok(GlobalType {
 			content_type: value_type,
 			mutability: mutable == 1
@@ -102,7 +120,7 @@ impl TableType {
 		match elem_type_res {
 			Ok(elem_type) => {
 				if elem_type != LanguageType::Anyfunc {
-					return Err(ParseError::CustomError("WASM 1.0 only valid table type is `anyfunc`".to_string()));
+					return Err(ParseErrorKind::CustomError("WASM 1.0 only valid table type is `anyfunc`".to_string()).into());
 				}
 				let limits_res = ResizableLimits::parse(reader);
 				match limits_res {
@@ -116,66 +134,74 @@ impl TableType {
 }
 
 impl InitExpression {
-	pub fn parse(reader: &mut Read, module: &ParseModule) -> Result<InitExpression, ParseError> {
-		let byte = reader.bytes().next().unwrap().unwrap();
+	// Which globals an expression may read depends on the whole module, so that's left to the validator
+	pub fn parse(reader: &mut Read) -> Result<InitExpression, ParseError> {
+		let byte = reader.read_u8()?;
 		match byte {
 			0x41 => {
-				let immediate = reader.bytes().read_varint(32).unwrap() as i32;
-				let end_op = reader.bytes().next().unwrap().unwrap();
+				let immediate = reader.bytes().read_varint(32)? as i32;
+				let end_op = reader.read_u8()?;
 				if end_op != 0x0b {
-					Err(ParseError::CustomError("invalid i32.const instruction in init expression".to_string()))
+					Err(ParseErrorKind::CustomError("invalid i32.const instruction in init expression".to_string()).into())
 				} else {
 					Ok(InitExpression::I32Const(immediate))
 				}
 			},
 			0x42 => {
-				let immediate = reader.bytes().read_varint(64).unwrap();
-				let end_op = reader.bytes().next().unwrap().unwrap();
+				let immediate = reader.bytes().read_varint(64)?;
+				let end_op = reader.read_u8()?;
 				if end_op != 0x0b {
-					Err(ParseError::CustomError("invalid i64.const instruction in init expression".to_string()))
+					Err(ParseErrorKind::CustomError("invalid i64.const instruction in init expression".to_string()).into())
 				} else {
 					Ok(InitExpression::I64Const(immediate))
 				}
 			},
 			0x43 => {
-				let immediate = reader.read_u32::<LittleEndian>().unwrap() as f32;
-				let end_op = reader.bytes().next().unwrap().unwrap();
+				let immediate = f32::from_bits(reader.read_u32::<LittleEndian>()?);
+				let end_op = reader.read_u8()?;
 				if end_op != 0x0b {
-					Err(ParseError::CustomError("invalid f32.const instruction in init expression".to_string()))
+					Err(ParseErrorKind::CustomError("invalid f32.const instruction in init expression".to_string()).into())
 				} else {
 					Ok(InitExpression::F32Const(immediate))
 				}
 			},
 			0x44 => {
-				let immediate = reader.read_u64::<LittleEndian>().unwrap() as f64;
-				let end_op = reader.bytes().next().unwrap().unwrap();
+				let immediate = f64::from_bits(reader.read_u64::<LittleEndian>()?);
+				let end_op = reader.read_u8()?;
 				if end_op != 0x0b {
-					Err(ParseError::CustomError("invalid f64.const instruction in init expression".to_string()))
+					Err(ParseErrorKind::CustomError("invalid f64.const instruction in init expression".to_string()).into())
 				} else {
 					Ok(InitExpression::F64Const(immediate))
 				}
 			},
 			0x23 => {
-				let immediate = reader.bytes().read_varint(32).unwrap() as usize;
-				let end_op = reader.bytes().next().unwrap().unwrap();
+				let immediate = reader.bytes().read_varuint(32)? as usize;
+				let end_op = reader.read_u8()?;
 				if end_op != 0x0b {
-					Err(ParseError::CustomError("invalid get_global instruction in init expression".to_string()))
-				} else if module.globals[immediate].constraints.mutability {
-					Err(ParseError::CustomError("get_global in init expressions can only refer to immutable globals".to_string()))
+					Err(ParseErrorKind::CustomError("invalid get_global instruction in init expression".to_string()).into())
 				} else {
 					Ok(InitExpression::GetGlobal(immediate))
 				}
 			},
-			_ => Err(ParseError::CustomError("Unexpected byte in init expression".to_string()))
+			_ => Err(ParseErrorKind::CustomError("Unexpected byte in init expression".to_string()).into())
 		}
 	}
 }
 
 impl Operation {
 	pub fn parse_multiple(reader: &mut Read, module: &ParseModule) -> Result<Vec<Operation>, ParseError> {
+		Operation::parse_sequence(reader, module, 0)
+	}
+
+	pub fn parse(reader: &mut Read, module: &ParseModule) -> Result<Operation, ParseError> {
+		Operation::parse_nested(reader, module, 0)
+	}
+
+	// Parses up to the `end` of a sequence that's `depth` blocks deep
+	fn parse_sequence(reader: &mut Read, module: &ParseModule, depth: usize) -> Result<Vec<Operation>, ParseError> {
 		let mut ops = vec![];
 		loop {
-			match Operation::parse(reader, module) {
+			match Operation::parse_nested(reader, module, depth) {
 				Ok(operation) => {
 					if let Operation::End = operation {
 						break;
@@ -188,29 +214,30 @@ impl Operation {
 		Ok(ops)
 	}
 
-	pub fn parse(reader: &mut Read, module: &ParseModule) -> Result<Operation, ParseError> {
-		let opcode = reader.bytes().next().unwrap().unwrap();
+	// Blocks are handled here and everything else in `parse_opcode`, so the stack frames of the recursion
+	// stay small
+	fn parse_nested(reader: &mut Read, module: &ParseModule, depth: usize) -> Result<Operation, ParseError> {
+		let opcode = reader.read_u8()?;
+		let operation = match opcode {
+			0x02 => Block::parse_nested(reader, module, depth + 1).map(Operation::Block),
+			0x03 => Block::parse_nested(reader, module, depth + 1).map(Operation::Loop),
+			0x04 => Block::parse_nested(reader, module, depth + 1).map(Operation::If),
+			_ => Operation::parse_opcode(opcode, reader, module)
+		};
+		// Errors from inside a block keep the opcode they were found in
+		operation.map_err(|e| e.in_opcode(opcode))
+	}
+
+	fn parse_opcode(opcode: u8, reader: &mut Read, module: &ParseModule) -> Result<Operation, ParseError> {
 		match opcode {
 
 			// Control flow operators
 			0x00 => Ok(Operation::Unreachable),
 			0x01 => Ok(Operation::Nop),
-			0x02 => match Block::parse(reader, module) {
-				Ok(block) => Ok(Operation::Block(block)),
-				Err(e) => Err(e)
-			},
-			0x03 => match Block::parse(reader, module) {
-				Ok(block) => Ok(Operation::Loop(block)),
-				Err(e) => Err(e)
-			},
-			0x04 => match Block::parse(reader, module) {
-				Ok(block) => Ok(Operation::If(block)),
-				Err(e) => Err(e)
-			},
 			0x05 => Ok(Operation::Else),
 			0x0b => Ok(Operation::End),
-			0x0c => Ok(Operation::Branch(reader.bytes().read_varuint(32).unwrap() as i32)),
-			0x0d => Ok(Operation::BranchIf(reader.bytes().read_varuint(32).unwrap() as i32)),
+			0x0c => Ok(Operation::Branch(reader.bytes().read_varuint(32)? as i32)),
+			0x0d => Ok(Operation::BranchIf(reader.bytes().read_varuint(32)? as i32)),
 			0x0e => {
 				match BranchTable::parse(reader, module) {
 					Ok(branch_table) => Ok(Operation::BranchTable(branch_table)),
@@ -221,14 +248,14 @@ impl Operation {
 
 			// Call operators
 			0x10 => {
-				let function_index = reader.bytes().read_varuint(32).unwrap() as usize;
+				let function_index = reader.bytes().read_varuint(32)? as usize;
 				Ok(Operation::Call(function_index))
 			}
 			0x11 => {
-				let type_index = reader.bytes().read_varuint(32).unwrap() as usize;
-				let reserved = reader.bytes().read_varuint(1).unwrap() == 1;
+				let type_index = reader.bytes().read_varuint(32)? as usize;
+				let reserved = reader.bytes().read_varuint(1)? == 1;
 				if reserved {
-					return Err(ParseError::CustomError("call_indirect reserved field must be 0".to_string()));
+					return Err(ParseErrorKind::CustomError("call_indirect reserved field must be 0".to_string()).into());
 				}
 				Ok(Operation::CallIndirect(type_index, reserved))
 
@@ -240,23 +267,23 @@ impl Operation {
 
 			// Variable access
 			0x20 => {
-				let immediate = reader.bytes().read_varuint(32).unwrap() as usize;
+				let immediate = reader.bytes().read_varuint(32)? as usize;
 				Ok(Operation::GetLocal(immediate))
 			},
 			0x21 => {
-				let immediate = reader.bytes().read_varuint(32).unwrap() as usize;
+				let immediate = reader.bytes().read_varuint(32)? as usize;
 				Ok(Operation::SetLocal(immediate))
 			},
 			0x22 => {
-				let immediate = reader.bytes().read_varuint(32).unwrap() as usize;
+				let immediate = reader.bytes().read_varuint(32)? as usize;
 				Ok(Operation::TeeLocal(immediate))
 			},
 			0x23 => {
-				let immediate = reader.bytes().read_varuint(32).unwrap() as usize;
+				let immediate = reader.bytes().read_varuint(32)? as usize;
 				Ok(Operation::GetGlobal(immediate))
 			},
 			0x24 => {
-				let immediate = reader.bytes().read_varuint(32).unwrap() as usize;
+				let immediate = reader.bytes().read_varuint(32)? as usize;
 				Ok(Operation::SetGlobal(immediate))
 			},
 
@@ -400,29 +427,29 @@ impl Operation {
 				}
 			},
 			0x3f => {
-				let reserved = reader.bytes().read_varuint(1).unwrap() == 1;
+				let reserved = reader.bytes().read_varuint(1)? == 1;
 				Ok(Operation::CurrentMemory(reserved))
 			},
 			0x40 => {
-				let reserved = reader.bytes().read_varuint(1).unwrap() == 1;
+				let reserved = reader.bytes().read_varuint(1)? == 1;
 				Ok(Operation::GrowMemory(reserved))
 			},
 
 			// Constants
 			0x41 => {
-				let immediate = reader.bytes().read_varint(32).unwrap() as i32;
+				let immediate = reader.bytes().read_varint(32)? as i32;
 				Ok(Operation::I32Const(immediate))
 			},
 			0x42 => {
-				let immediate = reader.bytes().read_varint(64).unwrap();
+				let immediate = reader.bytes().read_varint(64)?;
 				Ok(Operation::I64Const(immediate))
 			},
 			0x43 => {
-				let immediate = reader.read_u32::<LittleEndian>().unwrap() as f32;
+				let immediate = f32::from_bits(reader.read_u32::<LittleEndian>()?);
 				Ok(Operation::F32Const(immediate))
 			},
 			0x44 => {
-				let immediate = reader.read_u64::<LittleEndian>().unwrap() as f64;
+				let immediate = f64::from_bits(reader.read_u64::<LittleEndian>()?);
 				Ok(Operation::F64Const(immediate))
 			},
 
@@ -557,40 +584,50 @@ impl Operation {
 			0xbe => Ok(Operation::F32ReinterpretI32),
 			0xbf => Ok(Operation::F64ReinterpretI64),
 
-			_ => Err(ParseError::CustomError("Unknown opcode".to_string()))
+			_ => Err(ParseErrorKind::CustomError("Unknown opcode".to_string()).into())
 		}
 	}
 }
 
 impl Block {
 	pub fn parse(reader: &mut Read, module: &ParseModule) -> Result<Block, ParseError> {
-		let block_type = BlockType::parse(reader, module).unwrap();
-		let operations = Operation::parse_multiple(reader, module).unwrap();
+		Block::parse_nested(reader, module, 1)
+	}
+
+	fn parse_nested(reader: &mut Read, module: &ParseModule, depth: usize) -> Result<Block, ParseError> {
+		if depth > MAX_NESTING {
+			return Err(ParseErrorKind::CustomError("Blocks are nested too deeply".to_string()).into());
+		}
+		let block_type = BlockType::parse(reader, module)?;
+		let operations = Operation::parse_sequence(reader, module, depth)?;
 		Ok(Block{block_type, operations})
 	}
 }
 
 impl BlockType {
 	pub fn parse(reader: &mut Read, module: &ParseModule) -> Result<BlockType, ParseError> {
-		let byte = reader.bytes().read_varint(7).unwrap();
-		if let Ok(value_type) = ValueType::get(byte) {
+		// A type index is written as a positive s33, so that it can't be mistaken for a value type
+		let byte = reader.bytes().read_varint(33)?;
+		if byte >= 0 {
+			Ok(BlockType::Type(byte as usize))
+		} else if let Ok(value_type) = ValueType::get(byte) {
 			Ok(BlockType::Value(value_type))
 		} else if byte == -0x40 {
 			Ok(BlockType::Empty)
 		} else {
-			Err(ParseError::CustomError("Block type wasn't valid".to_string()))
+			Err(ParseErrorKind::CustomError("Block type wasn't valid".to_string()).into())
 		}
 	}
 }
 
 impl BranchTable {
 	pub fn parse(reader: &mut Read, module: &ParseModule) -> Result<BranchTable, ParseError> {
-		let target_count = reader.bytes().read_varuint(32).unwrap() as u32;
+		let target_count = reader.bytes().read_varuint(32)? as u32;
 		let mut targets = vec![];
 		for _ in 0..target_count {
-			targets.push(reader.bytes().read_varuint(32).unwrap() as i32);
+			targets.push(reader.bytes().read_varuint(32)? as i32);
 		}
-		let default = reader.bytes().read_varuint(32).unwrap() as i32;
+		let default = reader.bytes().read_varuint(32)? as i32;
 		Ok(BranchTable {
 			targets,
 			default
@@ -600,8 +637,8 @@ impl BranchTable {
 
 impl MemoryImmediate {
 	pub fn parse(reader: &mut Read, module: &ParseModule) -> Result<MemoryImmediate, ParseError> {
-		let flags = reader.bytes().read_varuint(32).unwrap() as u32;
-		let offset = reader.bytes().read_varuint(32).unwrap() as u32;
+		let flags = reader.bytes().read_varuint(32)? as u32;
+		let offset = reader.bytes().read_varuint(32)? as u32;
 		Ok(MemoryImmediate{flags, offset})
 	}
 }
@@ -609,7 +646,7 @@ impl MemoryImmediate {
 #[cfg(test)]
 mod tests {
 	use super::*;
-	use std::collections::HashMap;
+	use parse_tree::builder::ModuleBuilder;
 	use std::io::{Bytes, Cursor, Read};
 
 	macro_rules! b {
@@ -619,19 +656,13 @@ mod tests {
 		}
 	}
 
-	fn p() -> ParseModule {
-		ParseModule {
-			version: 1,
-			exports: HashMap::new(),
-			function_bodies: vec![],
-			function_signatures: vec![],
-			globals: vec![],
-			imports: HashMap::new(),
-			memories: vec![],
-			tables: vec![],
-			types: vec![],
-			start_function: None
-		}
+	fn p() -> ParseModule<'static> {
+		ModuleBuilder::new().build()
+	}
+
+	#[test]
+	fn init_expression_global_indices_are_unsigned() {
+		assert_eq!(InitExpression::parse(b!(0x23 0x40 0x0b)).unwrap(), InitExpression::GetGlobal(0x40));
 	}
 
 	#[test]
@@ -670,4 +701,13 @@ mod tests {
 			Operation::I32Const(6)
 		]})]);
 	}
+
+	#[test]
+	fn read_block_with_type_index() {
+		let ops = Operation::parse_multiple(b!(0x02 0x01 0x0b 0x03 0x80 0x01 0x0b 0x0b), &p()).unwrap();
+		assert_eq!(ops, vec![
+			Operation::Block(Block {block_type: BlockType::Type(1), operations: vec![]}),
+			Operation::Loop(Block {block_type: BlockType::Type(128), operations: vec![]})
+		]);
+	}
 }
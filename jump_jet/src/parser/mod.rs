@@ -8,13 +8,32 @@ use std::io;
 use std::io::ErrorKind;
 use std::io::Read;
 use std::collections::HashMap;
+use std::thread;
 
+use parse_tree::functions::EncodedBody;
+use parse_tree::functions::FuncBody;
 use parse_tree::ParseModule;
 
+mod parse_error;
+pub use parser::parse_error::ParseError;
+pub use parser::parse_error::ParseErrorKind;
+
+mod streaming;
+pub use parser::streaming::ParseEvent;
+pub use parser::streaming::StreamingParser;
+
 mod utils;
+use parser::utils::cut_short;
+use parser::utils::Copying;
+use parser::utils::Position;
+use parser::utils::Source;
+
+use text;
 
 mod language_types;
 
+mod custom_section;
+mod name_section;
 mod types_section;
 mod imports_section;
 mod functions_section;
@@ -29,27 +48,8 @@ mod data_section;
 
 const MAGIC_NUMBER: u32 = 0x6d736100;
 
-#[derive(Debug)]
-pub enum ParseError {
-    WrongMagicNumber,
-    UnknownSectionId(u64),
-    UnsupportedModuleVersion,
-    SectionLengthWrong,
-    InvalidTypeForm,
-    InvalidValueType(i64),
-    InvalidLanguageType(i64),
-    InvalidExternalKind(u8),
-    TooManyReturns,
-    Io(io::Error),
-    NonExistantTypeReference,
-    CustomError(String),
-}
-
-impl From<io::Error> for ParseError {
-    fn from(err: io::Error) -> ParseError {
-        ParseError::Io(err)
-    }
-}
+// How big a code section has to be before its bodies are decoded in parallel
+const PARALLEL_CODE_SIZE: u64 = 64 * 1024;
 
 pub struct ModuleParser {
     sections: HashMap<
@@ -57,7 +57,15 @@ pub struct ModuleParser {
         Box<
             Fn(&mut Read, &mut ParseModule) -> Result<(), ParseError>
         >
-    >
+    >,
+    custom_sections: HashMap<
+        String,
+        Box<
+            Fn(&mut Read, &mut ParseModule) -> Result<(), ParseError>
+        >
+    >,
+    threads: usize,
+    lazy: bool
 }
 
 impl ModuleParser {
@@ -77,40 +85,83 @@ impl ModuleParser {
         sections.insert(10, Box::new(code_section::parse));
         sections.insert(11, Box::new(data_section::parse));
 
-        ModuleParser{sections}
+        let mut custom_sections: HashMap<String, Box<Fn(&mut Read, &mut ParseModule) -> Result<(), ParseError>>> = HashMap::new();
+        custom_sections.insert("name".to_string(), Box::new(name_section::parse_or_ignore));
+
+        let threads = thread::available_parallelism().map(|count| count.get()).unwrap_or(1);
+
+        ModuleParser{sections, custom_sections, threads, lazy: false}
+    }
+
+    /// Decodes custom sections called `name` with `handler`, which is given the section's payload.
+    /// Custom sections without a handler are kept in `ParseModule::custom_sections`.
+    pub fn custom_section(&mut self, name: &str, handler: Box<Fn(&mut Read, &mut ParseModule) -> Result<(), ParseError>>) -> &mut ModuleParser {
+        self.custom_sections.insert(name.to_string(), handler);
+        self
+    }
+
+    /// Decodes large code sections on up to `threads` threads, which defaults to the number of
+    /// cores. Setting it to 1 decodes everything on the calling thread, for environments that
+    /// can't spawn threads.
+    pub fn threads(&mut self, threads: usize) -> &mut ModuleParser {
+        self.threads = threads;
+        self
     }
 
-    pub fn parse_module<T: Read>(&self, mut reader: T) -> Result<ParseModule,ParseError> {
+    /// Leaves function bodies encoded in `ParseModule::encoded_bodies` rather than decoding them,
+    /// so a function that's never called is never decoded.
+    pub fn lazy(&mut self, lazy: bool) -> &mut ModuleParser {
+        self.lazy = lazy;
+        self
+    }
+
+    pub fn parse_module<T: Read>(&self, reader: T) -> Result<ParseModule<'static>, ParseError> {
+        let mut reader = Position::new(reader);
+        self.parse_contents(&mut reader).map_err(|e| e.at(reader.offset))
+    }
+
+    /// Parses a module that's already in memory. Names, custom sections and data segments borrow
+    /// from `bytes` rather than being copied out of them. Only the copies are saved: every other
+    /// section, the code section included, is still decoded a byte at a time through `Read`, so
+    /// this isn't much faster than `parse_module` on modules that are mostly code.
+    pub fn parse_module_from_slice<'a>(&self, bytes: &'a [u8]) -> Result<ParseModule<'a>, ParseError> {
+        let mut rest = bytes;
+        let result = self.parse_slice(&mut rest);
+        result.map_err(|e| e.at((bytes.len() - rest.len()) as u64))
+    }
+
+    /// Parses a module that arrives in chunks, see `StreamingParser`. Bodies are left encoded if
+    /// the parser is lazy, but are never decoded on other threads, since each one is decoded as
+    /// soon as it arrives.
+    pub fn stream(&self) -> StreamingParser {
+        StreamingParser::new(self)
+    }
+
+    fn parse_contents<T: Read>(&self, reader: &mut Position<T>) -> Result<ParseModule<'static>, ParseError> {
         info!("Beginning parsing");
+        let version = ModuleParser::parse_header(reader)?;
+        let mut module = ParseModule { version, ..ParseModule::new() };
+        self.parse_sections(&mut module, reader)?;
+        info!("parsed module");
+        trace!("module structure\n{}", text::print_module(&module));
+        Ok(module)
+    }
+
+    fn parse_header<T: Read>(reader: &mut T) -> Result<u32, ParseError> {
         let magic_number = reader.read_u32::<LittleEndian>()?;
         if magic_number != MAGIC_NUMBER {
-            return Err(ParseError::WrongMagicNumber)
+            return Err(ParseErrorKind::WrongMagicNumber.into())
         }
         let version = reader.read_u32::<LittleEndian>()?;
         if version != 1 {
-            return Err(ParseError::UnsupportedModuleVersion)
-        } else {
-            let mut module = ParseModule {
-                version,
-                types: vec![],
-                imports: HashMap::new(),
-                function_signatures: vec![],
-                function_bodies: vec![],
-                tables: vec![],
-                memories: vec![],
-                globals: vec![],
-                exports: HashMap::new(),
-                start_function: None,
-            };
-            self.parse_sections(&mut module, &mut reader)?;
-            info!("parsed module");
-            println!("module structure {:#?}", module);
-            return Ok(module)
+            return Err(ParseErrorKind::UnsupportedModuleVersion.into())
         }
+        Ok(version)
     }
 
-    fn parse_sections<T: Read>(&self, module: &mut ParseModule, reader: &mut T) -> Result<(), ParseError> {
+    fn parse_sections<T: Read>(&self, module: &mut ParseModule, reader: &mut Position<T>) -> Result<(), ParseError> {
 
+        let mut previous = 0;
         loop {
             let id = match reader.bytes().read_varuint(7) {
                 Ok(id) => id,
@@ -118,35 +169,502 @@ impl ModuleParser {
                     if e.kind() == ErrorKind::UnexpectedEof {
                         break;
                     }
-                    panic!("{:?}", e);
+                    return Err(ParseErrorKind::Io(e).into());
                 }
             };
             info!("parsing section {}", id);
+            ModuleParser::check_order(id, &mut previous)?;
             match self.parse_section(id, reader, module) {
                 Err(error) => {
                     error!("Failure parsing section {}", id);
-                    return Err(error)
+                    return Err(error.in_section(id))
                 },
                 _ => {}
             };
             info!("Section parsed {}", id);
         }
+        ModuleParser::check_complete(module)?;
         debug!("Module parsing complete");
         Ok(())
 
     }
 
-    fn parse_section<T: Read>(&self, id: u64, reader: &mut T, module: &mut ParseModule) -> Result<(), ParseError> {
+    fn parse_slice<'a>(&self, rest: &mut &'a [u8]) -> Result<ParseModule<'a>, ParseError> {
+        info!("Beginning parsing");
+        let version = ModuleParser::parse_header(rest)?;
+        let mut module = ParseModule { version, ..ParseModule::new() };
+        let mut previous = 0;
+        while !rest.is_empty() {
+            let id = (&mut *rest).bytes().read_varuint(7)?;
+            info!("parsing section {}", id);
+            ModuleParser::check_order(id, &mut previous)?;
+            self.parse_slice_section(id, rest, &mut module).map_err(|e| e.in_section(id))?;
+        }
+        ModuleParser::check_complete(&module)?;
+        info!("parsed module");
+        Ok(module)
+    }
+
+    // Only the sections that hold names and payloads borrow, the rest go through their usual parser,
+    // reading the slice through `Read` like any other reader
+    fn parse_slice_section<'a>(&self, id: u64, rest: &mut &'a [u8], module: &mut ParseModule<'a>) -> Result<(), ParseError> {
+        if id != 0 && id != 2 && id != 7 && id != 11 {
+            let mut reader = Position::new(*rest);
+            let result = self.parse_section(id, &mut reader, module);
+            *rest = &rest[reader.offset as usize..];
+            return result;
+        }
+        let length = (&mut *rest).bytes().read_varuint(32)? as usize;
+        if length > rest.len() {
+            *rest = &rest[rest.len()..];
+            return Err(cut_short());
+        }
+        let (mut section, after) = rest.split_at(length);
+        let result = match id {
+            0 => self.parse_slice_custom_section(&mut section, module),
+            2 => imports_section::parse_from(&mut section, module),
+            7 => exports_section::parse_from(&mut section, module),
+            _ => data_section::parse_from(&mut section, module)
+        };
+        if result.is_err() || (id != 0 && !section.is_empty()) {
+            // Errors are found part way through the section, rather than after it
+            *rest = &rest[length - section.len()..];
+            result?;
+            return Err(ParseErrorKind::SectionLengthWrong.into());
+        }
+        *rest = after;
+        Ok(())
+    }
+
+    // Known sections appear at most once and in order of id, custom sections can go anywhere
+    fn check_order(id: u64, previous: &mut u64) -> Result<(), ParseError> {
+        if id == 0 {
+            return Ok(());
+        }
+        if id == *previous {
+            return Err(ParseError::from(ParseErrorKind::DuplicateSection(id)).in_section(id));
+        }
+        if id < *previous {
+            return Err(ParseError::from(ParseErrorKind::SectionOutOfOrder(id)).in_section(id));
+        }
+        *previous = id;
+        Ok(())
+    }
+
+    // A module that declares functions but has no code section never gets to check them
+    fn check_complete(module: &ParseModule) -> Result<(), ParseError> {
+        let bodies = module.function_bodies.len() + module.encoded_bodies.len();
+        if module.function_signatures.len() != bodies {
+            return Err(ParseErrorKind::FunctionCountMismatch {
+                signatures: module.function_signatures.len(),
+                bodies
+            }.into());
+        }
+        Ok(())
+    }
+
+    fn parse_section<T: Read>(&self, id: u64, reader: &mut Position<T>, module: &mut ParseModule) -> Result<(), ParseError> {
+        if id == 0 {
+            return self.parse_custom_section(reader, module);
+        }
         let parser_function = match self.sections.get(&id) {
             Some(func) => func,
-            None => return Err(ParseError::UnknownSectionId(id))
+            None => return Err(ParseErrorKind::UnknownSectionId(id).into())
         };
-        let length = reader.bytes().read_varuint(32).unwrap();
+        let length = reader.bytes().read_varuint(32)?;
+        let offset = reader.offset;
+        let mut subreader = reader.take(length);
+        // Spawning threads only pays off once there's a lot to decode
+        if id == 10 && self.lazy {
+            code_section::parse_encoded(&mut subreader, module, offset)?;
+        } else if id == 10 && self.threads > 1 && length >= PARALLEL_CODE_SIZE {
+            code_section::parse_parallel(&mut subreader, module, self.threads, offset)?;
+        } else {
+            parser_function(&mut subreader, module)?;
+        }
+        if subreader.limit() != 0 {
+            return Err(ParseErrorKind::SectionLengthWrong.into());
+        }
+        Ok(())
+    }
+
+    fn parse_custom_section<T: Read>(&self, reader: &mut T, module: &mut ParseModule) -> Result<(), ParseError> {
+        let length = reader.bytes().read_varuint(32)?;
         let mut subreader = reader.take(length);
-        match parser_function(&mut subreader, module) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(e)
+        let name = Copying::new(&mut subreader).read_name()?;
+        info!("parsing custom section {}", name);
+        match self.custom_sections.get(&*name) {
+            Some(handler) => handler(&mut subreader, module)?,
+            None => custom_section::parse(&mut Copying::new(&mut subreader), name, module)?
+        }
+        // A handler needn't read the whole payload, but the next section starts after it
+        io::copy(&mut subreader, &mut io::sink())?;
+        if subreader.limit() != 0 {
+            return Err(cut_short());
         }
+        Ok(())
+    }
 
+    fn parse_slice_custom_section<'a>(&self, section: &mut &'a [u8], module: &mut ParseModule<'a>) -> Result<(), ParseError> {
+        let name = section.read_name()?;
+        info!("parsing custom section {}", name);
+        match self.custom_sections.get(&*name) {
+            Some(handler) => handler(section, module),
+            None => custom_section::parse(section, name, module)
+        }
     }
-}
\ No newline at end of file
+}
+
+/// Decodes a body a lazy `ModuleParser` left encoded. `index` is the function's index, including
+/// imported functions, which errors refer to it by.
+pub fn decode_body(body: &EncodedBody, index: usize) -> Result<FuncBody, ParseError> {
+    // Bodies don't refer to anything else in the module while they're decoded
+    code_section::decode_encoded(body, &ParseModule::new(), index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    pub fn module(sections: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+        bytes.extend_from_slice(sections);
+        bytes
+    }
+
+    #[test]
+    fn custom_sections_are_preserved() {
+        let bytes = module(&[
+            0x00, 0x06, 0x02, b'h', b'i', 0x01, 0x02, 0x03,
+            0x08, 0x01, 0x00,
+            0x00, 0x03, 0x01, b'x', 0xff,
+        ]);
+        let module = ModuleParser::default().parse_module(&bytes[..]).unwrap();
+        assert_eq!(module.start_function, Some(0));
+        assert_eq!(module.custom_sections.len(), 2);
+        assert_eq!(module.custom_sections[0].name, "hi");
+        assert_eq!(module.custom_sections[0].payload, vec![0x01, 0x02, 0x03]);
+        assert_eq!(module.custom_sections[1].name, "x");
+        assert_eq!(module.custom_sections[1].payload, vec![0xff]);
+    }
+
+    #[test]
+    fn custom_section_handlers_are_called() {
+        let seen = Rc::new(Cell::new(0));
+        let observed = seen.clone();
+        let mut parser = ModuleParser::default();
+        parser.custom_section("meta", Box::new(move |reader, _| {
+            // Only reads part of the payload
+            observed.set(reader.read_u8()?);
+            Ok(())
+        }));
+        let bytes = module(&[
+            0x00, 0x07, 0x04, b'm', b'e', b't', b'a', 0x2a, 0x2b,
+            0x08, 0x01, 0x03,
+        ]);
+        let module = parser.parse_module(&bytes[..]).unwrap();
+        assert_eq!(seen.get(), 0x2a);
+        assert!(module.custom_sections.is_empty());
+        assert_eq!(module.start_function, Some(3));
+    }
+
+    #[test]
+    fn name_section_is_decoded() {
+        let bytes = module(&[
+            0x00, 0x1a, 0x04, b'n', b'a', b'm', b'e',
+            0x00, 0x02, 0x01, b'm',
+            0x01, 0x06, 0x01, 0x03, 0x03, b'a', b'd', b'd',
+            0x02, 0x07, 0x01, 0x03, 0x01, 0x01, 0x02, b'l', b'o',
+            0x07, 0x01, 0x00,
+        ]);
+        let module = ModuleParser::default().parse_module(&bytes[..]).unwrap();
+        assert!(module.custom_sections.is_empty());
+        assert_eq!(module.names.module, Some("m".to_string()));
+        assert_eq!(module.names.function(3), Some("add"));
+        assert_eq!(module.names.describe_function(3), "$add");
+        assert_eq!(module.names.describe_function(2), "function 2");
+        assert_eq!(module.names.local(3, 1), Some("lo"));
+        assert_eq!(module.names.describe_local(3, 0), "local 0");
+    }
+
+    #[test]
+    fn malformed_name_sections_are_ignored() {
+        // The module name claims 5 bytes but its subsection only holds 1
+        let bytes = module(&[
+            0x00, 0x09, 0x04, b'n', b'a', b'm', b'e',
+            0x00, 0x02, 0x05, b'm',
+            0x08, 0x01, 0x03,
+        ]);
+        let copied = ModuleParser::default().parse_module(&bytes[..]).unwrap();
+        let borrowed = ModuleParser::default().parse_module_from_slice(&bytes).unwrap();
+        for module in &[copied, borrowed] {
+            assert_eq!(module.names.module, None);
+            assert_eq!(module.start_function, Some(3));
+        }
+        assert!(utils::read_string(&mut &[0x05, b'm'][..]).is_err());
+    }
+
+    // A function `() -> i32` exported as "f", returning a constant from inside a block
+    pub const SAMPLE: &[u8] = &[
+        0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f,
+        0x03, 0x02, 0x01, 0x00,
+        0x07, 0x05, 0x01, 0x01, b'f', 0x00, 0x00,
+        0x0a, 0x0b, 0x01, 0x09, 0x01, 0x01, 0x7f, 0x02, 0x7f, 0x41, 0x2a, 0x0b, 0x0b,
+    ];
+
+    #[test]
+    fn truncated_modules_are_errors() {
+        let bytes = module(SAMPLE);
+        assert!(ModuleParser::default().parse_module(&bytes[..]).is_ok());
+        // Cutting at a section boundary can leave a valid module, anywhere else has to fail cleanly
+        for length in 0..bytes.len() {
+            let _ = ModuleParser::default().parse_module(&bytes[..length]);
+        }
+        assert!(ModuleParser::default().parse_module(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    fn error_kind(sections: &[u8]) -> ParseErrorKind {
+        ModuleParser::default().parse_module(&module(sections)[..]).unwrap_err().kind
+    }
+
+    #[test]
+    fn sections_are_ordered_and_unique() {
+        let out_of_order = error_kind(&[
+            0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f,
+            0x07, 0x05, 0x01, 0x01, b'f', 0x00, 0x00,
+            0x03, 0x02, 0x01, 0x00,
+        ]);
+        assert!(matches!(out_of_order, ParseErrorKind::SectionOutOfOrder(3)));
+        let duplicated = error_kind(&[
+            0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f,
+            0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f,
+        ]);
+        assert!(matches!(duplicated, ParseErrorKind::DuplicateSection(1)));
+        // Custom sections can go anywhere, any number of times
+        let mut sections = vec![0x00, 0x02, 0x01, b'a'];
+        sections.extend_from_slice(&SAMPLE[..18]);
+        sections.extend_from_slice(&[0x00, 0x02, 0x01, b'a']);
+        sections.extend_from_slice(&SAMPLE[18..]);
+        assert!(ModuleParser::default().parse_module(&module(&sections)[..]).is_ok());
+    }
+
+    #[test]
+    fn sections_are_read_to_their_length() {
+        let too_long = error_kind(&[0x01, 0x06, 0x01, 0x60, 0x00, 0x01, 0x7f, 0x00]);
+        assert!(matches!(too_long, ParseErrorKind::SectionLengthWrong));
+        let too_short = error_kind(&[0x01, 0x04, 0x01, 0x60, 0x00, 0x01, 0x7f]);
+        assert!(matches!(too_short, ParseErrorKind::Io(_)));
+    }
+
+    #[test]
+    fn every_function_has_a_body() {
+        let missing = error_kind(&SAMPLE[..18]);
+        assert!(matches!(missing, ParseErrorKind::FunctionCountMismatch { signatures: 1, bodies: 0 }));
+        let mut extra = SAMPLE.to_vec();
+        extra[20] = 0x02;
+        assert!(matches!(error_kind(&extra), ParseErrorKind::FunctionCountMismatch { signatures: 1, bodies: 2 }));
+    }
+
+    #[test]
+    fn corrupted_modules_are_errors() {
+        let bytes = module(SAMPLE);
+        for index in 8..bytes.len() {
+            for value in &[0x00, 0x0b, 0x7f, 0x80, 0xff] {
+                let mut corrupted = bytes.clone();
+                corrupted[index] = *value;
+                let _ = ModuleParser::default().parse_module(&corrupted[..]);
+            }
+        }
+    }
+
+    #[test]
+    fn errors_say_where_they_are() {
+        let bytes = module(&[
+            0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+            0x03, 0x02, 0x01, 0x00,
+            0x0a, 0x05, 0x01, 0x03, 0x00, 0xff, 0x0b
+        ]);
+        let error = ModuleParser::default().parse_module(&bytes[..]).unwrap_err();
+        assert_eq!(error.offset, Some(24));
+        assert_eq!(error.section, Some(10));
+        assert_eq!(error.function, Some(0));
+        assert_eq!(error.opcode, Some(0xff));
+        assert_eq!(error.to_string(), "Unknown opcode (at offset 0x18, in section 10, in function 0, in opcode 0xff)");
+
+        let error = ModuleParser::default().parse_module(&[0x00, 0x61, 0x73, 0x6d, 0x02][..]).unwrap_err();
+        assert_eq!(error.offset, Some(5));
+        assert_eq!(error.section, None);
+    }
+
+    // A module with a function whose body is `depth` empty blocks nested inside each other
+    fn nested_blocks(depth: usize) -> Vec<u8> {
+        let mut body = vec![0x00];
+        for _ in 0..depth {
+            body.extend_from_slice(&[0x02, 0x40]);
+        }
+        body.extend(::std::iter::repeat(0x0b).take(depth + 1));
+        let mut code = vec![0x01];
+        code.extend(leb(body.len()));
+        code.extend(body);
+        let mut bytes = module(&[0x01, 0x04, 0x01, 0x60, 0x00, 0x00, 0x03, 0x02, 0x01, 0x00, 0x0a]);
+        bytes.extend(leb(code.len()));
+        bytes.extend(code);
+        bytes
+    }
+
+    #[test]
+    fn deep_nesting_is_rejected() {
+        assert!(ModuleParser::default().parse_module(&nested_blocks(1000)[..]).is_ok());
+        assert!(ModuleParser::default().parse_module(&nested_blocks(100_000)[..]).is_err());
+    }
+
+    #[test]
+    fn huge_local_counts_are_rejected() {
+        let bytes = module(&[
+            0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+            0x03, 0x02, 0x01, 0x00,
+            0x0a, 0x0a, 0x01, 0x08, 0x01, 0xff, 0xff, 0xff, 0xff, 0x0f, 0x7f, 0x0b,
+        ]);
+        assert!(ModuleParser::default().parse_module(&bytes[..]).is_err());
+    }
+
+    // A module with `count` functions `() -> i32`, with big enough bodies to be decoded in parallel
+    fn many_functions(count: usize) -> Vec<u8> {
+        let mut functions = leb(count);
+        functions.extend(::std::iter::repeat(0x00).take(count));
+        let mut code = leb(count);
+        for index in 0..count {
+            let mut body = vec![0x00];
+            body.extend(::std::iter::repeat(0x01).take(100));
+            body.extend_from_slice(&[0x41, (index % 64) as u8, 0x0b]);
+            code.extend(leb(body.len()));
+            code.extend(body);
+        }
+        let mut bytes = module(&[0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f, 0x03]);
+        bytes.extend(leb(functions.len()));
+        bytes.extend(functions);
+        bytes.push(0x0a);
+        bytes.extend(leb(code.len()));
+        bytes.extend(code);
+        bytes
+    }
+
+    fn parse_on(threads: usize, bytes: &[u8]) -> Result<ParseModule<'static>, ParseError> {
+        ModuleParser::default().threads(threads).parse_module(bytes)
+    }
+
+    #[test]
+    fn bodies_decode_the_same_in_parallel() {
+        let bytes = many_functions(1000);
+        let serial = parse_on(1, &bytes).unwrap();
+        let parallel = parse_on(4, &bytes).unwrap();
+        assert_eq!(parallel.function_bodies.len(), 1000);
+        assert_eq!(parallel, serial);
+    }
+
+    #[test]
+    fn parallel_errors_match_serial_ones() {
+        let bytes = many_functions(1000);
+        let body_start = bytes.len() - 1000 * 105;
+        let mut corrupted = bytes.clone();
+        // The nops of functions 700 and 500 become unknown opcodes
+        corrupted[body_start + 700 * 105 + 50] = 0xff;
+        corrupted[body_start + 500 * 105 + 50] = 0xff;
+        assert_eq!(parse_on(4, &corrupted).unwrap_err().function, Some(500));
+        for broken in &[corrupted, bytes[..bytes.len() - 1].to_vec()] {
+            let serial = parse_on(1, broken).unwrap_err();
+            let parallel = parse_on(4, broken).unwrap_err();
+            assert_eq!(parallel.to_string(), serial.to_string());
+        }
+    }
+
+    #[test]
+    fn lazy_bodies_decode_like_eager_ones() {
+        let bytes = module(SAMPLE);
+        let eager = ModuleParser::default().parse_module(&bytes[..]).unwrap();
+        let lazy = ModuleParser::default().lazy(true).parse_module(&bytes[..]).unwrap();
+        assert!(lazy.function_bodies.is_empty());
+        assert_eq!(lazy.encoded_bodies.len(), 1);
+        let decoded = decode_body(&lazy.encoded_bodies[0], 0).unwrap();
+        assert_eq!(decoded, eager.function_bodies[0]);
+
+        // Decoding fails where the eager parser would have
+        let mut corrupted = bytes.clone();
+        corrupted[bytes.len() - 4] = 0xff;
+        let eager = ModuleParser::default().parse_module(&corrupted[..]).unwrap_err();
+        let lazy = ModuleParser::default().lazy(true).parse_module(&corrupted[..]).unwrap();
+        assert_eq!(decode_body(&lazy.encoded_bodies[0], 0).unwrap_err().to_string(), eager.to_string());
+    }
+
+    // Imports env.f, exports g and has a data segment "hi" and a custom section "c"
+    const NAMED: &[u8] = &[
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+        0x02, 0x09, 0x01, 0x03, b'e', b'n', b'v', 0x01, b'f', 0x00, 0x00,
+        0x03, 0x02, 0x01, 0x00,
+        0x05, 0x03, 0x01, 0x00, 0x01,
+        0x07, 0x05, 0x01, 0x01, b'g', 0x00, 0x01,
+        0x0a, 0x04, 0x01, 0x02, 0x00, 0x0b,
+        0x0b, 0x08, 0x01, 0x00, 0x41, 0x00, 0x0b, 0x02, b'h', b'i',
+        0x00, 0x03, 0x01, b'c', 0xff,
+    ];
+
+    #[test]
+    fn slices_are_borrowed_from() {
+        let bytes = module(NAMED);
+        let borrowed = ModuleParser::default().parse_module_from_slice(&bytes).unwrap();
+        let copied = ModuleParser::default().parse_module(&bytes[..]).unwrap();
+        assert!(matches!(borrowed.imports[0].module, Cow::Borrowed("env")));
+        assert!(matches!(borrowed.imports[0].field, Cow::Borrowed("f")));
+        assert!(matches!(borrowed.exports[0].field, Cow::Borrowed("g")));
+        assert!(matches!(borrowed.data[0].data, Cow::Borrowed(b"hi")));
+        assert!(matches!(borrowed.custom_sections[0].name, Cow::Borrowed("c")));
+        assert!(matches!(borrowed.custom_sections[0].payload, Cow::Borrowed(&[0xff])));
+        assert!(matches!(copied.data[0].data, Cow::Owned(_)));
+        assert_eq!(borrowed, copied);
+    }
+
+    #[test]
+    fn slice_errors_match_read_errors() {
+        let bytes = module(NAMED);
+        for length in 0..bytes.len() {
+            let copied = ModuleParser::default().parse_module(&bytes[..length]);
+            let borrowed = ModuleParser::default().parse_module_from_slice(&bytes[..length]);
+            assert_eq!(copied.is_ok(), borrowed.is_ok());
+        }
+        // The module ends with a 5 byte custom section, cutting into it is an error either way
+        for length in bytes.len() - 4..bytes.len() {
+            assert!(ModuleParser::default().parse_module(&bytes[..length]).is_err());
+            assert!(ModuleParser::default().parse_module_from_slice(&bytes[..length]).is_err());
+        }
+        for index in 8..bytes.len() {
+            let mut corrupted = bytes.clone();
+            corrupted[index] = 0xff;
+            let copied = ModuleParser::default().parse_module(&corrupted[..]);
+            let borrowed = ModuleParser::default().parse_module_from_slice(&corrupted);
+            match (copied, borrowed) {
+                (Ok(copied), Ok(borrowed)) => assert_eq!(borrowed, copied),
+                (Err(copied), Err(borrowed)) => {
+                    assert_eq!(borrowed.section, copied.section);
+                    assert_eq!(borrowed.function, copied.function);
+                },
+                (copied, borrowed) => panic!("corrupting byte {} gave {:?} and {:?}", index, copied, borrowed)
+            }
+        }
+    }
+
+    fn leb(mut value: usize) -> Vec<u8> {
+        let mut bytes = vec![];
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(byte);
+                return bytes;
+            }
+            bytes.push(byte | 0x80);
+        }
+    }
+}
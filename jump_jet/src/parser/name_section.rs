@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::Read;
+
+use parser::leb::ReadLEB;
+use parser::ParseError;
+use parser::ParseErrorKind;
+use parser::utils::read_string;
+
+use parse_tree::names::Names;
+use parse_tree::ParseModule;
+
+const MODULE_NAME: u64 = 0;
+const FUNCTION_NAMES: u64 = 1;
+const LOCAL_NAMES: u64 = 2;
+
+/// Like `parse`, but a malformed section is only logged. Names are for debugging, so per the spec a bad
+/// name section doesn't make the module invalid, and it's the one custom section toolchains often emit.
+pub fn parse_or_ignore(reader: &mut Read, module: &mut ParseModule) -> Result<(), ParseError> {
+    if let Err(error) = parse(reader, module) {
+        warn!("Ignoring the malformed name section: {}", error);
+        module.names = Names::default();
+    }
+    Ok(())
+}
+
+pub fn parse(reader: &mut Read, module: &mut ParseModule) -> Result<(), ParseError> {
+    debug!("Parsing name section");
+    loop {
+        let id = match reader.bytes().read_varuint(7) {
+            Ok(id) => id,
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(ParseErrorKind::Io(e).into())
+        };
+        let length = reader.bytes().read_varuint(32)?;
+        let mut subsection = reader.take(length);
+        match id {
+            MODULE_NAME => module.names.module = Some(read_string(&mut subsection)?),
+            FUNCTION_NAMES => module.names.functions = read_name_map(&mut subsection)?,
+            LOCAL_NAMES => module.names.locals = read_indirect_name_map(&mut subsection)?,
+            // Other subsections are skipped
+            _ => {}
+        }
+        io::copy(&mut subsection, &mut io::sink())?;
+    }
+    Ok(())
+}
+
+fn read_name_map(reader: &mut Read) -> Result<HashMap<usize, String>, ParseError> {
+    let count = reader.bytes().read_varuint(32)?;
+    let mut names = HashMap::new();
+    for _ in 0..count {
+        let index = reader.bytes().read_varuint(32)? as usize;
+        names.insert(index, read_string(reader)?);
+    }
+    Ok(names)
+}
+
+fn read_indirect_name_map(reader: &mut Read) -> Result<HashMap<usize, HashMap<usize, String>>, ParseError> {
+    let count = reader.bytes().read_varuint(32)?;
+    let mut names = HashMap::new();
+    for _ in 0..count {
+        let index = reader.bytes().read_varuint(32)? as usize;
+        names.insert(index, read_name_map(reader)?);
+    }
+    Ok(names)
+}
@@ -0,0 +1,132 @@
+use std::error::Error;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::io;
+
+/// The ways in which decoding a module's binary can fail.
+#[derive(Debug)]
+pub enum ParseErrorKind {
+    WrongMagicNumber,
+    UnknownSectionId(u64),
+    UnsupportedModuleVersion,
+    SectionLengthWrong,
+    SectionOutOfOrder(u64),
+    DuplicateSection(u64),
+    FunctionCountMismatch { signatures: usize, bodies: usize },
+    InvalidTypeForm,
+    InvalidValueType(i64),
+    InvalidLanguageType(i64),
+    InvalidExternalKind(u8),
+    Io(io::Error),
+    NonExistantTypeReference,
+    CustomError(String),
+}
+
+impl Display for ParseErrorKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            ParseErrorKind::WrongMagicNumber => write!(f, "not a wasm module, the magic number is wrong"),
+            ParseErrorKind::UnknownSectionId(id) => write!(f, "unknown section id {}", id),
+            ParseErrorKind::UnsupportedModuleVersion => write!(f, "unsupported module version"),
+            ParseErrorKind::SectionLengthWrong => write!(f, "section length doesn't match its contents"),
+            ParseErrorKind::SectionOutOfOrder(id) => write!(f, "section {} is out of order", id),
+            ParseErrorKind::DuplicateSection(id) => write!(f, "section {} appears more than once", id),
+            ParseErrorKind::FunctionCountMismatch { signatures, bodies } =>
+                write!(f, "{} functions are declared but {} bodies are given", signatures, bodies),
+            ParseErrorKind::InvalidTypeForm => write!(f, "invalid type form"),
+            ParseErrorKind::InvalidValueType(key) => write!(f, "invalid value type {}", key),
+            ParseErrorKind::InvalidLanguageType(key) => write!(f, "invalid language type {}", key),
+            ParseErrorKind::InvalidExternalKind(kind) => write!(f, "invalid external kind {}", kind),
+            ParseErrorKind::Io(ref err) => write!(f, "{}", err),
+            ParseErrorKind::NonExistantTypeReference => write!(f, "reference to a type that doesn't exist"),
+            ParseErrorKind::CustomError(ref message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// A `ParseErrorKind` along with where in the binary it was found.
+///
+/// `offset` is the position just past the last byte read when the error was found. `function` is
+/// in the function index space, so it counts imported functions.
+#[derive(Debug)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub offset: Option<u64>,
+    pub section: Option<u64>,
+    pub function: Option<usize>,
+    pub opcode: Option<u8>,
+}
+
+impl ParseError {
+    /// Records the offset the error was found at, unless a more precise one is already known.
+    pub fn at(mut self, offset: u64) -> ParseError {
+        self.offset = self.offset.or(Some(offset));
+        self
+    }
+
+    pub fn in_section(mut self, id: u64) -> ParseError {
+        self.section = self.section.or(Some(id));
+        self
+    }
+
+    pub fn in_function(mut self, index: usize) -> ParseError {
+        self.function = self.function.or(Some(index));
+        self
+    }
+
+    pub fn in_opcode(mut self, opcode: u8) -> ParseError {
+        self.opcode = self.opcode.or(Some(opcode));
+        self
+    }
+}
+
+impl From<ParseErrorKind> for ParseError {
+    fn from(kind: ParseErrorKind) -> ParseError {
+        ParseError {
+            kind,
+            offset: None,
+            section: None,
+            function: None,
+            opcode: None,
+        }
+    }
+}
+
+impl From<io::Error> for ParseError {
+    fn from(err: io::Error) -> ParseError {
+        ParseErrorKind::Io(err).into()
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut location = vec![];
+        if let Some(offset) = self.offset {
+            location.push(format!("at offset {:#x}", offset));
+        }
+        if let Some(id) = self.section {
+            location.push(format!("in section {}", id));
+        }
+        if let Some(index) = self.function {
+            location.push(format!("in function {}", index));
+        }
+        if let Some(opcode) = self.opcode {
+            location.push(format!("in opcode {:#04x}", opcode));
+        }
+        if location.is_empty() {
+            write!(f, "{}", self.kind)
+        } else {
+            write!(f, "{} ({})", self.kind, location.join(", "))
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(Error + 'static)> {
+        match self.kind {
+            ParseErrorKind::Io(ref err) => Some(err),
+            _ => None
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use std::io;
+use std::io::ErrorKind;
+use std::io::Read;
+
+use parser::leb::ReadLEB;
+use parser::code_section;
+use parser::utils::Position;
+use parser::ModuleParser;
+use parser::ParseError;
+use parser::ParseErrorKind;
+
+use parse_tree::functions::EncodedBody;
+use parse_tree::ParseModule;
+
+/// What a `StreamingParser` has finished reading.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseEvent {
+    /// The module header, with the binary format version.
+    Version(u32),
+    /// The section with this id has been parsed into the module. Custom sections have id 0.
+    Section(u64),
+    /// The code section has started, and will have this many bodies.
+    CodeSection(usize),
+    /// The body of the function with this index has been parsed, it's the last one in
+    /// `module().function_bodies`, or in `module().encoded_bodies` if the parser is lazy. Imported
+    /// functions are counted, as they are in the index space.
+    Function(usize),
+}
+
+enum State {
+    Header,
+    Sections,
+    Code { end: u64, count: Option<u64>, index: u64, imported_functions: usize },
+}
+
+/// Parses a module that's pushed to it in chunks, as they arrive.
+///
+/// Each call to `push` parses as much as the bytes so far allow and says what it got through, so
+/// everything before the code section, and each function body in turn, can be used before the
+/// rest of the module arrives. Once `push` has returned an error the stream can't be continued.
+///
+/// ```ignore
+/// let parser = ModuleParser::default();
+/// let mut stream = parser.stream();
+/// for chunk in chunks {
+///     for event in stream.push(&chunk)? {
+///         if let ParseEvent::Function(index) = event {
+///             // stream.module().function_bodies.last() is function `index`
+///         }
+///     }
+/// }
+/// let module = stream.finish()?;
+/// ```
+pub struct StreamingParser<'a> {
+    parser: &'a ModuleParser,
+    state: State,
+    module: ParseModule<'static>,
+    buffer: Vec<u8>,
+    // How far into `buffer` has been parsed, and where `buffer` starts in the module
+    start: usize,
+    offset: u64,
+    previous_section: u64,
+}
+
+impl<'a> StreamingParser<'a> {
+    pub fn new(parser: &'a ModuleParser) -> StreamingParser<'a> {
+        StreamingParser {
+            parser,
+            state: State::Header,
+            module: ParseModule { version: 0, ..ParseModule::new() },
+            buffer: vec![],
+            start: 0,
+            offset: 0,
+            previous_section: 0,
+        }
+    }
+
+    /// What has been parsed so far.
+    pub fn module(&self) -> &ParseModule<'static> {
+        &self.module
+    }
+
+    /// Parses as much of the module as `chunk` and the bytes before it make up.
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<ParseEvent>, ParseError> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = vec![];
+        let result = self.advance(&mut events);
+        self.buffer.drain(..self.start);
+        self.offset += self.start as u64;
+        self.start = 0;
+        result.map(|_| events)
+    }
+
+    /// Ends the stream, failing if the module stopped part way through.
+    pub fn finish(self) -> Result<ParseModule<'static>, ParseError> {
+        let position = self.offset + self.start as u64;
+        match self.state {
+            State::Sections if self.start == self.buffer.len() => {
+                ModuleParser::check_complete(&self.module).map_err(|e| e.at(position))?;
+                Ok(self.module)
+            },
+            _ => {
+                let error: ParseError = io::Error::new(ErrorKind::UnexpectedEof, "module ended part way through").into();
+                let error = error.at(self.offset + self.buffer.len() as u64);
+                // Say which section or function was cut short
+                Err(match self.state {
+                    State::Header => error,
+                    State::Sections => match read_varuint(&mut &self.buffer[self.start..], 7) {
+                        Ok(id) => error.in_section(id),
+                        Err(_) => error
+                    },
+                    State::Code { count: Some(total), index, imported_functions, .. } if index < total =>
+                        error.in_section(10).in_function(imported_functions + index as usize),
+                    State::Code { .. } => error.in_section(10)
+                })
+            }
+        }
+    }
+
+    fn advance(&mut self, events: &mut Vec<ParseEvent>) -> Result<(), ParseError> {
+        loop {
+            let position = self.offset + self.start as u64;
+            let available = &self.buffer[self.start..];
+            let mut reader = Position::new(available);
+            let consumed = match self.state {
+                State::Header => {
+                    let version = match incomplete(ModuleParser::parse_header(&mut reader)) {
+                        Some(result) => result.map_err(|e| e.at(position + reader.offset))?,
+                        None => return Ok(())
+                    };
+                    self.module.version = version;
+                    self.state = State::Sections;
+                    events.push(ParseEvent::Version(version));
+                    reader.offset
+                },
+                State::Sections => {
+                    let id = match incomplete(read_varuint(&mut reader, 7)) {
+                        Some(result) => result.map_err(|e| e.at(position + reader.offset))?,
+                        None => return Ok(())
+                    };
+                    // The section parsers read the length themselves
+                    let id_length = reader.offset;
+                    let length = match incomplete(read_varuint(&mut reader, 32)) {
+                        Some(result) => result.map_err(|e| e.in_section(id).at(position + reader.offset))?,
+                        None => return Ok(())
+                    };
+                    if id != 0 && !self.parser.sections.contains_key(&id) {
+                        return Err(ParseError::from(ParseErrorKind::UnknownSectionId(id)).in_section(id).at(position + id_length));
+                    }
+                    if id != 10 && ((available.len() as u64) - reader.offset) < length {
+                        return Ok(());
+                    }
+                    ModuleParser::check_order(id, &mut self.previous_section).map_err(|e| e.at(position + reader.offset))?;
+                    if id == 10 {
+                        self.state = State::Code {
+                            end: position + reader.offset + length,
+                            count: None,
+                            index: 0,
+                            imported_functions: self.module.function_space().len() - self.module.function_signatures.len()
+                        };
+                        reader.offset
+                    } else {
+                        let mut section = Position::new(&available[id_length as usize..]);
+                        self.parser.parse_section(id, &mut section, &mut self.module)
+                            .map_err(|e| e.in_section(id).at(position + id_length + section.offset))?;
+                        events.push(ParseEvent::Section(id));
+                        id_length + section.offset
+                    }
+                },
+                State::Code { end, ref mut count, ref mut index, imported_functions } => {
+                    let in_code = |e: ParseError| e.in_section(10);
+                    match *count {
+                        None => {
+                            let read = match incomplete(code_section::parse_count(&mut reader, &self.module)) {
+                                Some(result) => result.map_err(|e| in_code(e).at(position + reader.offset))?,
+                                None => return Ok(())
+                            };
+                            *count = Some(read);
+                            events.push(ParseEvent::CodeSection(read as usize));
+                            reader.offset
+                        },
+                        Some(total) if *index == total => {
+                            if position != end {
+                                return Err(in_code(ParseErrorKind::SectionLengthWrong.into()).at(position));
+                            }
+                            self.state = State::Sections;
+                            events.push(ParseEvent::Section(10));
+                            0
+                        },
+                        Some(_) => {
+                            let function = imported_functions + *index as usize;
+                            let in_body = |e: ParseError| in_code(e).in_function(function);
+                            let size = match incomplete(read_varuint(&mut reader, 32)) {
+                                Some(result) => result.map_err(|e| in_body(e).at(position + reader.offset))?,
+                                None => return Ok(())
+                            };
+                            if position + reader.offset + size > end {
+                                return Err(in_body(ParseErrorKind::SectionLengthWrong.into()).at(position + reader.offset));
+                            }
+                            if ((available.len() as u64) - reader.offset) < size {
+                                return Ok(());
+                            }
+                            let consumed = if self.parser.lazy {
+                                let start = reader.offset as usize;
+                                self.module.encoded_bodies.push(EncodedBody {
+                                    offset: position + reader.offset,
+                                    bytes: available[start..start + size as usize].to_vec()
+                                });
+                                reader.offset + size
+                            } else {
+                                let mut body = Position::new(available);
+                                code_section::parse_body(&mut body, &mut self.module, *index)
+                                    .map_err(|e| in_body(e).at(position + body.offset))?;
+                                body.offset
+                            };
+                            *index += 1;
+                            events.push(ParseEvent::Function(function));
+                            consumed
+                        }
+                    }
+                }
+            };
+            self.start += consumed as usize;
+        }
+    }
+}
+
+fn read_varuint<T: Read>(reader: &mut T, bits: u32) -> Result<u64, ParseError> {
+    Ok(reader.bytes().read_varuint(bits)?)
+}
+
+// Running out of bytes only means more need to be pushed
+fn incomplete<T>(result: Result<T, ParseError>) -> Option<Result<T, ParseError>> {
+    match result {
+        Err(ParseError { kind: ParseErrorKind::Io(ref e), .. }) if e.kind() == ErrorKind::UnexpectedEof => None,
+        result => Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::tests::module;
+    use parser::tests::SAMPLE;
+
+    fn stream(bytes: &[u8], chunk_size: usize) -> Result<(Vec<ParseEvent>, ParseModule<'static>), ParseError> {
+        let parser = ModuleParser::default();
+        let mut stream = parser.stream();
+        let mut events = vec![];
+        for chunk in bytes.chunks(chunk_size) {
+            events.extend(stream.push(chunk)?);
+        }
+        Ok((events, stream.finish()?))
+    }
+
+    #[test]
+    fn chunks_parse_like_a_whole_module() {
+        let bytes = module(SAMPLE);
+        let whole = ModuleParser::default().parse_module(&bytes[..]).unwrap();
+        for chunk_size in 1..bytes.len() + 1 {
+            let (events, streamed) = stream(&bytes, chunk_size).unwrap();
+            assert_eq!(events, vec![
+                ParseEvent::Version(1),
+                ParseEvent::Section(1),
+                ParseEvent::Section(3),
+                ParseEvent::Section(7),
+                ParseEvent::CodeSection(1),
+                ParseEvent::Function(0),
+                ParseEvent::Section(10),
+            ]);
+            assert_eq!(streamed, whole);
+        }
+    }
+
+    #[test]
+    fn lazy_streams_leave_bodies_encoded() {
+        let bytes = module(SAMPLE);
+        let mut parser = ModuleParser::default();
+        parser.lazy(true);
+        let whole = parser.parse_module(&bytes[..]).unwrap();
+        for chunk_size in 1..bytes.len() + 1 {
+            let mut stream = parser.stream();
+            for chunk in bytes.chunks(chunk_size) {
+                stream.push(chunk).unwrap();
+            }
+            let streamed = stream.finish().unwrap();
+            assert_eq!(streamed.encoded_bodies.len(), 1);
+            assert_eq!(streamed, whole);
+        }
+    }
+
+    #[test]
+    fn bodies_are_available_before_the_module_ends() {
+        let bytes = module(SAMPLE);
+        let parser = ModuleParser::default();
+        let mut stream = parser.stream();
+        // Everything but the body's final `end`
+        let events = stream.push(&bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(events.last(), Some(&ParseEvent::CodeSection(1)));
+        assert_eq!(stream.push(&bytes[bytes.len() - 1..]).unwrap(), vec![ParseEvent::Function(0), ParseEvent::Section(10)]);
+        assert_eq!(stream.module().function_bodies.len(), 1);
+    }
+
+    #[test]
+    fn truncated_streams_are_errors() {
+        let bytes = module(SAMPLE);
+        for length in 0..bytes.len() {
+            let whole = ModuleParser::default().parse_module(&bytes[..length]);
+            let streamed = stream(&bytes[..length], 3);
+            assert_eq!(whole.is_ok(), streamed.is_ok());
+        }
+    }
+
+    #[test]
+    fn errors_match_the_blocking_parser() {
+        let bytes = module(SAMPLE);
+        for index in 8..bytes.len() {
+            let mut corrupted = bytes.clone();
+            corrupted[index] = 0xff;
+            let whole = ModuleParser::default().parse_module(&corrupted[..]);
+            let streamed = stream(&corrupted, 4);
+            match (whole, streamed) {
+                (Ok(_), Ok(_)) => {},
+                (Err(whole), Err(streamed)) => {
+                    assert_eq!(whole.section, streamed.section);
+                    assert_eq!(whole.function, streamed.function);
+                    assert_eq!(whole.opcode, streamed.opcode);
+                },
+                (whole, streamed) => panic!("corrupting byte {} gave {:?} and {:?}", index, whole, streamed)
+            }
+        }
+    }
+}
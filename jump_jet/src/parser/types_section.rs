@@ -2,6 +2,7 @@ use std::io::Read;
 
 use parser::leb::ReadLEB;
 use parser::ParseError;
+use parser::ParseErrorKind;
 
 use parse_tree::language_types::LanguageType;
 use parse_tree::language_types::ValueType;
@@ -14,21 +15,19 @@ use parse_tree::functions::FuncSignature;
 pub fn parse(reader: &mut Read, module: &mut ParseModule) -> Result<(), ParseError> {
     debug!("Parsing types section");
     let bytes = &mut reader.bytes();
-    let count = bytes.read_varuint(32).unwrap();
+    let count = bytes.read_varuint(32)?;
     for _ in 0..count {
         let form = LanguageType::parse(bytes)?;
         match form {
             LanguageType::Func => {
-                let parameter_count = bytes.read_varuint(32).unwrap();
+                let parameter_count = bytes.read_varuint(32)?;
                 let mut parameters: Vec<ValueType> = vec![];
                 for _ in 0..parameter_count {
                     parameters.push(ValueType::parse(bytes)?);
                 }
-                let return_count =  bytes.read_varuint(1).unwrap();
+                let return_count = bytes.read_varuint(32)?;
                 let mut returns: Vec<ValueType> = vec![];
-                if return_count > 1 {
-                    return Err(ParseError::TooManyReturns);
-                } else if return_count == 1 {
+                for _ in 0..return_count {
                     returns.push(ValueType::parse(bytes)?);
                 }
                 module.types.push(TypeDefinition::Func(FuncSignature {
@@ -36,7 +35,7 @@ pub fn parse(reader: &mut Read, module: &mut ParseModule) -> Result<(), ParseErr
                     returns,
                 }));
             },
-            _ => return Err(ParseError::CustomError("WASM 1.0 requires all defined types to be of type `func`".to_string()))
+            _ => return Err(ParseErrorKind::CustomError("WASM 1.0 requires all defined types to be of type `func`".to_string()).into())
         }
     }
     Ok(())
@@ -1,13 +1,117 @@
+use std::borrow::Cow;
+use std::io;
+use std::io::ErrorKind;
 use std::io::Read;
+use std::str;
 
 use parser::leb::ReadLEB;
 use parser::ParseError;
+use parser::ParseErrorKind;
 
 pub fn read_string(reader: &mut Read) -> Result<String,ParseError> {
-    let field_len = reader.bytes().read_varuint(32).unwrap();
+    let field_len = reader.bytes().read_varuint(32)?;
     let mut field = "".to_string();
     match reader.take(field_len).read_to_string(&mut field) {
+    	Ok(read) if (read as u64) < field_len => Err(cut_short()),
     	Ok(_) => Ok(field),
-    	Err(e) => Err(ParseError::Io(e))
+    	Err(e) => Err(ParseErrorKind::Io(e).into())
     }
-}
\ No newline at end of file
+}
+
+/// Where the sections that hold names and payloads get their bytes from. Modules that are already
+/// in memory lend them out, anything else has them copied.
+pub trait Source<'a>: Read {
+    /// Reads the next `length` bytes.
+    fn read_bytes(&mut self, length: u64) -> Result<Cow<'a, [u8]>, ParseError>;
+
+    /// Reads everything that's left.
+    fn read_rest(&mut self) -> Result<Cow<'a, [u8]>, ParseError>;
+
+    /// Reads a length prefixed UTF-8 name.
+    fn read_name(&mut self) -> Result<Cow<'a, str>, ParseError> {
+        let length = (&mut *self).bytes().read_varuint(32)?;
+        let name = match self.read_bytes(length)? {
+            Cow::Borrowed(bytes) => str::from_utf8(bytes).ok().map(Cow::Borrowed),
+            Cow::Owned(bytes) => String::from_utf8(bytes).ok().map(Cow::Owned)
+        };
+        match name {
+            Some(name) => Ok(name),
+            None => Err(io::Error::new(ErrorKind::InvalidData, "name isn't valid UTF-8").into())
+        }
+    }
+}
+
+impl<'a> Source<'a> for &'a [u8] {
+    fn read_bytes(&mut self, length: u64) -> Result<Cow<'a, [u8]>, ParseError> {
+        if length > self.len() as u64 {
+            return Err(cut_short());
+        }
+        let (bytes, rest) = self.split_at(length as usize);
+        *self = rest;
+        Ok(Cow::Borrowed(bytes))
+    }
+
+    fn read_rest(&mut self) -> Result<Cow<'a, [u8]>, ParseError> {
+        let length = self.len() as u64;
+        self.read_bytes(length)
+    }
+}
+
+/// A `Source` that copies what it reads out of any reader.
+pub struct Copying<'r> {
+    reader: &'r mut Read,
+}
+
+impl<'r> Copying<'r> {
+    pub fn new(reader: &'r mut Read) -> Copying<'r> {
+        Copying { reader }
+    }
+}
+
+impl<'r> Read for Copying<'r> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl<'a, 'r> Source<'a> for Copying<'r> {
+    fn read_bytes(&mut self, length: u64) -> Result<Cow<'a, [u8]>, ParseError> {
+        // Growing as bytes arrive, rather than trusting `length` up front
+        let mut bytes = vec![];
+        (&mut *self.reader).take(length).read_to_end(&mut bytes)?;
+        if (bytes.len() as u64) < length {
+            return Err(cut_short());
+        }
+        Ok(Cow::Owned(bytes))
+    }
+
+    fn read_rest(&mut self) -> Result<Cow<'a, [u8]>, ParseError> {
+        let mut bytes = vec![];
+        self.reader.read_to_end(&mut bytes)?;
+        Ok(Cow::Owned(bytes))
+    }
+}
+
+pub fn cut_short() -> ParseError {
+    io::Error::new(ErrorKind::UnexpectedEof, "failed to fill whole buffer").into()
+}
+
+/// Counts the bytes read through it, so errors can say where they were found.
+pub struct Position<R> {
+    inner: R,
+    pub offset: u64,
+}
+
+impl<R: Read> Position<R> {
+    pub fn new(inner: R) -> Position<R> {
+        Position { inner, offset: 0 }
+    }
+}
+
+impl<R: Read> Read for Position<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.offset += read as u64;
+        Ok(read)
+    }
+}
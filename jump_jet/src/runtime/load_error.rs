@@ -0,0 +1,56 @@
+use std::error::Error;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+
+use parser::ParseError;
+
+use runtime_tree::LinkError;
+
+use text::TextError;
+
+/// The ways in which `instantiate` and `instantiate_wat` can fail to turn a module into a template.
+#[derive(Debug)]
+pub enum LoadError {
+    Parse(ParseError),
+    Text(TextError),
+    Link(LinkError),
+}
+
+impl From<ParseError> for LoadError {
+    fn from(err: ParseError) -> LoadError {
+        LoadError::Parse(err)
+    }
+}
+
+impl From<TextError> for LoadError {
+    fn from(err: TextError) -> LoadError {
+        LoadError::Text(err)
+    }
+}
+
+impl From<LinkError> for LoadError {
+    fn from(err: LinkError) -> LoadError {
+        LoadError::Link(err)
+    }
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            LoadError::Parse(ref err) => write!(f, "couldn't parse module: {}", err),
+            LoadError::Text(ref err) => write!(f, "couldn't parse module text: {}", err),
+            LoadError::Link(ref err) => write!(f, "couldn't link module: {}", err),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(Error + 'static)> {
+        match *self {
+            LoadError::Parse(ref err) => Some(err),
+            LoadError::Text(ref err) => Some(err),
+            LoadError::Link(ref err) => Some(err),
+        }
+    }
+}
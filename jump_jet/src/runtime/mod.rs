@@ -3,22 +3,33 @@ use std::io::Read;
 use std::collections::HashMap;
 
 use parser::ModuleParser;
-use parser::ParseError;
 
 use runtime_tree::ExternalKindInstance;
 use runtime_tree::Import;
 use runtime_tree::ModuleTemplate;
 use runtime_tree::ModuleTemplateBuilder;
 
+use text;
+
 #[macro_use]
 pub mod exports;
 pub mod language_types;
 pub mod functions;
+mod load_error;
+pub use runtime::load_error::LoadError;
 
-pub fn instantiate(reader: &mut Read, imports: HashMap<String, HashMap<String, Import>>) -> Result<ModuleTemplate, ParseError> {
+pub fn instantiate(reader: &mut Read, imports: HashMap<String, HashMap<String, Import>>) -> Result<ModuleTemplate, LoadError> {
     info!("Attempting to parse WebAssembly module");
     let parser = ModuleParser::default();
-    parser.parse_module(reader).unwrap().build(imports)
+    let module = parser.parse_module(reader)?;
+    Ok(module.build(imports)?)
+}
+
+/// Like `instantiate`, for a module written in the text format.
+pub fn instantiate_wat(text: &str, imports: HashMap<String, HashMap<String, Import>>) -> Result<ModuleTemplate, LoadError> {
+    info!("Attempting to parse WebAssembly text");
+    let module = text::parse_module(text)?;
+    Ok(module.build(imports)?)
 }
 
 /*
@@ -103,4 +114,30 @@ fn main() {
     module_template.instantiate().add(10, 5, 3.14);
 }
 
-*/
\ No newline at end of file
+*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use runtime_tree::LinkError;
+    use std::error::Error;
+
+    #[test]
+    fn load_errors_say_which_step_failed() {
+        match instantiate(&mut &[0x00, 0x61, 0x73][..], HashMap::new()) {
+            Err(error @ LoadError::Parse(_)) => assert!(error.source().is_some()),
+            _ => panic!("expected a parse error")
+        }
+        match instantiate_wat("(module", HashMap::new()) {
+            Err(LoadError::Text(_)) => {},
+            _ => panic!("expected a text error")
+        }
+        match instantiate_wat(r#"(module (import "env" "f" (func)))"#, HashMap::new()) {
+            Err(LoadError::Link(LinkError::Unresolved { .. })) => {},
+            _ => panic!("expected a link error")
+        }
+        match instantiate_wat("(module (func (result i32)))", HashMap::new()) {
+            Err(LoadError::Link(error @ LinkError::Validation(_))) => assert!(error.source().is_some()),
+            _ => panic!("expected a validation error")
+        }
+    }
+}
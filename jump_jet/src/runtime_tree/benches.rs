@@ -0,0 +1,121 @@
+#[cfg(feature = "nightly")]
+extern crate test;
+#[cfg(feature = "nightly")]
+use self::test::Bencher;
+
+use std::cell::RefCell;
+use std::time::Instant;
+
+use parse_tree::functions::FuncBody;
+use parse_tree::functions::FuncSignature;
+use parse_tree::language_types::Block;
+use parse_tree::language_types::BlockType;
+use parse_tree::language_types::Operation;
+use runtime_tree::bytecode;
+use runtime_tree::bytecode::CompiledBody;
+use runtime_tree::language_types::Execute;
+use runtime_tree::language_types::StackFrame;
+use runtime_tree::Fuel;
+use runtime_tree::Limits;
+use runtime_tree::ModuleInstanceData;
+use runtime_tree::ValueTypeProvider;
+
+const ITERATIONS: i32 = 10_000;
+
+/// Counts local 0 down to zero, one iteration per pass through the loop.
+fn countdown() -> Vec<Operation> {
+    vec![
+        Operation::Block(Block {
+            block_type: BlockType::Empty,
+            operations: vec![
+                Operation::Loop(Block {
+                    block_type: BlockType::Empty,
+                    operations: vec![
+                        Operation::GetLocal(0),
+                        Operation::I32Eqz,
+                        Operation::BranchIf(1),
+                        Operation::GetLocal(0),
+                        Operation::I32Const(1),
+                        Operation::I32Sub,
+                        Operation::SetLocal(0),
+                        Operation::Branch(0),
+                        Operation::End
+                    ]
+                }),
+                Operation::End
+            ]
+        }),
+        Operation::End
+    ]
+}
+
+fn run<E: Execute + ?Sized>(code: &E) {
+    let functions = vec![];
+    let tables = RefCell::new(vec![]);
+    let fuel = RefCell::new(Fuel::default());
+    let mut sf = StackFrame {
+        data: &mut ModuleInstanceData {
+            functions: &functions,
+            globals: vec![],
+            memories: vec![],
+            tables: tables.borrow_mut(),
+            types: vec![],
+            limits: Limits::default(),
+            fuel: fuel.borrow_mut(),
+            memory_limiter: None
+        },
+        locals: &mut vec![ValueTypeProvider::I32(ITERATIONS)],
+        stack: &mut vec![]
+    };
+    code.execute(&mut sf).unwrap();
+    assert_eq!(sf.locals, &mut vec![ValueTypeProvider::I32(0)]);
+}
+
+fn walked() -> Block {
+    Block {
+        block_type: BlockType::Empty,
+        operations: countdown()
+    }
+}
+
+fn compiled() -> CompiledBody {
+    bytecode::compile(&FuncBody {
+        locals: vec![],
+        code: countdown()
+    }, &FuncSignature {
+        parameters: vec![],
+        returns: vec![]
+    }, &[], &[]).unwrap()
+}
+
+#[cfg(feature = "nightly")]
+#[bench]
+fn tree_walker_countdown(b: &mut Bencher) {
+    let block = walked();
+    b.iter(|| run(&block));
+}
+
+#[cfg(feature = "nightly")]
+#[bench]
+fn compiled_countdown(b: &mut Bencher) {
+    let code = compiled();
+    b.iter(|| run(&code));
+}
+
+// The same comparison for stable toolchains, run it with
+// `cargo test --release compiled_against_tree_walker -- --ignored --nocapture`
+#[test]
+#[ignore]
+fn compiled_against_tree_walker() {
+    let time = |code: &Execute| {
+        let start = Instant::now();
+        for _ in 0..100 {
+            run(code);
+        }
+        start.elapsed() / 100
+    };
+    let walked = time(&walked());
+    let compiled = time(&compiled());
+    println!("countdown from {}: tree walker {:?}, compiled {:?}, {:.1}x faster", ITERATIONS, walked, compiled,
+        walked.as_secs_f64() / compiled.as_secs_f64());
+}
@@ -0,0 +1,476 @@
+use parse_tree::functions::FuncBody;
+use parse_tree::functions::FuncSignature;
+use parse_tree::language_types::Block;
+use parse_tree::language_types::BlockType;
+use parse_tree::language_types::Operation;
+use parse_tree::types::TypeDefinition;
+
+use parser::ParseError;
+use parser::ParseErrorKind;
+
+/// Where a branch lands and how much of the operand stack survives it.
+///
+/// `height` is relative to the bottom of the function's operand stack. Taking the branch keeps the
+/// top `arity` values and drops everything between them and `height`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Target {
+    pub pc: usize,
+    pub height: usize,
+    pub arity: usize,
+}
+
+/// A flattened function body instruction. Structured control flow is lowered to jumps with resolved
+/// targets and calls are singled out so the interpreter can push a frame for them, every other
+/// operation is carried over untouched.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    Op(Operation),
+    Jump(usize),
+    JumpUnless(usize), // pops an i32 condition
+    Branch(Target),
+    BranchIf(Target),
+    BranchTable(Vec<Target>, Target),
+    Return,
+    Call(usize), // function index
+    CallIndirect(usize), // type index, pops the table index
+}
+
+#[derive(Debug, Clone)]
+pub struct CompiledBody {
+    pub instructions: Vec<Instruction>,
+    // The most values the body ever has on its operand stack at once
+    pub max_height: usize,
+}
+
+struct Scope {
+    label: usize,
+    height: usize,
+    arity: usize,
+}
+
+struct Compiler<'m> {
+    functions: &'m [FuncSignature],
+    types: &'m [TypeDefinition],
+    instructions: Vec<Instruction>,
+    // Labels are handed out as ids while compiling and swapped for their pc once everything is placed
+    labels: Vec<usize>,
+    scopes: Vec<Scope>,
+    height: usize,
+    max_height: usize,
+}
+
+/// Lowers a function body to flat bytecode.
+///
+/// `functions` is the signature of every function in the module's index space, `types` is the type
+/// section, both are needed to know how calls change the height of the operand stack.
+pub fn compile(body: &FuncBody, signature: &FuncSignature, functions: &[FuncSignature], types: &[TypeDefinition]) -> Result<CompiledBody, ParseError> {
+    let mut compiler = Compiler {
+        functions,
+        types,
+        instructions: vec![],
+        labels: vec![],
+        scopes: vec![],
+        height: 0,
+        max_height: 0,
+    };
+
+    // The function body is itself a label, branching to it behaves like a return
+    let end = compiler.new_label();
+    compiler.scopes.push(Scope {
+        label: end,
+        height: 0,
+        arity: signature.returns.len(),
+    });
+    compiler.compile_sequence(&body.code)?;
+    compiler.place_label(end);
+    compiler.instructions.push(Instruction::Return);
+
+    let max_height = compiler.max_height;
+    Ok(CompiledBody {
+        instructions: compiler.resolve_labels(),
+        max_height,
+    })
+}
+
+impl<'m> Compiler<'m> {
+    fn new_label(&mut self) -> usize {
+        self.labels.push(0);
+        self.labels.len() - 1
+    }
+
+    fn place_label(&mut self, label: usize) {
+        self.labels[label] = self.instructions.len();
+    }
+
+    fn pop(&mut self, count: usize) -> Result<(), ParseError> {
+        if self.height < count {
+            return Err(ParseErrorKind::CustomError("Operand stack underflow in function body".to_string()).into());
+        }
+        self.height -= count;
+        Ok(())
+    }
+
+    fn push(&mut self, count: usize) {
+        self.height += count;
+        if self.height > self.max_height {
+            self.max_height = self.height;
+        }
+    }
+
+    fn target(&self, depth: i32) -> Result<Target, ParseError> {
+        let depth = depth as u32 as usize;
+        if depth >= self.scopes.len() {
+            return Err(ParseErrorKind::CustomError(format!("Branch depth {} is out of range", depth)).into());
+        }
+        let scope = &self.scopes[self.scopes.len() - 1 - depth];
+        Ok(Target {
+            pc: scope.label,
+            height: scope.height,
+            arity: scope.arity,
+        })
+    }
+
+    // The block's parameters have already been popped, they're pushed back as the block's own
+    fn enter(&mut self, label: usize, parameters: usize, arity: usize, block: &[Operation]) -> Result<(), ParseError> {
+        let height = self.height;
+        self.scopes.push(Scope {label, height, arity});
+        self.push(parameters);
+        self.compile_sequence(block)?;
+        self.scopes.pop();
+        self.height = height;
+        Ok(())
+    }
+
+    // Compiles operations up to the end of the enclosing block. Anything after an unconditional
+    // transfer of control is unreachable and is left out.
+    fn compile_sequence(&mut self, operations: &[Operation]) -> Result<(), ParseError> {
+        for operation in operations {
+            match *operation {
+                Operation::Block(ref b) => {
+                    let end = self.new_label();
+                    let (parameters, arity) = self.block_arity(&b.block_type)?;
+                    self.pop(parameters)?;
+                    self.enter(end, parameters, arity, &b.operations)?;
+                    self.place_label(end);
+                    self.push(arity);
+                },
+                Operation::Loop(ref b) => {
+                    // Branching back to the start carries the loop's parameters
+                    let start = self.new_label();
+                    let (parameters, arity) = self.block_arity(&b.block_type)?;
+                    self.pop(parameters)?;
+                    self.place_label(start);
+                    self.enter(start, parameters, parameters, &b.operations)?;
+                    self.push(arity);
+                },
+                Operation::If(ref b) => {
+                    self.pop(1)?;
+                    let end = self.new_label();
+                    let otherwise = self.new_label();
+                    let (parameters, arity) = self.block_arity(&b.block_type)?;
+                    self.pop(parameters)?;
+                    let (then, other) = split_if(b);
+                    self.instructions.push(Instruction::JumpUnless(otherwise));
+                    self.enter(end, parameters, arity, then)?;
+                    if let Some(other) = other {
+                        self.instructions.push(Instruction::Jump(end));
+                        self.place_label(otherwise);
+                        self.enter(end, parameters, arity, other)?;
+                    } else {
+                        self.place_label(otherwise);
+                    }
+                    self.place_label(end);
+                    self.push(arity);
+                },
+                Operation::Else | Operation::End => return Ok(()),
+                Operation::Branch(depth) => {
+                    let target = self.target(depth)?;
+                    self.instructions.push(Instruction::Branch(target));
+                    return Ok(());
+                },
+                Operation::BranchIf(depth) => {
+                    self.pop(1)?;
+                    let target = self.target(depth)?;
+                    self.instructions.push(Instruction::BranchIf(target));
+                },
+                Operation::BranchTable(ref table) => {
+                    self.pop(1)?;
+                    let mut targets = Vec::with_capacity(table.targets.len());
+                    for depth in &table.targets {
+                        targets.push(self.target(*depth)?);
+                    }
+                    let default = self.target(table.default)?;
+                    self.instructions.push(Instruction::BranchTable(targets, default));
+                    return Ok(());
+                },
+                Operation::Return => {
+                    self.instructions.push(Instruction::Return);
+                    return Ok(());
+                },
+                Operation::Unreachable => {
+                    self.instructions.push(Instruction::Op(Operation::Unreachable));
+                    return Ok(());
+                },
+                ref operation => {
+                    let (pops, pushes) = self.stack_effect(operation)?;
+                    self.pop(pops)?;
+                    self.push(pushes);
+                    self.instructions.push(match *operation {
+                        Operation::Call(index) => Instruction::Call(index),
+                        Operation::CallIndirect(index, _) => Instruction::CallIndirect(index),
+                        _ => Instruction::Op(operation.clone())
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve_labels(self) -> Vec<Instruction> {
+        let labels = self.labels;
+        let mut instructions = self.instructions;
+        for instruction in &mut instructions {
+            match *instruction {
+                Instruction::Jump(ref mut pc) | Instruction::JumpUnless(ref mut pc) => *pc = labels[*pc],
+                Instruction::Branch(ref mut target) | Instruction::BranchIf(ref mut target) => target.pc = labels[target.pc],
+                Instruction::BranchTable(ref mut targets, ref mut default) => {
+                    for target in targets.iter_mut() {
+                        target.pc = labels[target.pc];
+                    }
+                    default.pc = labels[default.pc];
+                },
+                _ => {}
+            }
+        }
+        instructions
+    }
+
+    fn block_arity(&self, block_type: &BlockType) -> Result<(usize, usize), ParseError> {
+        match block_type.arity(self.types) {
+            Ok(arity) => Ok(arity),
+            Err(trap) => Err(ParseErrorKind::CustomError(format!("Block has an {}", trap)).into())
+        }
+    }
+
+    // How many values a non control flow operation pops and pushes
+    fn stack_effect(&self, operation: &Operation) -> Result<(usize, usize), ParseError> {
+        Ok(match *operation {
+            Operation::Nop => (0, 0),
+            Operation::Call(index) => match self.functions.get(index) {
+                Some(signature) => (signature.parameters.len(), signature.returns.len()),
+                None => return Err(ParseErrorKind::CustomError(format!("Call to undefined function {}", index)).into())
+            },
+            Operation::CallIndirect(index, _) => match self.types.get(index) {
+                Some(&TypeDefinition::Func(ref signature)) => (signature.parameters.len() + 1, signature.returns.len()),
+                None => return Err(ParseErrorKind::CustomError(format!("Indirect call to undefined type {}", index)).into())
+            },
+            Operation::Drop => (1, 0),
+            Operation::Select => (3, 1),
+            Operation::GetLocal(_) | Operation::GetGlobal(_) => (0, 1),
+            Operation::SetLocal(_) | Operation::SetGlobal(_) => (1, 0),
+            Operation::TeeLocal(_) => (1, 1),
+            Operation::I32Load(_) | Operation::I64Load(_) | Operation::F32Load(_) | Operation::F64Load(_) |
+            Operation::I32Load8S(_) | Operation::I32Load8U(_) | Operation::I32Load16S(_) | Operation::I32Load16U(_) |
+            Operation::I64Load8S(_) | Operation::I64Load8U(_) | Operation::I64Load16S(_) | Operation::I64Load16U(_) |
+            Operation::I64Load32S(_) | Operation::I64Load32U(_) => (1, 1),
+            Operation::I32Store(_) | Operation::I64Store(_) | Operation::F32Store(_) | Operation::F64Store(_) |
+            Operation::I32Store8(_) | Operation::I32Store16(_) |
+            Operation::I64Store8(_) | Operation::I64Store16(_) | Operation::I64Store32(_) => (2, 0),
+            Operation::CurrentMemory(_) => (0, 1),
+            Operation::GrowMemory(_) => (1, 1),
+            Operation::I32Const(_) | Operation::I64Const(_) | Operation::F32Const(_) | Operation::F64Const(_) => (0, 1),
+            Operation::I32Eqz | Operation::I64Eqz |
+            Operation::I32Clz | Operation::I32Ctz | Operation::I32Popcnt |
+            Operation::I64Clz | Operation::I64Ctz | Operation::I64Popcnt |
+            Operation::F32Abs | Operation::F32Neg | Operation::F32Ceil | Operation::F32Floor |
+            Operation::F32Trunc | Operation::F32Nearest | Operation::F32Sqrt |
+            Operation::F64Abs | Operation::F64Neg | Operation::F64Ceil | Operation::F64Floor |
+            Operation::F64Trunc | Operation::F64Nearest | Operation::F64Sqrt |
+            Operation::I32WrapI64 | Operation::I32TruncSF32 | Operation::I32TruncUF32 |
+            Operation::I32TruncSF64 | Operation::I32TruncUF64 | Operation::I64ExtendSI32 |
+            Operation::I64ExtendUI32 | Operation::I64TruncSF32 | Operation::I64TruncUF32 |
+            Operation::I64TruncSF64 | Operation::I64TruncUF64 | Operation::F32ConvertSI32 |
+            Operation::F32ConvertUI32 | Operation::F32ConvertSI64 | Operation::F32ConvertUI64 |
+            Operation::F32DemoteF64 | Operation::F64ConvertSI32 | Operation::F64ConvertUI32 |
+            Operation::F64ConvertSI64 | Operation::F64ConvertUI64 | Operation::F64PromoteF32 |
+            Operation::I32ReinterpretF32 | Operation::I64ReinterpretF64 |
+            Operation::F32ReinterpretI32 | Operation::F64ReinterpretI64 => (1, 1),
+            Operation::Unreachable | Operation::Block(_) | Operation::Loop(_) | Operation::If(_) |
+            Operation::Else | Operation::End | Operation::Branch(_) | Operation::BranchIf(_) |
+            Operation::BranchTable(_) | Operation::Return => unreachable!("control flow is compiled separately"),
+            // Everything left is a binary comparison or numeric operator
+            _ => (2, 1),
+        })
+    }
+}
+
+// Splits an if block at its own `else`, nested ifs keep theirs inside their own blocks
+fn split_if(block: &Block) -> (&[Operation], Option<&[Operation]>) {
+    let operations = &block.operations[..];
+    match operations.iter().position(|op| *op == Operation::Else) {
+        Some(index) => (&operations[..index], Some(&operations[index+1..])),
+        None => (operations, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parse_tree::language_types::BlockType;
+    use parse_tree::language_types::BranchTable;
+    use parse_tree::language_types::ValueType;
+
+    fn compile_ops(returns: Vec<ValueType>, code: Vec<Operation>) -> Vec<Instruction> {
+        let signature = FuncSignature {
+            parameters: vec![],
+            returns
+        };
+        compile(&FuncBody {locals: vec![], code}, &signature, &[], &[]).unwrap().instructions
+    }
+
+    #[test]
+    fn straight_line_code_is_copied() {
+        let instructions = compile_ops(vec![ValueType::I32], vec![
+            Operation::I32Const(1),
+            Operation::I32Const(2),
+            Operation::I32Add,
+        ]);
+        assert_eq!(instructions, vec![
+            Instruction::Op(Operation::I32Const(1)),
+            Instruction::Op(Operation::I32Const(2)),
+            Instruction::Op(Operation::I32Add),
+            Instruction::Return,
+        ]);
+    }
+
+    #[test]
+    fn branch_out_of_block_targets_its_end() {
+        let instructions = compile_ops(vec![ValueType::I32], vec![
+            Operation::I32Const(7),
+            Operation::Block(Block {
+                block_type: BlockType::Value(ValueType::I32),
+                operations: vec![
+                    Operation::I32Const(1),
+                    Operation::I32Const(42),
+                    Operation::Branch(0),
+                    Operation::I32Const(13),
+                ]
+            }),
+            Operation::I32Add,
+        ]);
+        assert_eq!(instructions, vec![
+            Instruction::Op(Operation::I32Const(7)),
+            Instruction::Op(Operation::I32Const(1)),
+            Instruction::Op(Operation::I32Const(42)),
+            Instruction::Branch(Target {pc: 4, height: 1, arity: 1}),
+            Instruction::Op(Operation::I32Add),
+            Instruction::Return,
+        ]);
+    }
+
+    #[test]
+    fn branch_in_loop_targets_its_start() {
+        let instructions = compile_ops(vec![], vec![
+            Operation::Loop(Block {
+                block_type: BlockType::Empty,
+                operations: vec![
+                    Operation::I32Const(1),
+                    Operation::BranchIf(0),
+                ]
+            }),
+        ]);
+        assert_eq!(instructions, vec![
+            Instruction::Op(Operation::I32Const(1)),
+            Instruction::BranchIf(Target {pc: 0, height: 0, arity: 0}),
+            Instruction::Return,
+        ]);
+    }
+
+    #[test]
+    fn if_else_jumps_over_each_arm() {
+        let instructions = compile_ops(vec![ValueType::I32], vec![
+            Operation::I32Const(0),
+            Operation::If(Block {
+                block_type: BlockType::Value(ValueType::I32),
+                operations: vec![
+                    Operation::I32Const(13),
+                    Operation::Else,
+                    Operation::I32Const(42),
+                ]
+            }),
+        ]);
+        assert_eq!(instructions, vec![
+            Instruction::Op(Operation::I32Const(0)),
+            Instruction::JumpUnless(4),
+            Instruction::Op(Operation::I32Const(13)),
+            Instruction::Jump(5),
+            Instruction::Op(Operation::I32Const(42)),
+            Instruction::Return,
+        ]);
+    }
+
+    #[test]
+    fn branch_table_resolves_every_target() {
+        let instructions = compile_ops(vec![], vec![
+            Operation::Block(Block {
+                block_type: BlockType::Empty,
+                operations: vec![
+                    Operation::I32Const(0),
+                    Operation::BranchTable(BranchTable {
+                        targets: vec![0, 1],
+                        default: 0
+                    }),
+                ]
+            }),
+        ]);
+        assert_eq!(instructions, vec![
+            Instruction::Op(Operation::I32Const(0)),
+            Instruction::BranchTable(vec![
+                Target {pc: 2, height: 0, arity: 0},
+                Target {pc: 2, height: 0, arity: 0},
+            ], Target {pc: 2, height: 0, arity: 0}),
+            Instruction::Return,
+        ]);
+    }
+
+    #[test]
+    fn out_of_range_branch_is_rejected() {
+        let signature = FuncSignature {
+            parameters: vec![],
+            returns: vec![]
+        };
+        let body = FuncBody {
+            locals: vec![],
+            code: vec![Operation::Branch(1)]
+        };
+        assert!(compile(&body, &signature, &[], &[]).is_err());
+    }
+
+    #[test]
+    fn calls_are_singled_out() {
+        let signature = FuncSignature {
+            parameters: vec![ValueType::I32, ValueType::I32],
+            returns: vec![ValueType::I32]
+        };
+        let body = FuncBody {
+            locals: vec![],
+            code: vec![
+                Operation::I32Const(1),
+                Operation::I32Const(2),
+                Operation::I32Const(3),
+                Operation::Call(0),
+                Operation::I32Add,
+            ]
+        };
+        let code = compile(&body, &signature, &[signature.clone()], &[]).unwrap();
+        assert_eq!(code.instructions, vec![
+            Instruction::Op(Operation::I32Const(1)),
+            Instruction::Op(Operation::I32Const(2)),
+            Instruction::Op(Operation::I32Const(3)),
+            Instruction::Call(0),
+            Instruction::Op(Operation::I32Add),
+            Instruction::Return,
+        ]);
+        assert_eq!(code.max_height, 3);
+    }
+}
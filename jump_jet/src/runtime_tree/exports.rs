@@ -1,20 +1,22 @@
+use runtime_tree::interpreter;
 use runtime_tree::language_types::ExternalKindInstance;
 use runtime_tree::ModuleInstance;
+use runtime_tree::Trap;
 use runtime_tree::ValueTypeProvider;
 
-pub struct ExportObj<'m> {
-    pub module: &'m mut ModuleInstance<'m>
+pub struct ExportObj<'m, 'a: 'm> {
+    pub module: &'m mut ModuleInstance<'a>
 }
 pub trait ExportObject {
-    fn call_fn(&mut self, name: &str, args: Vec<ValueTypeProvider>) -> Vec<ValueTypeProvider>;
+    fn call_fn(&mut self, name: &str, args: Vec<ValueTypeProvider>) -> Result<Vec<ValueTypeProvider>, Trap>;
 }
-impl<'m> ExportObject for ExportObj<'m> {
-    fn call_fn(&mut self, name: &str, args: Vec<ValueTypeProvider>) -> Vec<ValueTypeProvider> {
-        let export = self.module.exports.get(name).unwrap();
-        if let ExternalKindInstance::Function(ref i) = *export {
-            return (i.callable)(&mut self.module.get_frame(), args);
+impl<'m, 'a> ExportObject for ExportObj<'m, 'a> {
+    fn call_fn(&mut self, name: &str, args: Vec<ValueTypeProvider>) -> Result<Vec<ValueTypeProvider>, Trap> {
+        if let Some(&ExternalKindInstance::Function(index)) = self.module.exports.get(name) {
+            trace!("Calling export {} ({})", name, self.module.names.describe_function(index));
+            interpreter::invoke(&mut self.module.get_frame(), index, args)
         } else {
-            panic!("export wasn't a function");
+            Err(Trap::UnknownExport(name.to_string()))
         }
     }
 }
\ No newline at end of file
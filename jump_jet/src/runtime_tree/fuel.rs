@@ -0,0 +1,144 @@
+use parse_tree::language_types::Block;
+use parse_tree::language_types::BlockType;
+use parse_tree::language_types::BranchTable;
+use parse_tree::language_types::Operation;
+
+use runtime_tree::bytecode::Instruction;
+use runtime_tree::Trap;
+
+/// A budget on how much work calls into a module instance may do.
+///
+/// Every instruction the interpreter executes costs the fuel its operation is priced at, once the
+/// budget can't cover an instruction the call traps with `Trap::OutOfFuel` before executing it.
+/// Without a budget nothing is metered.
+pub struct Fuel {
+    remaining: Option<u64>,
+    cost: Box<Fn(&Operation) -> u64>
+}
+
+impl Default for Fuel {
+    fn default() -> Fuel {
+        Fuel {
+            remaining: None,
+            cost: Box::new(|_| 1)
+        }
+    }
+}
+
+impl Fuel {
+    /// The fuel left, or `None` if execution isn't metered.
+    pub fn remaining(&self) -> Option<u64> {
+        self.remaining
+    }
+
+    pub fn set(&mut self, fuel: u64) {
+        self.remaining = Some(fuel);
+    }
+
+    /// Tops the budget up by `fuel`, starting one if there wasn't one already.
+    pub fn add(&mut self, fuel: u64) {
+        self.remaining = Some(self.remaining.unwrap_or(0).saturating_add(fuel));
+    }
+
+    /// Stops metering execution.
+    pub fn clear(&mut self) {
+        self.remaining = None;
+    }
+
+    /// Replaces how much each operation costs, by default everything costs 1.
+    ///
+    /// `cost` sees the module's own operations, except for control flow, which runs as compiled
+    /// jumps and branches. Those are priced as a stand-in operation with empty immediates:
+    ///
+    /// - the test at the start of an `if` as an empty `Operation::If`
+    /// - the jump from the end of an `if`'s first branch past its `else` branch as `Operation::Else`
+    /// - branches as `Operation::Branch(0)`, `Operation::BranchIf(0)` and an empty `Operation::BranchTable`
+    /// - returns and calls as `Operation::Return`, `Operation::Call` and `Operation::CallIndirect`
+    ///
+    /// `Block`, `Loop` and `End` compile to nothing, so they're never charged.
+    pub fn set_cost(&mut self, cost: Box<Fn(&Operation) -> u64>) {
+        self.cost = cost;
+    }
+
+    pub fn charge(&mut self, instruction: &Instruction) -> Result<(), Trap> {
+        let remaining = match self.remaining {
+            Some(remaining) => remaining,
+            None => return Ok(())
+        };
+        let cost = match *instruction {
+            Instruction::Op(ref operation) => (self.cost)(operation),
+            ref control => (self.cost)(&lowered_from(control))
+        };
+        match remaining.checked_sub(cost) {
+            Some(remaining) => {
+                self.remaining = Some(remaining);
+                Ok(())
+            },
+            None => Err(Trap::OutOfFuel)
+        }
+    }
+}
+
+// Rebuilds the operation a control flow instruction was compiled from, as much of it as pricing needs.
+// Blocks and loops compile to nothing so entering them is free, the branches that leave them aren't.
+fn lowered_from(instruction: &Instruction) -> Operation {
+    match *instruction {
+        Instruction::Op(ref operation) => operation.clone(),
+        Instruction::Jump(_) => Operation::Else,
+        Instruction::JumpUnless(_) => Operation::If(Block {
+            block_type: BlockType::Empty,
+            operations: vec![]
+        }),
+        Instruction::Branch(_) => Operation::Branch(0),
+        Instruction::BranchIf(_) => Operation::BranchIf(0),
+        Instruction::BranchTable(..) => Operation::BranchTable(BranchTable {
+            targets: vec![],
+            default: 0
+        }),
+        Instruction::Return => Operation::Return,
+        Instruction::Call(index) => Operation::Call(index),
+        Instruction::CallIndirect(index) => Operation::CallIndirect(index, false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unmetered_by_default() {
+        let mut fuel = Fuel::default();
+        assert_eq!(fuel.charge(&Instruction::Op(Operation::Nop)), Ok(()));
+        assert_eq!(fuel.remaining(), None);
+    }
+
+    #[test]
+    fn charges_until_exhausted() {
+        let mut fuel = Fuel::default();
+        fuel.set(2);
+        assert_eq!(fuel.charge(&Instruction::Op(Operation::Nop)), Ok(()));
+        assert_eq!(fuel.charge(&Instruction::Return), Ok(()));
+        assert_eq!(fuel.remaining(), Some(0));
+        assert_eq!(fuel.charge(&Instruction::Op(Operation::Nop)), Err(Trap::OutOfFuel));
+        assert_eq!(fuel.remaining(), Some(0));
+        fuel.add(1);
+        assert_eq!(fuel.charge(&Instruction::Op(Operation::Nop)), Ok(()));
+    }
+
+    #[test]
+    fn costs_are_configurable() {
+        let mut fuel = Fuel::default();
+        fuel.set(10);
+        fuel.set_cost(Box::new(|operation| match *operation {
+            Operation::Call(_) => 5,
+            Operation::Nop => 0,
+            _ => 1
+        }));
+        assert_eq!(fuel.charge(&Instruction::Op(Operation::Nop)), Ok(()));
+        assert_eq!(fuel.charge(&Instruction::Call(0)), Ok(()));
+        assert_eq!(fuel.charge(&Instruction::Op(Operation::I32Add)), Ok(()));
+        assert_eq!(fuel.remaining(), Some(4));
+        assert_eq!(fuel.charge(&Instruction::Call(0)), Err(Trap::OutOfFuel));
+        assert_eq!(fuel.remaining(), Some(4));
+    }
+}
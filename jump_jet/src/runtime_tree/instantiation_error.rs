@@ -0,0 +1,44 @@
+use std::error::Error;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+
+use runtime_tree::Trap;
+
+/// The ways in which turning a module template into a running instance can fail.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstantiationError {
+    UndefinedGlobal(usize),
+    GlobalTypeMismatch(usize),
+    InvalidLimits,
+    UndefinedMemory(usize),
+    MemoryLimitExceeded(usize),
+    UndefinedTable(usize),
+    UndefinedFunction(usize),
+    OffsetTypeMismatch,
+    DataSegmentOutOfBounds(usize),
+    ElementSegmentOutOfBounds(usize),
+    InvalidStartFunction(usize),
+    StartFunctionTrapped(Trap),
+}
+
+impl Display for InstantiationError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            InstantiationError::UndefinedGlobal(index) => write!(f, "init expression refers to undefined global {}", index),
+            InstantiationError::GlobalTypeMismatch(index) => write!(f, "global {} initialised with the wrong type", index),
+            InstantiationError::InvalidLimits => write!(f, "initial size is larger than the maximum"),
+            InstantiationError::UndefinedMemory(index) => write!(f, "undefined memory {}", index),
+            InstantiationError::MemoryLimitExceeded(index) => write!(f, "memory {} is larger than the memory limiter allows", index),
+            InstantiationError::UndefinedTable(index) => write!(f, "undefined table {}", index),
+            InstantiationError::UndefinedFunction(index) => write!(f, "undefined function {}", index),
+            InstantiationError::OffsetTypeMismatch => write!(f, "segment offset must be an i32"),
+            InstantiationError::DataSegmentOutOfBounds(index) => write!(f, "data segment {} does not fit in memory", index),
+            InstantiationError::ElementSegmentOutOfBounds(index) => write!(f, "element segment {} does not fit in table", index),
+            InstantiationError::InvalidStartFunction(index) => write!(f, "start function {} must take and return nothing", index),
+            InstantiationError::StartFunctionTrapped(ref trap) => write!(f, "start function trapped: {}", trap),
+        }
+    }
+}
+
+impl Error for InstantiationError {}
@@ -0,0 +1,399 @@
+use parse_tree::language_types::ValueType;
+use parse_tree::tables::Table;
+use parse_tree::types::TypeDefinition;
+
+use runtime_tree::bytecode::CompiledBody;
+use runtime_tree::language_types::Exit;
+use runtime_tree::language_types::StackFrame;
+use runtime_tree::Func;
+use runtime_tree::FuncKind;
+use runtime_tree::ModuleInstanceData;
+use runtime_tree::Trap;
+use runtime_tree::ValueTypeProvider;
+
+use std::rc::Rc;
+
+/// How much of the interpreter's own stacks a single call into a module may use. Going over either
+/// limit traps with `Trap::StackOverflow`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// The most wasm frames that can be live at once.
+    pub max_call_depth: usize,
+    /// The most values, locals and operands together, that all live frames can hold.
+    pub max_value_stack: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_call_depth: 10_000,
+            max_value_stack: 1 << 20,
+        }
+    }
+}
+
+struct Frame {
+    code: Rc<CompiledBody>,
+    pc: usize,
+    base: usize,
+    arity: usize,
+    locals: Vec<ValueTypeProvider>,
+    // How much of `Limits::max_value_stack` this frame accounts for
+    reserved: usize,
+}
+
+struct CallStack {
+    frames: Vec<Frame>,
+    reserved: usize,
+    limits: Limits,
+}
+
+/// Calls function `index` with `args` and runs it to completion.
+///
+/// Wasm frames live on a call stack of their own rather than on the host's, so however deeply the
+/// module recurses the host only ever sees this one call.
+pub fn invoke(data: &mut ModuleInstanceData, index: usize, args: Vec<ValueTypeProvider>) -> Result<Vec<ValueTypeProvider>, Trap> {
+    let functions = data.functions;
+    let function = self::function(functions, index)?;
+    if args.len() != function.signature.parameters.len() {
+        return Err(Trap::TypeMismatch);
+    }
+
+    let mut stack = args;
+    let mut call_stack = CallStack {
+        frames: vec![],
+        reserved: 0,
+        limits: data.limits,
+    };
+    call_stack.call(function, &mut stack, data)?;
+
+    loop {
+        let exit = match call_stack.frames.last_mut() {
+            Some(frame) => frame.code.run(&mut frame.pc, frame.base, &mut StackFrame {
+                data,
+                locals: &mut frame.locals,
+                stack: &mut stack
+            })?,
+            None => break
+        };
+        match exit {
+            Exit::Return => call_stack.ret(&mut stack)?,
+            Exit::Call(index) => call_stack.call(self::function(functions, index)?, &mut stack, data)?,
+            Exit::CallIndirect(index) => {
+                let index = resolve_indirect(data, index, &mut stack)?;
+                call_stack.call(self::function(functions, index)?, &mut stack, data)?;
+            }
+        }
+    }
+
+    // Only the results of the outermost call are left
+    check_types(&function.signature.returns, &stack)?;
+    Ok(stack)
+}
+
+impl CallStack {
+    // Calls `function` with its arguments on top of `stack`. Host functions run to completion here,
+    // wasm functions get a frame which `invoke` runs next.
+    fn call(&mut self, function: &Func, stack: &mut Vec<ValueTypeProvider>, data: &mut ModuleInstanceData) -> Result<(), Trap> {
+        let args = pop_args(stack, &function.signature.parameters)?;
+        let lazy;
+        let (locals, code) = match function.kind {
+            FuncKind::Host(ref callable) => {
+                let results = callable(data, args)?;
+                check_types(&function.signature.returns, &results)?;
+                stack.extend(results);
+                return Ok(());
+            },
+            FuncKind::Wasm { ref locals, ref code } => (locals, code.clone()),
+            FuncKind::Lazy(ref body) => {
+                lazy = body.compiled()?;
+                (&lazy.0, lazy.1.clone())
+            }
+        };
+        let reserved = args.len() + locals.len() + code.max_height;
+        if self.frames.len() >= self.limits.max_call_depth ||
+            self.reserved + reserved > self.limits.max_value_stack {
+            return Err(Trap::StackOverflow);
+        }
+        let mut frame_locals = args;
+        frame_locals.extend(locals.iter().map(|local| match *local {
+            ValueType::I32 => ValueTypeProvider::I32(0),
+            ValueType::I64 => ValueTypeProvider::I64(0),
+            ValueType::F32 => ValueTypeProvider::F32(0.0),
+            ValueType::F64 => ValueTypeProvider::F64(0.0),
+        }));
+        self.reserved += reserved;
+        self.frames.push(Frame {
+            code,
+            pc: 0,
+            base: stack.len(),
+            arity: function.signature.returns.len(),
+            locals: frame_locals,
+            reserved
+        });
+        Ok(())
+    }
+
+    // Pops the running frame, leaving its results where its operands started
+    fn ret(&mut self, stack: &mut Vec<ValueTypeProvider>) -> Result<(), Trap> {
+        let frame = match self.frames.pop() {
+            Some(frame) => frame,
+            None => return Ok(())
+        };
+        self.reserved -= frame.reserved;
+        if stack.len() < frame.base + frame.arity {
+            return Err(Trap::StackUnderflow);
+        }
+        let top = stack.len() - frame.arity;
+        stack.drain(frame.base..top);
+        Ok(())
+    }
+}
+
+pub fn function(functions: &Vec<Func>, index: usize) -> Result<&Func, Trap> {
+    match functions.get(index) {
+        Some(function) => Ok(function),
+        None => Err(Trap::UndefinedFunction(index))
+    }
+}
+
+/// Pops the arguments for a call off `stack`. The last parameter is on top.
+pub fn pop_args(stack: &mut Vec<ValueTypeProvider>, parameters: &[ValueType]) -> Result<Vec<ValueTypeProvider>, Trap> {
+    if stack.len() < parameters.len() {
+        return Err(Trap::StackUnderflow);
+    }
+    let args = stack.split_off(stack.len() - parameters.len());
+    check_types(parameters, &args)?;
+    Ok(args)
+}
+
+/// Pops a table index off `stack` and finds the function it refers to, checking it has the
+/// signature of type `type_index`.
+pub fn resolve_indirect(data: &ModuleInstanceData, type_index: usize, stack: &mut Vec<ValueTypeProvider>) -> Result<usize, Trap> {
+    let TypeDefinition::Func(ref signature) = match data.types.get(type_index) {
+        Some(definition) => definition.clone(),
+        None => return Err(Trap::UndefinedType(type_index))
+    };
+    let index = match stack.pop() {
+        Some(ValueTypeProvider::I32(index)) => index,
+        Some(_) => return Err(Trap::TypeMismatch),
+        None => return Err(Trap::StackUnderflow)
+    };
+    let fn_index = match data.tables.get(0) {
+        Some(&Table::AnyFunc{ref values, ..}) => match values.get(index as u32 as usize) {
+            Some(&Some(fn_index)) => fn_index,
+            _ => return Err(Trap::UndefinedTableElement)
+        },
+        None => return Err(Trap::UndefinedTableElement)
+    };
+    match data.functions.get(fn_index) {
+        Some(function) if function.signature == *signature => Ok(fn_index),
+        Some(_) => Err(Trap::IndirectCallTypeMismatch),
+        None => Err(Trap::UndefinedTableElement)
+    }
+}
+
+fn check_types(types: &[ValueType], values: &[ValueTypeProvider]) -> Result<(), Trap> {
+    if types.len() != values.len() {
+        return Err(Trap::TypeMismatch);
+    }
+    for (value_type, value) in types.iter().zip(values.iter()) {
+        match (value_type, value) {
+            (&ValueType::I32, &ValueTypeProvider::I32(_)) |
+            (&ValueType::I64, &ValueTypeProvider::I64(_)) |
+            (&ValueType::F32, &ValueTypeProvider::F32(_)) |
+            (&ValueType::F64, &ValueTypeProvider::F64(_)) => {},
+            _ => return Err(Trap::TypeMismatch)
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use parse_tree::functions::FuncBody;
+    use parse_tree::functions::FuncSignature;
+    use parse_tree::language_types::Block;
+    use parse_tree::language_types::BlockType;
+    use parse_tree::language_types::Operation;
+    use runtime_tree::bytecode;
+    use runtime_tree::Fuel;
+
+    // Compiles `bodies` into functions which come after `imports` and can call any of them
+    fn functions(mut imports: Vec<Func>, bodies: Vec<(FuncSignature, Vec<Operation>)>) -> Vec<Func> {
+        let signatures: Vec<FuncSignature> = imports.iter().map(|f| f.signature.clone())
+            .chain(bodies.iter().map(|body| body.0.clone()))
+            .collect();
+        let defined: Vec<Func> = bodies.into_iter().map(|(signature, code)| {
+            let body = FuncBody {
+                locals: vec![],
+                code
+            };
+            let code = bytecode::compile(&body, &signature, &signatures, &[]).unwrap();
+            Func {
+                signature,
+                kind: FuncKind::Wasm {
+                    locals: vec![],
+                    code: Rc::new(code)
+                }
+            }
+        }).collect();
+        imports.extend(defined);
+        imports
+    }
+
+    fn call(functions: &Vec<Func>, index: usize, limits: Limits, args: Vec<ValueTypeProvider>) -> Result<Vec<ValueTypeProvider>, Trap> {
+        call_with_fuel(functions, index, limits, &RefCell::new(Fuel::default()), args)
+    }
+
+    fn call_with_fuel(functions: &Vec<Func>, index: usize, limits: Limits, fuel: &RefCell<Fuel>, args: Vec<ValueTypeProvider>) -> Result<Vec<ValueTypeProvider>, Trap> {
+        let tables = RefCell::new(vec![]);
+        let mut data = ModuleInstanceData {
+            functions,
+            globals: vec![],
+            memories: vec![],
+            tables: tables.borrow_mut(),
+            types: vec![],
+            limits,
+            fuel: fuel.borrow_mut(),
+            memory_limiter: None
+        };
+        invoke(&mut data, index, args)
+    }
+
+    fn i32_to_i32() -> FuncSignature {
+        FuncSignature {
+            parameters: vec![ValueType::I32],
+            returns: vec![ValueType::I32]
+        }
+    }
+
+    // Counts its argument down to zero, one call per step
+    fn countdown() -> Vec<Func> {
+        functions(vec![], vec![(i32_to_i32(), vec![
+            Operation::GetLocal(0),
+            Operation::I32Eqz,
+            Operation::If(Block {
+                block_type: BlockType::Value(ValueType::I32),
+                operations: vec![
+                    Operation::I32Const(0),
+                    Operation::Else,
+                    Operation::GetLocal(0),
+                    Operation::I32Const(1),
+                    Operation::I32Sub,
+                    Operation::Call(0),
+                    Operation::End,
+                ]
+            }),
+            Operation::End,
+        ])])
+    }
+
+    #[test]
+    fn arguments_are_passed_in_order() {
+        let signature = FuncSignature {
+            parameters: vec![ValueType::I32, ValueType::I64],
+            returns: vec![ValueType::I32]
+        };
+        let functions = functions(vec![], vec![
+            (i32_to_i32(), vec![
+                Operation::GetLocal(0),
+                Operation::I64Const(2),
+                Operation::Call(1),
+            ]),
+            (signature, vec![
+                Operation::GetLocal(0),
+            ]),
+        ]);
+        assert_eq!(call(&functions, 0, Limits::default(), vec![ValueTypeProvider::I32(1)]), Ok(vec![ValueTypeProvider::I32(1)]));
+    }
+
+    #[test]
+    fn host_functions_are_called() {
+        let double = Func {
+            signature: i32_to_i32(),
+            kind: FuncKind::Host(Rc::new(|_, args| match args[0] {
+                ValueTypeProvider::I32(i) => Ok(vec![ValueTypeProvider::I32(i * 2)]),
+                _ => Err(Trap::TypeMismatch)
+            }))
+        };
+        let functions = functions(vec![double], vec![(i32_to_i32(), vec![
+            Operation::GetLocal(0),
+            Operation::Call(0),
+        ])]);
+        assert_eq!(call(&functions, 1, Limits::default(), vec![ValueTypeProvider::I32(21)]), Ok(vec![ValueTypeProvider::I32(42)]));
+    }
+
+    #[test]
+    fn recursion_does_not_use_the_host_stack() {
+        let limits = Limits {
+            max_call_depth: 200_000,
+            max_value_stack: 1 << 22
+        };
+        assert_eq!(call(&countdown(), 0, limits, vec![ValueTypeProvider::I32(100_000)]), Ok(vec![ValueTypeProvider::I32(0)]));
+    }
+
+    #[test]
+    fn call_depth_is_limited() {
+        let limits = Limits {
+            max_call_depth: 10,
+            max_value_stack: 1 << 20
+        };
+        assert_eq!(call(&countdown(), 0, limits, vec![ValueTypeProvider::I32(9)]), Ok(vec![ValueTypeProvider::I32(0)]));
+        assert_eq!(call(&countdown(), 0, limits, vec![ValueTypeProvider::I32(10)]), Err(Trap::StackOverflow));
+    }
+
+    #[test]
+    fn value_stack_is_limited() {
+        // Each frame holds one local and at most two operands
+        let limits = Limits {
+            max_call_depth: 10_000,
+            max_value_stack: 30
+        };
+        assert_eq!(call(&countdown(), 0, limits, vec![ValueTypeProvider::I32(9)]), Ok(vec![ValueTypeProvider::I32(0)]));
+        assert_eq!(call(&countdown(), 0, limits, vec![ValueTypeProvider::I32(10)]), Err(Trap::StackOverflow));
+    }
+
+    #[test]
+    fn arguments_are_type_checked() {
+        assert_eq!(call(&countdown(), 0, Limits::default(), vec![ValueTypeProvider::I64(1)]), Err(Trap::TypeMismatch));
+        assert_eq!(call(&countdown(), 0, Limits::default(), vec![]), Err(Trap::TypeMismatch));
+    }
+
+    #[test]
+    fn endless_loops_run_out_of_fuel() {
+        let functions = functions(vec![], vec![(FuncSignature {
+            parameters: vec![],
+            returns: vec![]
+        }, vec![
+            Operation::Loop(Block {
+                block_type: BlockType::Empty,
+                operations: vec![
+                    Operation::Branch(0),
+                    Operation::End,
+                ]
+            }),
+            Operation::End,
+        ])]);
+        let fuel = RefCell::new(Fuel::default());
+        fuel.borrow_mut().set(1000);
+        assert_eq!(call_with_fuel(&functions, 0, Limits::default(), &fuel, vec![]), Err(Trap::OutOfFuel));
+        assert_eq!(fuel.borrow().remaining(), Some(0));
+    }
+
+    #[test]
+    fn fuel_is_consumed_across_calls() {
+        let fuel = RefCell::new(Fuel::default());
+        fuel.borrow_mut().set(100);
+        assert_eq!(call_with_fuel(&countdown(), 0, Limits::default(), &fuel, vec![ValueTypeProvider::I32(1)]), Ok(vec![ValueTypeProvider::I32(0)]));
+        let used = 100 - fuel.borrow().remaining().unwrap();
+        assert!(used > 0);
+        assert_eq!(call_with_fuel(&countdown(), 0, Limits::default(), &fuel, vec![ValueTypeProvider::I32(1)]), Ok(vec![ValueTypeProvider::I32(0)]));
+        assert_eq!(fuel.borrow().remaining(), Some(100 - 2 * used));
+
+        fuel.borrow_mut().set(used - 1);
+        assert_eq!(call_with_fuel(&countdown(), 0, Limits::default(), &fuel, vec![ValueTypeProvider::I32(1)]), Err(Trap::OutOfFuel));
+    }
+}
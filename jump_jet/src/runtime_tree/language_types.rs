@@ -1,33 +1,42 @@
+use parse_tree::functions::FuncSignature;
 use parse_tree::language_types::Block;
+use parse_tree::language_types::BlockType;
+use parse_tree::language_types::GlobalType;
 use parse_tree::language_types::Operation;
-use parse_tree::language_types::ValueType;
 use parse_tree::memory::Memory;
 use parse_tree::tables::Table;
 use parse_tree::types::TypeDefinition;
 
+use runtime_tree::byteorder::ByteOrder;
 use runtime_tree::byteorder::LittleEndian;
-use runtime_tree::byteorder::ReadBytesExt;
-use runtime_tree::byteorder::WriteBytesExt;
+use runtime_tree::bytecode::CompiledBody;
+use runtime_tree::bytecode::Instruction;
+use runtime_tree::interpreter;
 //use runtime_tree::RuntimeModule;
 use runtime_tree::ModuleInstanceData;
-use runtime_tree::Func;
+use runtime_tree::Trap;
 
-use std::cell::RefMut;
+use std::cell::RefCell;
 use std::mem;
+use std::rc::Rc;
 
 pub enum ExternalKindInstance {
-    Function(Func),
+    Function(usize),
     Table(usize),
     Memory(usize),
     Global(usize),
 }
 
+/// An item the embedder provides for a module to import, see `Linker`.
+///
+/// Memories and globals are shared: every instance that imports one works on the same value as the
+/// embedder, who can keep a handle to it. Tables are still copied into each instance.
 pub enum Import {
     //TODO imported functions probably shouldn't get access to ModuleInstanceData. Wrap it in another closure that swallows that.
-    Function(Box<Fn(&mut ModuleInstanceData, Vec<ValueTypeProvider>)->Vec<ValueTypeProvider>>),
-    Table(usize),
-    Memory(usize),
-    Global(usize),
+    Function(FuncSignature, Rc<Fn(&mut ModuleInstanceData, Vec<ValueTypeProvider>)->Result<Vec<ValueTypeProvider>, Trap>>),
+    Table(Table),
+    Memory(Rc<RefCell<Memory>>),
+    Global(GlobalType, Rc<RefCell<ValueTypeProvider>>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -44,38 +53,226 @@ pub struct StackFrame<'b, 'a: 'b> {
     pub stack: &'b mut Vec<ValueTypeProvider>
 }
 
+/// How control left a sequence of operations.
+#[derive(Debug, PartialEq)]
+pub enum Flow {
+    Continue,
+    Branch(u32), // relative label depth, 0 is the innermost enclosing label
+    Return,
+}
+
 pub trait Execute {
-    fn execute(&self, &mut StackFrame) -> i32;
+    fn execute(&self, &mut StackFrame) -> Result<Flow, Trap>;
 }
 
 impl Execute for Block {
-    fn execute(&self, stack_frame: &mut StackFrame) -> i32 {
+    fn execute(&self, stack_frame: &mut StackFrame) -> Result<Flow, Trap> {
+        let (parameters, arity) = self.block_type.arity(&stack_frame.data.types)?;
+        Block::enter(&self.operations, parameters, arity, stack_frame)
+    }
+}
+
+/// Why a compiled body handed control back to the interpreter.
+#[derive(Debug, PartialEq)]
+pub enum Exit {
+    Return,
+    Call(usize),
+    CallIndirect(usize),
+}
+
+impl Execute for CompiledBody {
+    fn execute(&self, stack_frame: &mut StackFrame) -> Result<Flow, Trap> {
+        let base = stack_frame.stack.len();
+        let mut pc = 0;
+        loop {
+            match self.run(&mut pc, base, stack_frame)? {
+                Exit::Return => return Ok(Flow::Return),
+                Exit::Call(index) => Operation::Call(index).execute(stack_frame)?,
+                Exit::CallIndirect(index) => Operation::CallIndirect(index, false).execute(stack_frame)?,
+            };
+        }
+    }
+}
+
+impl CompiledBody {
+    /// Runs from `pc` until the body returns or makes a call. Calls are left to the caller, which
+    /// resumes the body afterwards with `pc` already pointing past the call.
+    ///
+    /// `base` is the height of the operand stack when the body was entered.
+    pub fn run(&self, pc: &mut usize, base: usize, stack_frame: &mut StackFrame) -> Result<Exit, Trap> {
+        let instructions = &self.instructions[..];
+        while *pc < instructions.len() {
+            stack_frame.data.fuel.charge(&instructions[*pc])?;
+            match instructions[*pc] {
+                Instruction::Op(ref operation) => {
+                    operation.execute(stack_frame)?;
+                },
+                Instruction::Jump(target) => {
+                    *pc = target;
+                    continue;
+                },
+                Instruction::JumpUnless(target) => {
+                    if Block::pop_condition(stack_frame.stack)? == 0 {
+                        *pc = target;
+                        continue;
+                    }
+                },
+                Instruction::Branch(ref target) => {
+                    Block::unwind(stack_frame.stack, base + target.height, target.arity)?;
+                    *pc = target.pc;
+                    continue;
+                },
+                Instruction::BranchIf(ref target) => {
+                    if Block::pop_condition(stack_frame.stack)? != 0 {
+                        Block::unwind(stack_frame.stack, base + target.height, target.arity)?;
+                        *pc = target.pc;
+                        continue;
+                    }
+                },
+                Instruction::BranchTable(ref targets, ref default) => {
+                    let index = Block::pop_condition(stack_frame.stack)? as u32 as usize;
+                    let target = targets.get(index).unwrap_or(default);
+                    Block::unwind(stack_frame.stack, base + target.height, target.arity)?;
+                    *pc = target.pc;
+                    continue;
+                },
+                Instruction::Return => return Ok(Exit::Return),
+                Instruction::Call(index) => {
+                    *pc += 1;
+                    return Ok(Exit::Call(index));
+                },
+                Instruction::CallIndirect(index) => {
+                    *pc += 1;
+                    return Ok(Exit::CallIndirect(index));
+                },
+            }
+            *pc += 1;
+        }
+        Ok(Exit::Return)
+    }
+}
 
-        println!("Executing operations");
-        let stack_size = stack_frame.stack.len();
+impl BlockType {
+    /// How many values a block of this type takes off the stack and how many it leaves there.
+    pub fn arity(&self, types: &[TypeDefinition]) -> Result<(usize, usize), Trap> {
+        match *self {
+            BlockType::Empty => Ok((0, 0)),
+            BlockType::Value(_) => Ok((0, 1)),
+            BlockType::Type(index) => match types.get(index) {
+                Some(&TypeDefinition::Func(ref signature)) => Ok((signature.parameters.len(), signature.returns.len())),
+                None => Err(Trap::UndefinedType(index))
+            }
+        }
+    }
+}
+
+impl Block {
+    // Runs `operations` under a new label, with the top `parameters` values as the block's own. A
+    // branch to that label leaves its `arity` results on top of the stack as it was below those,
+    // branches further out are passed up a level.
+    fn enter(operations: &[Operation], parameters: usize, arity: usize, stack_frame: &mut StackFrame) -> Result<Flow, Trap> {
+        let height = Block::base(stack_frame.stack, parameters)?;
+        match Block::execute_operations(operations, stack_frame)? {
+            Flow::Branch(0) => {
+                Block::unwind(stack_frame.stack, height, arity)?;
+                Ok(Flow::Continue)
+            },
+            Flow::Branch(depth) => Ok(Flow::Branch(depth - 1)),
+            flow => Ok(flow)
+        }
+    }
+
+    // The height of the stack below a block's parameters
+    fn base(stack: &[ValueTypeProvider], parameters: usize) -> Result<usize, Trap> {
+        match stack.len().checked_sub(parameters) {
+            Some(height) => Ok(height),
+            None => Err(Trap::StackUnderflow)
+        }
+    }
+
+    fn pop_condition(stack: &mut Vec<ValueTypeProvider>) -> Result<i32, Trap> {
+        match stack.pop() {
+            Some(ValueTypeProvider::I32(value)) => Ok(value),
+            Some(_) => Err(Trap::TypeMismatch),
+            None => Err(Trap::StackUnderflow)
+        }
+    }
+
+    fn unwind(stack: &mut Vec<ValueTypeProvider>, height: usize, arity: usize) -> Result<(), Trap> {
+        if stack.len() < height + arity {
+            return Err(Trap::StackUnderflow);
+        }
+        let top = stack.len() - arity;
+        stack.drain(height..top);
+        Ok(())
+    }
+
+    fn execute_operations(operations: &[Operation], stack_frame: &mut StackFrame) -> Result<Flow, Trap> {
+        trace!("Executing operations");
+        for operation in operations {
+            match *operation {
+                Operation::Else | Operation::End => break,
+                _ => {}
+            }
+            match operation.execute(stack_frame)? {
+                Flow::Continue => {},
+                flow => return Ok(flow)
+            }
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+impl Execute for Operation {
+    fn execute(&self, stack_frame: &mut StackFrame) -> Result<Flow, Trap> {
+
+        macro_rules! pop {
+            () => {
+                match stack_frame.stack.pop() {
+                    Some(value) => value,
+                    None => return Err(Trap::StackUnderflow)
+                }
+            };
+
+            ($t:ident) => {
+                match pop!() {
+                    ValueTypeProvider::$t(value) => value,
+                    _ => return Err(Trap::TypeMismatch)
+                }
+            };
+        }
 
         macro_rules! op {
             ($($a:ident:$b:ident),* | $r:ident => $op:expr) => {
-                op!(@a stack_frame.stack.push(ValueTypeProvider::$r($op)), $($a:$b),*);
+                op!(@a stack_frame.stack.push(ValueTypeProvider::$r($op)), $($a:$b),*)
             };
 
             ($($a:ident:$b:ident),* | @bool => $op:expr) => {
-                op!(@a stack_frame.stack.push(ValueTypeProvider::I32($op as i32)), $($a:$b),*);
+                op!(@a stack_frame.stack.push(ValueTypeProvider::I32($op as i32)), $($a:$b),*)
             };
 
             ($($a:ident:$b:ident),* | @any => $op:expr) => {
-                op!(@a stack_frame.stack.push($op), $($a:$b),*);
+                op!(@a stack_frame.stack.push($op), $($a:$b),*)
             };
 
-            (@a $op:expr, $hn:ident:$ht:ident, $($a:ident:$b:ident),*) => {
-                if let Some(ValueTypeProvider::$ht($hn)) = stack_frame.stack.pop() {
-                    op!(@a $op, $($a:$b),*);
-                }
-            };
+            // Operands are named in the order they were pushed, so the last one is popped first
+            (@a $op:expr, $a:ident:$b:ident) => {{
+                let $a = pop!($b);
+                $op;
+            }};
 
-            (@a $op:expr, $hn:ident:$ht:ident) => {
-                if let Some(ValueTypeProvider::$ht($hn)) = stack_frame.stack.pop() {
-                    $op;
+            (@a $op:expr, $a:ident:$b:ident, $c:ident:$d:ident) => {{
+                let $c = pop!($d);
+                let $a = pop!($b);
+                $op;
+            }};
+        }
+
+        macro_rules! memory {
+            () => {
+                match stack_frame.data.memories.get(0) {
+                    Some(memory) => memory.borrow_mut(),
+                    None => return Err(Trap::MemoryOutOfBounds)
                 }
             };
         }
@@ -88,19 +285,19 @@ impl Execute for Block {
             // mem_op!(I32(i32) => mem);
 
             (@i $a:expr => $b:ident($c:ty,$d:ty)) => {
-                let offset = ($a.flags + $a.offset) as usize;
-                let size = mem::size_of::<$c>() as usize;
-                let mut a = &stack_frame.data.memories[0].values[offset..offset+8];
-                let value = a.read_int::<LittleEndian>(size).unwrap() as $d;
-                stack_frame.stack.push(ValueTypeProvider::$b(value));
+                let size = mem::size_of::<$c>();
+                let base = pop!(I32);
+                let memory = memory!();
+                let bytes = memory.slice($a, base, size)?;
+                stack_frame.stack.push(ValueTypeProvider::$b(LittleEndian::read_int(bytes, size) as $d));
             };
 
             (@u $a:expr => $b:ident($c:ty,$d:ty)) => {
-                let offset = ($a.flags + $a.offset) as usize;
-                let size = mem::size_of::<$c>() as usize;
-                let mut a = &stack_frame.data.memories[0].values[offset..offset+8];
-                let value = a.read_uint::<LittleEndian>(size).unwrap() as $d;
-                stack_frame.stack.push(ValueTypeProvider::$b(value));
+                let size = mem::size_of::<$c>();
+                let base = pop!(I32);
+                let memory = memory!();
+                let bytes = memory.slice($a, base, size)?;
+                stack_frame.stack.push(ValueTypeProvider::$b(LittleEndian::read_uint(bytes, size) as $d));
             };
 
             ($a:expr => $b:ident($c:ty)) => {
@@ -108,405 +305,391 @@ impl Execute for Block {
             };
 
             ($a:ident($c:ty) => $d:expr) => {
-                if let Some(ValueTypeProvider::$a(value)) = stack_frame.stack.pop() {
-                    let offset = ($d.flags + $d.offset) as usize;
-                    let size = mem::size_of::<$c>() as usize;
-                    let mut a = &mut stack_frame.data.memories[0].values[offset..offset+8];
-                    a.write_int::<LittleEndian>(value as $c as i64, size);
-                } else {
-                    panic!("VTP was wrong type or not present!");
-                }
+                let value = pop!($a);
+                let size = mem::size_of::<$c>();
+                let base = pop!(I32);
+                let mut memory = memory!();
+                let bytes = memory.slice_mut($d, base, size)?;
+                LittleEndian::write_int(bytes, value as $c as i64, size);
             };
 
         }
 
+        // Float to int conversions trap rather than saturate, bounds are exclusive
+        macro_rules! trunc {
+            ($a:ident, $min:expr, $max:expr => $convert:expr) => {{
+                if $a.is_nan() {
+                    return Err(Trap::InvalidConversionToInteger);
+                }
+                if $a <= $min || $a >= $max {
+                    return Err(Trap::IntegerOverflow);
+                }
+                $convert
+            }};
+        }
+
         macro_rules! wasm_if {
             ($truthy:expr) => {
                 wasm_if!($truthy, {});
             };
 
             ($truthy:expr, $falsey:expr) => {
-                if let Some(ValueTypeProvider::I32(i)) = stack_frame.stack.pop() {
-                    println!("{:?}", i);
-                    if i != 0 {
-                        println!("truthy {:?}", i);
-                        $truthy;
-                    } else {
-                        println!("falsey {:?}", i);
-                        $falsey;
-                    }
+                let i = pop!(I32);
+                if i != 0 {
+                    $truthy;
                 } else {
-                    panic!("if statement didn't have value present to check");
+                    $falsey;
                 }
             };
         }
 
-        for operation in &(self.operations) {
-            println!("{:?} {:?}", stack_frame.stack, operation);
-            match *operation {
-                Operation::Unreachable => panic!("Unreachable code executed"),
-                Operation::Nop => {},
-                Operation::Block(ref b) => {
-                    let x = b.execute(stack_frame);
-                    if x != 0 { return x-1}
-                },
-                Operation::Loop(ref b) => {
-                    let mut x = 0;
-                    while x == 0 {
-                        x = b.execute(stack_frame);
-                    }
-                    if x-1 != 0 {return x-1}
-                },
-                Operation::If(ref b) => {wasm_if!({
-                    b.execute(stack_frame);
-                }, {
-                    // TODO this searches the entirety of the program for another else
-                    // It probably also bugs out if there is a later else part of a different if/else
-                    if let Some(index) = b.operations.iter().position(|r|r == &Operation::Else){
-                        Block {
-                            block_type: b.block_type.clone(),
-                            operations: b.operations.clone().split_off(index+1)
-                        }.execute(stack_frame);
-                    }
-                });},
-                Operation::Else => {break},
-                Operation::End => {break},
-                Operation::Branch(b) => {return b},
-                Operation::BranchIf(b) => {wasm_if!({return b});},
-                Operation::BranchTable(ref b) => {
-                    if let Some(ValueTypeProvider::I32(index)) = stack_frame.stack.pop() {
-                        let depth = if (index as usize) < b.targets.len() {
-                            b.targets[index as usize]-1
-                        } else {
-                            b.default
-                        };
-                        return depth;
-                    }
-                },
-                Operation::Call(index) => {
-                    let data = &mut stack_frame.data;
-                    let function = data.functions.get(index).unwrap();
-                    let mut args = vec![];
-                    for param in &(function.signature.parameters) {
-                        match *param {
-                            ValueType::I32 => {
-                                if let Some(ValueTypeProvider::I32(v)) = stack_frame.stack.pop() {
-                                    args.push(ValueTypeProvider::I32(v));
-                                } else {
-                                    panic!("wrong argument type");
-                                }
-                            },
-                            ValueType::I64 => {
-                                if let Some(ValueTypeProvider::I64(v)) = stack_frame.stack.pop() {
-                                    args.push(ValueTypeProvider::I64(v));
-                                } else {
-                                    panic!("wrong argument type");
-                                }
-                            },
-                            ValueType::F32 => {
-                                if let Some(ValueTypeProvider::F32(v)) = stack_frame.stack.pop() {
-                                    args.push(ValueTypeProvider::F32(v));
-                                } else {
-                                    panic!("wrong argument type");
-                                }
-                            },
-                            ValueType::F64 => {
-                                if let Some(ValueTypeProvider::F64(v)) = stack_frame.stack.pop() {
-                                    args.push(ValueTypeProvider::F64(v));
-                                } else {
-                                    panic!("wrong argument type");
-                                }
-                            },
-                        }
-                    }
-                    println!("{:?}", function.signature);
-                    for ValueTypeProvider in (function.callable)(data, args) {
-                        stack_frame.stack.push(ValueTypeProvider);
-                    }
-                },
-                Operation::Return => {return -1;}, //TODO FIX THIS !! TODO TODO TODO
-                Operation::CallIndirect(idx, _) => {
-                    let data = &mut stack_frame.data;
-                    let TypeDefinition::Func(ref signature) = data.types[idx].clone();
-                    let mut args = vec![];
-                    for param in &(signature.parameters) {
-                        match *param {
-                            ValueType::I32 => {
-                                if let Some(ValueTypeProvider::I32(v)) = stack_frame.stack.pop() {
-                                    args.push(ValueTypeProvider::I32(v));
-                                } else {
-                                    panic!("wrong argument type");
-                                }
-                            },
-                            ValueType::I64 => {
-                                if let Some(ValueTypeProvider::I64(v)) = stack_frame.stack.pop() {
-                                    args.push(ValueTypeProvider::I64(v));
-                                } else {
-                                    panic!("wrong argument type");
-                                }
-                            },
-                            ValueType::F32 => {
-                                if let Some(ValueTypeProvider::F32(v)) = stack_frame.stack.pop() {
-                                    args.push(ValueTypeProvider::F32(v));
-                                } else {
-                                    panic!("wrong argument type");
-                                }
-                            },
-                            ValueType::F64 => {
-                                if let Some(ValueTypeProvider::F64(v)) = stack_frame.stack.pop() {
-                                    args.push(ValueTypeProvider::F64(v));
-                                } else {
-                                    panic!("wrong argument type");
-                                }
-                            },
-                        }
-                    }
-                    if let Some(ValueTypeProvider::I32(index)) = stack_frame.stack.pop() {
-                        let fn_index = {
-                            let &Table::AnyFunc{ref limits, ref values} = &(data.tables)[0];
-                            values.get(index as usize).unwrap().clone()
-                        };
-                        let callable = &data.functions.get(fn_index).unwrap().callable;
-                        for ValueTypeProvider in callable(data, args) {
-                            stack_frame.stack.push(ValueTypeProvider);
-                        }
-                    } else {
-                        panic!("function not found or not indexed by i32");
-                    }
-                },
-                Operation::Drop => {stack_frame.stack.pop();},
-                Operation::Select => {
-                    let a = stack_frame.stack.pop().unwrap();
-                    let b = stack_frame.stack.pop().unwrap();
-                    if mem::discriminant(&a) != mem::discriminant(&b) {
-                        panic!("ValueTypeProvider must be the same for 'a' and 'b' in 'select' op!")
+        trace!("{:?} {:?}", stack_frame.stack, self);
+        match *self {
+            Operation::Unreachable => return Err(Trap::Unreachable),
+            Operation::Nop => {},
+            Operation::Block(ref b) => {
+                match b.execute(stack_frame)? {
+                    Flow::Continue => {},
+                    flow => return Ok(flow)
+                }
+            },
+            Operation::Loop(ref b) => {
+                // A branch to a loop label carries the loop's parameters and restarts it
+                let (parameters, _) = b.block_type.arity(&stack_frame.data.types)?;
+                let height = Block::base(stack_frame.stack, parameters)?;
+                loop {
+                    match Block::execute_operations(&b.operations, stack_frame)? {
+                        Flow::Branch(0) => Block::unwind(stack_frame.stack, height, parameters)?,
+                        Flow::Branch(depth) => return Ok(Flow::Branch(depth - 1)),
+                        Flow::Continue => break,
+                        Flow::Return => return Ok(Flow::Return)
                     }
-                    wasm_if!(stack_frame.stack.push(a), stack_frame.stack.push(b));
-                },
-                Operation::GetLocal(idx) => stack_frame.stack.push(stack_frame.locals[idx].clone()),
-                Operation::SetLocal(idx) => {
-                    if let Some(vtp) = stack_frame.stack.pop() {
-                        if mem::discriminant(&stack_frame.locals[idx]) == mem::discriminant(&vtp) {
-                            stack_frame.locals[idx] = vtp;
-                        } else {
-                            panic!("Wrong type provided for local set");
+                }
+            },
+            Operation::If(ref b) => {
+                // Only the if's own `else` is at this level, nested ones live in their own blocks
+                let operations = &b.operations[..];
+                let (then, otherwise) = match operations.iter().position(|op| *op == Operation::Else) {
+                    Some(index) => (&operations[..index], &operations[index+1..]),
+                    None => (operations, &operations[operations.len()..])
+                };
+                let branch = if pop!(I32) != 0 { then } else { otherwise };
+                let (parameters, arity) = b.block_type.arity(&stack_frame.data.types)?;
+                match Block::enter(branch, parameters, arity, stack_frame)? {
+                    Flow::Continue => {},
+                    flow => return Ok(flow)
+                }
+            },
+            Operation::Else => return Ok(Flow::Continue),
+            Operation::End => return Ok(Flow::Continue),
+            Operation::Branch(depth) => return Ok(Flow::Branch(depth as u32)),
+            Operation::BranchIf(depth) => {wasm_if!({return Ok(Flow::Branch(depth as u32))});},
+            Operation::BranchTable(ref b) => {
+                let index = pop!(I32) as u32 as usize;
+                let depth = *b.targets.get(index).unwrap_or(&b.default);
+                return Ok(Flow::Branch(depth as u32));
+            },
+            Operation::Call(index) => {
+                let function = interpreter::function(stack_frame.data.functions, index)?;
+                let args = interpreter::pop_args(stack_frame.stack, &function.signature.parameters)?;
+                let results = interpreter::invoke(stack_frame.data, index, args)?;
+                stack_frame.stack.extend(results);
+            },
+            Operation::Return => return Ok(Flow::Return),
+            Operation::CallIndirect(idx, _) => {
+                let index = interpreter::resolve_indirect(stack_frame.data, idx, stack_frame.stack)?;
+                let function = interpreter::function(stack_frame.data.functions, index)?;
+                let args = interpreter::pop_args(stack_frame.stack, &function.signature.parameters)?;
+                let results = interpreter::invoke(stack_frame.data, index, args)?;
+                stack_frame.stack.extend(results);
+            },
+            Operation::Drop => {pop!();},
+            Operation::Select => {
+                let condition = pop!(I32);
+                let second = pop!();
+                let first = pop!();
+                if mem::discriminant(&first) != mem::discriminant(&second) {
+                    return Err(Trap::TypeMismatch);
+                }
+                stack_frame.stack.push(if condition != 0 { first } else { second });
+            },
+            Operation::GetLocal(idx) => {
+                let vtp = match stack_frame.locals.get(idx) {
+                    Some(vtp) => vtp.clone(),
+                    None => return Err(Trap::UndefinedLocal(idx))
+                };
+                stack_frame.stack.push(vtp);
+            },
+            Operation::SetLocal(idx) => {
+                let vtp = pop!();
+                match stack_frame.locals.get_mut(idx) {
+                    Some(local) => {
+                        if mem::discriminant(local) != mem::discriminant(&vtp) {
+                            return Err(Trap::TypeMismatch);
                         }
-                    } else {
-                        panic!("no values on stack");
-                    }
-                },
-                Operation::TeeLocal(idx) => {
-                    if let Some(vtp) = stack_frame.stack.pop() {
-                        if mem::discriminant(&stack_frame.locals[idx]) == mem::discriminant(&vtp) {
-                            stack_frame.locals[idx] = vtp.clone();
-                            stack_frame.stack.push(vtp);
-                        } else {
-                            panic!("Wrong type provided for local set");
+                        *local = vtp;
+                    },
+                    None => return Err(Trap::UndefinedLocal(idx))
+                }
+            },
+            Operation::TeeLocal(idx) => {
+                let vtp = pop!();
+                match stack_frame.locals.get_mut(idx) {
+                    Some(local) => {
+                        if mem::discriminant(local) != mem::discriminant(&vtp) {
+                            return Err(Trap::TypeMismatch);
                         }
-                    } else {
-                        panic!("no values on stack");
-                    }
-                },
-                Operation::GetGlobal(idx) => stack_frame.stack.push(stack_frame.data.globals[idx].clone()),
-                Operation::SetGlobal(idx) => {
-                    if let Some(vtp) = stack_frame.stack.pop() {
-                        if mem::discriminant(&stack_frame.data.globals[idx]) == mem::discriminant(&vtp) {
-                            stack_frame.data.globals[idx] = vtp;
-                        } else {
-                            panic!("Wrong type provided for local set");
+                        *local = vtp.clone();
+                    },
+                    None => return Err(Trap::UndefinedLocal(idx))
+                }
+                stack_frame.stack.push(vtp);
+            },
+            Operation::GetGlobal(idx) => {
+                let vtp = match stack_frame.data.globals.get(idx) {
+                    Some(vtp) => vtp.borrow().clone(),
+                    None => return Err(Trap::UndefinedGlobal(idx))
+                };
+                stack_frame.stack.push(vtp);
+            },
+            Operation::SetGlobal(idx) => {
+                let vtp = pop!();
+                match stack_frame.data.globals.get(idx) {
+                    Some(global) => {
+                        let mut global = global.borrow_mut();
+                        if mem::discriminant(&*global) != mem::discriminant(&vtp) {
+                            return Err(Trap::TypeMismatch);
                         }
-                    } else {
-                        panic!("no values on stack");
-                    }
-                },
-                Operation::I32Load(ref mem) => {mem_op!(mem => I32(i32));},
-                Operation::I64Load(ref mem) => {mem_op!(mem => I64(i64));},
-                Operation::F32Load(ref mem) => {
-                    let offset = (mem.flags + mem.offset) as usize;
-                    let mut a = &stack_frame.data.memories[0].values[offset..offset+8];
-                    let value = a.read_f32::<LittleEndian>().unwrap();
-                    stack_frame.stack.push(ValueTypeProvider::F32(value));
-                },
-                Operation::F64Load(ref mem) => {
-                    let offset = (mem.flags + mem.offset) as usize;
-                    let mut a = &stack_frame.data.memories[0].values[offset..offset+8];
-                    let value = a.read_f64::<LittleEndian>().unwrap();
-                    stack_frame.stack.push(ValueTypeProvider::F64(value));
-                },
-                Operation::I32Load8S(ref mem) => {mem_op!(@i mem => I32(i8,i32));},
-                Operation::I32Load8U(ref mem) => {mem_op!(@u mem => I32(u8,i32));},
-                Operation::I32Load16S(ref mem) => {mem_op!(@i mem => I32(i16,i32));},
-                Operation::I32Load16U(ref mem) => {mem_op!(@u mem => I32(u16,i32));},
-                Operation::I64Load8S(ref mem) => {mem_op!(@i mem => I64(i8,i64));},
-                Operation::I64Load8U(ref mem) => {mem_op!(@u mem => I64(u8,i64));},
-                Operation::I64Load16S(ref mem) => {mem_op!(@i mem => I64(i16,i64));},
-                Operation::I64Load16U(ref mem) => {mem_op!(@u mem => I64(u16,i64));},
-                Operation::I64Load32S(ref mem) => {mem_op!(@i mem => I64(i32,i64));},
-                Operation::I64Load32U(ref mem) => {mem_op!(@u mem => I64(u32,i64));},
-                Operation::I32Store(ref mem) => {mem_op!(I32(i32) => mem);},
-                Operation::I64Store(ref mem) => {mem_op!(I64(i64) => mem);},
-                Operation::F32Store(ref mem) => {
-                    if let Some(ValueTypeProvider::F32(value)) = stack_frame.stack.pop() {
-                        let offset = (mem.flags + mem.offset) as usize;
-                        let size = mem::size_of::<f32>() as usize;
-                        let mut a = &mut stack_frame.data.memories[0].values[offset..offset+8];
-                        a.write_f32::<LittleEndian>(value);
-                    } else {
-                        panic!("VTP was wrong type or not present!");
-                    }
-                },
-                Operation::F64Store(ref mem) => {
-                    if let Some(ValueTypeProvider::F64(value)) = stack_frame.stack.pop() {
-                        let offset = (mem.flags + mem.offset) as usize;
-                        let size = mem::size_of::<f64>() as usize;
-                        let mut a = &mut stack_frame.data.memories[0].values[offset..offset+8];
-                        a.write_f64::<LittleEndian>(value);
-                    } else {
-                        panic!("VTP was wrong type or not present!");
-                    }
-                },
-                Operation::I32Store8(ref mem) => {mem_op!(I32(i8) => mem);},
-                Operation::I32Store16(ref mem) => {mem_op!(I32(i16) => mem);},
-                Operation::I64Store8(ref mem) => {mem_op!(I64(i8) => mem);},
-                Operation::I64Store16(ref mem) => {mem_op!(I64(i16) => mem);},
-                Operation::I64Store32(ref mem) => {mem_op!(I64(i32) => mem);},
-                Operation::CurrentMemory(_) => {
-                    stack_frame.stack.push(ValueTypeProvider::I32(stack_frame.data.memories[0].size()));
-                },
-                Operation::GrowMemory(_) => {
-                    stack_frame.stack.push(ValueTypeProvider::I32(stack_frame.data.memories[0].grow()));
-                },
-                Operation::I32Const(value) => {stack_frame.stack.push(ValueTypeProvider::I32(value))},
-                Operation::I64Const(value) => {stack_frame.stack.push(ValueTypeProvider::I64(value))},
-                Operation::F32Const(value) => {stack_frame.stack.push(ValueTypeProvider::F32(value))},
-                Operation::F64Const(value) => {stack_frame.stack.push(ValueTypeProvider::F64(value))},
-                Operation::I32Eqz => {op!(a:I32 | @bool => a==0)},
-                Operation::I32Eq => {op!(a:I32,b:I32 | @bool => a==b)},
-                Operation::I32Ne => {op!(a:I32, b:I32 | @bool => a!=b)},
-                Operation::I32LtS => {op!(a:I32, b:I32 | @bool => a<b)},
-                Operation::I32LtU => {op!(a:I32, b:I32 | @bool => (a as u32) < (b as u32))},
-                Operation::I32GtS => {op!(a:I32, b:I32 | @bool => a>b)},
-                Operation::I32GtU => {op!(a:I32, b:I32 | @bool => (a as u32) > (b as u32))},
-                Operation::I32LeS => {op!(a:I32, b:I32 | @bool => a<=b)},
-                Operation::I32LeU => {op!(a:I32, b:I32 | @bool => (a as u32) <= (b as u32))},
-                Operation::I32GeS => {op!(a:I32, b:I32 | @bool => a>=b)},
-                Operation::I32GeU => {op!(a:I32, b:I32 | @bool => (a as u32) >= (b as u32))},
-                Operation::I64Eqz => {op!(a:I64 | @bool => a==0)},
-                Operation::I64Eq => {op!(a:I64, b:I64 | @bool => a==b)},
-                Operation::I64Ne => {op!(a:I64, b:I64 | @bool => a!=b)},
-                Operation::I64LtS => {op!(a:I64, b:I64 | @bool => a<b)},
-                Operation::I64LtU => {op!(a:I64, b:I64 | @bool => (a as u32) < (b as u32))},
-                Operation::I64GtS => {op!(a:I64, b:I64 | @bool => a>b)},
-                Operation::I64GtU => {op!(a:I64, b:I64 | @bool => (a as u32) > (b as u32))},
-                Operation::I64LeS => {op!(a:I64, b:I64 | @bool => a<=b)},
-                Operation::I64LeU => {op!(a:I64, b:I64 | @bool => (a as u32) <= (b as u32))},
-                Operation::I64GeS => {op!(a:I64, b:I64 | @bool => a>=b)},
-                Operation::I64GeU => {op!(a:I64, b:I64 | @bool => (a as u32) >= (b as u32))},
-                Operation::F32Eq => {op!(a:F32, b:F32 | @bool => a==b)},
-                Operation::F32Ne => {op!(a:F32, b:F32 | @bool => a!=b)},
-                Operation::F32Lt => {op!(a:F32, b:F32 | @bool => a<b)},
-                Operation::F32Gt => {op!(a:F32, b:F32 | @bool => a>b)},
-                Operation::F32Le => {op!(a:F32, b:F32 | @bool => a<=b)},
-                Operation::F32Ge => {op!(a:F32, b:F32 | @bool => a>=b)},
-                Operation::F64Eq => {op!(a:F64, b:F64 | @bool => a==b)},
-                Operation::F64Ne => {op!(a:F64, b:F64 | @bool => a!=b)},
-                Operation::F64Lt => {op!(a:F64, b:F64 | @bool => a<b)},
-                Operation::F64Gt => {op!(a:F64, b:F64 | @bool => a>b)},
-                Operation::F64Le => {op!(a:F64, b:F64 | @bool => a<=b)},
-                Operation::F64Ge => {op!(a:F64, b:F64 | @bool => a>=b)},
-                Operation::I32Clz => {op!(a:I32 | I32 => a.leading_zeros() as i32)},
-                Operation::I32Ctz => {op!(a:I32 | I32 => a.trailing_zeros() as i32)},
-                Operation::I32Popcnt => {op!(a:I32 | I32 => a.count_ones() as i32)},
-                Operation::I32Add => {op!(a:I32, b:I32 | I32 => a+b)}
-                Operation::I32Sub => {op!(a:I32, b:I32 | I32 => a - b)},
-                Operation::I32Mul => {op!(a:I32, b:I32 | I32 => a * b)},
-                Operation::I32DivS => {op!(a:I32, b:I32 | I32 => a / b)},
-                Operation::I32DivU => {op!(a:I32, b:I32 | I32 => ((a as u32) / (b as u32)) as i32)},
-                Operation::I32RemS => {op!(a:I32, b:I32 | I32 => a % b)},
-                Operation::I32RemU => {op!(a:I32, b:I32 | I32 => ((a as u32) % (b as u32)) as i32)},
-                Operation::I32And => {op!(a:I32, b:I32 | I32 => a & b)},
-                Operation::I32Or => {op!(a:I32, b:I32 | I32 => a | b)},
-                Operation::I32Xor => {op!(a:I32, b:I32 | I32 => a ^ b)},
-                Operation::I32Shl => {op!(a:I32, b:I32 | I32 => a << b)},
-                Operation::I32ShrS => {op!(a:I32, b:I32 | I32 => a >> b)},
-                Operation::I32ShrU => {op!(a:I32, b:I32 | I32 => ((a as u32) >> (b as u32)) as i32)},
-                Operation::I32Rotl => {op!(a:I32, b:I32 | I32 => a.rotate_left(b as u32))},
-                Operation::I32Rotr => {op!(a:I32, b:I32 | I32 => a.rotate_right(b as u32))},
-                Operation::I64Clz => {op!(a:I64 | I32 => a.leading_zeros() as i32)},
-                Operation::I64Ctz => {op!(a:I64 | I32 => a.trailing_zeros() as i32)},
-                Operation::I64Popcnt => {op!(a:I64 | I32 => a.count_ones() as i32)},
-                Operation::I64Add => {op!(a:I64, b:I64 | I64 => a+b)}
-                Operation::I64Sub => {op!(a:I64, b:I64 | I64 => a - b)},
-                Operation::I64Mul => {op!(a:I64, b:I64 | I64 => a * b)},
-                Operation::I64DivS => {op!(a:I64, b:I64 | I64 => a / b)},
-                Operation::I64DivU => {op!(a:I64, b:I64 | I64 => ((a as u64) / (b as u64)) as i64)},
-                Operation::I64RemS => {op!(a:I64, b:I64 | I64 => a % b)},
-                Operation::I64RemU => {op!(a:I64, b:I64 | I64 => ((a as u64) % (b as u64)) as i64)},
-                Operation::I64And => {op!(a:I64, b:I64 | I64 => a & b)},
-                Operation::I64Or => {op!(a:I64, b:I64 | I64 => a | b)},
-                Operation::I64Xor => {op!(a:I64, b:I64 | I64 => a ^ b)},
-                Operation::I64Shl => {op!(a:I64, b:I64 | I64 => a << b)},
-                Operation::I64ShrS => {op!(a:I64, b:I64 | I64 => a >> b)},
-                Operation::I64ShrU => {op!(a:I64, b:I64 | I64 => ((a as u64) >> (b as u64)) as i64)},
-                Operation::I64Rotl => {op!(a:I64, b:I64 | I64 => a.rotate_left(b as u32))},
-                Operation::I64Rotr => {op!(a:I64, b:I64 | I64 => a.rotate_right(b as u32))},
-                Operation::F32Abs => {op!(a:F32 | F32 => a.abs())},
-                Operation::F32Neg => {op!(a:F32 | F32 => -a)},
-                Operation::F32Ceil => {op!(a:F32 | F32 => a.ceil())},
-                Operation::F32Floor => {op!(a:F32 | F32 => a.floor())},
-                Operation::F32Trunc => {op!(a:F32 | F32 => a.trunc())},
-                Operation::F32Nearest => {op!(a:F32 | F32 => a.round())},
-                Operation::F32Sqrt => {op!(a:F32 | F32 => a.sqrt())},
-                Operation::F32Add => {op!(a:F32, b:F32 | F32 => a+b)},
-                Operation::F32Sub => {op!(a:F32, b:F32 | F32 => a-b)},
-                Operation::F32Mul => {op!(a:F32, b:F32 | F32 => a*b)},
-                Operation::F32Div => {op!(a:F32, b:F32 | F32 => a/b)},
-                Operation::F32Min => {op!(a:F32, b:F32 | F32 => a.min(b))},
-                Operation::F32Max => {op!(a:F32, b:F32 | F32 => a.max(b))},
-                Operation::F32Copysign => {op!(a:F32, b:F32 | F32 => a.signum() * b)},
-                Operation::F64Abs => {op!(a:F64 | F64 => a.abs())},
-                Operation::F64Neg => {op!(a:F64 | F64 => -a)},
-                Operation::F64Ceil => {op!(a:F64 | F64 => a.ceil())},
-                Operation::F64Floor => {op!(a:F64 | F64 => a.floor())},
-                Operation::F64Trunc => {op!(a:F64 | F64 => a.trunc())},
-                Operation::F64Nearest => {op!(a:F64 | F64 => a.round())},
-                Operation::F64Sqrt => {op!(a:F64 | F64 => a.sqrt())},
-                Operation::F64Add => {op!(a:F64, b:F64 | F64 => a+b)},
-                Operation::F64Sub => {op!(a:F64, b:F64 | F64 => a-b)},
-                Operation::F64Mul => {op!(a:F64, b:F64 | F64 => a*b)},
-                Operation::F64Div => {op!(a:F64, b:F64 | F64 => a/b)},
-                Operation::F64Min => {op!(a:F64, b:F64 | F64 => a.min(b))},
-                Operation::F64Max => {op!(a:F64, b:F64 | F64 => a.max(b))},
-                Operation::F64Copysign => {op!(a:F64, b:F64 | F64 => a.signum() * b)},
-                Operation::I32WrapI64 => {op!(a:I64 | I32 => a as i32)},
-                Operation::I32TruncSF32 => {op!(a:F32 | I32 => a as i32)},
-                Operation::I32TruncUF32 => {op!(a:F32 | I32 => a as u32 as i32)},
-                Operation::I32TruncSF64 => {op!(a:F64 | I32 => a as i32)},
-                Operation::I32TruncUF64 => {op!(a:F64 | I32 => a as i32)},
-                Operation::I64ExtendSI32 => {op!(a:I32 | I64 => a as i64)},
-                Operation::I64ExtendUI32 => {op!(a:I32 | I64 => (a as u32) as i64)},
-                Operation::I64TruncSF32 => {op!(a:F32 | I64 => a as i64)},
-                Operation::I64TruncUF32 => {op!(a:F32 | I64 => a as i64)},
-                Operation::I64TruncSF64 => {op!(a:F64 | I64 => a as i64)},
-                Operation::I64TruncUF64 => {op!(a:F64 | I64 => a as i64)},
-                Operation::F32ConvertSI32 => {op!(a:I32 | F32 => a as f32)},
-                Operation::F32ConvertUI32 => {op!(a:I32 | F32 => (a as u32) as f32)},
-                Operation::F32ConvertSI64 => {op!(a:I64 | F32 => a as f32)},
-                Operation::F32ConvertUI64 => {op!(a:I64 | F32 => (a as u64) as f32)},
-                Operation::F32DemoteF64 => {op!(a:F64 | F32 => a as f32)},
-                Operation::F64ConvertSI32 => {op!(a:I32 | F64 => a as f64)},
-                Operation::F64ConvertUI32 => {op!(a:I32 | F64 => (a as u32) as f64)},
-                Operation::F64ConvertSI64 => {op!(a:I64 | F64 => a as f64)},
-                Operation::F64ConvertUI64 => {op!(a:I64 | F64 => (a as u64) as f64)},
-                Operation::F64PromoteF32 => {op!(a:F32 | F64 => a as f64)},
-                Operation::I32ReinterpretF32 => {op!(a:F32 | I32 => a.to_bits() as i32)},
-                Operation::I64ReinterpretF64 => {op!(a:F64 | I64 => a.to_bits() as i64)},
-                Operation::F32ReinterpretI32 => {op!(a:I32 | F32 => f32::from_bits(a as u32))},
-                Operation::F64ReinterpretI64 => {op!(a:I64 | F64 => f64::from_bits(a as u64))}
-
-            }
-        }
-        0
+                        *global = vtp;
+                    },
+                    None => return Err(Trap::UndefinedGlobal(idx))
+                }
+            },
+            Operation::I32Load(ref mem) => {mem_op!(mem => I32(i32));},
+            Operation::I64Load(ref mem) => {mem_op!(mem => I64(i64));},
+            Operation::F32Load(ref mem) => {
+                let base = pop!(I32);
+                let value = LittleEndian::read_f32(memory!().slice(mem, base, 4)?);
+                stack_frame.stack.push(ValueTypeProvider::F32(value));
+            },
+            Operation::F64Load(ref mem) => {
+                let base = pop!(I32);
+                let value = LittleEndian::read_f64(memory!().slice(mem, base, 8)?);
+                stack_frame.stack.push(ValueTypeProvider::F64(value));
+            },
+            Operation::I32Load8S(ref mem) => {mem_op!(@i mem => I32(i8,i32));},
+            Operation::I32Load8U(ref mem) => {mem_op!(@u mem => I32(u8,i32));},
+            Operation::I32Load16S(ref mem) => {mem_op!(@i mem => I32(i16,i32));},
+            Operation::I32Load16U(ref mem) => {mem_op!(@u mem => I32(u16,i32));},
+            Operation::I64Load8S(ref mem) => {mem_op!(@i mem => I64(i8,i64));},
+            Operation::I64Load8U(ref mem) => {mem_op!(@u mem => I64(u8,i64));},
+            Operation::I64Load16S(ref mem) => {mem_op!(@i mem => I64(i16,i64));},
+            Operation::I64Load16U(ref mem) => {mem_op!(@u mem => I64(u16,i64));},
+            Operation::I64Load32S(ref mem) => {mem_op!(@i mem => I64(i32,i64));},
+            Operation::I64Load32U(ref mem) => {mem_op!(@u mem => I64(u32,i64));},
+            Operation::I32Store(ref mem) => {mem_op!(I32(i32) => mem);},
+            Operation::I64Store(ref mem) => {mem_op!(I64(i64) => mem);},
+            Operation::F32Store(ref mem) => {
+                let value = pop!(F32);
+                let base = pop!(I32);
+                LittleEndian::write_f32(memory!().slice_mut(mem, base, 4)?, value);
+            },
+            Operation::F64Store(ref mem) => {
+                let value = pop!(F64);
+                let base = pop!(I32);
+                LittleEndian::write_f64(memory!().slice_mut(mem, base, 8)?, value);
+            },
+            Operation::I32Store8(ref mem) => {mem_op!(I32(i8) => mem);},
+            Operation::I32Store16(ref mem) => {mem_op!(I32(i16) => mem);},
+            Operation::I64Store8(ref mem) => {mem_op!(I64(i8) => mem);},
+            Operation::I64Store16(ref mem) => {mem_op!(I64(i16) => mem);},
+            Operation::I64Store32(ref mem) => {mem_op!(I64(i32) => mem);},
+            Operation::CurrentMemory(_) => {
+                let size = memory!().size();
+                stack_frame.stack.push(ValueTypeProvider::I32(size as i32));
+            },
+            Operation::GrowMemory(_) => {
+                let delta = pop!(I32) as u32;
+                let limiter = stack_frame.data.memory_limiter;
+                let previous = memory!().grow_limited(delta, limiter);
+                stack_frame.stack.push(ValueTypeProvider::I32(previous));
+            },
+            Operation::I32Const(value) => {stack_frame.stack.push(ValueTypeProvider::I32(value))},
+            Operation::I64Const(value) => {stack_frame.stack.push(ValueTypeProvider::I64(value))},
+            Operation::F32Const(value) => {stack_frame.stack.push(ValueTypeProvider::F32(value))},
+            Operation::F64Const(value) => {stack_frame.stack.push(ValueTypeProvider::F64(value))},
+            Operation::I32Eqz => {op!(a:I32 | @bool => a==0)},
+            Operation::I32Eq => {op!(a:I32,b:I32 | @bool => a==b)},
+            Operation::I32Ne => {op!(a:I32, b:I32 | @bool => a!=b)},
+            Operation::I32LtS => {op!(a:I32, b:I32 | @bool => a<b)},
+            Operation::I32LtU => {op!(a:I32, b:I32 | @bool => (a as u32) < (b as u32))},
+            Operation::I32GtS => {op!(a:I32, b:I32 | @bool => a>b)},
+            Operation::I32GtU => {op!(a:I32, b:I32 | @bool => (a as u32) > (b as u32))},
+            Operation::I32LeS => {op!(a:I32, b:I32 | @bool => a<=b)},
+            Operation::I32LeU => {op!(a:I32, b:I32 | @bool => (a as u32) <= (b as u32))},
+            Operation::I32GeS => {op!(a:I32, b:I32 | @bool => a>=b)},
+            Operation::I32GeU => {op!(a:I32, b:I32 | @bool => (a as u32) >= (b as u32))},
+            Operation::I64Eqz => {op!(a:I64 | @bool => a==0)},
+            Operation::I64Eq => {op!(a:I64, b:I64 | @bool => a==b)},
+            Operation::I64Ne => {op!(a:I64, b:I64 | @bool => a!=b)},
+            Operation::I64LtS => {op!(a:I64, b:I64 | @bool => a<b)},
+            Operation::I64LtU => {op!(a:I64, b:I64 | @bool => (a as u64) < (b as u64))},
+            Operation::I64GtS => {op!(a:I64, b:I64 | @bool => a>b)},
+            Operation::I64GtU => {op!(a:I64, b:I64 | @bool => (a as u64) > (b as u64))},
+            Operation::I64LeS => {op!(a:I64, b:I64 | @bool => a<=b)},
+            Operation::I64LeU => {op!(a:I64, b:I64 | @bool => (a as u64) <= (b as u64))},
+            Operation::I64GeS => {op!(a:I64, b:I64 | @bool => a>=b)},
+            Operation::I64GeU => {op!(a:I64, b:I64 | @bool => (a as u64) >= (b as u64))},
+            Operation::F32Eq => {op!(a:F32, b:F32 | @bool => a==b)},
+            Operation::F32Ne => {op!(a:F32, b:F32 | @bool => a!=b)},
+            Operation::F32Lt => {op!(a:F32, b:F32 | @bool => a<b)},
+            Operation::F32Gt => {op!(a:F32, b:F32 | @bool => a>b)},
+            Operation::F32Le => {op!(a:F32, b:F32 | @bool => a<=b)},
+            Operation::F32Ge => {op!(a:F32, b:F32 | @bool => a>=b)},
+            Operation::F64Eq => {op!(a:F64, b:F64 | @bool => a==b)},
+            Operation::F64Ne => {op!(a:F64, b:F64 | @bool => a!=b)},
+            Operation::F64Lt => {op!(a:F64, b:F64 | @bool => a<b)},
+            Operation::F64Gt => {op!(a:F64, b:F64 | @bool => a>b)},
+            Operation::F64Le => {op!(a:F64, b:F64 | @bool => a<=b)},
+            Operation::F64Ge => {op!(a:F64, b:F64 | @bool => a>=b)},
+            Operation::I32Clz => {op!(a:I32 | I32 => a.leading_zeros() as i32)},
+            Operation::I32Ctz => {op!(a:I32 | I32 => a.trailing_zeros() as i32)},
+            Operation::I32Popcnt => {op!(a:I32 | I32 => a.count_ones() as i32)},
+            Operation::I32Add => {op!(a:I32, b:I32 | I32 => a.wrapping_add(b))}
+            Operation::I32Sub => {op!(a:I32, b:I32 | I32 => a.wrapping_sub(b))},
+            Operation::I32Mul => {op!(a:I32, b:I32 | I32 => a.wrapping_mul(b))},
+            Operation::I32DivS => {op!(a:I32, b:I32 | I32 => {
+                if b == 0 {
+                    return Err(Trap::IntegerDivideByZero);
+                }
+                match a.checked_div(b) {
+                    Some(result) => result,
+                    None => return Err(Trap::IntegerOverflow)
+                }
+            })},
+            Operation::I32DivU => {op!(a:I32, b:I32 | I32 => {
+                if b == 0 {
+                    return Err(Trap::IntegerDivideByZero);
+                }
+                ((a as u32) / (b as u32)) as i32
+            })},
+            Operation::I32RemS => {op!(a:I32, b:I32 | I32 => {
+                if b == 0 {
+                    return Err(Trap::IntegerDivideByZero);
+                }
+                a.wrapping_rem(b)
+            })},
+            Operation::I32RemU => {op!(a:I32, b:I32 | I32 => {
+                if b == 0 {
+                    return Err(Trap::IntegerDivideByZero);
+                }
+                ((a as u32) % (b as u32)) as i32
+            })},
+            Operation::I32And => {op!(a:I32, b:I32 | I32 => a & b)},
+            Operation::I32Or => {op!(a:I32, b:I32 | I32 => a | b)},
+            Operation::I32Xor => {op!(a:I32, b:I32 | I32 => a ^ b)},
+            Operation::I32Shl => {op!(a:I32, b:I32 | I32 => a.wrapping_shl(b as u32))},
+            Operation::I32ShrS => {op!(a:I32, b:I32 | I32 => a.wrapping_shr(b as u32))},
+            Operation::I32ShrU => {op!(a:I32, b:I32 | I32 => (a as u32).wrapping_shr(b as u32) as i32)},
+            Operation::I32Rotl => {op!(a:I32, b:I32 | I32 => a.rotate_left(b as u32))},
+            Operation::I32Rotr => {op!(a:I32, b:I32 | I32 => a.rotate_right(b as u32))},
+            Operation::I64Clz => {op!(a:I64 | I32 => a.leading_zeros() as i32)},
+            Operation::I64Ctz => {op!(a:I64 | I32 => a.trailing_zeros() as i32)},
+            Operation::I64Popcnt => {op!(a:I64 | I32 => a.count_ones() as i32)},
+            Operation::I64Add => {op!(a:I64, b:I64 | I64 => a.wrapping_add(b))}
+            Operation::I64Sub => {op!(a:I64, b:I64 | I64 => a.wrapping_sub(b))},
+            Operation::I64Mul => {op!(a:I64, b:I64 | I64 => a.wrapping_mul(b))},
+            Operation::I64DivS => {op!(a:I64, b:I64 | I64 => {
+                if b == 0 {
+                    return Err(Trap::IntegerDivideByZero);
+                }
+                match a.checked_div(b) {
+                    Some(result) => result,
+                    None => return Err(Trap::IntegerOverflow)
+                }
+            })},
+            Operation::I64DivU => {op!(a:I64, b:I64 | I64 => {
+                if b == 0 {
+                    return Err(Trap::IntegerDivideByZero);
+                }
+                ((a as u64) / (b as u64)) as i64
+            })},
+            Operation::I64RemS => {op!(a:I64, b:I64 | I64 => {
+                if b == 0 {
+                    return Err(Trap::IntegerDivideByZero);
+                }
+                a.wrapping_rem(b)
+            })},
+            Operation::I64RemU => {op!(a:I64, b:I64 | I64 => {
+                if b == 0 {
+                    return Err(Trap::IntegerDivideByZero);
+                }
+                ((a as u64) % (b as u64)) as i64
+            })},
+            Operation::I64And => {op!(a:I64, b:I64 | I64 => a & b)},
+            Operation::I64Or => {op!(a:I64, b:I64 | I64 => a | b)},
+            Operation::I64Xor => {op!(a:I64, b:I64 | I64 => a ^ b)},
+            Operation::I64Shl => {op!(a:I64, b:I64 | I64 => a.wrapping_shl(b as u32))},
+            Operation::I64ShrS => {op!(a:I64, b:I64 | I64 => a.wrapping_shr(b as u32))},
+            Operation::I64ShrU => {op!(a:I64, b:I64 | I64 => (a as u64).wrapping_shr(b as u32) as i64)},
+            Operation::I64Rotl => {op!(a:I64, b:I64 | I64 => a.rotate_left(b as u32))},
+            Operation::I64Rotr => {op!(a:I64, b:I64 | I64 => a.rotate_right(b as u32))},
+            Operation::F32Abs => {op!(a:F32 | F32 => a.abs())},
+            Operation::F32Neg => {op!(a:F32 | F32 => -a)},
+            Operation::F32Ceil => {op!(a:F32 | F32 => a.ceil())},
+            Operation::F32Floor => {op!(a:F32 | F32 => a.floor())},
+            Operation::F32Trunc => {op!(a:F32 | F32 => a.trunc())},
+            Operation::F32Nearest => {op!(a:F32 | F32 => a.round())},
+            Operation::F32Sqrt => {op!(a:F32 | F32 => a.sqrt())},
+            Operation::F32Add => {op!(a:F32, b:F32 | F32 => a+b)},
+            Operation::F32Sub => {op!(a:F32, b:F32 | F32 => a-b)},
+            Operation::F32Mul => {op!(a:F32, b:F32 | F32 => a*b)},
+            Operation::F32Div => {op!(a:F32, b:F32 | F32 => a/b)},
+            Operation::F32Min => {op!(a:F32, b:F32 | F32 => a.min(b))},
+            Operation::F32Max => {op!(a:F32, b:F32 | F32 => a.max(b))},
+            Operation::F32Copysign => {op!(a:F32, b:F32 | F32 => a.signum() * b)},
+            Operation::F64Abs => {op!(a:F64 | F64 => a.abs())},
+            Operation::F64Neg => {op!(a:F64 | F64 => -a)},
+            Operation::F64Ceil => {op!(a:F64 | F64 => a.ceil())},
+            Operation::F64Floor => {op!(a:F64 | F64 => a.floor())},
+            Operation::F64Trunc => {op!(a:F64 | F64 => a.trunc())},
+            Operation::F64Nearest => {op!(a:F64 | F64 => a.round())},
+            Operation::F64Sqrt => {op!(a:F64 | F64 => a.sqrt())},
+            Operation::F64Add => {op!(a:F64, b:F64 | F64 => a+b)},
+            Operation::F64Sub => {op!(a:F64, b:F64 | F64 => a-b)},
+            Operation::F64Mul => {op!(a:F64, b:F64 | F64 => a*b)},
+            Operation::F64Div => {op!(a:F64, b:F64 | F64 => a/b)},
+            Operation::F64Min => {op!(a:F64, b:F64 | F64 => a.min(b))},
+            Operation::F64Max => {op!(a:F64, b:F64 | F64 => a.max(b))},
+            Operation::F64Copysign => {op!(a:F64, b:F64 | F64 => a.signum() * b)},
+            Operation::I32WrapI64 => {op!(a:I64 | I32 => a as i32)},
+            Operation::I32TruncSF32 => {op!(a:F32 | I32 => trunc!(a, -2147483904.0, 2147483648.0 => a as i32))},
+            Operation::I32TruncUF32 => {op!(a:F32 | I32 => trunc!(a, -1.0, 4294967296.0 => a as u32 as i32))},
+            Operation::I32TruncSF64 => {op!(a:F64 | I32 => trunc!(a, -2147483649.0, 2147483648.0 => a as i32))},
+            Operation::I32TruncUF64 => {op!(a:F64 | I32 => trunc!(a, -1.0, 4294967296.0 => a as u32 as i32))},
+            Operation::I64ExtendSI32 => {op!(a:I32 | I64 => a as i64)},
+            Operation::I64ExtendUI32 => {op!(a:I32 | I64 => (a as u32) as i64)},
+            Operation::I64TruncSF32 => {op!(a:F32 | I64 => trunc!(a, -9223373136366403584.0, 9223372036854775808.0 => a as i64))},
+            Operation::I64TruncUF32 => {op!(a:F32 | I64 => trunc!(a, -1.0, 18446744073709551616.0 => a as u64 as i64))},
+            Operation::I64TruncSF64 => {op!(a:F64 | I64 => trunc!(a, -9223372036854777856.0, 9223372036854775808.0 => a as i64))},
+            Operation::I64TruncUF64 => {op!(a:F64 | I64 => trunc!(a, -1.0, 18446744073709551616.0 => a as u64 as i64))},
+            Operation::F32ConvertSI32 => {op!(a:I32 | F32 => a as f32)},
+            Operation::F32ConvertUI32 => {op!(a:I32 | F32 => (a as u32) as f32)},
+            Operation::F32ConvertSI64 => {op!(a:I64 | F32 => a as f32)},
+            Operation::F32ConvertUI64 => {op!(a:I64 | F32 => (a as u64) as f32)},
+            Operation::F32DemoteF64 => {op!(a:F64 | F32 => a as f32)},
+            Operation::F64ConvertSI32 => {op!(a:I32 | F64 => a as f64)},
+            Operation::F64ConvertUI32 => {op!(a:I32 | F64 => (a as u32) as f64)},
+            Operation::F64ConvertSI64 => {op!(a:I64 | F64 => a as f64)},
+            Operation::F64ConvertUI64 => {op!(a:I64 | F64 => (a as u64) as f64)},
+            Operation::F64PromoteF32 => {op!(a:F32 | F64 => a as f64)},
+            Operation::I32ReinterpretF32 => {op!(a:F32 | I32 => a.to_bits() as i32)},
+            Operation::I64ReinterpretF64 => {op!(a:F64 | I64 => a.to_bits() as i64)},
+            Operation::F32ReinterpretI32 => {op!(a:I32 | F32 => f32::from_bits(a as u32))},
+            Operation::F64ReinterpretI64 => {op!(a:I64 | F64 => f64::from_bits(a as u64))}
+
+        }
+        Ok(Flow::Continue)
     }
 }
 
@@ -514,25 +697,34 @@ impl Execute for Block {
 mod tests {
     use super::*;
     use std::cell::RefCell;
+    use parse_tree::functions::FuncBody;
+    use parse_tree::functions::FuncSignature;
+    use runtime_tree::bytecode;
+    use runtime_tree::Fuel;
+    use runtime_tree::Limits;
     use parse_tree::language_types::BlockType;
     use parse_tree::language_types::BranchTable;
     use parse_tree::language_types::MemoryImmediate;
     use parse_tree::language_types::ResizableLimits;
+    use parse_tree::language_types::ValueType;
+    use parse_tree::types::TypeDefinition;
 
     // Generates a simple stackframe to work with
     macro_rules! sf {
         ($a:ident) => {
             let functions = vec![];
-            let globals = RefCell::new(vec![]);
-            let memories = RefCell::new(vec![]);
             let tables = RefCell::new(vec![]);
+            let fuel = RefCell::new(Fuel::default());
             let mut $a = StackFrame {
                 data: &mut ModuleInstanceData {
                     functions: &functions,
-                    globals: globals.borrow_mut(),
-                    memories: memories.borrow_mut(),
+                    globals: vec![],
+                    memories: vec![],
                     tables: tables.borrow_mut(),
-                    types: vec![]
+                    types: vec![],
+                    limits: Limits::default(),
+                    fuel: fuel.borrow_mut(),
+                    memory_limiter: None
                 },
                 locals: &mut vec![],
                 stack: &mut vec![]
@@ -558,13 +750,12 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
-    fn unreachable_panics() {
+    fn unreachable_traps() {
         sf!(sf);
         let block = block!{ Empty, {
             Operation::Unreachable;
         }};
-        block.execute(&mut sf);
+        assert_eq!(block.execute(&mut sf), Err(Trap::Unreachable));
     }
 
     #[test]
@@ -573,7 +764,7 @@ mod tests {
         let block = block! { Empty, {
             Operation::Nop;
         }};
-        block.execute(&mut sf);
+        block.execute(&mut sf).unwrap();
         // TODO actually check that the sf hasn't changed?
     }
 
@@ -585,27 +776,49 @@ mod tests {
                 Operation::I32Const(1);
             }});
         }};
-        block.execute(&mut sf);
+        block.execute(&mut sf).unwrap();
         assert_eq!(sf.stack.pop(), Some(ValueTypeProvider::I32(1)));
     }
 
     #[test]
     fn loop_runs_multiple_times() {
         sf!(sf);
+        sf.locals.push(ValueTypeProvider::I32(3));
+        sf.locals.push(ValueTypeProvider::I32(0));
         let block = block! { Empty, {
-            Operation::I32Const(42);
-            Operation::I32Const(0);
-            Operation::I32Const(1);
-            Operation::I32Const(2);
-            Operation::I32Const(3);
+            Operation::Block(block! { Empty, {
+                Operation::Loop(block! { Empty, {
+                    Operation::GetLocal(0);
+                    Operation::I32Eqz;
+                    Operation::BranchIf(1);
+                    Operation::GetLocal(0);
+                    Operation::I32Const(1);
+                    Operation::I32Sub;
+                    Operation::SetLocal(0);
+                    Operation::I32Const(1);
+                    Operation::GetLocal(1);
+                    Operation::I32Add;
+                    Operation::SetLocal(1);
+                    Operation::Branch(0);
+                }});
+            }});
+            Operation::End;
+        }};
+        block.execute(&mut sf).unwrap();
+        assert_eq!(sf.locals, &mut vec![ValueTypeProvider::I32(0), ValueTypeProvider::I32(3)]);
+        assert_eq!(sf.stack, &mut vec![]);
+    }
+
+    #[test]
+    fn loop_falls_through() {
+        sf!(sf);
+        let block = block! { Value(ValueType::I32), {
             Operation::Loop(block! { Value(ValueType::I32), {
-                Operation::I32Eqz;
-                Operation::BranchIf(1);
-                Operation::End;
+                Operation::I32Const(42);
             }});
             Operation::End;
         }};
-        block.execute(&mut sf);
+        assert_eq!(block.execute(&mut sf), Ok(Flow::Continue));
         assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(42)]);
     }
 
@@ -623,7 +836,7 @@ mod tests {
             }});
             Operation::End;
         }};
-        block.execute(&mut sf);
+        block.execute(&mut sf).unwrap();
         assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(6)]);
     }
 
@@ -639,7 +852,7 @@ mod tests {
             }});
             Operation::End;
         }};
-        block.execute(&mut sf);
+        block.execute(&mut sf).unwrap();
         assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(42)]);
     }
 
@@ -650,23 +863,77 @@ mod tests {
             Operation::End;
             Operation::I32Const(0);
         }};
-        block.execute(&mut sf);
+        block.execute(&mut sf).unwrap();
         assert_eq!(sf.stack, &mut vec![]);
     }
 
+    #[test]
+    fn nested_if_uses_its_own_else() {
+        sf!(sf);
+        let block = block! { Value(ValueType::I32), {
+            Operation::I32Const(0);
+            Operation::If(block! { Value(ValueType::I32), {
+                Operation::I32Const(1);
+                Operation::If(block! { Empty, {
+                    Operation::Else;
+                }});
+                Operation::I32Const(13);
+                Operation::Else;
+                Operation::I32Const(42);
+            }});
+            Operation::End;
+        }};
+        block.execute(&mut sf).unwrap();
+        assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(42)]);
+    }
+
     #[test]
     fn branch_leaves_block() {
         sf!(sf);
         let block = block! { Value(ValueType::I32), {
-            Operation::Block(block! { Empty, {
+            Operation::Block(block! { Value(ValueType::I32), {
                 Operation::I32Const(42);
-                Operation::Branch(1);
+                Operation::Branch(0);
                 Operation::I32Const(13);
                 Operation::End;
             }});
             Operation::End;
         }};
-        block.execute(&mut sf);
+        block.execute(&mut sf).unwrap();
+        assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(42)]);
+    }
+
+    #[test]
+    fn branch_unwinds_to_label_arity() {
+        sf!(sf);
+        let block = block! { Empty, {
+            Operation::I32Const(7);
+            Operation::Block(block! { Value(ValueType::I32), {
+                Operation::I32Const(1);
+                Operation::I64Const(2);
+                Operation::I32Const(42);
+                Operation::Branch(0);
+            }});
+            Operation::End;
+        }};
+        block.execute(&mut sf).unwrap();
+        assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(7), ValueTypeProvider::I32(42)]);
+    }
+
+    #[test]
+    fn branch_leaves_nested_blocks() {
+        sf!(sf);
+        let block = block! { Value(ValueType::I32), {
+            Operation::Block(block! { Value(ValueType::I32), {
+                Operation::Block(block! { Empty, {
+                    Operation::I32Const(42);
+                    Operation::Branch(1);
+                }});
+                Operation::I32Const(13);
+            }});
+            Operation::End;
+        }};
+        block.execute(&mut sf).unwrap();
         assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(42)]);
     }
 
@@ -674,16 +941,17 @@ mod tests {
     fn branch_if_true_leaves() {
         sf!(sf);
         let block = block! { Value(ValueType::I32), {
-            Operation::Block(block! { Empty, {
+            Operation::Block(block! { Value(ValueType::I32), {
                 Operation::I32Const(42);
                 Operation::I32Const(1);
-                Operation::BranchIf(1);
+                Operation::BranchIf(0);
+                Operation::Drop;
                 Operation::I32Const(13);
                 Operation::End;
             }});
             Operation::End;
         }};
-        block.execute(&mut sf);
+        block.execute(&mut sf).unwrap();
         assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(42)]);
     }
 
@@ -691,68 +959,256 @@ mod tests {
     fn branch_if_false_continues() {
         sf!(sf);
         let block = block! { Value(ValueType::I32), {
-            Operation::Block(block! { Empty, {
+            Operation::Block(block! { Value(ValueType::I32), {
+                Operation::I32Const(13);
                 Operation::I32Const(0);
-                Operation::BranchIf(1);
+                Operation::BranchIf(0);
+                Operation::Drop;
                 Operation::I32Const(42);
                 Operation::End;
             }});
             Operation::End;
         }};
-        block.execute(&mut sf);
+        block.execute(&mut sf).unwrap();
         assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(42)]);
     }
 
+    macro_rules! branch_table {
+        ($index:expr) => {
+            block! { Value(ValueType::I32), {
+                Operation::Block(block! { Value(ValueType::I32), {
+                    Operation::Block(block! { Empty, {
+                        Operation::I32Const(42);
+                        Operation::I32Const($index);
+                        Operation::BranchTable(BranchTable {
+                            default: 0,
+                            targets: vec![1]
+                        });
+                    }});
+                    Operation::I32Const(13);
+                }});
+                Operation::End;
+            }}
+        };
+    }
+
     #[test]
     fn branch_table_within_bounds_branches() {
+        sf!(sf);
+        branch_table!(0).execute(&mut sf).unwrap();
+        assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(42)]);
+    }
+
+    #[test]
+    fn branch_table_outside_bounds_branches_default() {
+        sf!(sf);
+        branch_table!(5).execute(&mut sf).unwrap();
+        assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(13)]);
+    }
+
+    #[test]
+    fn return_leaves_every_label() {
         sf!(sf);
         let block = block! { Value(ValueType::I32), {
             Operation::Block(block! { Empty, {
-                Operation::Block(block! { Empty, {
+                Operation::Loop(block! { Empty, {
                     Operation::I32Const(42);
-                    Operation::I32Const(0);
-                    Operation::BranchTable(BranchTable {
-                        default: 0,
-                        targets: vec![2]
-                    });
-                    Operation::I32Const(13);
-                    Operation::End;
+                    Operation::Return;
                 }});
-                Operation::I32Const(13);
-                Operation::End;
             }});
-            Operation::I32Const(42);
+            Operation::I32Const(13);
             Operation::End;
         }};
-        block.execute(&mut sf);
-        assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(42), ValueTypeProvider::I32(42)]);
+        assert_eq!(block.execute(&mut sf), Ok(Flow::Return));
+        assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(42)]);
+    }
+
+    // Lowers a function body that takes no parameters
+    macro_rules! compiled {
+        ([$($ret:expr),*], {$($op:expr;)*}) => {
+            bytecode::compile(&FuncBody {
+                locals: vec![],
+                code: vec![$($op,)*]
+            }, &FuncSignature {
+                parameters: vec![],
+                returns: vec![$($ret),*]
+            }, &[], &[]).unwrap()
+        };
     }
 
     #[test]
-    fn branch_table_outside_bounds_branches_default() {
+    fn compiled_loop_runs_multiple_times() {
         sf!(sf);
-        let block = block! { Value(ValueType::I32), {
+        sf.locals.push(ValueTypeProvider::I32(3));
+        sf.locals.push(ValueTypeProvider::I32(0));
+        let code = compiled!([], {
             Operation::Block(block! { Empty, {
-                Operation::Block(block! { Empty, {
+                Operation::Loop(block! { Empty, {
+                    Operation::GetLocal(0);
+                    Operation::I32Eqz;
+                    Operation::BranchIf(1);
+                    Operation::GetLocal(0);
                     Operation::I32Const(1);
-                    Operation::BranchTable(BranchTable {
-                        default: 0,
-                        targets: vec![2]
-                    });
-                    Operation::I32Const(13);
-                    Operation::End;
+                    Operation::I32Sub;
+                    Operation::SetLocal(0);
+                    Operation::I32Const(1);
+                    Operation::GetLocal(1);
+                    Operation::I32Add;
+                    Operation::SetLocal(1);
+                    Operation::Branch(0);
                 }});
+            }});
+        });
+        code.execute(&mut sf).unwrap();
+        assert_eq!(sf.locals, &mut vec![ValueTypeProvider::I32(0), ValueTypeProvider::I32(3)]);
+        assert_eq!(sf.stack, &mut vec![]);
+    }
+
+    #[test]
+    fn compiled_branch_unwinds_to_label_arity() {
+        sf!(sf);
+        let code = compiled!([ValueType::I32, ValueType::I32], {
+            Operation::I32Const(7);
+            Operation::Block(block! { Value(ValueType::I32), {
+                Operation::I32Const(1);
+                Operation::I64Const(2);
                 Operation::I32Const(42);
-                Operation::End;
+                Operation::Branch(0);
             }});
-            Operation::I32Const(42);
-            Operation::End;
+        });
+        code.execute(&mut sf).unwrap();
+        assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(7), ValueTypeProvider::I32(42)]);
+    }
+
+    #[test]
+    fn typed_blocks_take_their_parameters() {
+        let types = vec![
+            TypeDefinition::Func(FuncSignature {
+                parameters: vec![ValueType::I32, ValueType::I32],
+                returns: vec![ValueType::I32, ValueType::I32]
+            }),
+            TypeDefinition::Func(FuncSignature {
+                parameters: vec![ValueType::I32],
+                returns: vec![ValueType::I32]
+            }),
+        ];
+        let code = |condition| vec![
+            // A branch drops what's left above the results, down to below the parameters
+            Operation::I32Const(1),
+            Operation::I32Const(2),
+            Operation::Block(Block { block_type: BlockType::Type(0), operations: vec![
+                Operation::I32Const(3),
+                Operation::Branch(0),
+            ] }),
+            Operation::I32Add,
+            // Branching back to a loop carries its parameters, counting down to zero here
+            Operation::Loop(Block { block_type: BlockType::Type(1), operations: vec![
+                Operation::GetLocal(1),
+                Operation::I32Const(1),
+                Operation::I32Add,
+                Operation::SetLocal(1),
+                Operation::I32Const(-1),
+                Operation::I32Add,
+                Operation::TeeLocal(0),
+                Operation::GetLocal(0),
+                Operation::BranchIf(0),
+            ] }),
+            // Without an else the parameters are the results
+            Operation::I32Const(4),
+            Operation::I32Const(condition),
+            Operation::If(Block { block_type: BlockType::Type(0), operations: vec![
+                Operation::I32Add,
+                Operation::I32Const(10),
+            ] }),
+        ];
+        for &(condition, first, second) in &[(1, 4, 10), (0, 0, 4)] {
+            let expected = vec![ValueTypeProvider::I32(first), ValueTypeProvider::I32(second)];
+            sf!(sf);
+            sf.data.types = types.clone();
+            sf.locals.extend(vec![ValueTypeProvider::I32(0); 2]);
+            let block = Block { block_type: BlockType::Empty, operations: code(condition) };
+            block.execute(&mut sf).unwrap();
+            assert_eq!(sf.stack, &expected);
+            assert_eq!(sf.locals[1], ValueTypeProvider::I32(5));
+
+            sf!(sf);
+            sf.locals.extend(vec![ValueTypeProvider::I32(0); 2]);
+            let compiled = bytecode::compile(&FuncBody {
+                locals: vec![],
+                code: code(condition)
+            }, &FuncSignature {
+                parameters: vec![],
+                returns: vec![ValueType::I32, ValueType::I32]
+            }, &[], &types).unwrap();
+            compiled.execute(&mut sf).unwrap();
+            assert_eq!(sf.stack, &expected);
+            assert_eq!(sf.locals[1], ValueTypeProvider::I32(5));
+        }
+    }
+
+    #[test]
+    fn undefined_block_types_trap() {
+        sf!(sf);
+        let block = block! { Empty, {
+            Operation::Block(block!(@ BlockType::Type(0), ));
         }};
-        block.execute(&mut sf);
-        assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(42), ValueTypeProvider::I32(42)]);
+        assert_eq!(block.execute(&mut sf), Err(Trap::UndefinedType(0)));
+    }
+
+    #[test]
+    fn compiled_if_else() {
+        for &(condition, result) in &[(1, 13), (0, 42)] {
+            sf!(sf);
+            let code = compiled!([ValueType::I32], {
+                Operation::I32Const(condition);
+                Operation::If(block! { Value(ValueType::I32), {
+                    Operation::I32Const(13);
+                    Operation::Else;
+                    Operation::I32Const(42);
+                }});
+            });
+            code.execute(&mut sf).unwrap();
+            assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(result)]);
+        }
+    }
+
+    #[test]
+    fn compiled_branch_table() {
+        for &(index, result) in &[(0, 42), (5, 13)] {
+            sf!(sf);
+            let code = compiled!([ValueType::I32], {
+                Operation::Block(block! { Value(ValueType::I32), {
+                    Operation::Block(block! { Empty, {
+                        Operation::I32Const(42);
+                        Operation::I32Const(index);
+                        Operation::BranchTable(BranchTable {
+                            default: 0,
+                            targets: vec![1]
+                        });
+                    }});
+                    Operation::I32Const(13);
+                }});
+            });
+            code.execute(&mut sf).unwrap();
+            assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(result)]);
+        }
     }
 
-    // TODO: Call, Return, CallIndirect
+    #[test]
+    fn compiled_return() {
+        sf!(sf);
+        let code = compiled!([ValueType::I32], {
+            Operation::Loop(block! { Empty, {
+                Operation::I32Const(42);
+                Operation::Return;
+            }});
+            Operation::I32Const(13);
+        });
+        assert_eq!(code.execute(&mut sf), Ok(Flow::Return));
+        assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(42)]);
+    }
+
+    // TODO: Call, CallIndirect
 
     #[test]
     fn drop_value_from_stack() {
@@ -762,21 +1218,42 @@ mod tests {
             Operation::Drop;
             Operation::End;
         }};
-        block.execute(&mut sf);
+        block.execute(&mut sf).unwrap();
         assert_eq!(sf.stack, &mut vec![]);
     }
 
+    #[test]
+    fn i64_unsigned_comparisons_use_all_64_bits() {
+        let cases = vec![
+            (Operation::I64LtU, 1),
+            (Operation::I64GtU, 0),
+            (Operation::I64LeU, 1),
+            (Operation::I64GeU, 0),
+        ];
+        for (operation, expected) in cases {
+            sf!(sf);
+            let block = block! { Value(ValueType::I32), {
+                Operation::I64Const(1);
+                Operation::I64Const(1 << 32);
+                operation;
+                Operation::End;
+            }};
+            block.execute(&mut sf).unwrap();
+            assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(expected)]);
+        }
+    }
+
     #[test]
     fn select_value_true() {
         sf!(sf);
         let block = block! { Value(ValueType::I32), {
-            Operation::I32Const(1);
-            Operation::I32Const(13);
             Operation::I32Const(42);
+            Operation::I32Const(13);
+            Operation::I32Const(1);
             Operation::Select;
             Operation::End;
         }};
-        block.execute(&mut sf);
+        block.execute(&mut sf).unwrap();
         assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(42)]);
     }
 
@@ -784,13 +1261,13 @@ mod tests {
     fn select_value_false() {
         sf!(sf);
         let block = block! { Value(ValueType::I32), {
-            Operation::I32Const(0);
-            Operation::I32Const(42);
             Operation::I32Const(13);
+            Operation::I32Const(42);
+            Operation::I32Const(0);
             Operation::Select;
             Operation::End;
         }};
-        block.execute(&mut sf);
+        block.execute(&mut sf).unwrap();
         assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(42)]);
     }
 
@@ -802,7 +1279,7 @@ mod tests {
             Operation::GetLocal(0);
             Operation::End;
         }};
-        block.execute(&mut sf);
+        block.execute(&mut sf).unwrap();
         assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(42)]);
     }
 
@@ -815,7 +1292,7 @@ mod tests {
             Operation::SetLocal(0);
             Operation::End;
         }};
-        block.execute(&mut sf);
+        block.execute(&mut sf).unwrap();
         assert_eq!(sf.locals, &mut vec![ValueTypeProvider::I32(42)]);
         assert_eq!(sf.stack, &mut vec![]);
     }
@@ -829,7 +1306,7 @@ mod tests {
             Operation::TeeLocal(0);
             Operation::End;
         }};
-        block.execute(&mut sf);
+        block.execute(&mut sf).unwrap();
         assert_eq!(sf.locals, &mut vec![ValueTypeProvider::I32(42)]);
         assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(42)]);
     }
@@ -837,39 +1314,39 @@ mod tests {
     #[test]
     fn get_global() {
         sf!(sf);
-        sf.data.globals.push(ValueTypeProvider::I32(42));
+        sf.data.globals.push(Rc::new(RefCell::new(ValueTypeProvider::I32(42))));
         let block = block! { Value(ValueType::I32), {
             Operation::GetGlobal(0);
             Operation::End;
         }};
-        block.execute(&mut sf);
+        block.execute(&mut sf).unwrap();
         assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(42)]);
     }
 
     #[test]
     fn set_global() {
         sf!(sf);
-        sf.data.globals.push(ValueTypeProvider::I32(13));
+        sf.data.globals.push(Rc::new(RefCell::new(ValueTypeProvider::I32(13))));
         let block = block! { Value(ValueType::I32), {
             Operation::I32Const(42);
             Operation::SetGlobal(0);
             Operation::End;
         }};
-        block.execute(&mut sf);
-        assert_eq!(&mut *sf.data.globals, &mut vec![ValueTypeProvider::I32(42)]);
+        block.execute(&mut sf).unwrap();
+        assert_eq!(*sf.data.globals[0].borrow(), ValueTypeProvider::I32(42));
     }
 
     macro_rules! setup_memory {
         ($sf:ident, $start:expr, [$($byte:expr),*]) => {
-            $sf.data.memories.push(Memory {
+            $sf.data.memories.push(Rc::new(RefCell::new(Memory {
                 limits: ResizableLimits {
                     initial: 1,
                     maximum: None
                 },
                 values: vec![0; 65536]
-            });
+            })));
             let bytes = vec![$($byte),*];
-            $sf.data.memories[0].values.splice($start..bytes.len(), bytes);
+            $sf.data.memories[0].borrow_mut().values.splice($start..$start + bytes.len(), bytes);
         };
     }
 
@@ -879,39 +1356,42 @@ mod tests {
             sf!(sf);
             setup_memory!(sf, 0, [42]);
             let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(0);
                 Operation::I32Load(MemoryImmediate {
                     flags: 0,
                     offset: 0
                 });
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(42)]);
         }
         {
             sf!(sf);
             setup_memory!(sf, 0, [0xef, 0xbe, 0xad, 0xde, 0xff]); // ff isn't read
             let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(0);
                 Operation::I32Load(MemoryImmediate {
                     flags: 0,
                     offset: 0
                 });
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(0xdeadbeef)]);
         }
         {
             sf!(sf);
             setup_memory!(sf, 3, [0xef, 0xbe, 0xad, 0xde, 0xff]); // ff isn't read
             let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(0);
                 Operation::I32Load(MemoryImmediate {
                     flags: 0,
                     offset: 3
                 });
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(0xdeadbeef)]);
         }
     }
@@ -922,26 +1402,28 @@ mod tests {
             sf!(sf);
             setup_memory!(sf, 0, [42]);
             let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(0);
                 Operation::I64Load(MemoryImmediate {
                     flags: 0,
                     offset: 0
                 });
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(42)]);
         }
         {
             sf!(sf);
             setup_memory!(sf, 0, [0xef, 0xbe, 0xad, 0xde, 0xbe, 0xba, 0xfe, 0xca, 0xff]); // ff isn't read
             let block = block! { Value(ValueType::I64), {
+                Operation::I32Const(0);
                 Operation::I64Load(MemoryImmediate {
                     flags: 0,
                     offset: 0
                 });
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(0xcafebabedeadbeef)]);
         }
     }
@@ -951,13 +1433,14 @@ mod tests {
         sf!(sf);
         setup_memory!(sf, 0, [0xc3, 0xf5, 0x48, 0x40]);
         let block = block! { Value(ValueType::I32), {
+            Operation::I32Const(0);
             Operation::F32Load(MemoryImmediate {
                 flags: 0,
                 offset: 0
             });
             Operation::End;
         }};
-        block.execute(&mut sf);
+        block.execute(&mut sf).unwrap();
         assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F32(3.14)]);
     }
 
@@ -966,13 +1449,14 @@ mod tests {
         sf!(sf);
         setup_memory!(sf, 0, [0x81, 0xf6, 0x97, 0x9b, 0x77, 0xe3, 0xf9, 0x3f]);
         let block = block! { Value(ValueType::F64), {
+            Operation::I32Const(0);
             Operation::F64Load(MemoryImmediate {
                 flags: 0,
                 offset: 0
             });
             Operation::End;
         }};
-        block.execute(&mut sf);
+        block.execute(&mut sf).unwrap();
         assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F64(1.61803398875)]);
     }
 
@@ -982,52 +1466,56 @@ mod tests {
             sf!(sf);
             setup_memory!(sf, 0, [-42i8 as u8]);
             let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(0);
                 Operation::I32Load8S(MemoryImmediate {
                     flags: 0,
                     offset: 0
                 });
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(-42)]);
         }
         { // I32Load8U
             sf!(sf);
             setup_memory!(sf, 0, [42]);
             let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(0);
                 Operation::I32Load8U(MemoryImmediate {
                     flags: 0,
                     offset: 0
                 });
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(42)]);
         }
         { // I32Load16S
             sf!(sf);
             setup_memory!(sf, 0, [0x00, 0x83]);
             let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(0);
                 Operation::I32Load16S(MemoryImmediate {
                     flags: 0,
                     offset: 0
                 });
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(-32000)]);
         }
         { // I32Load16U
             sf!(sf);
             setup_memory!(sf, 0, [0x00, 0x7d]);
             let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(0);
                 Operation::I32Load16U(MemoryImmediate {
                     flags: 0,
                     offset: 0
                 });
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(32000)]);
         }
     }
@@ -1038,78 +1526,84 @@ mod tests {
             sf!(sf);
             setup_memory!(sf, 0, [-42i8 as u8]);
             let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(0);
                 Operation::I64Load8S(MemoryImmediate {
                     flags: 0,
                     offset: 0
                 });
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(-42)]);
         }
         { // I64Load8U
             sf!(sf);
             setup_memory!(sf, 0, [42]);
             let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(0);
                 Operation::I64Load8U(MemoryImmediate {
                     flags: 0,
                     offset: 0
                 });
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(42)]);
         }
         { // I64Load16S
             sf!(sf);
             setup_memory!(sf, 0, [0x00, 0x83]);
             let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(0);
                 Operation::I64Load16S(MemoryImmediate {
                     flags: 0,
                     offset: 0
                 });
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(-32000)]);
         }
         { // I64Load16U
             sf!(sf);
             setup_memory!(sf, 0, [0x00, 0x7d]);
             let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(0);
                 Operation::I64Load16U(MemoryImmediate {
                     flags: 0,
                     offset: 0
                 });
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(32000)]);
         }
         { // I64Load32S
             sf!(sf);
             setup_memory!(sf, 0, [0x2e, 0xfd, 0x69, 0xb6]);
             let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(0);
                 Operation::I64Load32S(MemoryImmediate {
                     flags: 0,
                     offset: 0
                 });
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(-1234567890)]);
         }
         { // I64Load32U
             sf!(sf);
             setup_memory!(sf, 0, [0xD2, 0x02, 0x96, 0x49]);
             let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(0);
                 Operation::I64Load32U(MemoryImmediate {
                     flags: 0,
                     offset: 0
                 });
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(1234567890)]);
         }
     }
@@ -1120,6 +1614,7 @@ mod tests {
             sf!(sf);
             setup_memory!(sf, 0, []);
             let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(0);
                 Operation::I32Const(1234567890);
                 Operation::I32Store(MemoryImmediate {
                     flags: 0,
@@ -1127,13 +1622,14 @@ mod tests {
                 });
                 Operation::End;
             }};
-            block.execute(&mut sf);
-            assert_eq!(sf.data.memories[0].values[0..5], [0xD2, 0x02, 0x96, 0x49, 0x00]);
+            block.execute(&mut sf).unwrap();
+            assert_eq!(sf.data.memories[0].borrow().values[0..5], [0xD2, 0x02, 0x96, 0x49, 0x00]);
         }
         { // I64Store
             sf!(sf);
             setup_memory!(sf, 0, []);
             let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(0);
                 Operation::I64Const(0x123456789abcdef0);
                 Operation::I64Store(MemoryImmediate {
                     flags: 0,
@@ -1141,13 +1637,14 @@ mod tests {
                 });
                 Operation::End;
             }};
-            block.execute(&mut sf);
-            assert_eq!(sf.data.memories[0].values[0..9], [0xf0, 0xde, 0xbc, 0x9a, 0x78, 0x56, 0x34, 0x12, 0x00]);
+            block.execute(&mut sf).unwrap();
+            assert_eq!(sf.data.memories[0].borrow().values[0..9], [0xf0, 0xde, 0xbc, 0x9a, 0x78, 0x56, 0x34, 0x12, 0x00]);
         }
         { // F32Store
             sf!(sf);
             setup_memory!(sf, 0, []);
             let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(0);
                 Operation::F32Const(3.1415);
                 Operation::F32Store(MemoryImmediate {
                     flags: 0,
@@ -1155,13 +1652,14 @@ mod tests {
                 });
                 Operation::End;
             }};
-            block.execute(&mut sf);
-            assert_eq!(sf.data.memories[0].values[0..5], [0x56, 0x0e, 0x49, 0x40, 0x00]);
+            block.execute(&mut sf).unwrap();
+            assert_eq!(sf.data.memories[0].borrow().values[0..5], [0x56, 0x0e, 0x49, 0x40, 0x00]);
         }
         { // F64Store
             sf!(sf);
             setup_memory!(sf, 0, []);
             let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(0);
                 Operation::F64Const(1.61803398875);
                 Operation::F64Store(MemoryImmediate {
                     flags: 0,
@@ -1169,8 +1667,8 @@ mod tests {
                 });
                 Operation::End;
             }};
-            block.execute(&mut sf);
-            assert_eq!(sf.data.memories[0].values[0..9], [0x81, 0xf6, 0x97, 0x9b, 0x77, 0xe3, 0xf9, 0x3f, 0x00]);
+            block.execute(&mut sf).unwrap();
+            assert_eq!(sf.data.memories[0].borrow().values[0..9], [0x81, 0xf6, 0x97, 0x9b, 0x77, 0xe3, 0xf9, 0x3f, 0x00]);
         }
     }
 
@@ -1181,6 +1679,7 @@ mod tests {
             sf!(sf);
             setup_memory!(sf, 0, []);
             let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(0);
                 Operation::I32Const(42);
                 Operation::I32Store8(MemoryImmediate {
                     flags: 0,
@@ -1188,13 +1687,14 @@ mod tests {
                 });
                 Operation::End;
             }};
-            block.execute(&mut sf);
-            assert_eq!(sf.data.memories[0].values[0..2], [42, 0x00]);
+            block.execute(&mut sf).unwrap();
+            assert_eq!(sf.data.memories[0].borrow().values[0..2], [42, 0x00]);
         }
         { // I32Store8
             sf!(sf);
             setup_memory!(sf, 0, []);
             let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(0);
                 Operation::I32Const(0xff42);
                 Operation::I32Store8(MemoryImmediate {
                     flags: 0,
@@ -1202,13 +1702,14 @@ mod tests {
                 });
                 Operation::End;
             }};
-            block.execute(&mut sf);
-            assert_eq!(sf.data.memories[0].values[0..2], [0x42, 0x00]);
+            block.execute(&mut sf).unwrap();
+            assert_eq!(sf.data.memories[0].borrow().values[0..2], [0x42, 0x00]);
         }
         { // I32Store16
             sf!(sf);
             setup_memory!(sf, 0, []);
             let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(0);
                 Operation::I32Const(0xbeef);
                 Operation::I32Store(MemoryImmediate {
                     flags: 0,
@@ -1216,13 +1717,14 @@ mod tests {
                 });
                 Operation::End;
             }};
-            block.execute(&mut sf);
-            assert_eq!(sf.data.memories[0].values[0..3], [0xef, 0xbe, 0x00]);
+            block.execute(&mut sf).unwrap();
+            assert_eq!(sf.data.memories[0].borrow().values[0..3], [0xef, 0xbe, 0x00]);
         }
         { // I64Store8
             sf!(sf);
             setup_memory!(sf, 0, []);
             let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(0);
                 Operation::I64Const(42);
                 Operation::I64Store(MemoryImmediate {
                     flags: 0,
@@ -1230,13 +1732,14 @@ mod tests {
                 });
                 Operation::End;
             }};
-            block.execute(&mut sf);
-            assert_eq!(sf.data.memories[0].values[0..2], [42, 0x00]);
+            block.execute(&mut sf).unwrap();
+            assert_eq!(sf.data.memories[0].borrow().values[0..2], [42, 0x00]);
         }
         { // I64Store16
             sf!(sf);
             setup_memory!(sf, 0, []);
             let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(0);
                 Operation::I64Const(0xbeef);
                 Operation::I64Store(MemoryImmediate {
                     flags: 0,
@@ -1244,13 +1747,14 @@ mod tests {
                 });
                 Operation::End;
             }};
-            block.execute(&mut sf);
-            assert_eq!(sf.data.memories[0].values[0..3], [0xef, 0xbe, 0x00]);
+            block.execute(&mut sf).unwrap();
+            assert_eq!(sf.data.memories[0].borrow().values[0..3], [0xef, 0xbe, 0x00]);
         }
         { // I64Store32
             sf!(sf);
             setup_memory!(sf, 0, []);
             let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(0);
                 Operation::I64Const(1234567890);
                 Operation::I64Store(MemoryImmediate {
                     flags: 0,
@@ -1258,8 +1762,8 @@ mod tests {
                 });
                 Operation::End;
             }};
-            block.execute(&mut sf);
-            assert_eq!(sf.data.memories[0].values[0..5], [0xD2, 0x02, 0x96, 0x49, 0x00]);
+            block.execute(&mut sf).unwrap();
+            assert_eq!(sf.data.memories[0].borrow().values[0..5], [0xD2, 0x02, 0x96, 0x49, 0x00]);
         }
     }
 
@@ -1272,7 +1776,7 @@ mod tests {
                     Operation::I32Const(1);
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack.pop(), Some(ValueTypeProvider::I32(1)));
         }
         {
@@ -1282,7 +1786,7 @@ mod tests {
                     Operation::I64Const(1);
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack.pop(), Some(ValueTypeProvider::I64(1)));
         }
         {
@@ -1292,7 +1796,7 @@ mod tests {
                     Operation::F32Const(3.14);
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack.pop(), Some(ValueTypeProvider::F32(3.14)));
         }
         {
@@ -1302,7 +1806,7 @@ mod tests {
                     Operation::F64Const(3.14);
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack.pop(), Some(ValueTypeProvider::F64(3.14)));
         }
     }
@@ -1322,7 +1826,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
@@ -1339,7 +1843,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // I32Eq
@@ -1356,7 +1860,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
@@ -1374,7 +1878,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // I32Ne
@@ -1391,7 +1895,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
@@ -1409,15 +1913,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // I32LtS
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::I32Const(999);
                     Operation::I32Const(-400);
+                    Operation::I32Const(999);
                     Operation::I32LtS;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -1426,15 +1930,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::I32Const(-100);
                     Operation::I32Const(-400);
+                    Operation::I32Const(-100);
                     Operation::I32LtS;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -1443,15 +1947,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // I32LtU
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::I32Const(100);
                     Operation::I32Const(50);
+                    Operation::I32Const(100);
                     Operation::I32LtU;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -1460,15 +1964,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // I32GtS
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::I32Const(-400);
                     Operation::I32Const(999);
+                    Operation::I32Const(-400);
                     Operation::I32GtS;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -1477,15 +1981,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::I32Const(-400);
                     Operation::I32Const(-100);
+                    Operation::I32Const(-400);
                     Operation::I32GtS;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -1494,15 +1998,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // I32GtU
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::I32Const(10);
                     Operation::I32Const(50);
+                    Operation::I32Const(10);
                     Operation::I32GtU;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -1511,15 +2015,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // I32LeS
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::I32Const(999);
                     Operation::I32Const(-400);
+                    Operation::I32Const(999);
                     Operation::I32LeS;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -1528,7 +2032,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
@@ -1545,7 +2049,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // I32LeU
@@ -1562,15 +2066,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::I32Const(50);
                     Operation::I32Const(25);
+                    Operation::I32Const(50);
                     Operation::I32LeU;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -1579,15 +2083,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // I32GeS
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::I32Const(999);
                     Operation::I32Const(1000);
+                    Operation::I32Const(999);
                     Operation::I32GeS;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -1596,7 +2100,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
@@ -1613,15 +2117,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // I32GeU
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::I32Const(999);
                     Operation::I32Const(1000);
+                    Operation::I32Const(999);
                     Operation::I32GeU;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -1630,7 +2134,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
@@ -1647,7 +2151,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
     }
@@ -1667,7 +2171,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
@@ -1684,7 +2188,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // I64Eq
@@ -1701,7 +2205,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
@@ -1719,7 +2223,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // I64Ne
@@ -1736,7 +2240,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
@@ -1754,15 +2258,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // I64LtS
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::I64Const(999);
                     Operation::I64Const(-400);
+                    Operation::I64Const(999);
                     Operation::I64LtS;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -1771,15 +2275,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::I64Const(-100);
                     Operation::I64Const(-400);
+                    Operation::I64Const(-100);
                     Operation::I64LtS;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -1788,15 +2292,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // I64LtU
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::I64Const(100);
                     Operation::I64Const(50);
+                    Operation::I64Const(100);
                     Operation::I64LtU;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -1805,15 +2309,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // I64GtS
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::I64Const(-400);
                     Operation::I64Const(999);
+                    Operation::I64Const(-400);
                     Operation::I64GtS;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -1822,15 +2326,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::I32Const(-400);
                     Operation::I32Const(-100);
+                    Operation::I32Const(-400);
                     Operation::I32GtS;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -1839,15 +2343,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // I64GtU
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::I64Const(10);
                     Operation::I64Const(50);
+                    Operation::I64Const(10);
                     Operation::I64GtU;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -1856,15 +2360,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // I64LeS
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::I64Const(999);
                     Operation::I64Const(-400);
+                    Operation::I64Const(999);
                     Operation::I64LeS;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -1873,7 +2377,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
@@ -1890,7 +2394,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // I64LeU
@@ -1907,15 +2411,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::I64Const(50);
                     Operation::I64Const(25);
+                    Operation::I64Const(50);
                     Operation::I64LeU;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -1924,15 +2428,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // I64GeS
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::I64Const(999);
                     Operation::I64Const(1000);
+                    Operation::I64Const(999);
                     Operation::I64GeS;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -1941,7 +2445,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
@@ -1958,15 +2462,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // I64GeU
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::I64Const(999);
                     Operation::I64Const(1000);
+                    Operation::I64Const(999);
                     Operation::I64GeU;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -1975,7 +2479,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
@@ -1992,7 +2496,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
     }
@@ -2013,7 +2517,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
@@ -2031,7 +2535,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // F32Ne
@@ -2048,7 +2552,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
@@ -2066,15 +2570,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // F32Lt
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::F32Const(5.00);
                     Operation::F32Const(3.14);
+                    Operation::F32Const(5.00);
                     Operation::F32Lt;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -2083,15 +2587,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::F32Const(5.00);
                     Operation::F32Const(6.00);
+                    Operation::F32Const(5.00);
                     Operation::F32Lt;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(13);
@@ -2101,15 +2605,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // F32Gt
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::F32Const(5.00);
                     Operation::F32Const(3.14);
+                    Operation::F32Const(5.00);
                     Operation::F32Gt;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(13);
@@ -2119,15 +2623,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::F32Const(5.00);
                     Operation::F32Const(6.00);
+                    Operation::F32Const(5.00);
                     Operation::F32Gt;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -2136,15 +2640,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // F32Le
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::F32Const(5.00);
                     Operation::F32Const(3.14);
+                    Operation::F32Const(5.00);
                     Operation::F32Le;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -2153,15 +2657,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::F32Const(5.00);
                     Operation::F32Const(6.00);
+                    Operation::F32Const(5.00);
                     Operation::F32Le;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(13);
@@ -2171,7 +2675,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
@@ -2188,15 +2692,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // F32Ge
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::F32Const(5.00);
                     Operation::F32Const(3.14);
+                    Operation::F32Const(5.00);
                     Operation::F32Ge;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(13);
@@ -2206,15 +2710,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::F32Const(5.00);
                     Operation::F32Const(6.00);
+                    Operation::F32Const(5.00);
                     Operation::F32Ge;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -2223,7 +2727,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
@@ -2240,7 +2744,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
     }
@@ -2261,7 +2765,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
@@ -2279,7 +2783,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // F64Ne
@@ -2296,7 +2800,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
@@ -2314,15 +2818,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // F64Lt
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::F64Const(5.00);
                     Operation::F64Const(3.14);
+                    Operation::F64Const(5.00);
                     Operation::F64Lt;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -2331,15 +2835,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::F64Const(5.00);
                     Operation::F64Const(6.00);
+                    Operation::F64Const(5.00);
                     Operation::F64Lt;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(13);
@@ -2349,15 +2853,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // F64Gt
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::F64Const(5.00);
                     Operation::F64Const(3.14);
+                    Operation::F64Const(5.00);
                     Operation::F64Gt;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(13);
@@ -2367,15 +2871,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::F64Const(5.00);
                     Operation::F64Const(6.00);
+                    Operation::F64Const(5.00);
                     Operation::F64Gt;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -2384,15 +2888,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // F64Le
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::F64Const(5.00);
                     Operation::F64Const(3.14);
+                    Operation::F64Const(5.00);
                     Operation::F64Le;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -2401,15 +2905,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::F64Const(5.00);
                     Operation::F64Const(6.00);
+                    Operation::F64Const(5.00);
                     Operation::F64Le;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(13);
@@ -2419,7 +2923,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
@@ -2436,15 +2940,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         { // F64Ge
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::F64Const(5.00);
                     Operation::F64Const(3.14);
+                    Operation::F64Const(5.00);
                     Operation::F64Ge;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(13);
@@ -2454,15 +2958,15 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
                 Operation::Block(block! { Value(ValueType::I32), {
-                    Operation::F64Const(5.00);
                     Operation::F64Const(6.00);
+                    Operation::F64Const(5.00);
                     Operation::F64Ge;
                     Operation::If(block! { Empty, {
                         Operation::I32Const(42);
@@ -2471,7 +2975,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
         {
@@ -2488,7 +2992,7 @@ mod tests {
                     Operation::End;
                 }});
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(*sf.stack, vec![ValueTypeProvider::I32(42)]);
         }
     }
@@ -2502,7 +3006,7 @@ mod tests {
                 Operation::I32Clz;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(16)]);
         }
         { // I32Ctz
@@ -2512,7 +3016,7 @@ mod tests {
                 Operation::I32Ctz;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(16)]);
         }
         { // I32Popcnt
@@ -2522,7 +3026,7 @@ mod tests {
                 Operation::I32Popcnt;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(12)]);
         }
         { // I32Add
@@ -2533,18 +3037,18 @@ mod tests {
                 Operation::I32Add;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(42)]);
         }
         { // I32Sub
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
-                Operation::I32Const(10);
                 Operation::I32Const(52);
+                Operation::I32Const(10);
                 Operation::I32Sub;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(42)]);
         }
         { // I32Mul
@@ -2555,51 +3059,51 @@ mod tests {
                 Operation::I32Mul;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(42)]);
         }
         { // I32DivS
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
-                Operation::I32Const(-2);
                 Operation::I32Const(80);
+                Operation::I32Const(-2);
                 Operation::I32DivS;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(-40)]);
         }
         { // I32DivU
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
-                Operation::I32Const(2);
                 Operation::I32Const(60000);
+                Operation::I32Const(2);
                 Operation::I32DivU;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(30000)]);
         }
         { // I32RemS
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
-                Operation::I32Const(3);
                 Operation::I32Const(-8);
+                Operation::I32Const(3);
                 Operation::I32RemS;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(-2)]);
         }
         { // I32RemU
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
-                Operation::I32Const(3);
                 Operation::I32Const(8);
+                Operation::I32Const(3);
                 Operation::I32RemS;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(2)]);
         }
         { // I32And
@@ -2610,7 +3114,7 @@ mod tests {
                 Operation::I32And;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(1)]);
         }
         {
@@ -2621,7 +3125,7 @@ mod tests {
                 Operation::I32And;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(0)]);
         }
         { // I32Or
@@ -2632,7 +3136,7 @@ mod tests {
                 Operation::I32Or;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(1)]);
         }
         { // I32Xor
@@ -2643,7 +3147,7 @@ mod tests {
                 Operation::I32Xor;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(1)]);
         }
         {
@@ -2654,73 +3158,73 @@ mod tests {
                 Operation::I32Xor;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(0)]);
         }
         { // I32Shl
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
-                Operation::I32Const(3); // shift by three places
                 Operation::I32Const(1);
+                Operation::I32Const(3); // shift by three places
                 Operation::I32Shl;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(8)]);
         }
         { // I32ShrS
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
-                Operation::I32Const(3); // shift by three places
                 Operation::I32Const(8);
+                Operation::I32Const(3); // shift by three places
                 Operation::I32ShrS;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(1)]);
         }
         {
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
-                Operation::I32Const(4); // shift by four places
                 Operation::I32Const(0xffffffff);
+                Operation::I32Const(4); // shift by four places
                 Operation::I32ShrS;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(0xffffffff)]); // sign is preserved, therefore it doesn't change
         }
         { // I32ShrU
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
-                Operation::I32Const(4); // shift by four places
                 Operation::I32Const(0xffffffff);
+                Operation::I32Const(4); // shift by four places
                 Operation::I32ShrU;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(0x0fffffff)]);
         }
         { // I32Rotl
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
-                Operation::I32Const(4); // shift by four places
                 Operation::I32Const(0xc0ffffff);
+                Operation::I32Const(4); // shift by four places
                 Operation::I32Rotl;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(0x0ffffffc)]);
         }
         { // I32Rotr
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
-                Operation::I32Const(4); // shift by four places
                 Operation::I32Const(0xdeadbeef);
+                Operation::I32Const(4); // shift by four places
                 Operation::I32Rotr;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(0xfdeadbee)]);
         }
     }
@@ -2734,7 +3238,7 @@ mod tests {
                 Operation::I64Clz;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(48)]);
         }
         { // I64Ctz
@@ -2744,7 +3248,7 @@ mod tests {
                 Operation::I64Ctz;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(16)]);
         }
         { // I64Popcnt
@@ -2754,7 +3258,7 @@ mod tests {
                 Operation::I64Popcnt;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(12)]);
         }
         { // I64Add
@@ -2765,18 +3269,18 @@ mod tests {
                 Operation::I64Add;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(42)]);
         }
         { // I64Sub
             sf!(sf);
             let block = block! { Value(ValueType::I64), {
-                Operation::I64Const(10);
                 Operation::I64Const(52);
+                Operation::I64Const(10);
                 Operation::I64Sub;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(42)]);
         }
         { // I64Mul
@@ -2787,51 +3291,51 @@ mod tests {
                 Operation::I64Mul;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(42)]);
         }
         { // I64DivS
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
-                Operation::I64Const(-2);
                 Operation::I64Const(80);
+                Operation::I64Const(-2);
                 Operation::I64DivS;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(-40)]);
         }
         { // I64DivU
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
-                Operation::I64Const(2);
                 Operation::I64Const(60000);
+                Operation::I64Const(2);
                 Operation::I64DivU;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(30000)]);
         }
         { // I64RemS
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
-                Operation::I64Const(3);
                 Operation::I64Const(-8);
+                Operation::I64Const(3);
                 Operation::I64RemS;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(-2)]);
         }
         { // I64RemU
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
-                Operation::I64Const(3);
                 Operation::I64Const(8);
+                Operation::I64Const(3);
                 Operation::I64RemS;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(2)]);
         }
         { // I64And
@@ -2842,7 +3346,7 @@ mod tests {
                 Operation::I64And;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(1)]);
         }
         {
@@ -2853,7 +3357,7 @@ mod tests {
                 Operation::I64And;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(0)]);
         }
         { // I64Or
@@ -2864,7 +3368,7 @@ mod tests {
                 Operation::I64Or;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(1)]);
         }
         { // I64Xor
@@ -2875,7 +3379,7 @@ mod tests {
                 Operation::I64Xor;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(1)]);
         }
         {
@@ -2886,73 +3390,73 @@ mod tests {
                 Operation::I64Xor;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(0)]);
         }
         { // I64Shl
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
-                Operation::I64Const(3); // shift by three places
                 Operation::I64Const(1);
+                Operation::I64Const(3); // shift by three places
                 Operation::I64Shl;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(8)]);
         }
         { // I64ShrS
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
-                Operation::I64Const(3); // shift by three places
                 Operation::I64Const(8);
+                Operation::I64Const(3); // shift by three places
                 Operation::I64ShrS;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(1)]);
         }
         {
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
-                Operation::I64Const(4); // shift by four places
                 Operation::I64Const(0xf0ffffff_ffffffff);
+                Operation::I64Const(4); // shift by four places
                 Operation::I64ShrS;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(0xff0fffff_ffffffff)]); // sign is preserved, therefore it doesn't change
         }
         { // I64ShrU
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
-                Operation::I64Const(4); // shift by four places
                 Operation::I64Const(0xf0ffffff_ffffffff);
+                Operation::I64Const(4); // shift by four places
                 Operation::I64ShrU;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(0x0f0fffff_ffffffff)]);
         }
         { // I64Rotl
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
-                Operation::I64Const(4); // shift by four places
                 Operation::I64Const(0xc0ffffff_ffffffff);
+                Operation::I64Const(4); // shift by four places
                 Operation::I64Rotl;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(0x0fffffff_fffffffc)]);
         }
         { // I64Rotr
             sf!(sf);
             let block = block! { Value(ValueType::I32), {
-                Operation::I64Const(4); // shift by four places
                 Operation::I64Const(0xdeadbeef_cafebabe);
+                Operation::I64Const(4); // shift by four places
                 Operation::I64Rotr;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(0xedeadbee_fcafebab)]);
         }
     }
@@ -2966,7 +3470,7 @@ mod tests {
                 Operation::F32Abs;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F32(1.5)]);
         }
         { // F32Negs
@@ -2976,7 +3480,7 @@ mod tests {
                 Operation::F32Neg;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F32(1.5)]);
         }
         { // F32Ceil
@@ -2986,7 +3490,7 @@ mod tests {
                 Operation::F32Ceil;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F32(-1.0)]);
         }
         { // F32Floor
@@ -2996,7 +3500,7 @@ mod tests {
                 Operation::F32Floor;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F32(-2.0)]);
         }
         { // F32Trunc
@@ -3006,7 +3510,7 @@ mod tests {
                 Operation::F32Trunc;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F32(-1.0)]);
         }
         { // F32Nearest
@@ -3016,7 +3520,7 @@ mod tests {
                 Operation::F32Nearest;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F32(2.0)]);
         }
         { // F32Sqrt
@@ -3026,7 +3530,7 @@ mod tests {
                 Operation::F32Sqrt;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F32(2.0)]);
         }
         { // F32Add
@@ -3037,18 +3541,18 @@ mod tests {
                 Operation::F32Add;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F32(5.5)]);
         }
         { // F32Add
             sf!(sf);
             let block = block! { Value(ValueType::F32), {
-                Operation::F32Const(4.0);
                 Operation::F32Const(1.5);
+                Operation::F32Const(4.0);
                 Operation::F32Sub;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F32(-2.5)]);
         }
         { // F32Mul
@@ -3059,51 +3563,51 @@ mod tests {
                 Operation::F32Mul;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F32(6.0)]);
         }
         { // F32Div
             sf!(sf);
             let block = block! { Value(ValueType::F32), {
-                Operation::F32Const(3.0);
                 Operation::F32Const(6.0);
+                Operation::F32Const(3.0);
                 Operation::F32Div;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F32(2.0)]);
         }
         { // F32Min
             sf!(sf);
             let block = block! { Value(ValueType::F32), {
-                Operation::F32Const(4.0);
                 Operation::F32Const(1.5);
+                Operation::F32Const(4.0);
                 Operation::F32Min;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F32(1.5)]);
         }
         { // F32Max
             sf!(sf);
             let block = block! { Value(ValueType::F32), {
-                Operation::F32Const(4.0);
                 Operation::F32Const(1.5);
+                Operation::F32Const(4.0);
                 Operation::F32Max;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F32(4.0)]);
         }
         { // F32Copysign
             sf!(sf);
             let block = block! { Value(ValueType::F32), {
-                Operation::F32Const(3.14);
                 Operation::F32Const(-100.0);
+                Operation::F32Const(3.14);
                 Operation::F32Copysign;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F32(-3.14)]);
         }
     }
@@ -3117,7 +3621,7 @@ mod tests {
                 Operation::F64Abs;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F64(1.5)]);
         }
         { // F64Neg
@@ -3127,7 +3631,7 @@ mod tests {
                 Operation::F64Neg;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F64(1.5)]);
         }
         { // F64Ceil
@@ -3137,7 +3641,7 @@ mod tests {
                 Operation::F64Ceil;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F64(-1.0)]);
         }
         { // F64Floor
@@ -3147,7 +3651,7 @@ mod tests {
                 Operation::F64Floor;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F64(-2.0)]);
         }
         { // F64Trunc
@@ -3157,7 +3661,7 @@ mod tests {
                 Operation::F64Trunc;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F64(-1.0)]);
         }
         { // F64Nearest
@@ -3167,7 +3671,7 @@ mod tests {
                 Operation::F64Nearest;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F64(2.0)]);
         }
         { // F64Sqrt
@@ -3177,7 +3681,7 @@ mod tests {
                 Operation::F64Sqrt;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F64(2.0)]);
         }
         { // F64Add
@@ -3188,18 +3692,18 @@ mod tests {
                 Operation::F64Add;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F64(5.5)]);
         }
         { // F64Add
             sf!(sf);
             let block = block! { Value(ValueType::F32), {
-                Operation::F64Const(4.0);
                 Operation::F64Const(1.5);
+                Operation::F64Const(4.0);
                 Operation::F64Sub;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F64(-2.5)]);
         }
         { // F64Mul
@@ -3210,51 +3714,51 @@ mod tests {
                 Operation::F64Mul;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F64(6.0)]);
         }
         { // F64Div
             sf!(sf);
             let block = block! { Value(ValueType::F64), {
-                Operation::F64Const(3.0);
                 Operation::F64Const(6.0);
+                Operation::F64Const(3.0);
                 Operation::F64Div;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F64(2.0)]);
         }
         { // F64Min
             sf!(sf);
             let block = block! { Value(ValueType::F32), {
-                Operation::F64Const(4.0);
                 Operation::F64Const(1.5);
+                Operation::F64Const(4.0);
                 Operation::F64Min;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F64(1.5)]);
         }
         { // F64Max
             sf!(sf);
             let block = block! { Value(ValueType::F32), {
-                Operation::F64Const(4.0);
                 Operation::F64Const(1.5);
+                Operation::F64Const(4.0);
                 Operation::F64Max;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F64(4.0)]);
         }
         { // F64Copysign
             sf!(sf);
             let block = block! { Value(ValueType::F32), {
-                Operation::F64Const(3.14);
                 Operation::F64Const(-100.0);
+                Operation::F64Const(3.14);
                 Operation::F64Copysign;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F64(-3.14)]);
         }
     }
@@ -3268,7 +3772,7 @@ mod tests {
                 Operation::I32WrapI64;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(0xcafebabe)]);
         }
         { // I32TruncSF32
@@ -3278,7 +3782,7 @@ mod tests {
                 Operation::I32TruncSF32;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(3)]);
         }
         { // I32TruncSF32
@@ -3288,7 +3792,7 @@ mod tests {
                 Operation::I32TruncSF32;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(-3)]);
         }
         { // I32TruncUF32
@@ -3298,8 +3802,16 @@ mod tests {
                 Operation::I32TruncUF32;
                 Operation::End;
             }};
-            block.execute(&mut sf);
-            assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(-3)]);
+            assert_eq!(block.execute(&mut sf), Err(Trap::IntegerOverflow));
+        }
+        { // I32TruncSF32
+            sf!(sf);
+            let block = block! { Value(ValueType::I32), {
+                Operation::F32Const(::std::f32::NAN);
+                Operation::I32TruncSF32;
+                Operation::End;
+            }};
+            assert_eq!(block.execute(&mut sf), Err(Trap::InvalidConversionToInteger));
         }
 //        Operation::I32TruncSF32 => {op!(a:F32 | I32 => a as i32)},
 //        Operation::I32TruncUF32 => {op!(a:F32 | I32 => a as i32)},
@@ -3332,7 +3844,7 @@ mod tests {
                 Operation::I32ReinterpretF32;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(1)]);
         }
         { // I64ReinterpretF64
@@ -3342,7 +3854,7 @@ mod tests {
                 Operation::I64ReinterpretF64;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I64(0x40091EB851EB851F)]);
         }
         { // F32ReinterpretI32
@@ -3352,7 +3864,7 @@ mod tests {
                 Operation::F32ReinterpretI32;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F32(1.40129846432481707092372958329E-45)]);
         }
         { // F64ReinterpretI64
@@ -3362,8 +3874,199 @@ mod tests {
                 Operation::F64ReinterpretI64;
                 Operation::End;
             }};
-            block.execute(&mut sf);
+            block.execute(&mut sf).unwrap();
             assert_eq!(sf.stack, &mut vec![ValueTypeProvider::F64(3.14)]);
         }
     }
+    #[test]
+    fn integer_division_traps() {
+        { // I32DivS by zero
+            sf!(sf);
+            let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(42);
+                Operation::I32Const(0);
+                Operation::I32DivS;
+                Operation::End;
+            }};
+            assert_eq!(block.execute(&mut sf), Err(Trap::IntegerDivideByZero));
+        }
+        { // I64RemU by zero
+            sf!(sf);
+            let block = block! { Value(ValueType::I64), {
+                Operation::I64Const(42);
+                Operation::I64Const(0);
+                Operation::I64RemU;
+                Operation::End;
+            }};
+            assert_eq!(block.execute(&mut sf), Err(Trap::IntegerDivideByZero));
+        }
+        { // I32DivS overflow
+            sf!(sf);
+            let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(::std::i32::MIN);
+                Operation::I32Const(-1);
+                Operation::I32DivS;
+                Operation::End;
+            }};
+            assert_eq!(block.execute(&mut sf), Err(Trap::IntegerOverflow));
+        }
+    }
+
+    #[test]
+    fn memory_access_out_of_bounds_traps() {
+        {
+            sf!(sf);
+            let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(0);
+                Operation::I32Load(MemoryImmediate {
+                    flags: 0,
+                    offset: 0
+                });
+                Operation::End;
+            }};
+            assert_eq!(block.execute(&mut sf), Err(Trap::MemoryOutOfBounds));
+        }
+        {
+            sf!(sf);
+            setup_memory!(sf, 0, []);
+            let block = block! { Value(ValueType::I32), {
+                Operation::I32Const(0);
+                Operation::I32Const(42);
+                Operation::I32Store(MemoryImmediate {
+                    flags: 0,
+                    offset: 65536
+                });
+                Operation::End;
+            }};
+            assert_eq!(block.execute(&mut sf), Err(Trap::MemoryOutOfBounds));
+        }
+    }
+
+    #[test]
+    fn memory_access_uses_the_address_operand() {
+        sf!(sf);
+        setup_memory!(sf, 100, [0xef, 0xbe, 0xad, 0xde]);
+        let block = block! { Value(ValueType::I32), {
+            Operation::I32Const(100);
+            Operation::I32Const(96);
+            Operation::I32Load(MemoryImmediate {
+                flags: 2,
+                offset: 4
+            });
+            Operation::I32Store(MemoryImmediate {
+                flags: 2,
+                offset: 4
+            });
+            Operation::End;
+        }};
+        block.execute(&mut sf).unwrap();
+        assert_eq!(sf.data.memories[0].borrow().values[100..108], [0xef, 0xbe, 0xad, 0xde, 0xef, 0xbe, 0xad, 0xde]);
+    }
+
+    #[test]
+    fn memory_access_is_exact_width() {
+        sf!(sf);
+        setup_memory!(sf, 65532, [0xef, 0xbe, 0xad, 0xde]);
+        let block = block! { Value(ValueType::F32), {
+            Operation::I32Const(65535);
+            Operation::I32Load8U(MemoryImmediate {
+                flags: 0,
+                offset: 0
+            });
+            Operation::I32Const(65532);
+            Operation::F32Load(MemoryImmediate {
+                flags: 2,
+                offset: 0
+            });
+            Operation::End;
+        }};
+        block.execute(&mut sf).unwrap();
+        assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(0xde), ValueTypeProvider::F32(f32::from_bits(0xdeadbeef))]);
+    }
+
+    #[test]
+    fn memory_access_straddling_the_end_traps() {
+        sf!(sf);
+        setup_memory!(sf, 0, []);
+        let block = block! { Empty, {
+            Operation::I32Const(65534);
+            Operation::I64Const(42);
+            Operation::I64Store32(MemoryImmediate {
+                flags: 2,
+                offset: 0
+            });
+            Operation::End;
+        }};
+        assert_eq!(block.execute(&mut sf), Err(Trap::MemoryOutOfBounds));
+        assert_eq!(sf.data.memories[0].borrow().values[65534..], [0, 0]);
+    }
+
+    #[test]
+    fn memory_address_does_not_wrap() {
+        sf!(sf);
+        setup_memory!(sf, 0, []);
+        let block = block! { Value(ValueType::I32), {
+            Operation::I32Const(-4);
+            Operation::I32Load(MemoryImmediate {
+                flags: 2,
+                offset: 4
+            });
+            Operation::End;
+        }};
+        assert_eq!(block.execute(&mut sf), Err(Trap::MemoryOutOfBounds));
+    }
+
+    #[test]
+    fn memory_size_and_grow() {
+        sf!(sf);
+        setup_memory!(sf, 0, []);
+        let block = block! { Value(ValueType::I32), {
+            Operation::I32Const(2);
+            Operation::GrowMemory(false);
+            Operation::I32Const(-1);
+            Operation::GrowMemory(false);
+            Operation::CurrentMemory(false);
+            Operation::End;
+        }};
+        block.execute(&mut sf).unwrap();
+        assert_eq!(sf.stack, &mut vec![ValueTypeProvider::I32(1), ValueTypeProvider::I32(-1), ValueTypeProvider::I32(3)]);
+    }
+
+    #[test]
+    fn missing_operands_trap() {
+        sf!(sf);
+        let block = block! { Value(ValueType::I32), {
+            Operation::I32Const(42);
+            Operation::I32Add;
+            Operation::End;
+        }};
+        assert_eq!(block.execute(&mut sf), Err(Trap::StackUnderflow));
+    }
+
+    #[test]
+    fn mismatched_operands_trap() {
+        sf!(sf);
+        let block = block! { Value(ValueType::I32), {
+            Operation::I64Const(1);
+            Operation::I32Const(42);
+            Operation::I32Add;
+            Operation::End;
+        }};
+        assert_eq!(block.execute(&mut sf), Err(Trap::TypeMismatch));
+    }
+
+    #[test]
+    fn call_indirect_without_table_traps() {
+        sf!(sf);
+        sf.data.types.push(TypeDefinition::Func(FuncSignature {
+            parameters: vec![],
+            returns: vec![]
+        }));
+        let block = block! { Empty, {
+            Operation::I32Const(0);
+            Operation::CallIndirect(0, false);
+            Operation::End;
+        }};
+        assert_eq!(block.execute(&mut sf), Err(Trap::UndefinedTableElement));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,121 @@
+use std::cell::Ref;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use parse_tree::functions::EncodedBody;
+use parse_tree::functions::FuncSignature;
+use parse_tree::language_types::ValueType;
+use parse_tree::names::Names;
+use parse_tree::types::TypeDefinition;
+
+use parser;
+
+use runtime_tree::bytecode;
+use runtime_tree::bytecode::CompiledBody;
+use runtime_tree::Trap;
+
+use validator::Context;
+
+/// What every lazily compiled function in a module needs to be checked and compiled.
+pub struct LazyContext {
+    pub validation: Context,
+    pub signatures: Vec<FuncSignature>,
+    pub types: Vec<TypeDefinition>,
+    pub names: Names,
+}
+
+/// A function body that's decoded, validated and compiled the first time it's called. If any of
+/// that fails, every call to it traps with `Trap::InvalidFunction`.
+pub struct LazyBody {
+    index: usize,
+    encoded: EncodedBody,
+    context: Rc<LazyContext>,
+    compiled: RefCell<Option<Result<(Vec<ValueType>, Rc<CompiledBody>), Trap>>>,
+}
+
+impl LazyBody {
+    pub fn new(index: usize, encoded: EncodedBody, context: Rc<LazyContext>) -> LazyBody {
+        LazyBody {
+            index,
+            encoded,
+            context,
+            compiled: RefCell::new(None),
+        }
+    }
+
+    /// The function's declared locals and code, compiling them if this is the first call.
+    pub fn compiled(&self) -> Result<Ref<(Vec<ValueType>, Rc<CompiledBody>)>, Trap> {
+        if self.compiled.borrow().is_none() {
+            let compiled = self.compile();
+            *self.compiled.borrow_mut() = Some(compiled);
+        }
+        let compiled = self.compiled.borrow();
+        if let Some(Err(ref trap)) = *compiled {
+            return Err(trap.clone());
+        }
+        Ok(Ref::map(compiled, |compiled| match *compiled {
+            Some(Ok(ref body)) => body,
+            _ => unreachable!()
+        }))
+    }
+
+    fn compile(&self) -> Result<(Vec<ValueType>, Rc<CompiledBody>), Trap> {
+        let context = &self.context;
+        let invalid = |error: &::std::fmt::Display| {
+            Trap::InvalidFunction(format!("{} is invalid: {}", context.names.describe_function(self.index), error))
+        };
+        debug!("Compiling {} on its first call", context.names.describe_function(self.index));
+        let body = parser::decode_body(&self.encoded, self.index).map_err(|e| invalid(&e))?;
+        context.validation.validate_body(self.index, &body).map_err(|e| invalid(&e))?;
+        let code = bytecode::compile(&body, &context.signatures[self.index], &context.signatures, &context.types)
+            .map_err(|e| invalid(&e))?;
+        Ok((body.locals, Rc::new(code)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::ModuleParser;
+    use runtime_tree::Linker;
+    use runtime_tree::Trap;
+    use runtime_tree::ValueTypeProvider;
+    use validator;
+
+    // Functions `() -> i32` exported as "good", which returns 42, "bad", which has an unknown
+    // opcode, and "wrong", which returns an i64
+    const MODULE: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f,
+        0x03, 0x04, 0x03, 0x00, 0x00, 0x00,
+        0x07, 0x16, 0x03,
+        0x04, b'g', b'o', b'o', b'd', 0x00, 0x00,
+        0x03, b'b', b'a', b'd', 0x00, 0x01,
+        0x05, b'w', b'r', b'o', b'n', b'g', 0x00, 0x02,
+        0x0a, 0x0f, 0x03,
+        0x04, 0x00, 0x41, 0x2a, 0x0b,
+        0x03, 0x00, 0xff, 0x0b,
+        0x04, 0x00, 0x42, 0x00, 0x0b,
+    ];
+
+    #[test]
+    fn bodies_are_compiled_when_first_called() {
+        let module = ModuleParser::default().lazy(true).parse_module(MODULE).unwrap();
+        validator::validate(&module).unwrap();
+        let template = Linker::new().link(&module).unwrap();
+        let mut instance = template.instantiate().unwrap();
+        let mut exports = instance.exports();
+        assert_eq!(exports.call_fn("good", vec![]), Ok(vec![ValueTypeProvider::I32(42)]));
+        assert_eq!(exports.call_fn("good", vec![]), Ok(vec![ValueTypeProvider::I32(42)]));
+        match exports.call_fn("bad", vec![]) {
+            Err(Trap::InvalidFunction(message)) => assert!(message.starts_with("function 1 is invalid: Unknown opcode")),
+            result => panic!("calling a malformed function gave {:?}", result)
+        }
+        assert_eq!(exports.call_fn("wrong", vec![]),
+            Err(Trap::InvalidFunction("function 2 is invalid: expected I32 but found I64".to_string())));
+    }
+
+    #[test]
+    fn eager_parsing_rejects_the_same_module() {
+        assert!(ModuleParser::default().parse_module(MODULE).is_err());
+    }
+}
@@ -0,0 +1,63 @@
+use std::error::Error;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+
+use parse_tree::functions::FuncSignature;
+
+use parser::ParseError;
+
+use validator::ValidationError;
+
+/// The ways in which resolving a module's imports against what the embedder provides can fail.
+#[derive(Debug)]
+pub enum LinkError {
+    Unresolved { module: String, field: String },
+    KindMismatch { module: String, field: String, expected: &'static str, found: &'static str },
+    UndefinedType { module: String, field: String, index: usize },
+    SignatureMismatch { module: String, field: String, expected: FuncSignature, found: FuncSignature },
+    LimitsMismatch { module: String, field: String },
+    GlobalTypeMismatch { module: String, field: String },
+    InvalidFunction { function: String, error: ParseError },
+    InvalidModule(ParseError),
+    Validation(ValidationError),
+}
+
+impl From<ValidationError> for LinkError {
+    fn from(err: ValidationError) -> LinkError {
+        LinkError::Validation(err)
+    }
+}
+
+impl Display for LinkError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            LinkError::Unresolved { ref module, ref field } =>
+                write!(f, "import {}.{} was not provided", module, field),
+            LinkError::KindMismatch { ref module, ref field, expected, found } =>
+                write!(f, "import {}.{} should be a {} but a {} was provided", module, field, expected, found),
+            LinkError::UndefinedType { ref module, ref field, index } =>
+                write!(f, "import {}.{} refers to undefined type {}", module, field, index),
+            LinkError::SignatureMismatch { ref module, ref field, ref expected, ref found } =>
+                write!(f, "import {}.{} should have signature {:?} but has {:?}", module, field, expected, found),
+            LinkError::LimitsMismatch { ref module, ref field } =>
+                write!(f, "import {}.{} does not satisfy the declared limits", module, field),
+            LinkError::GlobalTypeMismatch { ref module, ref field } =>
+                write!(f, "import {}.{} does not have the declared global type", module, field),
+            LinkError::InvalidFunction { ref function, ref error } => write!(f, "{} is invalid: {}", function, error),
+            LinkError::InvalidModule(ref err) => write!(f, "invalid module: {}", err),
+            LinkError::Validation(ref err) => write!(f, "invalid module: {}", err),
+        }
+    }
+}
+
+impl Error for LinkError {
+    fn source(&self) -> Option<&(Error + 'static)> {
+        match *self {
+            LinkError::InvalidFunction { ref error, .. } => Some(error),
+            LinkError::InvalidModule(ref err) => Some(err),
+            LinkError::Validation(ref err) => Some(err),
+            _ => None
+        }
+    }
+}
@@ -0,0 +1,623 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use parse_tree::functions::FuncSignature;
+use parse_tree::language_types::ImportKind;
+use parse_tree::language_types::ResizableLimits;
+use parse_tree::language_types::ValueType;
+use parse_tree::tables::Table;
+use parse_tree::types::TypeDefinition;
+use parse_tree::ParseModule;
+
+use parser::ParseErrorKind;
+
+use runtime_tree::bytecode;
+use runtime_tree::Func;
+use runtime_tree::FuncKind;
+use runtime_tree::Import;
+use runtime_tree::lazy::LazyBody;
+use runtime_tree::lazy::LazyContext;
+use runtime_tree::LinkError;
+use runtime_tree::ModuleTemplate;
+use runtime_tree::ValueTypeProvider;
+
+use validator;
+use validator::Context;
+
+/// Holds the items an embedder provides under module and field names, and resolves a module's
+/// imports against them.
+///
+/// Every import has to be provided with a matching type: functions need the same signature, tables
+/// and memories have to be at least as big as the import asks for and can't be allowed to grow
+/// further than it allows, and globals need the same type and mutability.
+#[derive(Default)]
+pub struct Linker {
+    imports: HashMap<String, HashMap<String, Import>>
+}
+
+impl Linker {
+    pub fn new() -> Linker {
+        Linker::default()
+    }
+
+    /// Provides `import` as `module.field`, replacing anything already provided under that name.
+    pub fn define(&mut self, module: &str, field: &str, import: Import) -> &mut Linker {
+        self.imports.entry(module.to_string()).or_insert_with(HashMap::new).insert(field.to_string(), import);
+        self
+    }
+
+    /// Validates `module`, then resolves all of its imports and compiles its functions, ready for
+    /// instantiation. Functions that were left encoded are validated and compiled the first time
+    /// they're called instead.
+    ///
+    /// Nothing is taken out of the linker, so it can resolve the same item more than once and link
+    /// any number of modules.
+    pub fn link(&self, module: &ParseModule) -> Result<ModuleTemplate, LinkError> {
+        validator::validate(module)?;
+        let mut functions = vec![];
+        let mut imported_tables = vec![];
+        let mut imported_memories = vec![];
+        let mut imported_globals = vec![];
+        for entry in &module.imports {
+            let module_name = &entry.module[..];
+            let field = &entry.field[..];
+            let import = self.imports.get(module_name).and_then(|provided| provided.get(field));
+            let import = match import {
+                Some(import) => import,
+                None => return Err(LinkError::Unresolved {
+                    module: module_name.to_string(),
+                    field: field.to_string()
+                })
+            };
+            match (&entry.kind, import) {
+                (&ImportKind::Function(index), &Import::Function(ref signature, ref f)) => {
+                    let expected = match module.types.get(index) {
+                        Some(&TypeDefinition::Func(ref expected)) => expected.clone(),
+                        None => return Err(LinkError::UndefinedType {
+                            module: module_name.to_string(),
+                            field: field.to_string(),
+                            index
+                        })
+                    };
+                    if *signature != expected {
+                        return Err(LinkError::SignatureMismatch {
+                            module: module_name.to_string(),
+                            field: field.to_string(),
+                            expected,
+                            found: signature.clone()
+                        });
+                    }
+                    functions.push(Func {
+                        signature: expected,
+                        kind: FuncKind::Host(f.clone())
+                    });
+                },
+                (&ImportKind::Table(ref table_type), &Import::Table(ref table)) => {
+                    let fits = {
+                        let Table::AnyFunc{ref limits, ref values} = *table;
+                        satisfies(limits, values.len() as u64, &table_type.limits)
+                    };
+                    if !fits {
+                        return Err(LinkError::LimitsMismatch {
+                            module: module_name.to_string(),
+                            field: field.to_string()
+                        });
+                    }
+                    imported_tables.push(table.clone());
+                },
+                (&ImportKind::Memory(ref limits), &Import::Memory(ref memory)) => {
+                    let fits = {
+                        let memory = memory.borrow();
+                        satisfies(&memory.limits, memory.size() as u64, limits)
+                    };
+                    if !fits {
+                        return Err(LinkError::LimitsMismatch {
+                            module: module_name.to_string(),
+                            field: field.to_string()
+                        });
+                    }
+                    imported_memories.push(memory.clone());
+                },
+                (&ImportKind::Global(ref expected), &Import::Global(ref global_type, ref value)) => {
+                    if expected != global_type || !has_type(&value.borrow(), &expected.content_type) {
+                        return Err(LinkError::GlobalTypeMismatch {
+                            module: module_name.to_string(),
+                            field: field.to_string()
+                        });
+                    }
+                    imported_globals.push(value.clone());
+                },
+                (kind, import) => return Err(LinkError::KindMismatch {
+                    module: module_name.to_string(),
+                    field: field.to_string(),
+                    expected: import_kind_name(kind),
+                    found: import_name(import)
+                })
+            }
+        }
+
+        let mut signatures: Vec<FuncSignature> = vec![];
+        for index in module.function_space() {
+            match module.types.get(index) {
+                Some(&TypeDefinition::Func(ref signature)) => signatures.push(signature.clone()),
+                None => return Err(LinkError::InvalidModule(ParseErrorKind::NonExistantTypeReference.into()))
+            }
+        }
+        let defined = signatures[functions.len()..].to_vec();
+        let bodies = module.encoded_bodies.len() + module.function_bodies.len();
+        if defined.len() != bodies {
+            return Err(LinkError::InvalidModule(ParseErrorKind::FunctionCountMismatch {
+                signatures: defined.len(),
+                bodies
+            }.into()));
+        }
+        if !module.encoded_bodies.is_empty() {
+            let context = Rc::new(LazyContext {
+                validation: Context::new(module)?,
+                signatures: signatures.clone(),
+                types: module.types.clone(),
+                names: module.names.clone()
+            });
+            for (signature, body) in defined.iter().zip(module.encoded_bodies.iter()) {
+                let index = functions.len();
+                functions.push(Func {
+                    signature: signature.clone(),
+                    kind: FuncKind::Lazy(LazyBody::new(index, body.clone(), context.clone()))
+                });
+            }
+        }
+        for (signature, body) in defined.into_iter().skip(module.encoded_bodies.len()).zip(module.function_bodies.iter()) {
+            let code = match bytecode::compile(body, &signature, &signatures, &module.types) {
+                Ok(code) => code,
+                Err(error) => return Err(LinkError::InvalidFunction {
+                    function: module.names.describe_function(functions.len()),
+                    error
+                })
+            };
+            functions.push(Func {
+                signature,
+                kind: FuncKind::Wasm {
+                    locals: body.locals.clone(),
+                    code: Rc::new(code)
+                }
+            });
+        }
+
+        Ok(ModuleTemplate {
+            exports: module.exports.iter().map(|export| (export.field.to_string(), export.kind.clone())).collect(),
+            start_function: module.start_function,
+            types: module.types.clone(),
+            imported_globals,
+            globals: module.globals.clone(),
+            imported_memories,
+            memories: module.memories.clone(),
+            functions,
+            imported_tables,
+            tables: module.tables.clone(),
+            elements: module.elements.clone(),
+            data: module.data.iter().map(|segment| segment.clone().into_owned()).collect(),
+            names: module.names.clone()
+        })
+    }
+}
+
+impl From<HashMap<String, HashMap<String, Import>>> for Linker {
+    fn from(imports: HashMap<String, HashMap<String, Import>>) -> Linker {
+        Linker {
+            imports
+        }
+    }
+}
+
+// Whether something with `limits` and currently `size` big can stand in for an import declaring `declared`
+fn satisfies(limits: &ResizableLimits, size: u64, declared: &ResizableLimits) -> bool {
+    if size < declared.initial {
+        return false;
+    }
+    match (declared.maximum, limits.maximum) {
+        (None, _) => true,
+        (Some(declared), Some(maximum)) => maximum <= declared,
+        (Some(_), None) => false
+    }
+}
+
+fn has_type(value: &ValueTypeProvider, value_type: &ValueType) -> bool {
+    match (value, value_type) {
+        (&ValueTypeProvider::I32(_), &ValueType::I32) |
+        (&ValueTypeProvider::I64(_), &ValueType::I64) |
+        (&ValueTypeProvider::F32(_), &ValueType::F32) |
+        (&ValueTypeProvider::F64(_), &ValueType::F64) => true,
+        _ => false
+    }
+}
+
+fn import_kind_name(kind: &ImportKind) -> &'static str {
+    match *kind {
+        ImportKind::Function(_) => "function",
+        ImportKind::Table(_) => "table",
+        ImportKind::Memory(_) => "memory",
+        ImportKind::Global(_) => "global",
+    }
+}
+
+fn import_name(import: &Import) -> &'static str {
+    match *import {
+        Import::Function(..) => "function",
+        Import::Table(_) => "table",
+        Import::Memory(_) => "memory",
+        Import::Global(..) => "global",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use parse_tree::builder::ModuleBuilder;
+    use parse_tree::globals::Global;
+    use parse_tree::language_types::ExportEntry;
+    use parse_tree::language_types::ExternalKind;
+    use parse_tree::language_types::GlobalType;
+    use parse_tree::language_types::ImportEntry;
+    use parse_tree::language_types::InitExpression;
+    use parse_tree::language_types::LanguageType;
+    use parse_tree::language_types::MemoryImmediate;
+    use parse_tree::language_types::TableType;
+    use parse_tree::language_types::Operation;
+    use parse_tree::functions::EncodedBody;
+    use parse_tree::functions::FuncBody;
+    use parse_tree::memory::Memory;
+    use parse_tree::memory::WASM_PAGE_SIZE;
+    use runtime_tree::ModuleTemplateBuilder;
+    use validator::ValidationError;
+
+    fn signature(parameters: Vec<ValueType>, returns: Vec<ValueType>) -> FuncSignature {
+        FuncSignature {
+            parameters,
+            returns
+        }
+    }
+
+    fn module(imports: Vec<(&str, ImportKind)>) -> ParseModule<'static> {
+        let mut builder = ModuleBuilder::new();
+        builder.signature(vec![ValueType::I32], vec![ValueType::I32]);
+        let mut module = builder.build();
+        // Some imports refer to types that don't exist, which the builder can't declare
+        module.imports = imports.into_iter().map(|(field, kind)| ImportEntry {
+            module: "env".into(),
+            field: field.to_string().into(),
+            kind
+        }).collect();
+        module
+    }
+
+    fn limits(initial: u64, maximum: Option<u64>) -> ResizableLimits {
+        ResizableLimits {
+            initial,
+            maximum
+        }
+    }
+
+    fn host(signature: FuncSignature) -> Import {
+        Import::Function(signature, Rc::new(|_, args| Ok(args)))
+    }
+
+    fn memory(pages: usize, maximum: Option<u64>) -> Import {
+        Import::Memory(Rc::new(RefCell::new(Memory {
+            limits: limits(pages as u64, maximum),
+            values: vec![0; pages * WASM_PAGE_SIZE]
+        })))
+    }
+
+    fn global(global_type: GlobalType, value: ValueTypeProvider) -> Import {
+        Import::Global(global_type, Rc::new(RefCell::new(value)))
+    }
+
+    fn link(imports: Vec<(&str, ImportKind)>, provided: Vec<(&str, Import)>) -> Result<ModuleTemplate, LinkError> {
+        let mut linker = Linker::new();
+        for (field, import) in provided {
+            linker.define("env", field, import);
+        }
+        linker.link(&module(imports))
+    }
+
+    #[test]
+    fn imports_are_resolved() {
+        let template = link(vec![
+            ("f", ImportKind::Function(0)),
+            ("memory", ImportKind::Memory(limits(1, Some(2)))),
+            ("g", ImportKind::Global(GlobalType {
+                content_type: ValueType::I64,
+                mutability: false
+            }))
+        ], vec![
+            ("f", host(signature(vec![ValueType::I32], vec![ValueType::I32]))),
+            ("memory", memory(1, Some(2))),
+            ("g", global(GlobalType {
+                content_type: ValueType::I64,
+                mutability: false
+            }, ValueTypeProvider::I64(7)))
+        ]).unwrap();
+        assert_eq!(template.functions.len(), 1);
+        assert_eq!(template.imported_memories.len(), 1);
+        assert_eq!(*template.imported_globals[0].borrow(), ValueTypeProvider::I64(7));
+
+        let instance = template.instantiate().unwrap();
+        assert_eq!(instance.memories[0].borrow().size(), 1);
+        assert_eq!(*instance.globals[0].borrow(), ValueTypeProvider::I64(7));
+    }
+
+    #[test]
+    fn imports_keep_their_declared_order() {
+        let constant = |value| Import::Function(
+            signature(vec![ValueType::I32], vec![ValueType::I32]),
+            Rc::new(move |_, _| Ok(vec![ValueTypeProvider::I32(value)]))
+        );
+        let fields = vec!["e", "d", "c", "b", "a"];
+        let mut module = module(fields.iter().map(|field| (*field, ImportKind::Function(0))).collect());
+        module.exports.push(ExportEntry {
+            field: "c".into(),
+            kind: ExternalKind::Function(2)
+        });
+        module.exports.push(ExportEntry {
+            field: "a".into(),
+            kind: ExternalKind::Function(4)
+        });
+        let mut linker = Linker::new();
+        for (value, field) in fields.iter().enumerate() {
+            linker.define("env", field, constant(value as i32));
+        }
+        let template = linker.link(&module).unwrap();
+        let mut instance = template.instantiate().unwrap();
+        let mut exports = instance.exports();
+        assert_eq!(exports.call_fn("c", vec![ValueTypeProvider::I32(0)]), Ok(vec![ValueTypeProvider::I32(2)]));
+        assert_eq!(exports.call_fn("a", vec![ValueTypeProvider::I32(0)]), Ok(vec![ValueTypeProvider::I32(4)]));
+    }
+
+    #[test]
+    fn index_spaces_start_with_imports() {
+        let global = |content_type| GlobalType {
+            content_type,
+            mutability: false
+        };
+        let mut module = module(vec![
+            ("g", ImportKind::Global(global(ValueType::I64))),
+            ("f", ImportKind::Function(0)),
+            ("memory", ImportKind::Memory(limits(1, None))),
+        ]);
+        module.types.push(TypeDefinition::Func(signature(vec![], vec![])));
+        module.function_signatures.push(1);
+        module.memories.push(Memory {
+            limits: limits(2, None),
+            values: vec![]
+        });
+        module.globals.push(Global {
+            constraints: global(ValueType::F32),
+            value: InitExpression::F32Const(0.0)
+        });
+        assert_eq!(module.function_space(), vec![0, 1]);
+        assert_eq!(module.memory_space().iter().map(|limits| limits.initial).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(module.global_space().into_iter().map(|global| global.content_type).collect::<Vec<_>>(), vec![ValueType::I64, ValueType::F32]);
+        assert!(module.table_space().is_empty());
+    }
+
+    #[test]
+    fn invalid_functions_are_named() {
+        let mut module = module(vec![("f", ImportKind::Function(0))]);
+        for _ in 0..2 {
+            module.function_signatures.push(0);
+            module.function_bodies.push(FuncBody {
+                locals: vec![],
+                code: vec![Operation::I32Add]
+            });
+        }
+        module.names.functions.insert(1, "first".to_string());
+        let mut linker = Linker::new();
+        linker.define("env", "f", host(signature(vec![ValueType::I32], vec![ValueType::I32])));
+        match linker.link(&module) {
+            Err(error @ LinkError::Validation(ValidationError::InFunction { .. })) => assert!(error.to_string().starts_with("invalid module: in $first")),
+            _ => panic!("expected an invalid function")
+        }
+    }
+
+    #[test]
+    fn every_function_needs_a_body() {
+        let mut module = module(vec![]);
+        module.function_signatures.push(0);
+        module.function_signatures.push(0);
+        module.function_bodies.push(FuncBody {
+            locals: vec![],
+            code: vec![Operation::GetLocal(0)]
+        });
+        match Linker::new().link(&module) {
+            Err(LinkError::Validation(ValidationError::FunctionCountMismatch { signatures: 2, bodies: 1 })) => {},
+            _ => panic!("expected a function count mismatch")
+        }
+    }
+
+    #[test]
+    fn encoded_bodies_come_before_decoded_ones() {
+        let mut module = module(vec![]);
+        module.types.push(TypeDefinition::Func(signature(vec![], vec![ValueType::I64])));
+        module.function_signatures.push(0);
+        module.function_signatures.push(1);
+        // (func (param i32) (result i32) (i32.const 1))
+        module.encoded_bodies.push(EncodedBody {
+            offset: 0,
+            bytes: vec![0x00, 0x41, 0x01, 0x0b]
+        });
+        module.function_bodies.push(FuncBody {
+            locals: vec![],
+            code: vec![Operation::I64Const(2)]
+        });
+        for (field, index) in vec![("encoded", 0), ("decoded", 1)] {
+            module.exports.push(ExportEntry {
+                field: field.into(),
+                kind: ExternalKind::Function(index)
+            });
+        }
+        let template = Linker::new().link(&module).unwrap();
+        let mut instance = template.instantiate().unwrap();
+        let mut exports = instance.exports();
+        assert_eq!(exports.call_fn("encoded", vec![ValueTypeProvider::I32(0)]), Ok(vec![ValueTypeProvider::I32(1)]));
+        assert_eq!(exports.call_fn("decoded", vec![]), Ok(vec![ValueTypeProvider::I64(2)]));
+    }
+
+    #[test]
+    fn missing_imports_are_reported() {
+        match link(vec![("f", ImportKind::Function(0))], vec![]) {
+            Err(LinkError::Unresolved { ref module, ref field }) => assert_eq!((&module[..], &field[..]), ("env", "f")),
+            _ => panic!("expected an unresolved import")
+        }
+        match module(vec![("f", ImportKind::Function(0))]).build(HashMap::new()) {
+            Err(LinkError::Unresolved { .. }) => {},
+            _ => panic!("expected an unresolved import")
+        }
+    }
+
+    #[test]
+    fn kinds_must_match() {
+        match link(vec![("f", ImportKind::Function(0))], vec![("f", memory(1, None))]) {
+            Err(LinkError::KindMismatch { expected, found, .. }) => assert_eq!((expected, found), ("function", "memory")),
+            _ => panic!("expected a kind mismatch")
+        }
+    }
+
+    #[test]
+    fn function_signatures_must_match() {
+        let provided = signature(vec![ValueType::I64], vec![ValueType::I32]);
+        match link(vec![("f", ImportKind::Function(0))], vec![("f", host(provided.clone()))]) {
+            Err(LinkError::SignatureMismatch { expected, found, .. }) => {
+                assert_eq!(expected, signature(vec![ValueType::I32], vec![ValueType::I32]));
+                assert_eq!(found, provided);
+            },
+            _ => panic!("expected a signature mismatch")
+        }
+        match link(vec![("f", ImportKind::Function(1))], vec![("f", host(provided))]) {
+            Err(LinkError::Validation(ValidationError::UndefinedType(1))) => {},
+            _ => panic!("expected an undefined type")
+        }
+    }
+
+    #[test]
+    fn limits_must_be_satisfied() {
+        let declared = || vec![("memory", ImportKind::Memory(limits(2, Some(4))))];
+        assert!(link(declared(), vec![("memory", memory(3, Some(3)))]).is_ok());
+        for provided in vec![memory(1, Some(4)), memory(2, Some(5)), memory(2, None)] {
+            match link(declared(), vec![("memory", provided)]) {
+                Err(LinkError::LimitsMismatch { .. }) => {},
+                _ => panic!("expected a limits mismatch")
+            }
+        }
+
+        let table = vec![("table", ImportKind::Table(TableType {
+            elem_type: LanguageType::Anyfunc,
+            limits: limits(2, None)
+        }))];
+        match link(table, vec![("table", Import::Table(Table::AnyFunc {
+            limits: limits(1, None),
+            values: vec![None]
+        }))]) {
+            Err(LinkError::LimitsMismatch { .. }) => {},
+            _ => panic!("expected a limits mismatch")
+        }
+    }
+
+    #[test]
+    fn global_types_must_match() {
+        let declared = || vec![("g", ImportKind::Global(GlobalType {
+            content_type: ValueType::I32,
+            mutability: false
+        }))];
+        let provided = vec![
+            global(GlobalType { content_type: ValueType::I32, mutability: true }, ValueTypeProvider::I32(1)),
+            global(GlobalType { content_type: ValueType::F32, mutability: false }, ValueTypeProvider::F32(1.0)),
+            global(GlobalType { content_type: ValueType::I32, mutability: false }, ValueTypeProvider::I64(1)),
+        ];
+        for provided in provided {
+            match link(declared(), vec![("g", provided)]) {
+                Err(LinkError::GlobalTypeMismatch { .. }) => {},
+                _ => panic!("expected a global type mismatch")
+            }
+        }
+    }
+
+    #[test]
+    fn the_same_import_can_be_used_twice() {
+        let mut module = module(vec![("f", ImportKind::Function(0)), ("f", ImportKind::Function(0))]);
+        module.exports.push(ExportEntry {
+            field: "second".into(),
+            kind: ExternalKind::Function(1)
+        });
+        let mut linker = Linker::new();
+        linker.define("env", "f", host(signature(vec![ValueType::I32], vec![ValueType::I32])));
+        let template = linker.link(&module).unwrap();
+        assert_eq!(template.functions.len(), 2);
+
+        let mut instance = template.instantiate().unwrap();
+        assert_eq!(instance.exports().call_fn("second", vec![ValueTypeProvider::I32(3)]), Ok(vec![ValueTypeProvider::I32(3)]));
+    }
+
+    #[test]
+    fn a_linker_can_link_several_modules() {
+        let mut linker = Linker::new();
+        linker.define("env", "f", host(signature(vec![ValueType::I32], vec![ValueType::I32])));
+        linker.define("env", "memory", memory(1, None));
+        let first = module(vec![("f", ImportKind::Function(0))]);
+        let second = module(vec![("memory", ImportKind::Memory(limits(1, None))), ("f", ImportKind::Function(0))]);
+        assert_eq!(linker.link(&first).unwrap().functions.len(), 1);
+        let template = linker.link(&second).unwrap();
+        assert_eq!(template.functions.len(), 1);
+        assert_eq!(template.imported_memories.len(), 1);
+    }
+
+    #[test]
+    fn memories_and_globals_are_shared_with_the_host() {
+        let counter = GlobalType {
+            content_type: ValueType::I32,
+            mutability: true
+        };
+        let mut module = module(vec![
+            ("memory", ImportKind::Memory(limits(1, None))),
+            ("counter", ImportKind::Global(counter.clone())),
+        ]);
+        module.function_signatures.push(0);
+        module.function_bodies.push(FuncBody {
+            locals: vec![],
+            code: vec![
+                Operation::I32Const(8),
+                Operation::GetLocal(0),
+                Operation::I32Store(MemoryImmediate { flags: 2, offset: 0 }),
+                Operation::GetGlobal(0),
+                Operation::GetLocal(0),
+                Operation::I32Add,
+                Operation::SetGlobal(0),
+                Operation::GetLocal(0)
+            ]
+        });
+        module.exports.push(ExportEntry {
+            field: "store".into(),
+            kind: ExternalKind::Function(0)
+        });
+
+        let memory = Rc::new(RefCell::new(Memory {
+            limits: limits(1, None),
+            values: vec![0; WASM_PAGE_SIZE]
+        }));
+        let total = Rc::new(RefCell::new(ValueTypeProvider::I32(1)));
+        let mut linker = Linker::new();
+        linker.define("env", "memory", Import::Memory(memory.clone()));
+        linker.define("env", "counter", Import::Global(counter, total.clone()));
+        let template = linker.link(&module).unwrap();
+        let mut instance = template.instantiate().unwrap();
+        instance.exports().call_fn("store", vec![ValueTypeProvider::I32(0x2a)]).unwrap();
+        assert_eq!(memory.borrow().values[8..12], [0x2a, 0, 0, 0]);
+        assert_eq!(*total.borrow(), ValueTypeProvider::I32(0x2b));
+
+        // A second instance works on the same memory and global as the first
+        let mut other = template.instantiate().unwrap();
+        other.exports().call_fn("store", vec![ValueTypeProvider::I32(1)]).unwrap();
+        assert_eq!(memory.borrow().values[8..12], [1, 0, 0, 0]);
+        assert_eq!(*total.borrow(), ValueTypeProvider::I32(0x2c));
+    }
+}
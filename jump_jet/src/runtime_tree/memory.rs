@@ -0,0 +1,169 @@
+use parse_tree::language_types::MemoryImmediate;
+use parse_tree::memory::Memory;
+
+use runtime_tree::Trap;
+
+/// Consulted whenever a module asks to grow its linear memory, with the current and requested size in
+/// pages. Returning false refuses the growth, which `memory.grow` reports to the module as -1.
+/// Instantiation asks about each memory's initial size as growth from 0, and fails if it's refused.
+pub type MemoryLimiter = Box<Fn(u32, u32) -> bool>;
+
+/// How the interpreter accesses and grows linear memory.
+///
+/// An access touches `width` bytes starting at the effective address, which is the i32 popped off
+/// the stack taken as unsigned plus the immediate's static offset. The sum is worked out without
+/// wrapping, so an access near the top of the 4GiB address space can't wrap round to the bottom.
+/// The immediate's alignment is only a hint and doesn't affect the access.
+impl Memory {
+    pub fn slice(&self, immediate: &MemoryImmediate, base: i32, width: usize) -> Result<&[u8], Trap> {
+        let range = self.range(immediate, base, width)?;
+        Ok(&self.values[range.0..range.1])
+    }
+
+    pub fn slice_mut(&mut self, immediate: &MemoryImmediate, base: i32, width: usize) -> Result<&mut [u8], Trap> {
+        let range = self.range(immediate, base, width)?;
+        Ok(&mut self.values[range.0..range.1])
+    }
+
+    /// Runs `memory.grow`, returning the previous size in pages or -1 if the memory can't grow by
+    /// `delta`. The limiter only hears about growth the memory's own maximum allows.
+    pub fn grow_limited(&mut self, delta: u32, limiter: Option<&MemoryLimiter>) -> i32 {
+        let current = self.size();
+        let permitted = match current.checked_add(delta) {
+            Some(desired) if desired <= self.maximum() => match limiter {
+                Some(limiter) => limiter(current, desired),
+                None => true
+            },
+            _ => false
+        };
+        if !permitted {
+            return -1;
+        }
+        match self.grow(delta) {
+            Some(previous) => previous as i32,
+            None => -1
+        }
+    }
+
+    fn range(&self, immediate: &MemoryImmediate, base: i32, width: usize) -> Result<(usize, usize), Trap> {
+        let address = base as u32 as u64 + immediate.offset as u64;
+        match address.checked_add(width as u64) {
+            Some(end) if end <= self.values.len() as u64 => Ok((address as usize, end as usize)),
+            _ => Err(Trap::MemoryOutOfBounds)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use parse_tree::language_types::ResizableLimits;
+    use parse_tree::memory::WASM_PAGE_SIZE;
+
+    fn memory(size: usize) -> Memory {
+        Memory {
+            limits: ResizableLimits {
+                initial: 1,
+                maximum: None
+            },
+            values: (0..size).map(|i| i as u8).collect()
+        }
+    }
+
+    fn immediate(offset: u32) -> MemoryImmediate {
+        MemoryImmediate {
+            flags: 2,
+            offset
+        }
+    }
+
+    #[test]
+    fn base_and_offset_are_added() {
+        let memory = memory(16);
+        assert_eq!(memory.slice(&immediate(2), 3, 4), Ok(&[5, 6, 7, 8][..]));
+    }
+
+    #[test]
+    fn access_must_fit_entirely() {
+        let memory = memory(16);
+        assert_eq!(memory.slice(&immediate(0), 12, 4), Ok(&[12, 13, 14, 15][..]));
+        assert_eq!(memory.slice(&immediate(0), 13, 4), Err(Trap::MemoryOutOfBounds));
+        assert_eq!(memory.slice(&immediate(13), 0, 4), Err(Trap::MemoryOutOfBounds));
+    }
+
+    #[test]
+    fn address_does_not_wrap() {
+        let memory = memory(16);
+        assert_eq!(memory.slice(&immediate(4), -1, 1), Err(Trap::MemoryOutOfBounds));
+        assert_eq!(memory.slice(&immediate(::std::u32::MAX), 1, 1), Err(Trap::MemoryOutOfBounds));
+    }
+
+    #[test]
+    fn alignment_is_only_a_hint() {
+        let mut memory = memory(16);
+        assert_eq!(memory.slice_mut(&immediate(0), 1, 4).map(|bytes| bytes.len()), Ok(4));
+    }
+
+    fn pages(initial: u32, maximum: Option<u64>) -> Memory {
+        Memory {
+            limits: ResizableLimits {
+                initial: initial as u64,
+                maximum
+            },
+            values: vec![0; initial as usize * WASM_PAGE_SIZE]
+        }
+    }
+
+    #[test]
+    fn grow_by_delta() {
+        let mut memory = pages(1, None);
+        assert_eq!(memory.size(), 1);
+        assert_eq!(memory.grow_limited(2, None), 1);
+        assert_eq!(memory.size(), 3);
+        assert_eq!(memory.grow_limited(0, None), 3);
+        assert_eq!(memory.values.len(), 3 * WASM_PAGE_SIZE);
+    }
+
+    #[test]
+    fn grow_respects_maximum() {
+        let mut memory = pages(1, Some(2));
+        assert_eq!(memory.grow_limited(2, None), -1);
+        assert_eq!(memory.size(), 1);
+        assert_eq!(memory.grow_limited(1, None), 1);
+        assert_eq!(memory.grow_limited(1, None), -1);
+    }
+
+    #[test]
+    fn grow_respects_address_space() {
+        let mut memory = pages(0, None);
+        assert_eq!(memory.grow_limited(65537, None), -1);
+        assert_eq!(memory.grow_limited(::std::u32::MAX, None), -1);
+        assert_eq!(memory.size(), 0);
+    }
+
+    #[test]
+    fn limiter_can_veto() {
+        let mut memory = pages(1, None);
+        let limiter: MemoryLimiter = Box::new(|_, desired| desired <= 4);
+        assert_eq!(memory.grow_limited(3, Some(&limiter)), 1);
+        assert_eq!(memory.grow_limited(1, Some(&limiter)), -1);
+        assert_eq!(memory.size(), 4);
+    }
+
+    #[test]
+    fn limiter_observes_permitted_growth() {
+        let mut memory = pages(1, Some(3));
+        let seen = Rc::new(Cell::new((0, 0)));
+        let observed = seen.clone();
+        let limiter: MemoryLimiter = Box::new(move |current, desired| {
+            observed.set((current, desired));
+            true
+        });
+        assert_eq!(memory.grow_limited(2, Some(&limiter)), 1);
+        assert_eq!(seen.get(), (1, 3));
+        assert_eq!(memory.grow_limited(1, Some(&limiter)), -1);
+        assert_eq!(seen.get(), (1, 3));
+    }
+}
@@ -7,76 +7,257 @@ use std;
 use std::cell::RefCell;
 use std::cell::RefMut;
 use std::collections::HashMap;
+use std::rc::Rc;
 
 use parse_tree::functions::FuncSignature;
-use parse_tree::language_types::Block;
-use parse_tree::language_types::BlockType;
+use parse_tree::globals::Global;
 use parse_tree::language_types::ExternalKind;
+use parse_tree::language_types::InitExpression;
 use parse_tree::language_types::Operation;
+use parse_tree::language_types::ResizableLimits;
 use parse_tree::language_types::ValueType;
+use parse_tree::memory::DataSegment;
 use parse_tree::memory::Memory;
+use parse_tree::memory::MAX_PAGES;
+use parse_tree::memory::WASM_PAGE_SIZE;
+use parse_tree::names::Names;
 use parse_tree::ParseModule;
+use parse_tree::tables::ElementSegment;
 use parse_tree::tables::Table;
 use parse_tree::types::TypeDefinition;
 
-use parser::ParseError;
+pub mod bytecode;
+use runtime_tree::bytecode::CompiledBody;
+
+// The benchmarks need nightly, the timing comparison next to them runs anywhere
+#[cfg(test)]
+mod benches;
 
 mod exports;
 use runtime_tree::exports::ExportObj;
 pub use runtime_tree::exports::ExportObject;
 
+mod fuel;
+pub use runtime_tree::fuel::Fuel;
+
 mod globals;
-use runtime_tree::globals::Global;
-use runtime_tree::language_types::StackFrame;
+
+mod instantiation_error;
+pub use runtime_tree::instantiation_error::InstantiationError;
+
+mod interpreter;
+pub use runtime_tree::interpreter::Limits;
 
 mod language_types;
+
+mod lazy;
+use runtime_tree::lazy::LazyBody;
+
+mod link_error;
+pub use runtime_tree::link_error::LinkError;
+
+mod linker;
+pub use runtime_tree::linker::Linker;
+
+mod memory;
+pub use runtime_tree::memory::MemoryLimiter;
 pub use runtime_tree::language_types::Import;
-use runtime_tree::language_types::Execute;
 pub use runtime_tree::language_types::ExternalKindInstance;
 pub use runtime_tree::language_types::ValueTypeProvider;
 
+mod trap;
+pub use runtime_tree::trap::Trap;
+
 //pub type Func = Box<Fn(&mut ModuleInstanceData, Vec<ValueTypeProvider>)->Vec<ValueTypeProvider>>;
 pub struct Func {
     signature: FuncSignature,
-    callable: Box<Fn(&mut ModuleInstanceData, Vec<ValueTypeProvider>)->Vec<ValueTypeProvider>>
+    kind: FuncKind
+}
+
+enum FuncKind {
+    // Shared with the `Linker` that provided it, and with every other module that imports it
+    Host(Rc<Fn(&mut ModuleInstanceData, Vec<ValueTypeProvider>)->Result<Vec<ValueTypeProvider>, Trap>>),
+    // `locals` are the declared locals, the parameters come first and aren't repeated here
+    // and `code` is shared with the frames running it
+    Wasm { locals: Vec<ValueType>, code: Rc<CompiledBody> },
+    // Compiled into a `Wasm` body the first time it's called
+    Lazy(LazyBody)
+}
+
+/// How an instance runs, starting with its start function. See `ModuleTemplate::instantiate_with`.
+#[derive(Default)]
+pub struct InstanceConfig {
+    pub limits: Limits,
+    pub fuel: Fuel,
+    pub memory_limiter: Option<MemoryLimiter>,
 }
 
 pub struct ModuleTemplate {
     exports: HashMap<String, ExternalKind>,
     start_function: Option<usize>,
     types: Vec<TypeDefinition>,
-    globals: Vec<ValueTypeProvider>,
+    // Imported items come first in their index spaces, followed by the ones the module defines
+    imported_globals: Vec<Rc<RefCell<ValueTypeProvider>>>,
+    globals: Vec<Global>,
+    imported_memories: Vec<Rc<RefCell<Memory>>>,
     memories: Vec<Memory>,
     functions: Vec<Func>,
-    tables: Vec<Table>
+    imported_tables: Vec<Table>,
+    tables: Vec<Table>,
+    elements: Vec<ElementSegment>,
+    data: Vec<DataSegment<'static>>,
+    names: Names
 }
 
 impl ModuleTemplate {
-    // TODO not ParseError
-    pub fn instantiate(&self) -> Result<ModuleInstance, ParseError> {
-        Ok(ModuleInstance {
+    /// The names the module gave itself, its functions and their locals, if it had a name section.
+    pub fn names(&self) -> &Names {
+        &self.names
+    }
+
+    /// Creates a new instance of the module with the default configuration, which doesn't meter fuel
+    /// or limit memory. Modules that aren't trusted should go through `instantiate_with` instead.
+    pub fn instantiate(&self) -> Result<ModuleInstance, InstantiationError> {
+        self.instantiate_with(InstanceConfig::default())
+    }
+
+    /// Creates a new instance of the module. Globals are initialised, memories and tables are allocated
+    /// and filled in from the data and element segments, and then the start function is run under
+    /// `config`, which the instance keeps for later calls.
+    ///
+    /// Every segment is checked before any is copied, so a segment that doesn't fit fails the whole
+    /// instantiation rather than leaving it half done.
+    pub fn instantiate_with(&self, config: InstanceConfig) -> Result<ModuleInstance, InstantiationError> {
+        let globals = self.build_globals()?;
+        let memories = self.build_memories(config.memory_limiter.as_ref())?;
+        let mut tables = self.build_tables()?;
+
+        let mut data_offsets = Vec::with_capacity(self.data.len());
+        for (i, segment) in self.data.iter().enumerate() {
+            let offset = evaluate_offset(&segment.offset, &globals)?;
+            let length = match memories.get(segment.index) {
+                Some(memory) => memory.borrow().values.len(),
+                None => return Err(InstantiationError::UndefinedMemory(segment.index))
+            };
+            match offset.checked_add(segment.data.len()) {
+                Some(end) if end <= length => {},
+                _ => return Err(InstantiationError::DataSegmentOutOfBounds(i))
+            }
+            data_offsets.push(offset);
+        }
+        let mut element_offsets = Vec::with_capacity(self.elements.len());
+        for (i, segment) in self.elements.iter().enumerate() {
+            let offset = evaluate_offset(&segment.offset, &globals)?;
+            let length = match tables.get(segment.index) {
+                Some(&Table::AnyFunc{ref values, ..}) => values.len(),
+                None => return Err(InstantiationError::UndefinedTable(segment.index))
+            };
+            match offset.checked_add(segment.elements.len()) {
+                Some(end) if end <= length => {},
+                _ => return Err(InstantiationError::ElementSegmentOutOfBounds(i))
+            }
+            if let Some(index) = segment.elements.iter().find(|index| **index >= self.functions.len()) {
+                return Err(InstantiationError::UndefinedFunction(*index));
+            }
+            element_offsets.push(offset);
+        }
+
+        for (segment, offset) in self.data.iter().zip(data_offsets) {
+            let mut memory = memories[segment.index].borrow_mut();
+            memory.values[offset..offset + segment.data.len()].copy_from_slice(&segment.data);
+        }
+        for (segment, offset) in self.elements.iter().zip(element_offsets) {
+            let Table::AnyFunc{ref mut values, ..} = tables[segment.index];
+            for (value, index) in values[offset..].iter_mut().zip(segment.elements.iter()) {
+                *value = Some(*index);
+            }
+        }
+
+        let instance = ModuleInstance {
             types: self.types.clone(),
             exports: self.build_exports(),
-            globals: RefCell::new(vec![]),
-            memories: RefCell::new(vec![]),
+            globals,
+            memories,
             functions: &self.functions,
-            tables: RefCell::new(vec![]),
-        })
+            tables: RefCell::new(tables),
+            names: &self.names,
+            limits: config.limits,
+            fuel: RefCell::new(config.fuel),
+            memory_limiter: config.memory_limiter,
+        };
+
+        if let Some(start) = self.start_function {
+            match self.functions.get(start) {
+                Some(function) => if !function.signature.parameters.is_empty() || !function.signature.returns.is_empty() {
+                    return Err(InstantiationError::InvalidStartFunction(start));
+                },
+                None => return Err(InstantiationError::UndefinedFunction(start))
+            }
+            if let Err(trap) = interpreter::invoke(&mut instance.get_frame(), start, vec![]) {
+                return Err(InstantiationError::StartFunctionTrapped(trap));
+            }
+        }
+        Ok(instance)
+    }
+
+    // Imported globals are shared with whoever provided them, the module's own are new to this instance
+    fn build_globals(&self) -> Result<Vec<Rc<RefCell<ValueTypeProvider>>>, InstantiationError> {
+        let mut globals = self.imported_globals.clone();
+        for global in &self.globals {
+            let value = evaluate(&global.value, &globals)?;
+            match (&global.constraints.content_type, &value) {
+                (&ValueType::I32, &ValueTypeProvider::I32(_)) |
+                (&ValueType::I64, &ValueTypeProvider::I64(_)) |
+                (&ValueType::F32, &ValueTypeProvider::F32(_)) |
+                (&ValueType::F64, &ValueTypeProvider::F64(_)) => globals.push(Rc::new(RefCell::new(value))),
+                _ => return Err(InstantiationError::GlobalTypeMismatch(globals.len()))
+            }
+        }
+        Ok(globals)
+    }
+
+    // The limiter hears about every memory as if it were growing from nothing, before any is allocated
+    fn build_memories(&self, limiter: Option<&MemoryLimiter>) -> Result<Vec<Rc<RefCell<Memory>>>, InstantiationError> {
+        let permitted = |index: usize, pages: u32| match limiter {
+            Some(limiter) if !limiter(0, pages) => Err(InstantiationError::MemoryLimitExceeded(index)),
+            _ => Ok(())
+        };
+        for (index, memory) in self.imported_memories.iter().enumerate() {
+            permitted(index, memory.borrow().size())?;
+        }
+        let mut memories = self.imported_memories.clone();
+        for memory in &self.memories {
+            let pages = initial_size(&memory.limits, MAX_PAGES as u64)?;
+            permitted(memories.len(), pages as u32)?;
+            memories.push(Rc::new(RefCell::new(Memory {
+                limits: memory.limits.clone(),
+                values: vec![0; pages * WASM_PAGE_SIZE]
+            })));
+        }
+        Ok(memories)
+    }
+
+    fn build_tables(&self) -> Result<Vec<Table>, InstantiationError> {
+        let mut tables = self.imported_tables.clone();
+        for table in &self.tables {
+            let Table::AnyFunc{ref limits, ..} = *table;
+            let size = initial_size(limits, ::std::u32::MAX as u64)?;
+            tables.push(Table::AnyFunc {
+                limits: limits.clone(),
+                values: vec![None; size]
+            });
+        }
+        Ok(tables)
     }
 
     fn build_exports(&self) -> HashMap<String, ExternalKindInstance> {
         let mut exports = HashMap::new();
         for (key, value) in self.exports.iter() {
             exports.insert(key.clone(), match *value {
-                ExternalKind::Function(f) => ExternalKindInstance::Function(
-                    Func{
-                        signature: self.functions[f].signature.clone(),
-                        callable: Box::new(move |module, args|{
-                            println!("getting function {:?}/{:?}", f, module.functions.len());
-                            (module.functions[f].callable)(module, args)
-                    })}
-                ),
-                _ => ExternalKindInstance::Memory(0)
+                ExternalKind::Function(index) => ExternalKindInstance::Function(index),
+                ExternalKind::Table(index) => ExternalKindInstance::Table(index),
+                ExternalKind::Memory(index) => ExternalKindInstance::Memory(index),
+                ExternalKind::Global(index) => ExternalKindInstance::Global(index),
             });
         }
         exports
@@ -84,17 +265,73 @@ impl ModuleTemplate {
 
 }
 
+fn evaluate(expression: &InitExpression, globals: &[Rc<RefCell<ValueTypeProvider>>]) -> Result<ValueTypeProvider, InstantiationError> {
+    Ok(match *expression {
+        InitExpression::I32Const(value) => ValueTypeProvider::I32(value),
+        InitExpression::I64Const(value) => ValueTypeProvider::I64(value),
+        InitExpression::F32Const(value) => ValueTypeProvider::F32(value),
+        InitExpression::F64Const(value) => ValueTypeProvider::F64(value),
+        InitExpression::GetGlobal(index) => match globals.get(index) {
+            Some(value) => value.borrow().clone(),
+            None => return Err(InstantiationError::UndefinedGlobal(index))
+        }
+    })
+}
+
+// Segment offsets are i32s, but are used as unsigned
+fn evaluate_offset(expression: &InitExpression, globals: &[Rc<RefCell<ValueTypeProvider>>]) -> Result<usize, InstantiationError> {
+    match evaluate(expression, globals)? {
+        ValueTypeProvider::I32(offset) => Ok(offset as u32 as usize),
+        _ => Err(InstantiationError::OffsetTypeMismatch)
+    }
+}
+
+fn initial_size(limits: &ResizableLimits, cap: u64) -> Result<usize, InstantiationError> {
+    if limits.initial > limits.maximum.unwrap_or(cap) || limits.initial > cap {
+        return Err(InstantiationError::InvalidLimits);
+    }
+    Ok(limits.initial as usize)
+}
+
 pub struct ModuleInstance<'a> {
     types: Vec<TypeDefinition>,
     exports: HashMap<String, ExternalKindInstance>,
-    globals: RefCell<Vec<ValueTypeProvider>>,
-    memories: RefCell<Vec<Memory>>,
+    globals: Vec<Rc<RefCell<ValueTypeProvider>>>,
+    memories: Vec<Rc<RefCell<Memory>>>,
     functions: &'a Vec<Func>, // TODO we might not need this?
-    tables: RefCell<Vec<Table>>
+    tables: RefCell<Vec<Table>>,
+    names: &'a Names,
+    limits: Limits,
+    fuel: RefCell<Fuel>,
+    memory_limiter: Option<MemoryLimiter>
 }
 
 impl<'a> ModuleInstance<'a> {
-    pub fn exports(&'a mut self) -> Box<ExportObject + 'a> {
+    /// Sets the stack limits that calls into this instance run under.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    pub fn names(&self) -> &Names {
+        self.names
+    }
+
+    /// The fuel budget calls into this instance draw on. It carries over from one call to the next,
+    /// so it can be checked and topped up in between.
+    pub fn fuel(&mut self) -> &mut Fuel {
+        self.fuel.get_mut()
+    }
+
+    /// Sets the hook that decides whether the instance's linear memory may grow.
+    pub fn set_memory_limiter(&mut self, limiter: MemoryLimiter) {
+        self.memory_limiter = Some(limiter);
+    }
+
+    pub fn exports<'b>(&'b mut self) -> Box<ExportObject + 'b> {
         Box::new(ExportObj {
             module: self
         })
@@ -103,176 +340,293 @@ impl<'a> ModuleInstance<'a> {
     pub fn get_frame(&self) -> ModuleInstanceData {
         ModuleInstanceData {
             types: self.types.clone(),
-            globals: self.globals.borrow_mut(),
+            globals: self.globals.clone(),
             functions: self.functions,
-            memories: self.memories.borrow_mut(),
-            tables: self.tables.borrow_mut()
+            memories: self.memories.clone(),
+            tables: self.tables.borrow_mut(),
+            limits: self.limits,
+            fuel: self.fuel.borrow_mut(),
+            memory_limiter: self.memory_limiter.as_ref()
         }
     }
 }
 
 pub struct ModuleInstanceData<'a> {
     types: Vec<TypeDefinition>,
-    globals: RefMut<'a, Vec<ValueTypeProvider>>,
-    memories: RefMut<'a, Vec<Memory>>,
+    globals: Vec<Rc<RefCell<ValueTypeProvider>>>,
+    memories: Vec<Rc<RefCell<Memory>>>,
     functions: &'a Vec<Func>,
-    tables: RefMut<'a, Vec<Table>>
+    tables: RefMut<'a, Vec<Table>>,
+    limits: Limits,
+    fuel: RefMut<'a, Fuel>,
+    memory_limiter: Option<&'a MemoryLimiter>
 }
 
 pub trait ModuleTemplateBuilder {
-    // TODO don't use ParseError
-    fn build(&self, imports: HashMap<String, HashMap<String, Import>>) -> Result<ModuleTemplate, ParseError>;
+    fn build(&self, imports: HashMap<String, HashMap<String, Import>>) -> Result<ModuleTemplate, LinkError>;
 }
 
-impl ModuleTemplateBuilder for ParseModule {
-    fn build(&self, mut imports: HashMap<String, HashMap<String, Import>>) -> Result<ModuleTemplate, ParseError> {
-        println!("{:?}", self.exports);
-        Ok(ModuleTemplate {
-            exports: self.exports.clone(),
-            functions: self.build_functions(&mut imports),
-            globals: vec![],
-            memories: self.memories.clone(),
-            start_function: None,
-            tables: vec![],
-            types: self.types.clone()
-        })
+impl<'a> ModuleTemplateBuilder for ParseModule<'a> {
+    fn build(&self, imports: HashMap<String, HashMap<String, Import>>) -> Result<ModuleTemplate, LinkError> {
+        Linker::from(imports).link(self)
     }
 }
 
-impl ParseModule {
-    pub fn build_functions(&self, imports: &mut HashMap<String, HashMap<String, Import>>) -> Vec<Func> {
-        let mut functions: Vec<Func> = vec![];
-        for imported_module in &(self.imports) {
-            for imported_item in imported_module.1.iter() {
-                if let ExternalKind::Function(signature_idx) = *imported_item.1 {
-                    if let Some(mut map) = imports.remove(imported_module.0) {
-                        if let Some(Import::Function(f)) = map.remove(imported_item.0) {
-                            match self.types[signature_idx].clone() {
-                                TypeDefinition::Func(signature) => {
-                                    functions.push(Func {
-                                        signature,
-                                        callable: f
-                                    });
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
-        for func in self.function_signatures.iter().zip(self.function_bodies.iter()) {
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parse_tree::builder::ModuleBuilder;
+    use parse_tree::functions::FuncBody;
+    use parse_tree::language_types::Block;
+    use parse_tree::language_types::BlockType;
+    use parse_tree::language_types::GlobalType;
+    use parse_tree::language_types::ImportEntry;
+    use parse_tree::language_types::ImportKind;
+    use validator::ValidationError;
 
-            let &TypeDefinition::Func(ref signature) = &self.types[*func.0];
-            let body = func.1;
-            let args_size = signature.parameters.len();
-            let locals_size = body.locals.len();
-            let local_space_size = args_size + locals_size;
+    fn module() -> ParseModule<'static> {
+        let mut builder = ModuleBuilder::new();
+        builder.signature(vec![], vec![]);
+        builder.build()
+    }
 
-            let mut locals = Vec::with_capacity(local_space_size);
-            locals.append(&mut signature.parameters.clone());
-            locals.append(&mut body.locals.clone());
+    fn global(content_type: ValueType, value: InitExpression) -> Global {
+        Global {
+            constraints: GlobalType {
+                content_type,
+                mutability: true
+            },
+            value
+        }
+    }
 
-            let operations = body.code.clone();
+    fn limits(initial: u64, maximum: Option<u64>) -> ResizableLimits {
+        ResizableLimits {
+            initial,
+            maximum
+        }
+    }
 
-            let rets = signature.returns.clone();
+    // Adds a function taking and returning nothing
+    fn function(module: &mut ParseModule, code: Vec<Operation>) {
+        module.function_signatures.push(0);
+        module.function_bodies.push(FuncBody {
+            locals: vec![],
+            code
+        });
+    }
 
-            let block_type = if rets.len() == 0 {
-                BlockType::Empty
-            } else {
-                BlockType::Value(rets.get(0).unwrap().clone())
-            };
-            let block = Block {
-                operations: operations.clone(),
-                block_type
-            };
-            functions.push(Func{
-                signature: signature.clone(),
-                callable: Box::new(move |mut module, args| {
-                println!("ayo in the function");
-
-                if args.len() != args_size {
-                    panic!("Wrong number of args provided");
-                }
-                let mut local_space: Vec<ValueTypeProvider> = Vec::with_capacity(local_space_size);
-                for (param, arg) in locals.iter().zip(args.iter()) {
-                    local_space.push(match *param {
-                        ValueType::I32 => {
-                            if let ValueTypeProvider::I32(val) = *arg {
-                                arg.clone()
-                            } else {
-                                panic!("wrong argument type provided");
-                            }
-                        },
-                        ValueType::I64 => {
-                            if let ValueTypeProvider::I64(val) = *arg {
-                                arg.clone()
-                            } else {
-                                panic!("wrong argument type provided");
-                            }
-                        },
-                        ValueType::F32 => {
-                            if let ValueTypeProvider::F32(val) = *arg {
-                                arg.clone()
-                            } else {
-                                panic!("wrong argument type provided");
-                            }
-                        },
-                        ValueType::F64 => {
-                            if let ValueTypeProvider::F64(val) = *arg {
-                                arg.clone()
-                            } else {
-                                panic!("wrong argument type provided");
-                            }
-                        },
-                    });
-                }
-                for l in &locals[args_size..local_space_size] {
-                    local_space.push(match *l {
-                        ValueType::I32 => ValueTypeProvider::I32(0),
-                        ValueType::I64 => ValueTypeProvider::I64(0),
-                        ValueType::F32 => ValueTypeProvider::F32(0.0),
-                        ValueType::F64 => ValueTypeProvider::F64(0.0),
-                    });
-                }
-
-                let mut stack = vec![];
-                let mut control_flow_stack: Vec<(usize, BlockType)> = vec![];
-
-                block.execute(&mut StackFrame {
-                    data: &mut module,
-                    locals: &mut local_space,
-                    stack: &mut stack
-                });
-
-                let mut results = vec![];
-                for ret in &rets {
-                    match *ret {
-                        ValueType::I32 => {
-                            if let Some(ValueTypeProvider::I32(i)) = stack.pop() {
-                                results.push(ValueTypeProvider::I32(i));
-                            }
-                        },
-                        ValueType::I64 => {
-                            if let Some(ValueTypeProvider::I64(i)) = stack.pop() {
-                                results.push(ValueTypeProvider::I64(i));
-                            }
-                        },
-                        ValueType::F32 => {
-                            if let Some(ValueTypeProvider::F32(i)) = stack.pop() {
-                                results.push(ValueTypeProvider::F32(i));
-                            }
-                        },
-                        ValueType::F64 => {
-                            if let Some(ValueTypeProvider::F64(i)) = stack.pop() {
-                                results.push(ValueTypeProvider::F64(i));
-                            }
-                        },
-                        _ => {}
-                    }
-                }
-                return results;
-            })});
+    fn instantiate(module: &ParseModule, check: &Fn(Result<ModuleInstance, InstantiationError>)) {
+        let template = module.build(HashMap::new()).unwrap();
+        check(template.instantiate());
+    }
+
+    fn invalid(module: &ParseModule) -> ValidationError {
+        match module.build(HashMap::new()) {
+            Err(LinkError::Validation(error)) => error,
+            _ => panic!("expected the module to be invalid")
         }
-        functions
+    }
+
+    fn values(globals: &[Rc<RefCell<ValueTypeProvider>>]) -> Vec<ValueTypeProvider> {
+        globals.iter().map(|global| global.borrow().clone()).collect()
+    }
+
+    #[test]
+    fn globals_are_initialised() {
+        let imported = GlobalType {
+            content_type: ValueType::I32,
+            mutability: false
+        };
+        let mut module = module();
+        module.imports.push(ImportEntry {
+            module: "env".into(),
+            field: "g".into(),
+            kind: ImportKind::Global(imported.clone())
+        });
+        module.globals.push(global(ValueType::I32, InitExpression::I32Const(42)));
+        module.globals.push(global(ValueType::F64, InitExpression::F64Const(1.5)));
+        module.globals.push(global(ValueType::I32, InitExpression::GetGlobal(0)));
+
+        let mut env = HashMap::new();
+        env.insert("g".to_string(), Import::Global(imported, Rc::new(RefCell::new(ValueTypeProvider::I32(7)))));
+        let mut imports = HashMap::new();
+        imports.insert("env".to_string(), env);
+        let template = module.build(imports).unwrap();
+        let instance = template.instantiate().unwrap();
+        assert_eq!(values(&instance.globals), vec![
+            ValueTypeProvider::I32(7),
+            ValueTypeProvider::I32(42),
+            ValueTypeProvider::F64(1.5),
+            ValueTypeProvider::I32(7),
+        ]);
+
+        module.globals.push(global(ValueType::I64, InitExpression::GetGlobal(0)));
+        assert_eq!(invalid(&module), ValidationError::TypeMismatch {
+            expected: ValueType::I64,
+            found: ValueType::I32
+        });
+    }
+
+    #[test]
+    fn memories_are_allocated_and_initialised() {
+        let mut module = module();
+        module.memories.push(Memory {
+            limits: limits(2, Some(4)),
+            values: vec![]
+        });
+        module.data.push(DataSegment {
+            index: 0,
+            offset: InitExpression::I32Const(WASM_PAGE_SIZE as i32 * 2 - 2),
+            data: vec![1, 2].into()
+        });
+        instantiate(&module, &|instance| {
+            let instance = instance.unwrap();
+            let memory = instance.memories[0].borrow();
+            assert_eq!(memory.values.len(), 2 * WASM_PAGE_SIZE);
+            assert_eq!(memory.values[2 * WASM_PAGE_SIZE - 3..], [0, 1, 2]);
+        });
+
+        module.data.push(DataSegment {
+            index: 0,
+            offset: InitExpression::I32Const(WASM_PAGE_SIZE as i32 * 2 - 1),
+            data: vec![1, 2].into()
+        });
+        instantiate(&module, &|instance| {
+            assert_eq!(instance.err(), Some(InstantiationError::DataSegmentOutOfBounds(1)));
+        });
+    }
+
+    #[test]
+    fn memory_limits_are_checked() {
+        let mut module = module();
+        module.memories.push(Memory {
+            limits: limits(2, Some(1)),
+            values: vec![]
+        });
+        assert_eq!(invalid(&module), ValidationError::InvalidLimits);
+    }
+
+    #[test]
+    fn memory_limiter_is_asked_before_allocating() {
+        let mut module = module();
+        module.memories.push(Memory {
+            limits: limits(MAX_PAGES as u64, None),
+            values: vec![]
+        });
+        let template = module.build(HashMap::new()).unwrap();
+        let mut config = InstanceConfig::default();
+        config.memory_limiter = Some(Box::new(|_, desired| desired <= 16));
+        assert_eq!(template.instantiate_with(config).err(), Some(InstantiationError::MemoryLimitExceeded(0)));
+    }
+
+    #[test]
+    fn tables_are_allocated_and_initialised() {
+        let mut module = module();
+        function(&mut module, vec![]);
+        function(&mut module, vec![]);
+        module.tables.push(Table::AnyFunc {
+            limits: limits(3, None),
+            values: vec![]
+        });
+        module.elements.push(ElementSegment {
+            index: 0,
+            offset: InitExpression::I32Const(1),
+            elements: vec![1, 0]
+        });
+        instantiate(&module, &|instance| {
+            let instance = instance.unwrap();
+            let Table::AnyFunc{ref values, ..} = instance.tables.borrow()[0];
+            assert_eq!(values, &vec![None, Some(1), Some(0)]);
+        });
+
+        module.elements.push(ElementSegment {
+            index: 0,
+            offset: InitExpression::I32Const(0),
+            elements: vec![2]
+        });
+        assert_eq!(invalid(&module), ValidationError::UndefinedFunction(2));
+    }
+
+    #[test]
+    fn segments_are_all_checked_before_any_are_applied() {
+        let mut module = module();
+        module.memories.push(Memory {
+            limits: limits(1, None),
+            values: vec![]
+        });
+        module.data.push(DataSegment {
+            index: 0,
+            offset: InitExpression::I32Const(0),
+            data: vec![1].into()
+        });
+        module.tables.push(Table::AnyFunc {
+            limits: limits(0, None),
+            values: vec![]
+        });
+        module.elements.push(ElementSegment {
+            index: 0,
+            offset: InitExpression::I32Const(-1),
+            elements: vec![]
+        });
+        instantiate(&module, &|instance| {
+            assert_eq!(instance.err(), Some(InstantiationError::ElementSegmentOutOfBounds(0)));
+        });
+    }
+
+    #[test]
+    fn start_function_is_run() {
+        let mut module = module();
+        module.globals.push(global(ValueType::I32, InitExpression::I32Const(0)));
+        function(&mut module, vec![
+            Operation::I32Const(42),
+            Operation::SetGlobal(0),
+        ]);
+        module.start_function = Some(0);
+        instantiate(&module, &|instance| {
+            assert_eq!(values(&instance.unwrap().globals), vec![ValueTypeProvider::I32(42)]);
+        });
+    }
+
+    #[test]
+    fn start_function_traps_fail_instantiation() {
+        let mut module = module();
+        function(&mut module, vec![Operation::Unreachable]);
+        module.start_function = Some(0);
+        instantiate(&module, &|instance| {
+            assert_eq!(instance.err(), Some(InstantiationError::StartFunctionTrapped(Trap::Unreachable)));
+        });
+    }
+
+    #[test]
+    fn start_function_runs_under_the_config() {
+        let mut module = module();
+        function(&mut module, vec![
+            Operation::Loop(Block {
+                block_type: BlockType::Empty,
+                operations: vec![Operation::Branch(0)]
+            }),
+        ]);
+        module.start_function = Some(0);
+        let template = module.build(HashMap::new()).unwrap();
+        let mut config = InstanceConfig::default();
+        config.fuel.set(1000);
+        assert_eq!(template.instantiate_with(config).err(), Some(InstantiationError::StartFunctionTrapped(Trap::OutOfFuel)));
+    }
+
+    #[test]
+    fn start_function_must_take_and_return_nothing() {
+        let mut module = module();
+        module.types.push(TypeDefinition::Func(FuncSignature {
+            parameters: vec![],
+            returns: vec![ValueType::I32]
+        }));
+        module.function_signatures.push(1);
+        module.function_bodies.push(FuncBody {
+            locals: vec![],
+            code: vec![Operation::I32Const(1)]
+        });
+        module.start_function = Some(0);
+        assert_eq!(invalid(&module), ValidationError::InvalidStartFunction(0));
     }
 }
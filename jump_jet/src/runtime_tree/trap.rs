@@ -0,0 +1,56 @@
+use std::error::Error;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+
+/// The ways in which executing a wasm function can fail.
+///
+/// A trap aborts the current call and is handed back to the embedder; it never unwinds the host.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trap {
+    Unreachable,
+    IntegerDivideByZero,
+    IntegerOverflow,
+    InvalidConversionToInteger,
+    MemoryOutOfBounds,
+    IndirectCallTypeMismatch,
+    UndefinedTableElement,
+    StackOverflow,
+    OutOfFuel,
+    StackUnderflow,
+    TypeMismatch,
+    UndefinedFunction(usize),
+    UndefinedLocal(usize),
+    UndefinedGlobal(usize),
+    UndefinedType(usize),
+    UnknownExport(String),
+    HostError(String),
+    InvalidFunction(String),
+}
+
+impl Display for Trap {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Trap::Unreachable => write!(f, "unreachable executed"),
+            Trap::IntegerDivideByZero => write!(f, "integer divide by zero"),
+            Trap::IntegerOverflow => write!(f, "integer overflow"),
+            Trap::InvalidConversionToInteger => write!(f, "invalid conversion to integer"),
+            Trap::MemoryOutOfBounds => write!(f, "out of bounds memory access"),
+            Trap::IndirectCallTypeMismatch => write!(f, "indirect call type mismatch"),
+            Trap::UndefinedTableElement => write!(f, "undefined table element"),
+            Trap::StackOverflow => write!(f, "call stack exhausted"),
+            Trap::OutOfFuel => write!(f, "all fuel consumed"),
+            Trap::StackUnderflow => write!(f, "value stack underflow"),
+            Trap::TypeMismatch => write!(f, "value had the wrong type"),
+            Trap::UndefinedFunction(index) => write!(f, "undefined function {}", index),
+            Trap::UndefinedLocal(index) => write!(f, "undefined local {}", index),
+            Trap::UndefinedGlobal(index) => write!(f, "undefined global {}", index),
+            Trap::UndefinedType(index) => write!(f, "undefined type {}", index),
+            Trap::UnknownExport(ref name) => write!(f, "no function exported as `{}`", name),
+            Trap::HostError(ref message) => write!(f, "host error: {}", message),
+            Trap::InvalidFunction(ref message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for Trap {}
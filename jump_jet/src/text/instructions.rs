@@ -0,0 +1,445 @@
+use parse_tree::language_types::Block;
+use parse_tree::language_types::BlockType;
+use parse_tree::language_types::BranchTable;
+use parse_tree::language_types::MemoryImmediate;
+use parse_tree::language_types::Operation;
+
+use text::module::signature;
+use text::module::Builder;
+use text::module::Space;
+use text::numbers;
+use text::sexpr::Items;
+use text::sexpr::SExpr;
+use text::sexpr::Token;
+use text::TextError;
+use text::TextErrorKind;
+
+// Instructions are read recursively, so like the binary parser this caps how deeply blocks and folded
+// instructions can nest
+const MAX_NESTING: usize = 1024;
+
+/// What the instructions in a function body can refer to, besides the module's index spaces.
+pub struct Body<'s> {
+    pub locals: Space,
+    // The labels of the blocks around the current instruction, innermost last
+    labels: Vec<Option<&'s str>>,
+}
+
+impl<'s> Body<'s> {
+    pub fn new() -> Body<'s> {
+        Body {
+            locals: Space::default(),
+            labels: vec![],
+        }
+    }
+
+    // Branches count outwards from the innermost block
+    fn label(&self, items: &mut Items) -> Result<i32, TextError> {
+        let position = items.position();
+        if let Some(id) = items.id() {
+            return match self.labels.iter().rev().position(|label| *label == Some(id)) {
+                Some(depth) => Ok(depth as i32),
+                None => Err(TextError::new(TextErrorKind::UnknownName(id.to_string()), position))
+            };
+        }
+        let number = items.keyword("a label")?;
+        match numbers::u32(number) {
+            Some(depth) => Ok(depth as i32),
+            None => Err(TextError::new(TextErrorKind::InvalidNumber(number.to_string()), position))
+        }
+    }
+
+    // A label can be repeated after `else` and `end`, as long as it's the block's own
+    fn end_label(&self, items: &mut Items, label: Option<&str>) -> Result<(), TextError> {
+        let position = items.position();
+        match items.id() {
+            Some(id) if Some(id) != label => Err(TextError::new(TextErrorKind::MismatchedLabel(id.to_string()), position)),
+            _ => Ok(())
+        }
+    }
+}
+
+impl Builder {
+    /// Parses instructions up to the end of `items`, which can be written plain or folded.
+    pub fn instructions<'s>(&mut self, items: &mut Items<'s>, body: &mut Body<'s>) -> Result<Vec<Operation>, TextError> {
+        self.nested(items, body, 0)
+    }
+
+    fn nested<'s>(&mut self, items: &mut Items<'s>, body: &mut Body<'s>, depth: usize) -> Result<Vec<Operation>, TextError> {
+        let mut code = vec![];
+        while !items.is_empty() {
+            self.instruction(items, body, &mut code, depth)?;
+        }
+        Ok(code)
+    }
+
+    // Adds the next instruction to `code`. A plain block runs up to its `end`, a folded instruction
+    // adds its operands first. `depth` counts the blocks and folded instructions around it.
+    fn instruction<'s>(&mut self, items: &mut Items<'s>, body: &mut Body<'s>, code: &mut Vec<Operation>, depth: usize) -> Result<(), TextError> {
+        if let Some(&SExpr::List(..)) = items.peek() {
+            let mut folded = items.list("a folded instruction")?;
+            return self.folded(&mut folded, body, code, depth + 1);
+        }
+        match items.peek_keyword() {
+            Some(keyword @ "block") | Some(keyword @ "loop") | Some(keyword @ "if") => {
+                if depth >= MAX_NESTING {
+                    return Err(TextError::new(TextErrorKind::NestedTooDeeply, items.position()));
+                }
+                items.next();
+                let label = items.id();
+                let block_type = self.block_type(items)?;
+                body.labels.push(label);
+                let mut operations = vec![];
+                loop {
+                    match items.peek_keyword() {
+                        Some("end") => break,
+                        Some("else") if keyword == "if" => {
+                            items.next();
+                            body.end_label(items, label)?;
+                            operations.push(Operation::Else);
+                        },
+                        _ if items.is_empty() => return Err(items.expected("`end`")),
+                        _ => self.instruction(items, body, &mut operations, depth + 1)?
+                    }
+                }
+                items.next();
+                body.end_label(items, label)?;
+                body.labels.pop();
+                code.push(block(keyword, block_type, operations));
+            },
+            _ => {
+                let operation = self.operation(items, body)?;
+                code.push(operation);
+            }
+        }
+        Ok(())
+    }
+
+    fn folded<'s>(&mut self, items: &mut Items<'s>, body: &mut Body<'s>, code: &mut Vec<Operation>, depth: usize) -> Result<(), TextError> {
+        if depth > MAX_NESTING {
+            return Err(TextError::new(TextErrorKind::NestedTooDeeply, items.position()));
+        }
+        match items.peek_keyword() {
+            Some(keyword @ "block") | Some(keyword @ "loop") => {
+                items.next();
+                let label = items.id();
+                let block_type = self.block_type(items)?;
+                body.labels.push(label);
+                let operations = self.nested(items, body, depth)?;
+                body.labels.pop();
+                code.push(block(keyword, block_type, operations));
+            },
+            Some("if") => {
+                items.next();
+                let label = items.id();
+                let block_type = self.block_type(items)?;
+                // The condition is folded in before the branches
+                while !items.is_empty() && !items.peek_list("then") {
+                    self.instruction(items, body, code, depth)?;
+                }
+                let mut then = match items.take_list("then") {
+                    Some(then) => then,
+                    None => return Err(items.expected("`(then ...)`"))
+                };
+                body.labels.push(label);
+                let mut operations = self.nested(&mut then, body, depth)?;
+                if let Some(mut otherwise) = items.take_list("else") {
+                    operations.push(Operation::Else);
+                    operations.extend(self.nested(&mut otherwise, body, depth)?);
+                }
+                body.labels.pop();
+                items.finish()?;
+                code.push(Operation::If(Block {
+                    block_type,
+                    operations
+                }));
+            },
+            _ => {
+                let operation = self.operation(items, body)?;
+                while !items.is_empty() {
+                    let mut operand = items.list("a folded instruction")?;
+                    self.folded(&mut operand, body, code, depth + 1)?;
+                }
+                code.push(operation);
+            }
+        }
+        Ok(())
+    }
+
+    // A block's type is written like a function's. Without parameters, one result or none is written
+    // out in the block itself, anything else refers to a type in the module.
+    fn block_type(&mut self, items: &mut Items) -> Result<BlockType, TextError> {
+        if items.peek_list("type") || items.peek_list("param") {
+            let (index, _) = self.type_use(items)?;
+            return Ok(BlockType::Type(index));
+        }
+        let (signature, _) = signature(items)?;
+        match signature.returns.len() {
+            0 => Ok(BlockType::Empty),
+            1 => Ok(BlockType::Value(signature.returns[0].clone())),
+            _ => Ok(BlockType::Type(self.type_index(signature)))
+        }
+    }
+
+    // Everything but blocks, along with its immediates
+    fn operation<'s>(&mut self, items: &mut Items<'s>, body: &mut Body<'s>) -> Result<Operation, TextError> {
+        let position = items.position();
+        let keyword = items.keyword("an instruction")?;
+        if let Some(operation) = simple_operation(keyword) {
+            return Ok(operation);
+        }
+        if let Some((alignment, operation)) = memory_operation(keyword) {
+            return Ok(operation(memory_immediate(items, alignment)?));
+        }
+        let operation = match keyword {
+            "br" => Operation::Branch(body.label(items)?),
+            "br_if" => Operation::BranchIf(body.label(items)?),
+            "br_table" => {
+                let mut targets = vec![body.label(items)?];
+                while Space::peek_index(items) {
+                    targets.push(body.label(items)?);
+                }
+                let default = targets.pop().unwrap();
+                Operation::BranchTable(BranchTable {
+                    targets,
+                    default
+                })
+            },
+            "call" => Operation::Call(self.functions.index(items)?),
+            "call_indirect" => {
+                let (index, _) = self.type_use(items)?;
+                Operation::CallIndirect(index, false)
+            },
+            "get_local" | "local.get" => Operation::GetLocal(body.locals.index(items)?),
+            "set_local" | "local.set" => Operation::SetLocal(body.locals.index(items)?),
+            "tee_local" | "local.tee" => Operation::TeeLocal(body.locals.index(items)?),
+            "get_global" | "global.get" => Operation::GetGlobal(self.globals.index(items)?),
+            "set_global" | "global.set" => Operation::SetGlobal(self.globals.index(items)?),
+            "i32.const" => Operation::I32Const(constant(items, numbers::i32)?),
+            "i64.const" => Operation::I64Const(constant(items, numbers::i64)?),
+            "f32.const" => Operation::F32Const(constant(items, numbers::f32)?),
+            "f64.const" => Operation::F64Const(constant(items, numbers::f64)?),
+            _ => return Err(TextError::new(TextErrorKind::UnknownInstruction(keyword.to_string()), position))
+        };
+        Ok(operation)
+    }
+}
+
+fn block(keyword: &str, block_type: BlockType, operations: Vec<Operation>) -> Operation {
+    let block = Block {
+        block_type,
+        operations
+    };
+    match keyword {
+        "block" => Operation::Block(block),
+        "loop" => Operation::Loop(block),
+        _ => Operation::If(block)
+    }
+}
+
+fn constant<T>(items: &mut Items, parse: fn(&str) -> Option<T>) -> Result<T, TextError> {
+    let position = items.position();
+    let number = match items.next() {
+        Some(&SExpr::Atom(Token::Keyword(ref number), _)) => number,
+        _ => return Err(TextError::new(TextErrorKind::Expected("a number"), position))
+    };
+    match parse(number) {
+        Some(value) => Ok(value),
+        None => Err(TextError::new(TextErrorKind::InvalidNumber(number.to_string()), position))
+    }
+}
+
+// `offset=` and `align=` are both optional, alignment defaults to the size of what's accessed
+fn memory_immediate(items: &mut Items, alignment: u32) -> Result<MemoryImmediate, TextError> {
+    let mut immediate = MemoryImmediate {
+        flags: alignment,
+        offset: 0
+    };
+    if let Some(offset) = items.peek_keyword().filter(|keyword| keyword.starts_with("offset=")) {
+        immediate.offset = match numbers::u32(&offset[7..]) {
+            Some(offset) => offset,
+            None => return Err(items.error(TextErrorKind::InvalidNumber(offset.to_string())))
+        };
+        items.next();
+    }
+    if let Some(align) = items.peek_keyword().filter(|keyword| keyword.starts_with("align=")) {
+        immediate.flags = match numbers::u32(&align[6..]) {
+            Some(align) if align.is_power_of_two() => align.trailing_zeros(),
+            _ => return Err(items.error(TextErrorKind::InvalidNumber(align.to_string())))
+        };
+        items.next();
+    }
+    Ok(immediate)
+}
+
+// Loads and stores, with the log2 of their natural alignment
+pub fn memory_operation(keyword: &str) -> Option<(u32, fn(MemoryImmediate) -> Operation)> {
+    let operation: (u32, fn(MemoryImmediate) -> Operation) = match keyword {
+        "i32.load" => (2, Operation::I32Load),
+        "i64.load" => (3, Operation::I64Load),
+        "f32.load" => (2, Operation::F32Load),
+        "f64.load" => (3, Operation::F64Load),
+        "i32.load8_s" => (0, Operation::I32Load8S),
+        "i32.load8_u" => (0, Operation::I32Load8U),
+        "i32.load16_s" => (1, Operation::I32Load16S),
+        "i32.load16_u" => (1, Operation::I32Load16U),
+        "i64.load8_s" => (0, Operation::I64Load8S),
+        "i64.load8_u" => (0, Operation::I64Load8U),
+        "i64.load16_s" => (1, Operation::I64Load16S),
+        "i64.load16_u" => (1, Operation::I64Load16U),
+        "i64.load32_s" => (2, Operation::I64Load32S),
+        "i64.load32_u" => (2, Operation::I64Load32U),
+        "i32.store" => (2, Operation::I32Store),
+        "i64.store" => (3, Operation::I64Store),
+        "f32.store" => (2, Operation::F32Store),
+        "f64.store" => (3, Operation::F64Store),
+        "i32.store8" => (0, Operation::I32Store8),
+        "i32.store16" => (1, Operation::I32Store16),
+        "i64.store8" => (0, Operation::I64Store8),
+        "i64.store16" => (1, Operation::I64Store16),
+        "i64.store32" => (2, Operation::I64Store32),
+        _ => return None
+    };
+    Some(operation)
+}
+
+// Instructions without immediates, under their current names and the ones they had before
+fn simple_operation(keyword: &str) -> Option<Operation> {
+    let operation = match keyword {
+        "unreachable" => Operation::Unreachable,
+        "nop" => Operation::Nop,
+        "return" => Operation::Return,
+        "drop" => Operation::Drop,
+        "select" => Operation::Select,
+        "memory.size" | "current_memory" => Operation::CurrentMemory(false),
+        "memory.grow" | "grow_memory" => Operation::GrowMemory(false),
+
+        "i32.eqz" => Operation::I32Eqz,
+        "i32.eq" => Operation::I32Eq,
+        "i32.ne" => Operation::I32Ne,
+        "i32.lt_s" => Operation::I32LtS,
+        "i32.lt_u" => Operation::I32LtU,
+        "i32.gt_s" => Operation::I32GtS,
+        "i32.gt_u" => Operation::I32GtU,
+        "i32.le_s" => Operation::I32LeS,
+        "i32.le_u" => Operation::I32LeU,
+        "i32.ge_s" => Operation::I32GeS,
+        "i32.ge_u" => Operation::I32GeU,
+        "i64.eqz" => Operation::I64Eqz,
+        "i64.eq" => Operation::I64Eq,
+        "i64.ne" => Operation::I64Ne,
+        "i64.lt_s" => Operation::I64LtS,
+        "i64.lt_u" => Operation::I64LtU,
+        "i64.gt_s" => Operation::I64GtS,
+        "i64.gt_u" => Operation::I64GtU,
+        "i64.le_s" => Operation::I64LeS,
+        "i64.le_u" => Operation::I64LeU,
+        "i64.ge_s" => Operation::I64GeS,
+        "i64.ge_u" => Operation::I64GeU,
+        "f32.eq" => Operation::F32Eq,
+        "f32.ne" => Operation::F32Ne,
+        "f32.lt" => Operation::F32Lt,
+        "f32.gt" => Operation::F32Gt,
+        "f32.le" => Operation::F32Le,
+        "f32.ge" => Operation::F32Ge,
+        "f64.eq" => Operation::F64Eq,
+        "f64.ne" => Operation::F64Ne,
+        "f64.lt" => Operation::F64Lt,
+        "f64.gt" => Operation::F64Gt,
+        "f64.le" => Operation::F64Le,
+        "f64.ge" => Operation::F64Ge,
+
+        "i32.clz" => Operation::I32Clz,
+        "i32.ctz" => Operation::I32Ctz,
+        "i32.popcnt" => Operation::I32Popcnt,
+        "i32.add" => Operation::I32Add,
+        "i32.sub" => Operation::I32Sub,
+        "i32.mul" => Operation::I32Mul,
+        "i32.div_s" => Operation::I32DivS,
+        "i32.div_u" => Operation::I32DivU,
+        "i32.rem_s" => Operation::I32RemS,
+        "i32.rem_u" => Operation::I32RemU,
+        "i32.and" => Operation::I32And,
+        "i32.or" => Operation::I32Or,
+        "i32.xor" => Operation::I32Xor,
+        "i32.shl" => Operation::I32Shl,
+        "i32.shr_s" => Operation::I32ShrS,
+        "i32.shr_u" => Operation::I32ShrU,
+        "i32.rotl" => Operation::I32Rotl,
+        "i32.rotr" => Operation::I32Rotr,
+        "i64.clz" => Operation::I64Clz,
+        "i64.ctz" => Operation::I64Ctz,
+        "i64.popcnt" => Operation::I64Popcnt,
+        "i64.add" => Operation::I64Add,
+        "i64.sub" => Operation::I64Sub,
+        "i64.mul" => Operation::I64Mul,
+        "i64.div_s" => Operation::I64DivS,
+        "i64.div_u" => Operation::I64DivU,
+        "i64.rem_s" => Operation::I64RemS,
+        "i64.rem_u" => Operation::I64RemU,
+        "i64.and" => Operation::I64And,
+        "i64.or" => Operation::I64Or,
+        "i64.xor" => Operation::I64Xor,
+        "i64.shl" => Operation::I64Shl,
+        "i64.shr_s" => Operation::I64ShrS,
+        "i64.shr_u" => Operation::I64ShrU,
+        "i64.rotl" => Operation::I64Rotl,
+        "i64.rotr" => Operation::I64Rotr,
+        "f32.abs" => Operation::F32Abs,
+        "f32.neg" => Operation::F32Neg,
+        "f32.ceil" => Operation::F32Ceil,
+        "f32.floor" => Operation::F32Floor,
+        "f32.trunc" => Operation::F32Trunc,
+        "f32.nearest" => Operation::F32Nearest,
+        "f32.sqrt" => Operation::F32Sqrt,
+        "f32.add" => Operation::F32Add,
+        "f32.sub" => Operation::F32Sub,
+        "f32.mul" => Operation::F32Mul,
+        "f32.div" => Operation::F32Div,
+        "f32.min" => Operation::F32Min,
+        "f32.max" => Operation::F32Max,
+        "f32.copysign" => Operation::F32Copysign,
+        "f64.abs" => Operation::F64Abs,
+        "f64.neg" => Operation::F64Neg,
+        "f64.ceil" => Operation::F64Ceil,
+        "f64.floor" => Operation::F64Floor,
+        "f64.trunc" => Operation::F64Trunc,
+        "f64.nearest" => Operation::F64Nearest,
+        "f64.sqrt" => Operation::F64Sqrt,
+        "f64.add" => Operation::F64Add,
+        "f64.sub" => Operation::F64Sub,
+        "f64.mul" => Operation::F64Mul,
+        "f64.div" => Operation::F64Div,
+        "f64.min" => Operation::F64Min,
+        "f64.max" => Operation::F64Max,
+        "f64.copysign" => Operation::F64Copysign,
+
+        "i32.wrap_i64" | "i32.wrap/i64" => Operation::I32WrapI64,
+        "i32.trunc_f32_s" | "i32.trunc_s/f32" => Operation::I32TruncSF32,
+        "i32.trunc_f32_u" | "i32.trunc_u/f32" => Operation::I32TruncUF32,
+        "i32.trunc_f64_s" | "i32.trunc_s/f64" => Operation::I32TruncSF64,
+        "i32.trunc_f64_u" | "i32.trunc_u/f64" => Operation::I32TruncUF64,
+        "i64.extend_i32_s" | "i64.extend_s/i32" => Operation::I64ExtendSI32,
+        "i64.extend_i32_u" | "i64.extend_u/i32" => Operation::I64ExtendUI32,
+        "i64.trunc_f32_s" | "i64.trunc_s/f32" => Operation::I64TruncSF32,
+        "i64.trunc_f32_u" | "i64.trunc_u/f32" => Operation::I64TruncUF32,
+        "i64.trunc_f64_s" | "i64.trunc_s/f64" => Operation::I64TruncSF64,
+        "i64.trunc_f64_u" | "i64.trunc_u/f64" => Operation::I64TruncUF64,
+        "f32.convert_i32_s" | "f32.convert_s/i32" => Operation::F32ConvertSI32,
+        "f32.convert_i32_u" | "f32.convert_u/i32" => Operation::F32ConvertUI32,
+        "f32.convert_i64_s" | "f32.convert_s/i64" => Operation::F32ConvertSI64,
+        "f32.convert_i64_u" | "f32.convert_u/i64" => Operation::F32ConvertUI64,
+        "f32.demote_f64" | "f32.demote/f64" => Operation::F32DemoteF64,
+        "f64.convert_i32_s" | "f64.convert_s/i32" => Operation::F64ConvertSI32,
+        "f64.convert_i32_u" | "f64.convert_u/i32" => Operation::F64ConvertUI32,
+        "f64.convert_i64_s" | "f64.convert_s/i64" => Operation::F64ConvertSI64,
+        "f64.convert_i64_u" | "f64.convert_u/i64" => Operation::F64ConvertUI64,
+        "f64.promote_f32" | "f64.promote/f32" => Operation::F64PromoteF32,
+        "i32.reinterpret_f32" | "i32.reinterpret/f32" => Operation::I32ReinterpretF32,
+        "i64.reinterpret_f64" | "i64.reinterpret/f64" => Operation::I64ReinterpretF64,
+        "f32.reinterpret_i32" | "f32.reinterpret/i32" => Operation::F32ReinterpretI32,
+        "f64.reinterpret_i64" | "f64.reinterpret/i64" => Operation::F64ReinterpretI64,
+        _ => return None
+    };
+    Some(operation)
+}
@@ -0,0 +1,324 @@
+use parse_tree::ParseModule;
+
+mod text_error;
+pub use text::text_error::TextError;
+pub use text::text_error::TextErrorKind;
+
+mod instructions;
+mod module;
+mod numbers;
+mod print;
+mod sexpr;
+
+pub use text::print::print_module;
+
+/// Parses a module written in the text format into the same tree the binary parser produces.
+/// Instructions can be written plain or folded, and anything can be referred to by `$name` as well
+/// as by index. Function and local names are kept in `ParseModule::names`.
+pub fn parse_module(text: &str) -> Result<ParseModule<'static>, TextError> {
+    info!("Parsing module text");
+    let exprs = sexpr::read(text)?;
+    module::parse(&exprs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parse_tree::functions::FuncSignature;
+    use parse_tree::language_types::Block;
+    use parse_tree::language_types::BlockType;
+    use parse_tree::language_types::ExternalKind;
+    use parse_tree::language_types::ImportKind;
+    use parse_tree::language_types::InitExpression;
+    use parse_tree::language_types::Operation;
+    use parse_tree::language_types::ValueType;
+    use parse_tree::types::TypeDefinition;
+    use parser::ModuleParser;
+    use runtime_tree::Import;
+    use runtime_tree::ValueTypeProvider;
+    use parse_tree::tests::assert_same;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+    use std::thread;
+
+    fn code(body: &str) -> Vec<Operation> {
+        let text = format!("(module (func (param $x i32) (local $y i32) {}))", body);
+        parse_module(&text).unwrap().function_bodies.remove(0).code
+    }
+
+    fn error(text: &str) -> TextError {
+        parse_module(text).unwrap_err()
+    }
+
+    #[test]
+    fn text_parses_like_the_binary_format() {
+        let text = r#"
+            (module
+              (func (export "f") (result i32) (local i32)
+                (block (result i32)
+                  i32.const 42)))
+        "#;
+        let bytes: &[u8] = &[
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+            0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f,
+            0x03, 0x02, 0x01, 0x00,
+            0x07, 0x05, 0x01, 0x01, b'f', 0x00, 0x00,
+            0x0a, 0x0b, 0x01, 0x09, 0x01, 0x01, 0x7f, 0x02, 0x7f, 0x41, 0x2a, 0x0b, 0x0b,
+        ];
+        let binary = ModuleParser::default().parse_module(bytes).unwrap();
+        assert_eq!(parse_module(text).unwrap(), binary);
+    }
+
+    #[test]
+    fn folded_and_plain_instructions_are_the_same() {
+        let folded = code("(if (result i32) (get_local $x) (then (i32.const 1)) (else (i32.add (get_local $y) (i32.const 2))))");
+        let plain = code("get_local $x if (result i32) i32.const 1 else local.get $y i32.const 2 i32.add end");
+        assert_eq!(folded, plain);
+        assert_eq!(plain, vec![
+            Operation::GetLocal(0),
+            Operation::If(Block {
+                block_type: BlockType::Value(ValueType::I32),
+                operations: vec![
+                    Operation::I32Const(1),
+                    Operation::Else,
+                    Operation::GetLocal(1),
+                    Operation::I32Const(2),
+                    Operation::I32Add,
+                ]
+            }),
+        ]);
+    }
+
+    #[test]
+    fn labels_count_outwards() {
+        let operations = code("(block $outer (loop $inner br $outer br $inner (br_table $inner $outer 0)))");
+        let inner = match operations[0] {
+            Operation::Block(ref outer) => outer.operations[0].clone(),
+            _ => panic!("expected a block")
+        };
+        assert_eq!(inner, Operation::Loop(Block {
+            block_type: BlockType::Empty,
+            operations: vec![
+                Operation::Branch(1),
+                Operation::Branch(0),
+                Operation::BranchTable(::parse_tree::language_types::BranchTable {
+                    targets: vec![0, 1],
+                    default: 0
+                }),
+            ]
+        }));
+    }
+
+    #[test]
+    fn names_resolve_to_indices() {
+        let module = parse_module(r#"
+            (module $m
+              (import "env" "log" (func $log (param i32)))
+              (global $g (import "env" "g") i32)
+              (func $main (export "main")
+                (call $log (get_global $g))
+                (call $helper))
+              (func $helper (param $first i32) (local $second f64))
+              (memory $mem (export "mem") 1 2)
+              (table anyfunc (elem $helper $main))
+              (data (i32.const 8) "hi" "\00\ff"))
+        "#).unwrap();
+        assert!(matches!(module.imports[0].kind, ImportKind::Function(0)));
+        assert!(matches!(module.imports[1].kind, ImportKind::Global(_)));
+        assert_eq!(module.function_signatures, vec![1, 0]);
+        assert_eq!(module.function_bodies[0].code, vec![
+            Operation::GetGlobal(0),
+            Operation::Call(0),
+            Operation::Call(2),
+        ]);
+        assert!(matches!(module.exports[0].kind, ExternalKind::Function(1)));
+        assert!(matches!(module.exports[1].kind, ExternalKind::Memory(0)));
+        assert_eq!(module.elements[0].elements, vec![2, 1]);
+        assert_eq!(module.data[0].offset, InitExpression::I32Const(8));
+        assert_eq!(&module.data[0].data[..], b"hi\x00\xff");
+        assert_eq!(module.names.module, Some("m".to_string()));
+        assert_eq!(module.names.function(1), Some("main"));
+        assert_eq!(module.names.local(2, 0), Some("first"));
+        assert_eq!(module.names.local(2, 1), Some("second"));
+    }
+
+    #[test]
+    fn errors_say_where_they_are() {
+        assert_eq!(error("(module\n  (func (call $missing)))"), TextError {
+            kind: TextErrorKind::UnknownName("missing".to_string()),
+            line: 2,
+            column: 15
+        });
+        assert_eq!(error("(module (func i32.frobnicate))").kind, TextErrorKind::UnknownInstruction("i32.frobnicate".to_string()));
+        assert_eq!(error("(module (func (i32.const 4294967296)))").kind, TextErrorKind::InvalidNumber("4294967296".to_string()));
+        assert_eq!(error("(module (func $f) (func $f))").kind, TextErrorKind::DuplicateName("f".to_string()));
+        assert_eq!(error("(module (func) (import \"env\" \"f\" (func)))").kind, TextErrorKind::ImportAfterDefinition);
+        assert_eq!(error("(module (func block $a end $b))").kind, TextErrorKind::MismatchedLabel("b".to_string()));
+        assert_eq!(error("(module (func (block)").kind, TextErrorKind::UnexpectedEnd);
+        assert_eq!(error("(module (func)) ;; trailing\n)").to_string(), "unexpected ')' (at line 2, column 1)");
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        // Unoptimised builds need several times the stack per level that the test threads get
+        let check = thread::Builder::new().stack_size(32 << 20).spawn(|| {
+            let plain = |depth| format!("(module (func {}{}))", "block ".repeat(depth), "end ".repeat(depth));
+            let folded = |depth| format!("(module (func {}{}))", "(block ".repeat(depth), ")".repeat(depth));
+            assert!(parse_module(&plain(1024)).is_ok());
+            assert!(parse_module(&folded(1024)).is_ok());
+            assert_eq!(error(&plain(1025)).kind, TextErrorKind::NestedTooDeeply);
+            assert_eq!(error(&folded(1025)).kind, TextErrorKind::NestedTooDeeply);
+            assert_eq!(error(&folded(100_000)).kind, TextErrorKind::NestedTooDeeply);
+        }).unwrap();
+        check.join().unwrap();
+    }
+
+    #[test]
+    fn comments_and_strings_are_read() {
+        let module = parse_module(r#"
+            (; a (; nested ;) comment ;)
+            (memory (data "tab\t quote\" unicode\u{e9}")) ;; and a line comment
+        "#).unwrap();
+        assert_eq!(&module.data[0].data[..], "tab\t quote\" unicode\u{e9}".as_bytes());
+        assert_eq!(module.memories[0].limits.initial, 1);
+    }
+
+    #[test]
+    fn printed_modules_parse_back_the_same() {
+        let module = parse_module(r#"
+            (module $m
+              (import "env" "log" (func $log (param i32)))
+              (import "env" "table" (table 1 10 anyfunc))
+              (import "env" "memory" (memory 1))
+              (import "env" "g" (global (mut f64)))
+              (global $limit i64 (i64.const -9223372036854775808))
+              (func $main (export "main") (param $n i32) (result f32) (local $i i32) (local f64)
+                (block $done
+                  (loop $again
+                    (br_if $done (i32.ge_u (get_local $i) (get_local $n)))
+                    (call $log (i32.load8_u offset=4 align=1 (get_local $i)))
+                    (set_local $i (i32.add (get_local $i) (i32.const 1)))
+                    (br_table $again $done (get_local $i))))
+                (if (result f32) (get_local $n)
+                  (then (f32.const -0x1.8p-3))
+                  (else (f32.add (f32.const nan:0x200) (f32.const -inf)))))
+              (func (result f64) (f64.const 0.1) (f64.store (i32.const 0) (f64.const 1e300)) (memory.grow (i32.const 1)) drop)
+              (export "limit" (global 1))
+              (start $main)
+              (elem (i32.const 3) $main 2 $log)
+              (data (i32.const 8) "quote\" backslash\\ bytes\00\ff\n"))
+        "#).unwrap();
+        let printed = print_module(&module);
+        let reparsed = parse_module(&printed).unwrap_or_else(|error| panic!("{}\n{}", error, printed));
+        assert_same(reparsed, module);
+    }
+
+    #[test]
+    fn printed_bodies_are_indented() {
+        let module = parse_module(r#"
+            (module
+              (func $f (param $x i32) (result i32)
+                (if (result i32) (get_local $x)
+                  (then (block (result i32) (i32.const 1)))
+                  (else (i32.load offset=8 (get_local $x))))))
+        "#).unwrap();
+        assert_eq!(print_module(&module), r#"(module
+  (type (;0;) (func (param i32) (result i32)))
+  (func $f (type 0) (param $x i32) (result i32)
+    local.get $x
+    if (result i32)
+      block (result i32)
+        i32.const 1
+      end
+    else
+      local.get $x
+      i32.load offset=8
+    end))
+"#);
+    }
+
+    #[test]
+    fn names_that_cant_be_printed_are_left_out() {
+        let mut module = parse_module("(module (func $a (call $b)) (func $b (param $x i32)))").unwrap();
+        module.names.functions.insert(0, "has space".to_string());
+        module.names.functions.insert(1, "same".to_string());
+        module.names.functions.insert(2, "same".to_string());
+        module.function_signatures.push(1);
+        module.function_bodies.push(module.function_bodies[1].clone());
+        let printed = print_module(&module);
+        assert!(printed.contains("(func (;0;) (type 0)\n    call $same)"), "{}", printed);
+        assert!(printed.contains("(func (;2;) (type 1) (param i32))"), "{}", printed);
+        let reparsed = parse_module(&printed).unwrap();
+        assert_eq!(reparsed.function_bodies, module.function_bodies);
+        assert_eq!(reparsed.names.function(1), Some("same"));
+    }
+
+    #[test]
+    fn text_modules_can_be_instantiated() {
+        let text = r#"
+            (module
+              (func $add (export "add") (param $a i32) (param $b i32) (result i32)
+                (i32.add (get_local $a) (get_local $b))))
+        "#;
+        let template = ::runtime::instantiate_wat(text, HashMap::new()).unwrap();
+        let mut instance = template.instantiate().unwrap();
+        let result = instance.exports().call_fn("add", vec![ValueTypeProvider::I32(42), ValueTypeProvider::I32(81)]);
+        assert_eq!(result.unwrap(), vec![ValueTypeProvider::I32(123)]);
+    }
+
+    #[test]
+    fn blocks_with_parameters_or_several_results_use_a_type() {
+        let module = parse_module(r#"
+            (module
+              (type $pair (func (param i32) (result i32 i32)))
+              (func
+                (block (result i32) unreachable)
+                (block (type $pair) unreachable)
+                (block (param i32) (result i32 i32) unreachable)
+                (block (result i64 i64) unreachable)))
+        "#).unwrap();
+        let block_types: Vec<BlockType> = module.function_bodies[0].code.iter().map(|operation| match *operation {
+            Operation::Block(ref block) => block.block_type.clone(),
+            _ => panic!("expected a block")
+        }).collect();
+        assert_eq!(block_types, vec![
+            BlockType::Value(ValueType::I32),
+            BlockType::Type(0),
+            BlockType::Type(0),
+            BlockType::Type(2),
+        ]);
+        let TypeDefinition::Func(ref signature) = module.types[2];
+        assert_eq!(*signature, FuncSignature {
+            parameters: vec![],
+            returns: vec![ValueType::I64, ValueType::I64]
+        });
+        let printed = print_module(&module);
+        assert!(printed.contains("\n    block (type 2)\n"), "{}", printed);
+        let reparsed = parse_module(&printed).unwrap();
+        assert_eq!(reparsed, module);
+    }
+
+    #[test]
+    fn functions_can_return_several_values() {
+        let text = r#"
+            (module
+              (import "env" "swap" (func $swap (param i32 i64) (result i64 i32)))
+              (func (export "f") (param $x i32) (result i64 i32 i32)
+                (call $swap (get_local $x) (i64.const 7))
+                (block (param i64 i32) (result i64 i32 i32)
+                  (br 0 (i32.const 1)))))
+        "#;
+        let swap = Import::Function(FuncSignature {
+            parameters: vec![ValueType::I32, ValueType::I64],
+            returns: vec![ValueType::I64, ValueType::I32]
+        }, Rc::new(|_, args| Ok(vec![args[1].clone(), args[0].clone()])));
+        let mut env = HashMap::new();
+        env.insert("swap".to_string(), swap);
+        let mut imports = HashMap::new();
+        imports.insert("env".to_string(), env);
+        let template = ::runtime::instantiate_wat(text, imports).unwrap();
+        let mut instance = template.instantiate().unwrap();
+        let result = instance.exports().call_fn("f", vec![ValueTypeProvider::I32(42)]);
+        assert_eq!(result.unwrap(), vec![ValueTypeProvider::I64(7), ValueTypeProvider::I32(42), ValueTypeProvider::I32(1)]);
+    }
+}
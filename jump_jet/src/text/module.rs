@@ -0,0 +1,626 @@
+use std::collections::HashMap;
+use std::slice;
+
+use parse_tree::functions::FuncBody;
+use parse_tree::functions::FuncSignature;
+use parse_tree::globals::Global;
+use parse_tree::language_types::ExportEntry;
+use parse_tree::language_types::ExternalKind;
+use parse_tree::language_types::GlobalType;
+use parse_tree::language_types::ImportEntry;
+use parse_tree::language_types::ImportKind;
+use parse_tree::language_types::InitExpression;
+use parse_tree::language_types::LanguageType;
+use parse_tree::language_types::Operation;
+use parse_tree::language_types::ResizableLimits;
+use parse_tree::language_types::TableType;
+use parse_tree::language_types::ValueType;
+use parse_tree::memory::DataSegment;
+use parse_tree::memory::Memory;
+use parse_tree::memory::WASM_PAGE_SIZE;
+use parse_tree::names::Names;
+use parse_tree::tables::ElementSegment;
+use parse_tree::tables::Table;
+use parse_tree::types::TypeDefinition;
+use parse_tree::ParseModule;
+
+use text::instructions::Body;
+use text::numbers;
+use text::sexpr::Items;
+use text::sexpr::Position;
+use text::sexpr::SExpr;
+use text::sexpr::Token;
+use text::TextError;
+use text::TextErrorKind;
+
+/// The `$names` given to one index space, and how many things are in it.
+#[derive(Default)]
+pub struct Space {
+    names: HashMap<String, usize>,
+    count: usize,
+}
+
+impl Space {
+    /// Adds the next index, called `id` if it has a name.
+    pub fn define(&mut self, id: Option<&str>, position: Position) -> Result<usize, TextError> {
+        let index = self.count;
+        if let Some(id) = id {
+            if self.names.insert(id.to_string(), index).is_some() {
+                return Err(TextError::new(TextErrorKind::DuplicateName(id.to_string()), position));
+            }
+        }
+        self.count += 1;
+        Ok(index)
+    }
+
+    /// Whether an index, by `$name` or number, is next.
+    pub fn peek_index(items: &Items) -> bool {
+        items.peek_id().is_some() || items.peek_number()
+    }
+
+    /// Reads an index, given by `$name` or number.
+    pub fn index(&self, items: &mut Items) -> Result<usize, TextError> {
+        let position = items.position();
+        match items.next() {
+            Some(&SExpr::Atom(Token::Id(ref id), _)) => match self.names.get(id) {
+                Some(index) => Ok(*index),
+                None => Err(TextError::new(TextErrorKind::UnknownName(id.to_string()), position))
+            },
+            Some(&SExpr::Atom(Token::Keyword(ref number), _)) => match numbers::u32(number) {
+                Some(index) => Ok(index as usize),
+                None => Err(TextError::new(TextErrorKind::InvalidNumber(number.to_string()), position))
+            },
+            _ => Err(TextError::new(TextErrorKind::Expected("an index"), position))
+        }
+    }
+}
+
+/// Builds a module out of its fields, in two passes: the first gives every `$name` its index, so
+/// the second can refer to things before they're defined.
+pub struct Builder {
+    pub module: ParseModule<'static>,
+    pub types: Space,
+    pub functions: Space,
+    pub tables: Space,
+    pub memories: Space,
+    pub globals: Space,
+    // Imports can't come after anything's been defined, or they'd shift its index
+    defined: bool,
+    next_function: usize,
+}
+
+/// Parses the text of a module, with or without the `(module ...)` around its fields.
+pub fn parse(exprs: &[SExpr]) -> Result<ParseModule<'static>, TextError> {
+    let start = Position { line: 1, column: 1 };
+    let mut fields = Items::new(exprs, start);
+    let mut name = None;
+    if fields.peek_list("module") {
+        let mut module = fields.take_list("module").unwrap();
+        fields.finish()?;
+        name = module.id();
+        fields = module;
+    }
+
+    let mut builder = Builder::new();
+    builder.module.names.module = name.map(|name| name.to_string());
+    builder.declare(fields.clone())?;
+    builder.define(fields)?;
+    Ok(builder.module)
+}
+
+impl Builder {
+    fn new() -> Builder {
+        Builder {
+            module: ParseModule {
+                version: 1,
+                types: vec![],
+                imports: vec![],
+                function_signatures: vec![],
+                function_bodies: vec![],
+                encoded_bodies: vec![],
+                tables: vec![],
+                memories: vec![],
+                globals: vec![],
+                exports: vec![],
+                start_function: None,
+                elements: vec![],
+                data: vec![],
+                custom_sections: vec![],
+                names: Names::default(),
+            },
+            types: Space::default(),
+            functions: Space::default(),
+            tables: Space::default(),
+            memories: Space::default(),
+            globals: Space::default(),
+            defined: false,
+            next_function: 0,
+        }
+    }
+
+    // Names every type, function, table, memory and global. Types are defined here too, so that
+    // type uses can tell which ones they match.
+    fn declare(&mut self, mut fields: Items) -> Result<(), TextError> {
+        while !fields.is_empty() {
+            let mut field = fields.list("a module field")?;
+            let position = field.position();
+            match field.keyword("a module field")? {
+                "type" => {
+                    let id = field.id();
+                    self.types.define(id, position)?;
+                    let mut func = match field.take_list("func") {
+                        Some(func) => func,
+                        None => return Err(field.expected("`(func ...)`"))
+                    };
+                    let (signature, _) = signature(&mut func)?;
+                    func.finish()?;
+                    field.finish()?;
+                    self.module.types.push(TypeDefinition::Func(signature));
+                },
+                "import" => {
+                    field.name()?;
+                    field.name()?;
+                    let mut description = field.list("an import description")?;
+                    let kind = description.keyword("`func`, `table`, `memory` or `global`")?;
+                    let id = description.id();
+                    self.declare_item(kind, id, true, position)?;
+                },
+                kind @ "func" | kind @ "table" | kind @ "memory" | kind @ "global" => {
+                    let id = field.id();
+                    while field.take_list("export").is_some() {}
+                    let imported = field.peek_list("import");
+                    self.declare_item(kind, id, imported, position)?;
+                },
+                "export" | "start" | "elem" | "data" => {},
+                _ => return Err(TextError::new(TextErrorKind::Expected("a module field"), position))
+            }
+        }
+        Ok(())
+    }
+
+    fn declare_item(&mut self, kind: &str, id: Option<&str>, imported: bool, position: Position) -> Result<(), TextError> {
+        if imported && self.defined {
+            return Err(TextError::new(TextErrorKind::ImportAfterDefinition, position));
+        }
+        self.defined |= !imported;
+        let space = match kind {
+            "func" => &mut self.functions,
+            "table" => &mut self.tables,
+            "memory" => &mut self.memories,
+            "global" => &mut self.globals,
+            _ => return Err(TextError::new(TextErrorKind::Expected("`func`, `table`, `memory` or `global`"), position))
+        };
+        space.define(id, position)?;
+        Ok(())
+    }
+
+    fn define(&mut self, mut fields: Items) -> Result<(), TextError> {
+        while !fields.is_empty() {
+            let mut field = fields.list("a module field")?;
+            match field.keyword("a module field")? {
+                "type" => continue,
+                "import" => {
+                    let module = field.name()?;
+                    let name = field.name()?;
+                    let mut description = field.list("an import description")?;
+                    let kind = description.keyword("an import description")?;
+                    let id = description.id();
+                    self.import(kind, id, module, name, &mut description)?;
+                    description.finish()?;
+                },
+                "func" => self.function(&mut field)?,
+                "table" => self.table(&mut field)?,
+                "memory" => self.memory(&mut field)?,
+                "global" => self.global(&mut field)?,
+                "export" => {
+                    let name = field.name()?;
+                    let mut description = field.list("an export description")?;
+                    let kind = match description.keyword("`func`, `table`, `memory` or `global`")? {
+                        "func" => ExternalKind::Function(self.functions.index(&mut description)?),
+                        "table" => ExternalKind::Table(self.tables.index(&mut description)?),
+                        "memory" => ExternalKind::Memory(self.memories.index(&mut description)?),
+                        "global" => ExternalKind::Global(self.globals.index(&mut description)?),
+                        _ => return Err(TextError::new(TextErrorKind::Expected("`func`, `table`, `memory` or `global`"), description.position()))
+                    };
+                    description.finish()?;
+                    self.module.exports.push(ExportEntry {
+                        field: name.into(),
+                        kind
+                    });
+                },
+                "start" => self.module.start_function = Some(self.functions.index(&mut field)?),
+                "elem" => {
+                    let index = if Space::peek_index(&field) { self.tables.index(&mut field)? } else { 0 };
+                    let offset = self.offset(&mut field)?;
+                    field.take_keyword("func");
+                    let mut elements = vec![];
+                    while !field.is_empty() {
+                        elements.push(self.functions.index(&mut field)?);
+                    }
+                    self.module.elements.push(ElementSegment {
+                        index,
+                        offset,
+                        elements
+                    });
+                },
+                "data" => {
+                    let index = if Space::peek_index(&field) { self.memories.index(&mut field)? } else { 0 };
+                    let offset = self.offset(&mut field)?;
+                    let data = strings(&mut field)?;
+                    self.module.data.push(DataSegment {
+                        index,
+                        offset,
+                        data: data.into()
+                    });
+                },
+                _ => unreachable!()
+            }
+            field.finish()?;
+        }
+        Ok(())
+    }
+
+    fn import(&mut self, kind: &str, id: Option<&str>, module: String, field: String, description: &mut Items) -> Result<(), TextError> {
+        let kind = match kind {
+            "func" => {
+                let (index, parameters) = self.type_use(description)?;
+                self.name_function(id, &parameters);
+                ImportKind::Function(index)
+            },
+            "table" => ImportKind::Table(table_type(description)?),
+            "memory" => ImportKind::Memory(limits(description)?),
+            "global" => ImportKind::Global(global_type(description)?),
+            _ => unreachable!()
+        };
+        self.module.imports.push(ImportEntry {
+            module: module.into(),
+            field: field.into(),
+            kind
+        });
+        Ok(())
+    }
+
+    // Inline imports are written after any inline exports
+    fn inline_import(&mut self, field: &mut Items) -> Result<Option<(String, String)>, TextError> {
+        match field.take_list("import") {
+            Some(mut import) => {
+                let names = (import.name()?, import.name()?);
+                import.finish()?;
+                Ok(Some(names))
+            },
+            None => Ok(None)
+        }
+    }
+
+    fn inline_exports(&mut self, field: &mut Items, kind: ExternalKind) -> Result<(), TextError> {
+        while let Some(mut export) = field.take_list("export") {
+            let name = export.name()?;
+            export.finish()?;
+            self.module.exports.push(ExportEntry {
+                field: name.into(),
+                kind: kind.clone()
+            });
+        }
+        Ok(())
+    }
+
+    fn function(&mut self, field: &mut Items) -> Result<(), TextError> {
+        let id = field.id();
+        let index = self.next_function;
+        self.inline_exports(field, ExternalKind::Function(index))?;
+        if let Some((module, name)) = self.inline_import(field)? {
+            return self.import("func", id, module, name, field);
+        }
+        let position = field.position();
+        let (type_index, parameters) = self.type_use(field)?;
+        let mut body = Body::new();
+        for parameter in &parameters {
+            body.locals.define(*parameter, position)?;
+        }
+        self.name_function(id, &parameters);
+        let mut locals = vec![];
+        while let Some(mut local) = field.take_list("local") {
+            // A named local is written on its own
+            if let Some(id) = local.id() {
+                let local_index = body.locals.define(Some(id), local.position())?;
+                self.module.names.locals.entry(index).or_insert_with(HashMap::new).insert(local_index, id.to_string());
+                locals.push(value_type(&mut local)?);
+                local.finish()?;
+                continue;
+            }
+            while !local.is_empty() {
+                body.locals.define(None, local.position())?;
+                locals.push(value_type(&mut local)?);
+            }
+        }
+        let code = self.instructions(field, &mut body)?;
+        self.module.function_signatures.push(type_index);
+        self.module.function_bodies.push(FuncBody {
+            locals,
+            code
+        });
+        Ok(())
+    }
+
+    // Functions are numbered as they're built, as imports and definitions go into different lists
+    fn name_function(&mut self, id: Option<&str>, parameters: &[Option<&str>]) {
+        let index = self.next_function;
+        self.next_function += 1;
+        if let Some(id) = id {
+            self.module.names.functions.insert(index, id.to_string());
+        }
+        for (position, parameter) in parameters.iter().enumerate() {
+            if let Some(name) = *parameter {
+                self.module.names.locals.entry(index).or_insert_with(HashMap::new).insert(position, name.to_string());
+            }
+        }
+    }
+
+    fn table(&mut self, field: &mut Items) -> Result<(), TextError> {
+        let id = field.id();
+        let index = self.module.table_space().len();
+        self.inline_exports(field, ExternalKind::Table(index))?;
+        if let Some((module, name)) = self.inline_import(field)? {
+            return self.import("table", id, module, name, field);
+        }
+        // `(table anyfunc (elem ...))` is sized to fit the elements it's given
+        if field.peek_keyword().map_or(false, |keyword| !numbers::is_number(keyword)) {
+            elem_type(field)?;
+            let mut elem = match field.take_list("elem") {
+                Some(elem) => elem,
+                None => return Err(field.expected("`(elem ...)`"))
+            };
+            let mut elements = vec![];
+            while !elem.is_empty() {
+                elements.push(self.functions.index(&mut elem)?);
+            }
+            self.module.tables.push(Table::AnyFunc {
+                limits: ResizableLimits {
+                    initial: elements.len() as u64,
+                    maximum: Some(elements.len() as u64)
+                },
+                values: vec![]
+            });
+            self.module.elements.push(ElementSegment {
+                index,
+                offset: InitExpression::I32Const(0),
+                elements
+            });
+            return Ok(());
+        }
+        let table_type = table_type(field)?;
+        self.module.tables.push(Table::AnyFunc {
+            limits: table_type.limits,
+            values: vec![]
+        });
+        Ok(())
+    }
+
+    fn memory(&mut self, field: &mut Items) -> Result<(), TextError> {
+        let id = field.id();
+        let index = self.module.memory_space().len();
+        self.inline_exports(field, ExternalKind::Memory(index))?;
+        if let Some((module, name)) = self.inline_import(field)? {
+            return self.import("memory", id, module, name, field);
+        }
+        // `(memory (data ...))` has as many pages as its data needs
+        if let Some(mut data) = field.take_list("data") {
+            let data = strings(&mut data)?;
+            let pages = ((data.len() + WASM_PAGE_SIZE - 1) / WASM_PAGE_SIZE) as u64;
+            self.module.memories.push(Memory {
+                limits: ResizableLimits {
+                    initial: pages,
+                    maximum: Some(pages)
+                },
+                values: vec![]
+            });
+            self.module.data.push(DataSegment {
+                index,
+                offset: InitExpression::I32Const(0),
+                data: data.into()
+            });
+            return Ok(());
+        }
+        let limits = limits(field)?;
+        self.module.memories.push(Memory {
+            limits,
+            values: vec![]
+        });
+        Ok(())
+    }
+
+    fn global(&mut self, field: &mut Items) -> Result<(), TextError> {
+        let id = field.id();
+        let index = self.module.global_space().len();
+        self.inline_exports(field, ExternalKind::Global(index))?;
+        if let Some((module, name)) = self.inline_import(field)? {
+            return self.import("global", id, module, name, field);
+        }
+        let constraints = global_type(field)?;
+        let value = self.init_expression(field)?;
+        self.module.globals.push(Global {
+            constraints,
+            value
+        });
+        Ok(())
+    }
+
+    /// Reads a reference to a function type, as either `(type $t)` or the parameters and results it
+    /// has, or both. Types that are only written out are added to the module if it doesn't have
+    /// them already. Returns the type's index and the names given to its parameters.
+    pub fn type_use<'s>(&mut self, items: &mut Items<'s>) -> Result<(usize, Vec<Option<&'s str>>), TextError> {
+        let position = items.position();
+        let declared = match items.take_list("type") {
+            Some(mut type_index) => {
+                let index = self.types.index(&mut type_index)?;
+                type_index.finish()?;
+                Some(index)
+            },
+            None => None
+        };
+        let (signature, names) = signature(items)?;
+        let index = match declared {
+            Some(index) => {
+                let declared = match self.module.types.get(index) {
+                    Some(&TypeDefinition::Func(ref declared)) => declared.clone(),
+                    None => return Err(TextError::new(TextErrorKind::UndefinedType(index), position))
+                };
+                if signature.parameters.is_empty() && signature.returns.is_empty() {
+                    return Ok((index, vec![None; declared.parameters.len()]));
+                }
+                if signature != declared {
+                    return Err(TextError::new(TextErrorKind::TypeMismatch, position));
+                }
+                index
+            },
+            None => self.type_index(signature)
+        };
+        Ok((index, names))
+    }
+
+    /// The index of the first type matching `signature`, which is added if there isn't one.
+    pub fn type_index(&mut self, signature: FuncSignature) -> usize {
+        let existing = self.module.types.iter().position(|definition| match *definition {
+            TypeDefinition::Func(ref existing) => *existing == signature
+        });
+        match existing {
+            Some(index) => index,
+            None => {
+                self.module.types.push(TypeDefinition::Func(signature));
+                self.module.types.len() - 1
+            }
+        }
+    }
+
+    /// Reads a constant expression, which is a single constant or `get_global`.
+    pub fn init_expression(&mut self, items: &mut Items) -> Result<InitExpression, TextError> {
+        let position = items.position();
+        let mut code = self.instructions(items, &mut Body::new())?;
+        match (code.pop(), code.is_empty()) {
+            (Some(operation), true) => match constant(operation) {
+                Some(expression) => Ok(expression),
+                None => Err(TextError::new(TextErrorKind::Expected("a constant expression"), position))
+            },
+            _ => Err(TextError::new(TextErrorKind::Expected("a constant expression"), position))
+        }
+    }
+
+    // Segment offsets are written `(offset ...)`, or as a single folded instruction
+    fn offset(&mut self, field: &mut Items) -> Result<InitExpression, TextError> {
+        if let Some(mut offset) = field.take_list("offset") {
+            return self.init_expression(&mut offset);
+        }
+        match field.next() {
+            Some(expression @ &SExpr::List(..)) => self.init_expression(&mut Items::new(slice::from_ref(expression), expression.position())),
+            _ => Err(field.expected("an offset"))
+        }
+    }
+}
+
+fn constant(operation: Operation) -> Option<InitExpression> {
+    match operation {
+        Operation::I32Const(value) => Some(InitExpression::I32Const(value)),
+        Operation::I64Const(value) => Some(InitExpression::I64Const(value)),
+        Operation::F32Const(value) => Some(InitExpression::F32Const(value)),
+        Operation::F64Const(value) => Some(InitExpression::F64Const(value)),
+        Operation::GetGlobal(index) => Some(InitExpression::GetGlobal(index)),
+        _ => None
+    }
+}
+
+/// Reads `(param ...)` and `(result ...)` lists, returning the signature they make up and the names
+/// given to the parameters.
+pub fn signature<'s>(items: &mut Items<'s>) -> Result<(FuncSignature, Vec<Option<&'s str>>), TextError> {
+    let mut parameters = vec![];
+    let mut names = vec![];
+    while let Some(mut param) = items.take_list("param") {
+        // A named parameter is written on its own
+        if let Some(id) = param.id() {
+            parameters.push(value_type(&mut param)?);
+            names.push(Some(id));
+            param.finish()?;
+            continue;
+        }
+        while !param.is_empty() {
+            parameters.push(value_type(&mut param)?);
+            names.push(None);
+        }
+    }
+    let mut returns = vec![];
+    while let Some(mut result) = items.take_list("result") {
+        while !result.is_empty() {
+            returns.push(value_type(&mut result)?);
+        }
+    }
+    Ok((FuncSignature { parameters, returns }, names))
+}
+
+pub fn value_type(items: &mut Items) -> Result<ValueType, TextError> {
+    let position = items.position();
+    match items.keyword("a value type")? {
+        "i32" => Ok(ValueType::I32),
+        "i64" => Ok(ValueType::I64),
+        "f32" => Ok(ValueType::F32),
+        "f64" => Ok(ValueType::F64),
+        _ => Err(TextError::new(TextErrorKind::Expected("a value type"), position))
+    }
+}
+
+fn limits(items: &mut Items) -> Result<ResizableLimits, TextError> {
+    let initial = number(items, "the initial size")?;
+    let maximum = if items.peek_number() { Some(number(items, "the maximum size")?) } else { None };
+    Ok(ResizableLimits {
+        initial,
+        maximum
+    })
+}
+
+fn number(items: &mut Items, what: &'static str) -> Result<u64, TextError> {
+    let position = items.position();
+    let number = items.keyword(what)?;
+    match numbers::u32(number) {
+        Some(number) => Ok(number as u64),
+        None => Err(TextError::new(TextErrorKind::InvalidNumber(number.to_string()), position))
+    }
+}
+
+fn elem_type(items: &mut Items) -> Result<LanguageType, TextError> {
+    let position = items.position();
+    match items.keyword("`anyfunc`")? {
+        "anyfunc" | "funcref" => Ok(LanguageType::Anyfunc),
+        _ => Err(TextError::new(TextErrorKind::Expected("`anyfunc`"), position))
+    }
+}
+
+fn table_type(items: &mut Items) -> Result<TableType, TextError> {
+    let limits = limits(items)?;
+    let elem_type = elem_type(items)?;
+    Ok(TableType {
+        elem_type,
+        limits
+    })
+}
+
+fn global_type(items: &mut Items) -> Result<GlobalType, TextError> {
+    if let Some(mut mutable) = items.take_list("mut") {
+        let content_type = value_type(&mut mutable)?;
+        mutable.finish()?;
+        return Ok(GlobalType {
+            content_type,
+            mutability: true
+        });
+    }
+    Ok(GlobalType {
+        content_type: value_type(items)?,
+        mutability: false
+    })
+}
+
+// Data is written as any number of strings, which are joined together
+fn strings(items: &mut Items) -> Result<Vec<u8>, TextError> {
+    let mut data = vec![];
+    while !items.is_empty() {
+        data.extend_from_slice(items.string()?);
+    }
+    Ok(data)
+}
@@ -0,0 +1,204 @@
+use std::f32;
+use std::f64;
+
+/// Whether `text` is written like a number rather than a keyword.
+pub fn is_number(text: &str) -> bool {
+    let (_, unsigned) = split_sign(text);
+    unsigned.starts_with(|c: char| c.is_ascii_digit()) || unsigned == "inf" || unsigned.starts_with("nan")
+}
+
+/// Indices, alignments and limits, which can't have a sign.
+pub fn u32(text: &str) -> Option<u32> {
+    let value = magnitude(text)?;
+    if value > ::std::u32::MAX as u64 {
+        return None;
+    }
+    Some(value as u32)
+}
+
+/// Constants can be written signed or unsigned, so either can set the top bit.
+pub fn i32(text: &str) -> Option<i32> {
+    let (negative, unsigned) = split_sign(text);
+    let value = magnitude(unsigned)?;
+    if negative {
+        if value > 1 << 31 {
+            return None;
+        }
+        Some((value as i64).wrapping_neg() as i32)
+    } else {
+        if value > ::std::u32::MAX as u64 {
+            return None;
+        }
+        Some(value as u32 as i32)
+    }
+}
+
+pub fn i64(text: &str) -> Option<i64> {
+    let (negative, unsigned) = split_sign(text);
+    let value = magnitude(unsigned)?;
+    if negative {
+        if value > 1 << 63 {
+            return None;
+        }
+        Some((value as i64).wrapping_neg())
+    } else {
+        Some(value as i64)
+    }
+}
+
+pub fn f32(text: &str) -> Option<f32> {
+    let (negative, unsigned) = split_sign(text);
+    let value = if unsigned.starts_with("nan:0x") {
+        let payload = digits(&unsigned[6..], 16)?;
+        if payload == 0 || payload >= 1 << 23 {
+            return None;
+        }
+        f32::from_bits(0x7f80_0000 | payload as u32)
+    } else if unsigned == "nan" {
+        f32::from_bits(0x7fc0_0000)
+    } else if unsigned == "inf" {
+        f32::INFINITY
+    } else if unsigned.starts_with("0x") {
+        hex_float(&unsigned[2..])? as f32
+    } else {
+        decimal(unsigned)?.parse::<f32>().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+pub fn f64(text: &str) -> Option<f64> {
+    let (negative, unsigned) = split_sign(text);
+    let value = if unsigned.starts_with("nan:0x") {
+        let payload = digits(&unsigned[6..], 16)?;
+        if payload == 0 || payload >= 1 << 52 {
+            return None;
+        }
+        f64::from_bits(0x7ff0_0000_0000_0000 | payload)
+    } else if unsigned == "nan" {
+        f64::from_bits(0x7ff8_0000_0000_0000)
+    } else if unsigned == "inf" {
+        f64::INFINITY
+    } else if unsigned.starts_with("0x") {
+        hex_float(&unsigned[2..])?
+    } else {
+        decimal(unsigned)?.parse::<f64>().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn split_sign(text: &str) -> (bool, &str) {
+    if text.starts_with('-') {
+        (true, &text[1..])
+    } else if text.starts_with('+') {
+        (false, &text[1..])
+    } else {
+        (false, text)
+    }
+}
+
+fn magnitude(text: &str) -> Option<u64> {
+    if text.starts_with("0x") {
+        digits(&text[2..], 16)
+    } else {
+        digits(text, 10)
+    }
+}
+
+// Digits can be separated by single underscores
+fn digits(text: &str, radix: u32) -> Option<u64> {
+    if !underscores_separate(text) {
+        return None;
+    }
+    let mut value: u64 = 0;
+    for c in text.chars().filter(|c| *c != '_') {
+        let digit = c.to_digit(radix)? as u64;
+        value = value.checked_mul(radix as u64)?.checked_add(digit)?;
+    }
+    Some(value)
+}
+
+fn underscores_separate(text: &str) -> bool {
+    !text.is_empty() && !text.starts_with('_') && !text.ends_with('_') && !text.contains("__")
+}
+
+// What's left is for `str::parse`, once it's known to be nothing but a decimal number
+fn decimal(text: &str) -> Option<String> {
+    let allowed = |c: char| c.is_ascii_digit() || c == '.' || c == 'e' || c == 'E' || c == '+' || c == '-' || c == '_';
+    if !text.starts_with(|c: char| c.is_ascii_digit()) || !text.chars().all(allowed) || !underscores_separate(text) {
+        return None;
+    }
+    Some(text.replace("_", ""))
+}
+
+// `1.8p3` and the like, after the `0x`
+fn hex_float(text: &str) -> Option<f64> {
+    let (digits, exponent) = match text.find(|c| c == 'p' || c == 'P') {
+        Some(p) => (&text[..p], i32(&text[p + 1..])?),
+        None => (text, 0)
+    };
+    let (whole, fraction) = match digits.find('.') {
+        Some(point) => (&digits[..point], &digits[point + 1..]),
+        None => (digits, "")
+    };
+    if !underscores_separate(whole) || (!fraction.is_empty() && !underscores_separate(fraction)) {
+        return None;
+    }
+    let mut mantissa: u64 = 0;
+    let mut exponent = exponent as i64;
+    for (c, in_fraction) in whole.chars().map(|c| (c, false)).chain(fraction.chars().map(|c| (c, true))) {
+        if c == '_' {
+            continue;
+        }
+        let digit = c.to_digit(16)? as u64;
+        // Digits past what an f64 can hold only move the point
+        if mantissa < 1 << 56 {
+            mantissa = mantissa * 16 + digit;
+            if in_fraction {
+                exponent -= 4;
+            }
+        } else if !in_fraction {
+            exponent += 4;
+        }
+    }
+    let mut value = mantissa as f64;
+    while exponent > 1000 {
+        value *= 2f64.powi(1000);
+        exponent -= 1000;
+    }
+    while exponent < -1000 {
+        value *= 2f64.powi(-1000);
+        exponent += 1000;
+    }
+    Some(value * 2f64.powi(exponent as i32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integers_can_be_signed_or_unsigned() {
+        assert_eq!(i32("42"), Some(42));
+        assert_eq!(i32("-0x10"), Some(-16));
+        assert_eq!(i32("4_294_967_295"), Some(-1));
+        assert_eq!(i32("-2147483648"), Some(::std::i32::MIN));
+        assert_eq!(i32("4294967296"), None);
+        assert_eq!(i32("-2147483649"), None);
+        assert_eq!(i64("0xffff_ffff_ffff_ffff"), Some(-1));
+        assert_eq!(u32("-1"), None);
+        assert_eq!(u32("1__0"), None);
+    }
+
+    #[test]
+    fn floats_can_be_decimal_hex_or_special() {
+        assert_eq!(f32("1.5"), Some(1.5));
+        assert_eq!(f64("-1e3"), Some(-1000.0));
+        assert_eq!(f64("0x1.8p3"), Some(12.0));
+        assert_eq!(f32("0x1p-2"), Some(0.25));
+        assert_eq!(f64("-inf"), Some(f64::NEG_INFINITY));
+        assert_eq!(f32("nan").map(f32::to_bits), Some(0x7fc0_0000));
+        assert_eq!(f32("-nan:0x1").map(f32::to_bits), Some(0xff80_0001));
+        assert_eq!(f64("nan:0x0"), None);
+        assert_eq!(f32("1.5x"), None);
+    }
+}
@@ -0,0 +1,565 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use parse_tree::functions::FuncBody;
+use parse_tree::functions::FuncSignature;
+use parse_tree::language_types::BlockType;
+use parse_tree::language_types::ExternalKind;
+use parse_tree::language_types::GlobalType;
+use parse_tree::language_types::ImportKind;
+use parse_tree::language_types::InitExpression;
+use parse_tree::language_types::MemoryImmediate;
+use parse_tree::language_types::Operation;
+use parse_tree::language_types::ResizableLimits;
+use parse_tree::language_types::ValueType;
+use parse_tree::tables::Table;
+use parse_tree::types::TypeDefinition;
+use parse_tree::ParseModule;
+
+use parser;
+
+use text::instructions::memory_operation;
+
+/// Renders `module` in the text format, so it can be read or parsed again. Functions and locals
+/// are referred to by the names in the name section where they have one.
+pub fn print_module(module: &ParseModule) -> String {
+    let names = Printable::new(module);
+    let mut out = String::from("(module");
+    if let Some(ref name) = module.names.module {
+        if is_id(name) {
+            write!(out, " ${}", name).unwrap();
+        }
+    }
+
+    for (index, definition) in module.types.iter().enumerate() {
+        let TypeDefinition::Func(ref signature) = *definition;
+        write!(out, "\n  (type (;{};) (func{}))", index, signature_text(signature)).unwrap();
+    }
+
+    let mut functions = 0;
+    for import in &module.imports {
+        let description = match import.kind {
+            ImportKind::Function(type_index) => {
+                let description = format!("func{} (type {}){}", names.function_id(functions), type_index, type_text(module, type_index));
+                functions += 1;
+                description
+            },
+            ImportKind::Table(ref table) => format!("table{} funcref", limits_text(&table.limits)),
+            ImportKind::Memory(ref limits) => format!("memory{}", limits_text(limits)),
+            ImportKind::Global(ref global) => format!("global {}", global_type_text(global)),
+        };
+        write!(out, "\n  (import {} {} ({}))", string_text(import.module.as_bytes()), string_text(import.field.as_bytes()), description).unwrap();
+    }
+
+    let imported = functions;
+    for (i, type_index) in module.function_signatures.iter().enumerate() {
+        let index = imported + i;
+        // Bodies left encoded by a lazy parser come first, and are decoded to be printed
+        let decoded;
+        let body = match module.encoded_bodies.get(i).map(|encoded| parser::decode_body(encoded, index)) {
+            Some(Ok(body)) => {
+                decoded = body;
+                Some(&decoded)
+            },
+            Some(Err(error)) => {
+                write!(out, "\n  ;; the body of function {} couldn't be decoded: {}", index, error).unwrap();
+                None
+            },
+            None => module.function_bodies.get(i - module.encoded_bodies.len())
+        };
+        print_function(&mut out, module, &names, index, *type_index, body);
+    }
+
+    for table in &module.tables {
+        let Table::AnyFunc { ref limits, .. } = *table;
+        write!(out, "\n  (table{} funcref)", limits_text(limits)).unwrap();
+    }
+    for memory in &module.memories {
+        write!(out, "\n  (memory{})", limits_text(&memory.limits)).unwrap();
+    }
+    for global in &module.globals {
+        write!(out, "\n  (global {} {})", global_type_text(&global.constraints), init_text(&global.value)).unwrap();
+    }
+    for export in &module.exports {
+        let description = match export.kind {
+            ExternalKind::Function(index) => format!("func {}", names.function(index)),
+            ExternalKind::Table(index) => format!("table {}", index),
+            ExternalKind::Memory(index) => format!("memory {}", index),
+            ExternalKind::Global(index) => format!("global {}", index),
+        };
+        write!(out, "\n  (export {} ({}))", string_text(export.field.as_bytes()), description).unwrap();
+    }
+    if let Some(start) = module.start_function {
+        write!(out, "\n  (start {})", names.function(start)).unwrap();
+    }
+    for segment in &module.elements {
+        write!(out, "\n  (elem{} {}", index_text(segment.index), init_text(&segment.offset)).unwrap();
+        for function in &segment.elements {
+            write!(out, " {}", names.function(*function)).unwrap();
+        }
+        out.push(')');
+    }
+    for segment in &module.data {
+        write!(out, "\n  (data{} {} {})", index_text(segment.index), init_text(&segment.offset), string_text(&segment.data)).unwrap();
+    }
+    for section in &module.custom_sections {
+        write!(out, "\n  ;; custom section {} of {} bytes", string_text(section.name.as_bytes()), section.payload.len()).unwrap();
+    }
+    out.push_str(")\n");
+    out
+}
+
+fn print_function(out: &mut String, module: &ParseModule, names: &Printable, index: usize, type_index: usize, body: Option<&FuncBody>) {
+    let signature = match module.types.get(type_index) {
+        Some(&TypeDefinition::Func(ref signature)) => signature.clone(),
+        None => FuncSignature { parameters: vec![], returns: vec![] }
+    };
+    write!(out, "\n  (func{} (type {})", names.function_id(index), type_index).unwrap();
+    let locals = names.locals(index);
+    for (local, parameter) in signature.parameters.iter().enumerate() {
+        match locals.get(&local) {
+            Some(name) => write!(out, " (param ${} {})", name, value_type_name(parameter)).unwrap(),
+            None => write!(out, " (param {})", value_type_name(parameter)).unwrap()
+        }
+    }
+    if !signature.returns.is_empty() {
+        write!(out, " (result {})", types_text(&signature.returns)).unwrap();
+    }
+    if let Some(body) = body {
+        for (i, local) in body.locals.iter().enumerate() {
+            match locals.get(&(signature.parameters.len() + i)) {
+                Some(name) => write!(out, "\n    (local ${} {})", name, value_type_name(local)).unwrap(),
+                None => write!(out, "\n    (local {})", value_type_name(local)).unwrap()
+            }
+        }
+        print_code(out, &body.code, 2, names, &locals);
+    }
+    out.push(')');
+}
+
+// Blocks are printed flat, with their contents indented
+fn print_code(out: &mut String, code: &[Operation], depth: usize, names: &Printable, locals: &HashMap<usize, &str>) {
+    for operation in code {
+        match *operation {
+            Operation::Block(ref block) | Operation::Loop(ref block) | Operation::If(ref block) => {
+                let keyword = match *operation {
+                    Operation::Block(_) => "block",
+                    Operation::Loop(_) => "loop",
+                    _ => "if"
+                };
+                match block.block_type {
+                    BlockType::Value(ref value_type) => line(out, depth, &format!("{} (result {})", keyword, value_type_name(value_type))),
+                    BlockType::Empty => line(out, depth, keyword),
+                    BlockType::Type(index) => line(out, depth, &format!("{} (type {})", keyword, index))
+                }
+                print_code(out, &block.operations, depth + 1, names, locals);
+                line(out, depth, "end");
+            },
+            // `else` is part of the `if` it's in, so it lines up with it
+            Operation::Else => line(out, depth - 1, "else"),
+            _ => line(out, depth, &instruction_text(operation, names, locals))
+        }
+    }
+}
+
+fn line(out: &mut String, depth: usize, text: &str) {
+    out.push('\n');
+    for _ in 0..depth {
+        out.push_str("  ");
+    }
+    out.push_str(text);
+}
+
+fn instruction_text(operation: &Operation, names: &Printable, locals: &HashMap<usize, &str>) -> String {
+    let name = operation_name(operation);
+    let local = |index: usize| match locals.get(&index) {
+        Some(name) => format!("${}", name),
+        None => index.to_string()
+    };
+    match *operation {
+        Operation::Branch(depth) | Operation::BranchIf(depth) => format!("{} {}", name, depth),
+        Operation::BranchTable(ref table) => {
+            let mut text = name.to_string();
+            for target in table.targets.iter().chain(Some(&table.default)) {
+                write!(text, " {}", target).unwrap();
+            }
+            text
+        },
+        Operation::Call(index) => format!("{} {}", name, names.function(index)),
+        Operation::CallIndirect(index, _) => format!("{} (type {})", name, index),
+        Operation::GetLocal(index) | Operation::SetLocal(index) | Operation::TeeLocal(index) => format!("{} {}", name, local(index)),
+        Operation::GetGlobal(index) | Operation::SetGlobal(index) => format!("{} {}", name, index),
+        Operation::I32Const(value) => format!("{} {}", name, value),
+        Operation::I64Const(value) => format!("{} {}", name, value),
+        Operation::F32Const(value) => format!("{} {}", name, f32_text(value)),
+        Operation::F64Const(value) => format!("{} {}", name, f64_text(value)),
+        Operation::I32Load(ref immediate) | Operation::I64Load(ref immediate) | Operation::F32Load(ref immediate) |
+        Operation::F64Load(ref immediate) | Operation::I32Load8S(ref immediate) | Operation::I32Load8U(ref immediate) |
+        Operation::I32Load16S(ref immediate) | Operation::I32Load16U(ref immediate) | Operation::I64Load8S(ref immediate) |
+        Operation::I64Load8U(ref immediate) | Operation::I64Load16S(ref immediate) | Operation::I64Load16U(ref immediate) |
+        Operation::I64Load32S(ref immediate) | Operation::I64Load32U(ref immediate) | Operation::I32Store(ref immediate) |
+        Operation::I64Store(ref immediate) | Operation::F32Store(ref immediate) | Operation::F64Store(ref immediate) |
+        Operation::I32Store8(ref immediate) | Operation::I32Store16(ref immediate) | Operation::I64Store8(ref immediate) |
+        Operation::I64Store16(ref immediate) | Operation::I64Store32(ref immediate) => format!("{}{}", name, memory_text(name, immediate)),
+        _ => name.to_string()
+    }
+}
+
+// Only what differs from the defaults is written
+fn memory_text(name: &str, immediate: &MemoryImmediate) -> String {
+    let mut text = String::new();
+    if immediate.offset != 0 {
+        write!(text, " offset={}", immediate.offset).unwrap();
+    }
+    let natural = memory_operation(name).map(|(alignment, _)| alignment);
+    if natural != Some(immediate.flags) {
+        write!(text, " align={}", 1u64 << immediate.flags).unwrap();
+    }
+    text
+}
+
+/// The `$names` that can be printed: those that are valid ids and haven't already been used.
+struct Printable<'m> {
+    functions: HashMap<usize, &'m str>,
+    module: &'m ParseModule<'m>,
+}
+
+impl<'m> Printable<'m> {
+    fn new(module: &'m ParseModule) -> Printable<'m> {
+        Printable {
+            functions: usable(&module.names.functions),
+            module,
+        }
+    }
+
+    /// How a function is referred to.
+    fn function(&self, index: usize) -> String {
+        match self.functions.get(&index) {
+            Some(name) => format!("${}", name),
+            None => index.to_string()
+        }
+    }
+
+    /// The id a function is defined with, if it has one.
+    fn function_id(&self, index: usize) -> String {
+        match self.functions.get(&index) {
+            Some(name) => format!(" ${}", name),
+            None => format!(" (;{};)", index)
+        }
+    }
+
+    fn locals(&self, function: usize) -> HashMap<usize, &'m str> {
+        match self.module.names.locals.get(&function) {
+            Some(locals) => usable(locals),
+            None => HashMap::new()
+        }
+    }
+}
+
+fn usable(names: &HashMap<usize, String>) -> HashMap<usize, &str> {
+    let mut indices: Vec<&usize> = names.keys().collect();
+    indices.sort();
+    let mut used = HashSet::new();
+    let mut usable = HashMap::new();
+    for index in indices {
+        let name = &names[index][..];
+        if is_id(name) && used.insert(name) {
+            usable.insert(*index, name);
+        }
+    }
+    usable
+}
+
+fn is_id(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-./:<=>?@\\^_`|~".contains(c))
+}
+
+fn signature_text(signature: &FuncSignature) -> String {
+    let mut text = String::new();
+    if !signature.parameters.is_empty() {
+        write!(text, " (param {})", types_text(&signature.parameters)).unwrap();
+    }
+    if !signature.returns.is_empty() {
+        write!(text, " (result {})", types_text(&signature.returns)).unwrap();
+    }
+    text
+}
+
+fn type_text(module: &ParseModule, index: usize) -> String {
+    match module.types.get(index) {
+        Some(&TypeDefinition::Func(ref signature)) => signature_text(signature),
+        None => String::new()
+    }
+}
+
+fn types_text(types: &[ValueType]) -> String {
+    types.iter().map(value_type_name).collect::<Vec<_>>().join(" ")
+}
+
+fn value_type_name(value_type: &ValueType) -> &'static str {
+    match *value_type {
+        ValueType::I32 => "i32",
+        ValueType::I64 => "i64",
+        ValueType::F32 => "f32",
+        ValueType::F64 => "f64",
+    }
+}
+
+fn limits_text(limits: &ResizableLimits) -> String {
+    match limits.maximum {
+        Some(maximum) => format!(" {} {}", limits.initial, maximum),
+        None => format!(" {}", limits.initial)
+    }
+}
+
+fn global_type_text(global: &GlobalType) -> String {
+    if global.mutability {
+        format!("(mut {})", value_type_name(&global.content_type))
+    } else {
+        value_type_name(&global.content_type).to_string()
+    }
+}
+
+fn init_text(expression: &InitExpression) -> String {
+    match *expression {
+        InitExpression::I32Const(value) => format!("(i32.const {})", value),
+        InitExpression::I64Const(value) => format!("(i64.const {})", value),
+        InitExpression::F32Const(value) => format!("(f32.const {})", f32_text(value)),
+        InitExpression::F64Const(value) => format!("(f64.const {})", f64_text(value)),
+        InitExpression::GetGlobal(index) => format!("(global.get {})", index),
+    }
+}
+
+// Segments in anything but the first table or memory say which they're for
+fn index_text(index: usize) -> String {
+    if index == 0 {
+        String::new()
+    } else {
+        format!(" {}", index)
+    }
+}
+
+fn string_text(bytes: &[u8]) -> String {
+    let mut text = String::from("\"");
+    for byte in bytes {
+        match *byte {
+            b'"' | b'\\' => write!(text, "\\{}", *byte as char).unwrap(),
+            0x20..=0x7e => text.push(*byte as char),
+            _ => write!(text, "\\{:02x}", byte).unwrap()
+        }
+    }
+    text.push('"');
+    text
+}
+
+// Debug formatting gives the shortest decimal that reads back as the same value
+fn f32_text(value: f32) -> String {
+    let sign = if value.is_sign_negative() { "-" } else { "" };
+    if value.is_nan() {
+        let payload = value.to_bits() & 0x007f_ffff;
+        if payload == 0x0040_0000 {
+            return format!("{}nan", sign);
+        }
+        return format!("{}nan:{:#x}", sign, payload);
+    }
+    if value.is_infinite() {
+        return format!("{}inf", sign);
+    }
+    format!("{:?}", value)
+}
+
+fn f64_text(value: f64) -> String {
+    let sign = if value.is_sign_negative() { "-" } else { "" };
+    if value.is_nan() {
+        let payload = value.to_bits() & 0x000f_ffff_ffff_ffff;
+        if payload == 0x0008_0000_0000_0000 {
+            return format!("{}nan", sign);
+        }
+        return format!("{}nan:{:#x}", sign, payload);
+    }
+    if value.is_infinite() {
+        return format!("{}inf", sign);
+    }
+    format!("{:?}", value)
+}
+
+fn operation_name(operation: &Operation) -> &'static str {
+    match *operation {
+        Operation::Unreachable => "unreachable",
+        Operation::Nop => "nop",
+        Operation::Block(_) => "block",
+        Operation::Loop(_) => "loop",
+        Operation::If(_) => "if",
+        Operation::Else => "else",
+        Operation::End => "end",
+        Operation::Branch(_) => "br",
+        Operation::BranchIf(_) => "br_if",
+        Operation::BranchTable(_) => "br_table",
+        Operation::Return => "return",
+        Operation::Call(_) => "call",
+        Operation::CallIndirect(..) => "call_indirect",
+        Operation::Drop => "drop",
+        Operation::Select => "select",
+        Operation::GetLocal(_) => "local.get",
+        Operation::SetLocal(_) => "local.set",
+        Operation::TeeLocal(_) => "local.tee",
+        Operation::GetGlobal(_) => "global.get",
+        Operation::SetGlobal(_) => "global.set",
+        Operation::I32Load(_) => "i32.load",
+        Operation::I64Load(_) => "i64.load",
+        Operation::F32Load(_) => "f32.load",
+        Operation::F64Load(_) => "f64.load",
+        Operation::I32Load8S(_) => "i32.load8_s",
+        Operation::I32Load8U(_) => "i32.load8_u",
+        Operation::I32Load16S(_) => "i32.load16_s",
+        Operation::I32Load16U(_) => "i32.load16_u",
+        Operation::I64Load8S(_) => "i64.load8_s",
+        Operation::I64Load8U(_) => "i64.load8_u",
+        Operation::I64Load16S(_) => "i64.load16_s",
+        Operation::I64Load16U(_) => "i64.load16_u",
+        Operation::I64Load32S(_) => "i64.load32_s",
+        Operation::I64Load32U(_) => "i64.load32_u",
+        Operation::I32Store(_) => "i32.store",
+        Operation::I64Store(_) => "i64.store",
+        Operation::F32Store(_) => "f32.store",
+        Operation::F64Store(_) => "f64.store",
+        Operation::I32Store8(_) => "i32.store8",
+        Operation::I32Store16(_) => "i32.store16",
+        Operation::I64Store8(_) => "i64.store8",
+        Operation::I64Store16(_) => "i64.store16",
+        Operation::I64Store32(_) => "i64.store32",
+        Operation::CurrentMemory(_) => "memory.size",
+        Operation::GrowMemory(_) => "memory.grow",
+        Operation::I32Const(_) => "i32.const",
+        Operation::I64Const(_) => "i64.const",
+        Operation::F32Const(_) => "f32.const",
+        Operation::F64Const(_) => "f64.const",
+
+        Operation::I32Eqz => "i32.eqz",
+        Operation::I32Eq => "i32.eq",
+        Operation::I32Ne => "i32.ne",
+        Operation::I32LtS => "i32.lt_s",
+        Operation::I32LtU => "i32.lt_u",
+        Operation::I32GtS => "i32.gt_s",
+        Operation::I32GtU => "i32.gt_u",
+        Operation::I32LeS => "i32.le_s",
+        Operation::I32LeU => "i32.le_u",
+        Operation::I32GeS => "i32.ge_s",
+        Operation::I32GeU => "i32.ge_u",
+        Operation::I64Eqz => "i64.eqz",
+        Operation::I64Eq => "i64.eq",
+        Operation::I64Ne => "i64.ne",
+        Operation::I64LtS => "i64.lt_s",
+        Operation::I64LtU => "i64.lt_u",
+        Operation::I64GtS => "i64.gt_s",
+        Operation::I64GtU => "i64.gt_u",
+        Operation::I64LeS => "i64.le_s",
+        Operation::I64LeU => "i64.le_u",
+        Operation::I64GeS => "i64.ge_s",
+        Operation::I64GeU => "i64.ge_u",
+        Operation::F32Eq => "f32.eq",
+        Operation::F32Ne => "f32.ne",
+        Operation::F32Lt => "f32.lt",
+        Operation::F32Gt => "f32.gt",
+        Operation::F32Le => "f32.le",
+        Operation::F32Ge => "f32.ge",
+        Operation::F64Eq => "f64.eq",
+        Operation::F64Ne => "f64.ne",
+        Operation::F64Lt => "f64.lt",
+        Operation::F64Gt => "f64.gt",
+        Operation::F64Le => "f64.le",
+        Operation::F64Ge => "f64.ge",
+
+        Operation::I32Clz => "i32.clz",
+        Operation::I32Ctz => "i32.ctz",
+        Operation::I32Popcnt => "i32.popcnt",
+        Operation::I32Add => "i32.add",
+        Operation::I32Sub => "i32.sub",
+        Operation::I32Mul => "i32.mul",
+        Operation::I32DivS => "i32.div_s",
+        Operation::I32DivU => "i32.div_u",
+        Operation::I32RemS => "i32.rem_s",
+        Operation::I32RemU => "i32.rem_u",
+        Operation::I32And => "i32.and",
+        Operation::I32Or => "i32.or",
+        Operation::I32Xor => "i32.xor",
+        Operation::I32Shl => "i32.shl",
+        Operation::I32ShrS => "i32.shr_s",
+        Operation::I32ShrU => "i32.shr_u",
+        Operation::I32Rotl => "i32.rotl",
+        Operation::I32Rotr => "i32.rotr",
+        Operation::I64Clz => "i64.clz",
+        Operation::I64Ctz => "i64.ctz",
+        Operation::I64Popcnt => "i64.popcnt",
+        Operation::I64Add => "i64.add",
+        Operation::I64Sub => "i64.sub",
+        Operation::I64Mul => "i64.mul",
+        Operation::I64DivS => "i64.div_s",
+        Operation::I64DivU => "i64.div_u",
+        Operation::I64RemS => "i64.rem_s",
+        Operation::I64RemU => "i64.rem_u",
+        Operation::I64And => "i64.and",
+        Operation::I64Or => "i64.or",
+        Operation::I64Xor => "i64.xor",
+        Operation::I64Shl => "i64.shl",
+        Operation::I64ShrS => "i64.shr_s",
+        Operation::I64ShrU => "i64.shr_u",
+        Operation::I64Rotl => "i64.rotl",
+        Operation::I64Rotr => "i64.rotr",
+        Operation::F32Abs => "f32.abs",
+        Operation::F32Neg => "f32.neg",
+        Operation::F32Ceil => "f32.ceil",
+        Operation::F32Floor => "f32.floor",
+        Operation::F32Trunc => "f32.trunc",
+        Operation::F32Nearest => "f32.nearest",
+        Operation::F32Sqrt => "f32.sqrt",
+        Operation::F32Add => "f32.add",
+        Operation::F32Sub => "f32.sub",
+        Operation::F32Mul => "f32.mul",
+        Operation::F32Div => "f32.div",
+        Operation::F32Min => "f32.min",
+        Operation::F32Max => "f32.max",
+        Operation::F32Copysign => "f32.copysign",
+        Operation::F64Abs => "f64.abs",
+        Operation::F64Neg => "f64.neg",
+        Operation::F64Ceil => "f64.ceil",
+        Operation::F64Floor => "f64.floor",
+        Operation::F64Trunc => "f64.trunc",
+        Operation::F64Nearest => "f64.nearest",
+        Operation::F64Sqrt => "f64.sqrt",
+        Operation::F64Add => "f64.add",
+        Operation::F64Sub => "f64.sub",
+        Operation::F64Mul => "f64.mul",
+        Operation::F64Div => "f64.div",
+        Operation::F64Min => "f64.min",
+        Operation::F64Max => "f64.max",
+        Operation::F64Copysign => "f64.copysign",
+
+        Operation::I32WrapI64 => "i32.wrap_i64",
+        Operation::I32TruncSF32 => "i32.trunc_f32_s",
+        Operation::I32TruncUF32 => "i32.trunc_f32_u",
+        Operation::I32TruncSF64 => "i32.trunc_f64_s",
+        Operation::I32TruncUF64 => "i32.trunc_f64_u",
+        Operation::I64ExtendSI32 => "i64.extend_i32_s",
+        Operation::I64ExtendUI32 => "i64.extend_i32_u",
+        Operation::I64TruncSF32 => "i64.trunc_f32_s",
+        Operation::I64TruncUF32 => "i64.trunc_f32_u",
+        Operation::I64TruncSF64 => "i64.trunc_f64_s",
+        Operation::I64TruncUF64 => "i64.trunc_f64_u",
+        Operation::F32ConvertSI32 => "f32.convert_i32_s",
+        Operation::F32ConvertUI32 => "f32.convert_i32_u",
+        Operation::F32ConvertSI64 => "f32.convert_i64_s",
+        Operation::F32ConvertUI64 => "f32.convert_i64_u",
+        Operation::F32DemoteF64 => "f32.demote_f64",
+        Operation::F64ConvertSI32 => "f64.convert_i32_s",
+        Operation::F64ConvertUI32 => "f64.convert_i32_u",
+        Operation::F64ConvertSI64 => "f64.convert_i64_s",
+        Operation::F64ConvertUI64 => "f64.convert_i64_u",
+        Operation::F64PromoteF32 => "f64.promote_f32",
+        Operation::I32ReinterpretF32 => "i32.reinterpret_f32",
+        Operation::I64ReinterpretF64 => "i64.reinterpret_f64",
+        Operation::F32ReinterpretI32 => "f32.reinterpret_i32",
+        Operation::F64ReinterpretI64 => "f64.reinterpret_i64",
+    }
+}
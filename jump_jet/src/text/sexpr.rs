@@ -0,0 +1,374 @@
+use std::char;
+use std::mem;
+use std::str;
+
+use text::numbers;
+use text::TextError;
+use text::TextErrorKind;
+
+/// Where something starts in the text, counting from 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    /// Keywords, numbers and `offset=`/`align=` immediates, which are told apart by where they're used.
+    Keyword(String),
+    /// A `$name`, without the `$`.
+    Id(String),
+    String(Vec<u8>),
+}
+
+#[derive(Debug)]
+pub enum SExpr {
+    Atom(Token, Position),
+    List(Vec<SExpr>, Position),
+}
+
+impl SExpr {
+    pub fn position(&self) -> Position {
+        match *self {
+            SExpr::Atom(_, position) | SExpr::List(_, position) => position
+        }
+    }
+}
+
+/// Splits `text` into the s-expressions it's made of, dropping whitespace and comments.
+pub fn read(text: &str) -> Result<Vec<SExpr>, TextError> {
+    let mut lexer = Lexer {
+        chars: text.chars().collect(),
+        index: 0,
+        position: Position { line: 1, column: 1 },
+    };
+    // The lists that are still open, along with what came before them in their parent
+    let mut open: Vec<(Vec<SExpr>, Position)> = vec![];
+    let mut current = vec![];
+    loop {
+        lexer.skip_blank()?;
+        let position = lexer.position;
+        match lexer.peek(0) {
+            None => break,
+            Some('(') => {
+                lexer.next();
+                open.push((mem::replace(&mut current, vec![]), position));
+            },
+            Some(')') => {
+                lexer.next();
+                let (parent, start) = match open.pop() {
+                    Some(parent) => parent,
+                    None => return Err(TextError::new(TextErrorKind::UnexpectedCharacter(')'), position))
+                };
+                let list = mem::replace(&mut current, parent);
+                current.push(SExpr::List(list, start));
+            },
+            Some('"') => {
+                let string = lexer.string()?;
+                current.push(SExpr::Atom(Token::String(string), position));
+            },
+            Some(c) => {
+                let word = lexer.word();
+                if word.is_empty() {
+                    return Err(TextError::new(TextErrorKind::UnexpectedCharacter(c), position));
+                }
+                let token = if word.len() > 1 && word.starts_with('$') {
+                    Token::Id(word[1..].to_string())
+                } else {
+                    Token::Keyword(word)
+                };
+                current.push(SExpr::Atom(token, position));
+            }
+        }
+    }
+    if !open.is_empty() {
+        return Err(TextError::new(TextErrorKind::UnexpectedEnd, lexer.position));
+    }
+    Ok(current)
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    index: usize,
+    position: Position,
+}
+
+impl Lexer {
+    fn peek(&self, ahead: usize) -> Option<char> {
+        self.chars.get(self.index + ahead).cloned()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let next = self.peek(0);
+        if let Some(c) = next {
+            self.index += 1;
+            if c == '\n' {
+                self.position.line += 1;
+                self.position.column = 1;
+            } else {
+                self.position.column += 1;
+            }
+        }
+        next
+    }
+
+    fn skip_blank(&mut self) -> Result<(), TextError> {
+        loop {
+            match (self.peek(0), self.peek(1)) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.next();
+                },
+                (Some(';'), Some(';')) => {
+                    while let Some(c) = self.next() {
+                        if c == '\n' {
+                            break;
+                        }
+                    }
+                },
+                (Some('('), Some(';')) => self.block_comment()?,
+                _ => return Ok(())
+            }
+        }
+    }
+
+    // Block comments can be nested
+    fn block_comment(&mut self) -> Result<(), TextError> {
+        let mut depth = 0;
+        loop {
+            match (self.peek(0), self.peek(1)) {
+                (Some('('), Some(';')) => depth += 1,
+                (Some(';'), Some(')')) => depth -= 1,
+                (Some(_), _) => {
+                    self.next();
+                    continue;
+                },
+                (None, _) => return Err(TextError::new(TextErrorKind::UnexpectedEnd, self.position))
+            }
+            self.next();
+            self.next();
+            if depth == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    fn word(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(c) = self.peek(0) {
+            if c.is_whitespace() || c == '(' || c == ')' || c == '"' || c == ';' {
+                break;
+            }
+            word.push(c);
+            self.next();
+        }
+        word
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>, TextError> {
+        self.next();
+        let mut bytes = vec![];
+        let mut buffer = [0; 4];
+        loop {
+            let position = self.position;
+            let invalid = || TextError::new(TextErrorKind::InvalidString, position);
+            let c = match self.next() {
+                Some(c) => c,
+                None => return Err(TextError::new(TextErrorKind::UnexpectedEnd, self.position))
+            };
+            if c == '"' {
+                return Ok(bytes);
+            }
+            if c != '\\' {
+                bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                continue;
+            }
+            match self.next() {
+                Some('t') => bytes.push(b'\t'),
+                Some('n') => bytes.push(b'\n'),
+                Some('r') => bytes.push(b'\r'),
+                Some('"') => bytes.push(b'"'),
+                Some('\'') => bytes.push(b'\''),
+                Some('\\') => bytes.push(b'\\'),
+                Some('u') => {
+                    if self.next() != Some('{') {
+                        return Err(invalid());
+                    }
+                    let mut digits = String::new();
+                    loop {
+                        match self.next() {
+                            Some('}') => break,
+                            Some(c) => digits.push(c),
+                            None => return Err(invalid())
+                        }
+                    }
+                    let c = u32::from_str_radix(&digits.replace("_", ""), 16).ok().and_then(char::from_u32).ok_or_else(&invalid)?;
+                    bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                },
+                Some(high) => {
+                    let low = self.next();
+                    match (high.to_digit(16), low.and_then(|c| c.to_digit(16))) {
+                        (Some(high), Some(low)) => bytes.push((high * 16 + low) as u8),
+                        _ => return Err(invalid())
+                    }
+                },
+                None => return Err(invalid())
+            }
+        }
+    }
+}
+
+/// Walks through the contents of a list.
+#[derive(Clone)]
+pub struct Items<'s> {
+    items: &'s [SExpr],
+    index: usize,
+    // Errors about what's missing at the end of the list point to its start
+    start: Position,
+}
+
+impl<'s> Items<'s> {
+    pub fn new(items: &'s [SExpr], start: Position) -> Items<'s> {
+        Items {
+            items,
+            index: 0,
+            start,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index == self.items.len()
+    }
+
+    pub fn peek(&self) -> Option<&'s SExpr> {
+        self.items.get(self.index)
+    }
+
+    pub fn next(&mut self) -> Option<&'s SExpr> {
+        let next = self.peek();
+        if next.is_some() {
+            self.index += 1;
+        }
+        next
+    }
+
+    /// Where the next item starts, or where the list does if there's nothing left.
+    pub fn position(&self) -> Position {
+        self.peek().map(SExpr::position).unwrap_or(self.start)
+    }
+
+    pub fn error(&self, kind: TextErrorKind) -> TextError {
+        TextError::new(kind, self.position())
+    }
+
+    pub fn expected(&self, what: &'static str) -> TextError {
+        self.error(TextErrorKind::Expected(what))
+    }
+
+    /// Fails if anything is left in the list.
+    pub fn finish(&self) -> Result<(), TextError> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self.expected("`)`"))
+        }
+    }
+
+    pub fn peek_keyword(&self) -> Option<&'s str> {
+        match self.peek() {
+            Some(&SExpr::Atom(Token::Keyword(ref keyword), _)) => Some(keyword),
+            _ => None
+        }
+    }
+
+    pub fn keyword(&mut self, what: &'static str) -> Result<&'s str, TextError> {
+        match self.peek_keyword() {
+            Some(keyword) => {
+                self.index += 1;
+                Ok(keyword)
+            },
+            None => Err(self.expected(what))
+        }
+    }
+
+    /// Moves past `keyword` if it's next.
+    pub fn take_keyword(&mut self, keyword: &str) -> bool {
+        if self.peek_keyword() == Some(keyword) {
+            self.index += 1;
+            return true;
+        }
+        false
+    }
+
+    /// Whether a number is next, rather than a keyword.
+    pub fn peek_number(&self) -> bool {
+        self.peek_keyword().map_or(false, numbers::is_number)
+    }
+
+    pub fn peek_id(&self) -> Option<&'s str> {
+        match self.peek() {
+            Some(&SExpr::Atom(Token::Id(ref id), _)) => Some(id),
+            _ => None
+        }
+    }
+
+    /// Moves past a `$name` if there's one next.
+    pub fn id(&mut self) -> Option<&'s str> {
+        let id = self.peek_id();
+        if id.is_some() {
+            self.index += 1;
+        }
+        id
+    }
+
+    pub fn string(&mut self) -> Result<&'s [u8], TextError> {
+        match self.peek() {
+            Some(&SExpr::Atom(Token::String(ref string), _)) => {
+                self.index += 1;
+                Ok(string)
+            },
+            _ => Err(self.expected("a string"))
+        }
+    }
+
+    /// A string that has to be valid UTF-8, like import and export names.
+    pub fn name(&mut self) -> Result<String, TextError> {
+        let position = self.position();
+        match str::from_utf8(self.string()?) {
+            Ok(name) => Ok(name.to_string()),
+            Err(_) => Err(TextError::new(TextErrorKind::InvalidString, position))
+        }
+    }
+
+    /// Whether the next item is a list starting with `keyword`.
+    pub fn peek_list(&self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(&SExpr::List(ref items, _)) => match items.first() {
+                Some(&SExpr::Atom(Token::Keyword(ref head), _)) => head == keyword,
+                _ => false
+            },
+            _ => false
+        }
+    }
+
+    /// Moves into the next item if it's a list starting with `keyword`, skipping the keyword.
+    pub fn take_list(&mut self, keyword: &str) -> Option<Items<'s>> {
+        if !self.peek_list(keyword) {
+            return None;
+        }
+        let mut list = self.list("a list").ok()?;
+        list.index = 1;
+        Some(list)
+    }
+
+    /// Moves into the next item, which has to be a list.
+    pub fn list(&mut self, what: &'static str) -> Result<Items<'s>, TextError> {
+        match self.peek() {
+            Some(&SExpr::List(ref items, start)) => {
+                self.index += 1;
+                Ok(Items::new(items, start))
+            },
+            _ => Err(self.expected(what))
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::error::Error;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+
+use text::sexpr::Position;
+
+/// The ways in which reading a module's text can fail.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TextErrorKind {
+    UnexpectedCharacter(char),
+    UnexpectedEnd,
+    InvalidString,
+    InvalidNumber(String),
+    Expected(&'static str),
+    UnknownInstruction(String),
+    UnknownName(String),
+    DuplicateName(String),
+    MismatchedLabel(String),
+    UndefinedType(usize),
+    TypeMismatch,
+    ImportAfterDefinition,
+    NestedTooDeeply,
+}
+
+impl Display for TextErrorKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            TextErrorKind::UnexpectedCharacter(c) => write!(f, "unexpected {:?}", c),
+            TextErrorKind::UnexpectedEnd => write!(f, "the text ended part way through"),
+            TextErrorKind::InvalidString => write!(f, "invalid string"),
+            TextErrorKind::InvalidNumber(ref number) => write!(f, "invalid number {}", number),
+            TextErrorKind::Expected(what) => write!(f, "expected {}", what),
+            TextErrorKind::UnknownInstruction(ref name) => write!(f, "unknown instruction {}", name),
+            TextErrorKind::UnknownName(ref name) => write!(f, "${} isn't defined", name),
+            TextErrorKind::DuplicateName(ref name) => write!(f, "${} is defined more than once", name),
+            TextErrorKind::MismatchedLabel(ref name) => write!(f, "${} doesn't match the block's label", name),
+            TextErrorKind::UndefinedType(index) => write!(f, "type {} isn't defined", index),
+            TextErrorKind::TypeMismatch => write!(f, "the parameters and results don't match the type"),
+            TextErrorKind::ImportAfterDefinition => write!(f, "imports have to come before any function, table, memory or global is defined"),
+            TextErrorKind::NestedTooDeeply => write!(f, "blocks and folded instructions are nested too deeply"),
+        }
+    }
+}
+
+/// A `TextErrorKind` along with where in the text it was found. Lines and columns count from 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextError {
+    pub kind: TextErrorKind,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl TextError {
+    pub fn new(kind: TextErrorKind, position: Position) -> TextError {
+        TextError {
+            kind,
+            line: position.line,
+            column: position.column,
+        }
+    }
+}
+
+impl Display for TextError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} (at line {}, column {})", self.kind, self.line, self.column)
+    }
+}
+
+impl Error for TextError {}
@@ -0,0 +1,640 @@
+use parse_tree::functions::FuncBody;
+use parse_tree::functions::FuncSignature;
+use parse_tree::language_types::Block;
+use parse_tree::language_types::BlockType;
+use parse_tree::language_types::MemoryImmediate;
+use parse_tree::language_types::Operation;
+use parse_tree::language_types::ValueType;
+use parse_tree::language_types::ValueType::*;
+
+use validator::Context;
+use validator::ValidationError;
+
+struct Frame {
+    // What a branch to the frame's label has to provide
+    label_types: Vec<ValueType>,
+    end_types: Vec<ValueType>,
+    height: usize,
+    unreachable: bool,
+}
+
+/// Type checks a function body with the operand and control stack algorithm from the spec.
+///
+/// Values pushed by unreachable code have an unknown type, shown as `None`, which matches whatever
+/// they are used as.
+struct FunctionValidator<'c> {
+    context: &'c Context,
+    locals: Vec<ValueType>,
+    returns: Vec<ValueType>,
+    operands: Vec<Option<ValueType>>,
+    frames: Vec<Frame>,
+}
+
+pub fn validate(context: &Context, signature: &FuncSignature, body: &FuncBody) -> Result<(), ValidationError> {
+    let mut locals = signature.parameters.clone();
+    locals.extend(body.locals.iter().cloned());
+    let mut validator = FunctionValidator {
+        context,
+        locals,
+        returns: signature.returns.clone(),
+        operands: vec![],
+        frames: vec![],
+    };
+    validator.push_frame(signature.returns.clone(), signature.returns.clone());
+    validator.validate_sequence(&body.code)?;
+    validator.pop_frame()?;
+    Ok(())
+}
+
+impl<'c> FunctionValidator<'c> {
+    fn push(&mut self, value: Option<ValueType>) {
+        self.operands.push(value);
+    }
+
+    fn push_all(&mut self, types: &[ValueType]) {
+        for value_type in types {
+            self.operands.push(Some(value_type.clone()));
+        }
+    }
+
+    fn pop(&mut self) -> Result<Option<ValueType>, ValidationError> {
+        let frame = self.frames.last().expect("the function body has a frame");
+        if self.operands.len() == frame.height {
+            return if frame.unreachable {
+                Ok(None)
+            } else {
+                Err(ValidationError::StackUnderflow)
+            };
+        }
+        Ok(self.operands.pop().expect("operands are above the frame's height"))
+    }
+
+    fn pop_expecting(&mut self, expected: &ValueType) -> Result<Option<ValueType>, ValidationError> {
+        match self.pop()? {
+            Some(ref found) if found != expected => Err(ValidationError::TypeMismatch {
+                expected: expected.clone(),
+                found: found.clone()
+            }),
+            _ => Ok(Some(expected.clone()))
+        }
+    }
+
+    fn pop_all(&mut self, types: &[ValueType]) -> Result<(), ValidationError> {
+        for value_type in types.iter().rev() {
+            self.pop_expecting(value_type)?;
+        }
+        Ok(())
+    }
+
+    fn push_frame(&mut self, label_types: Vec<ValueType>, end_types: Vec<ValueType>) {
+        self.frames.push(Frame {
+            label_types,
+            end_types,
+            height: self.operands.len(),
+            unreachable: false,
+        });
+    }
+
+    // Checks the frame's results are the only thing left on its part of the stack, and returns them
+    fn pop_frame(&mut self) -> Result<Vec<ValueType>, ValidationError> {
+        let end_types = self.frames.last().expect("a frame to pop").end_types.clone();
+        self.pop_all(&end_types)?;
+        let frame = self.frames.pop().expect("a frame to pop");
+        if self.operands.len() != frame.height {
+            return Err(ValidationError::ValuesLeftOnStack(self.operands.len() - frame.height));
+        }
+        Ok(frame.end_types)
+    }
+
+    fn set_unreachable(&mut self) {
+        let frame = self.frames.last_mut().expect("the function body has a frame");
+        self.operands.truncate(frame.height);
+        frame.unreachable = true;
+    }
+
+    fn label_types(&self, depth: i32) -> Result<Vec<ValueType>, ValidationError> {
+        let depth = depth as u32 as usize;
+        if depth >= self.frames.len() {
+            return Err(ValidationError::UndefinedLabel(depth));
+        }
+        Ok(self.frames[self.frames.len() - 1 - depth].label_types.clone())
+    }
+
+    // The block's parameters are already off the stack, they start off its own part of it
+    fn validate_block(&mut self, parameters: &[ValueType], label_types: Vec<ValueType>, end_types: Vec<ValueType>, operations: &[Operation]) -> Result<(), ValidationError> {
+        self.push_frame(label_types, end_types);
+        self.push_all(parameters);
+        self.validate_sequence(operations)?;
+        let results = self.pop_frame()?;
+        self.push_all(&results);
+        Ok(())
+    }
+
+    fn block_signature(&self, block_type: &BlockType) -> Result<FuncSignature, ValidationError> {
+        match *block_type {
+            BlockType::Empty => Ok(FuncSignature { parameters: vec![], returns: vec![] }),
+            BlockType::Value(ref value_type) => Ok(FuncSignature { parameters: vec![], returns: vec![value_type.clone()] }),
+            BlockType::Type(index) => match self.context.types.get(index) {
+                Some(signature) => Ok(signature.clone()),
+                None => Err(ValidationError::UndefinedType(index))
+            }
+        }
+    }
+
+    fn validate_sequence(&mut self, operations: &[Operation]) -> Result<(), ValidationError> {
+        for operation in operations {
+            self.validate_operation(operation)?;
+        }
+        Ok(())
+    }
+
+    fn validate_operation(&mut self, operation: &Operation) -> Result<(), ValidationError> {
+        match *operation {
+            Operation::Unreachable => self.set_unreachable(),
+            Operation::Nop => {},
+            Operation::Block(ref b) => {
+                let signature = self.block_signature(&b.block_type)?;
+                self.pop_all(&signature.parameters)?;
+                self.validate_block(&signature.parameters, signature.returns.clone(), signature.returns, &b.operations)?;
+            },
+            Operation::Loop(ref b) => {
+                let signature = self.block_signature(&b.block_type)?;
+                self.pop_all(&signature.parameters)?;
+                self.validate_block(&signature.parameters, signature.parameters.clone(), signature.returns, &b.operations)?;
+            },
+            Operation::If(ref b) => {
+                self.pop_expecting(&I32)?;
+                let signature = self.block_signature(&b.block_type)?;
+                self.pop_all(&signature.parameters)?;
+                let (then, other) = split_if(b);
+                // Without an else the condition failing has to produce the results from the parameters
+                self.push_frame(signature.returns.clone(), signature.returns.clone());
+                self.push_all(&signature.parameters);
+                self.validate_sequence(then)?;
+                self.pop_frame()?;
+                self.validate_block(&signature.parameters, signature.returns.clone(), signature.returns, other)?;
+            },
+            Operation::Else | Operation::End => return Err(ValidationError::MisplacedElse),
+            Operation::Branch(depth) => {
+                let types = self.label_types(depth)?;
+                self.pop_all(&types)?;
+                self.set_unreachable();
+            },
+            Operation::BranchIf(depth) => {
+                self.pop_expecting(&I32)?;
+                let types = self.label_types(depth)?;
+                self.pop_all(&types)?;
+                self.push_all(&types);
+            },
+            Operation::BranchTable(ref table) => {
+                self.pop_expecting(&I32)?;
+                let types = self.label_types(table.default)?;
+                for depth in &table.targets {
+                    if self.label_types(*depth)? != types {
+                        return Err(ValidationError::BranchTableArityMismatch);
+                    }
+                }
+                self.pop_all(&types)?;
+                self.set_unreachable();
+            },
+            Operation::Return => {
+                let returns = self.returns.clone();
+                self.pop_all(&returns)?;
+                self.set_unreachable();
+            },
+            Operation::Call(index) => {
+                let signature = match self.context.functions.get(index) {
+                    Some(signature) => signature.clone(),
+                    None => return Err(ValidationError::UndefinedFunction(index))
+                };
+                self.pop_all(&signature.parameters)?;
+                self.push_all(&signature.returns);
+            },
+            Operation::CallIndirect(index, _) => {
+                if self.context.tables == 0 {
+                    return Err(ValidationError::UndefinedTable(0));
+                }
+                let signature = match self.context.types.get(index) {
+                    Some(signature) => signature.clone(),
+                    None => return Err(ValidationError::UndefinedType(index))
+                };
+                self.pop_expecting(&I32)?;
+                self.pop_all(&signature.parameters)?;
+                self.push_all(&signature.returns);
+            },
+            Operation::Drop => {
+                self.pop()?;
+            },
+            Operation::Select => {
+                self.pop_expecting(&I32)?;
+                let value = match self.pop()? {
+                    Some(first) => self.pop_expecting(&first)?,
+                    None => self.pop()?
+                };
+                self.push(value);
+            },
+            Operation::GetLocal(index) => {
+                let local = self.local(index)?;
+                self.push(Some(local));
+            },
+            Operation::SetLocal(index) => {
+                let local = self.local(index)?;
+                self.pop_expecting(&local)?;
+            },
+            Operation::TeeLocal(index) => {
+                let local = self.local(index)?;
+                self.pop_expecting(&local)?;
+                self.push(Some(local));
+            },
+            Operation::GetGlobal(index) => {
+                let global = match self.context.globals.get(index) {
+                    Some(global) => global.content_type.clone(),
+                    None => return Err(ValidationError::UndefinedGlobal(index))
+                };
+                self.push(Some(global));
+            },
+            Operation::SetGlobal(index) => {
+                let global = match self.context.globals.get(index) {
+                    Some(global) if global.mutability => global.content_type.clone(),
+                    Some(_) => return Err(ValidationError::ImmutableGlobal(index)),
+                    None => return Err(ValidationError::UndefinedGlobal(index))
+                };
+                self.pop_expecting(&global)?;
+            },
+            Operation::I32Load(ref m) => self.load(m, 4, I32)?,
+            Operation::I64Load(ref m) => self.load(m, 8, I64)?,
+            Operation::F32Load(ref m) => self.load(m, 4, F32)?,
+            Operation::F64Load(ref m) => self.load(m, 8, F64)?,
+            Operation::I32Load8S(ref m) | Operation::I32Load8U(ref m) => self.load(m, 1, I32)?,
+            Operation::I32Load16S(ref m) | Operation::I32Load16U(ref m) => self.load(m, 2, I32)?,
+            Operation::I64Load8S(ref m) | Operation::I64Load8U(ref m) => self.load(m, 1, I64)?,
+            Operation::I64Load16S(ref m) | Operation::I64Load16U(ref m) => self.load(m, 2, I64)?,
+            Operation::I64Load32S(ref m) | Operation::I64Load32U(ref m) => self.load(m, 4, I64)?,
+            Operation::I32Store(ref m) => self.store(m, 4, I32)?,
+            Operation::I64Store(ref m) => self.store(m, 8, I64)?,
+            Operation::F32Store(ref m) => self.store(m, 4, F32)?,
+            Operation::F64Store(ref m) => self.store(m, 8, F64)?,
+            Operation::I32Store8(ref m) => self.store(m, 1, I32)?,
+            Operation::I32Store16(ref m) => self.store(m, 2, I32)?,
+            Operation::I64Store8(ref m) => self.store(m, 1, I64)?,
+            Operation::I64Store16(ref m) => self.store(m, 2, I64)?,
+            Operation::I64Store32(ref m) => self.store(m, 4, I64)?,
+            Operation::CurrentMemory(_) => {
+                self.memory()?;
+                self.push(Some(I32));
+            },
+            Operation::GrowMemory(_) => {
+                self.memory()?;
+                self.pop_expecting(&I32)?;
+                self.push(Some(I32));
+            },
+            Operation::I32Const(_) => self.push(Some(I32)),
+            Operation::I64Const(_) => self.push(Some(I64)),
+            Operation::F32Const(_) => self.push(Some(F32)),
+            Operation::F64Const(_) => self.push(Some(F64)),
+            ref operation => {
+                let (parameters, result) = numeric_type(operation);
+                self.pop_all(parameters)?;
+                self.push(Some(result));
+            }
+        }
+        Ok(())
+    }
+
+    fn local(&self, index: usize) -> Result<ValueType, ValidationError> {
+        match self.locals.get(index) {
+            Some(local) => Ok(local.clone()),
+            None => Err(ValidationError::UndefinedLocal(index))
+        }
+    }
+
+    fn memory(&self) -> Result<(), ValidationError> {
+        if self.context.memories == 0 {
+            return Err(ValidationError::UndefinedMemory(0));
+        }
+        Ok(())
+    }
+
+    // Checks there's a memory and the alignment hint isn't more than the access's natural alignment
+    fn memory_access(&self, immediate: &MemoryImmediate, width: u32) -> Result<(), ValidationError> {
+        self.memory()?;
+        if immediate.flags >= 32 || 1u32 << immediate.flags > width {
+            return Err(ValidationError::InvalidAlignment(immediate.flags));
+        }
+        Ok(())
+    }
+
+    fn load(&mut self, immediate: &MemoryImmediate, width: u32, value_type: ValueType) -> Result<(), ValidationError> {
+        self.memory_access(immediate, width)?;
+        self.pop_expecting(&I32)?;
+        self.push(Some(value_type));
+        Ok(())
+    }
+
+    fn store(&mut self, immediate: &MemoryImmediate, width: u32, value_type: ValueType) -> Result<(), ValidationError> {
+        self.memory_access(immediate, width)?;
+        self.pop_expecting(&value_type)?;
+        self.pop_expecting(&I32)?;
+        Ok(())
+    }
+}
+
+// Splits an if block at its own `else`, nested ifs keep theirs inside their own blocks
+fn split_if(block: &Block) -> (&[Operation], &[Operation]) {
+    let operations = &block.operations[..];
+    match operations.iter().position(|op| *op == Operation::Else) {
+        Some(index) => (&operations[..index], &operations[index+1..]),
+        None => (operations, &[])
+    }
+}
+
+const I32_1: &[ValueType] = &[I32];
+const I32_2: &[ValueType] = &[I32, I32];
+const I64_1: &[ValueType] = &[I64];
+const I64_2: &[ValueType] = &[I64, I64];
+const F32_1: &[ValueType] = &[F32];
+const F32_2: &[ValueType] = &[F32, F32];
+const F64_1: &[ValueType] = &[F64];
+const F64_2: &[ValueType] = &[F64, F64];
+
+// The operands and result of a comparison, numeric operator, conversion or reinterpretation
+fn numeric_type(operation: &Operation) -> (&'static [ValueType], ValueType) {
+    match *operation {
+        Operation::I32Eqz => (I32_1, I32),
+        Operation::I32Eq | Operation::I32Ne | Operation::I32LtS | Operation::I32LtU | Operation::I32GtS |
+        Operation::I32GtU | Operation::I32LeS | Operation::I32LeU | Operation::I32GeS | Operation::I32GeU => (I32_2, I32),
+        Operation::I64Eqz => (I64_1, I32),
+        Operation::I64Eq | Operation::I64Ne | Operation::I64LtS | Operation::I64LtU | Operation::I64GtS |
+        Operation::I64GtU | Operation::I64LeS | Operation::I64LeU | Operation::I64GeS | Operation::I64GeU => (I64_2, I32),
+        Operation::F32Eq | Operation::F32Ne | Operation::F32Lt | Operation::F32Gt |
+        Operation::F32Le | Operation::F32Ge => (F32_2, I32),
+        Operation::F64Eq | Operation::F64Ne | Operation::F64Lt | Operation::F64Gt |
+        Operation::F64Le | Operation::F64Ge => (F64_2, I32),
+
+        Operation::I32Clz | Operation::I32Ctz | Operation::I32Popcnt => (I32_1, I32),
+        Operation::I32Add | Operation::I32Sub | Operation::I32Mul | Operation::I32DivS | Operation::I32DivU |
+        Operation::I32RemS | Operation::I32RemU | Operation::I32And | Operation::I32Or | Operation::I32Xor |
+        Operation::I32Shl | Operation::I32ShrS | Operation::I32ShrU | Operation::I32Rotl | Operation::I32Rotr => (I32_2, I32),
+        Operation::I64Clz | Operation::I64Ctz | Operation::I64Popcnt => (I64_1, I64),
+        Operation::I64Add | Operation::I64Sub | Operation::I64Mul | Operation::I64DivS | Operation::I64DivU |
+        Operation::I64RemS | Operation::I64RemU | Operation::I64And | Operation::I64Or | Operation::I64Xor |
+        Operation::I64Shl | Operation::I64ShrS | Operation::I64ShrU | Operation::I64Rotl | Operation::I64Rotr => (I64_2, I64),
+        Operation::F32Abs | Operation::F32Neg | Operation::F32Ceil | Operation::F32Floor | Operation::F32Trunc |
+        Operation::F32Nearest | Operation::F32Sqrt => (F32_1, F32),
+        Operation::F32Add | Operation::F32Sub | Operation::F32Mul | Operation::F32Div | Operation::F32Min |
+        Operation::F32Max | Operation::F32Copysign => (F32_2, F32),
+        Operation::F64Abs | Operation::F64Neg | Operation::F64Ceil | Operation::F64Floor | Operation::F64Trunc |
+        Operation::F64Nearest | Operation::F64Sqrt => (F64_1, F64),
+        Operation::F64Add | Operation::F64Sub | Operation::F64Mul | Operation::F64Div | Operation::F64Min |
+        Operation::F64Max | Operation::F64Copysign => (F64_2, F64),
+
+        Operation::I32WrapI64 => (I64_1, I32),
+        Operation::I32TruncSF32 | Operation::I32TruncUF32 => (F32_1, I32),
+        Operation::I32TruncSF64 | Operation::I32TruncUF64 => (F64_1, I32),
+        Operation::I64ExtendSI32 | Operation::I64ExtendUI32 => (I32_1, I64),
+        Operation::I64TruncSF32 | Operation::I64TruncUF32 => (F32_1, I64),
+        Operation::I64TruncSF64 | Operation::I64TruncUF64 => (F64_1, I64),
+        Operation::F32ConvertSI32 | Operation::F32ConvertUI32 => (I32_1, F32),
+        Operation::F32ConvertSI64 | Operation::F32ConvertUI64 => (I64_1, F32),
+        Operation::F32DemoteF64 => (F64_1, F32),
+        Operation::F64ConvertSI32 | Operation::F64ConvertUI32 => (I32_1, F64),
+        Operation::F64ConvertSI64 | Operation::F64ConvertUI64 => (I64_1, F64),
+        Operation::F64PromoteF32 => (F32_1, F64),
+
+        Operation::I32ReinterpretF32 => (F32_1, I32),
+        Operation::I64ReinterpretF64 => (F64_1, I64),
+        Operation::F32ReinterpretI32 => (I32_1, F32),
+        Operation::F64ReinterpretI64 => (I64_1, F64),
+
+        ref operation => unreachable!("{:?} isn't a numeric operation", operation),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parse_tree::language_types::BranchTable;
+    use parse_tree::language_types::GlobalType;
+
+    fn context() -> Context {
+        Context {
+            types: vec![FuncSignature {
+                parameters: vec![I32],
+                returns: vec![I64]
+            }],
+            functions: vec![FuncSignature {
+                parameters: vec![I32, F32],
+                returns: vec![I32]
+            }],
+            tables: 1,
+            memories: 1,
+            globals: vec![
+                GlobalType { content_type: I32, mutability: false },
+                GlobalType { content_type: F64, mutability: true },
+            ],
+        }
+    }
+
+    fn check(parameters: Vec<ValueType>, returns: Vec<ValueType>, code: Vec<Operation>) -> Result<(), ValidationError> {
+        let signature = FuncSignature {
+            parameters,
+            returns
+        };
+        validate(&context(), &signature, &FuncBody {
+            locals: vec![I64],
+            code
+        })
+    }
+
+    fn block(block_type: BlockType, operations: Vec<Operation>) -> Block {
+        Block {
+            block_type,
+            operations
+        }
+    }
+
+    fn memory(flags: u32) -> MemoryImmediate {
+        MemoryImmediate {
+            flags,
+            offset: 0
+        }
+    }
+
+    #[test]
+    fn well_typed_code_passes() {
+        assert_eq!(check(vec![I32], vec![I32], vec![
+            Operation::GetLocal(0),
+            Operation::F32Const(1.0),
+            Operation::Call(0),
+            Operation::GetGlobal(0),
+            Operation::I32Add,
+        ]), Ok(()));
+        assert_eq!(check(vec![], vec![I64], vec![
+            Operation::I32Const(0),
+            Operation::I32Const(1),
+            Operation::CallIndirect(0, false),
+        ]), Ok(()));
+    }
+
+    #[test]
+    fn operand_types_are_checked() {
+        assert_eq!(check(vec![], vec![I32], vec![
+            Operation::I32Const(1),
+            Operation::I64Const(2),
+            Operation::I32Add,
+        ]), Err(ValidationError::TypeMismatch { expected: I32, found: I64 }));
+        assert_eq!(check(vec![], vec![], vec![Operation::I32Add]), Err(ValidationError::StackUnderflow));
+        assert_eq!(check(vec![], vec![], vec![Operation::I32Const(1)]), Err(ValidationError::ValuesLeftOnStack(1)));
+        assert_eq!(check(vec![], vec![I32], vec![Operation::F32Const(1.0)]),
+            Err(ValidationError::TypeMismatch { expected: I32, found: F32 }));
+    }
+
+    #[test]
+    fn blocks_produce_their_results() {
+        assert_eq!(check(vec![], vec![I32], vec![
+            Operation::Block(block(BlockType::Value(I32), vec![
+                Operation::I32Const(1),
+                Operation::Branch(0),
+            ])),
+        ]), Ok(()));
+        assert_eq!(check(vec![], vec![I32], vec![
+            Operation::Block(block(BlockType::Value(I32), vec![
+                Operation::I64Const(1),
+                Operation::Branch(0),
+            ])),
+        ]), Err(ValidationError::TypeMismatch { expected: I32, found: I64 }));
+        // Values from outside a block can't be used inside it
+        assert_eq!(check(vec![], vec![], vec![
+            Operation::I32Const(1),
+            Operation::Block(block(BlockType::Empty, vec![Operation::Drop])),
+        ]), Err(ValidationError::StackUnderflow));
+        assert_eq!(check(vec![], vec![], vec![Operation::Branch(1)]), Err(ValidationError::UndefinedLabel(1)));
+    }
+
+    #[test]
+    fn if_without_else_has_no_results() {
+        assert_eq!(check(vec![], vec![I32], vec![
+            Operation::I32Const(1),
+            Operation::If(block(BlockType::Value(I32), vec![
+                Operation::I32Const(2),
+                Operation::Else,
+                Operation::I32Const(3),
+            ])),
+        ]), Ok(()));
+        assert_eq!(check(vec![], vec![I32], vec![
+            Operation::I32Const(1),
+            Operation::If(block(BlockType::Value(I32), vec![Operation::I32Const(2)])),
+        ]), Err(ValidationError::StackUnderflow));
+    }
+
+    #[test]
+    fn loops_branch_to_their_start() {
+        assert_eq!(check(vec![], vec![], vec![
+            Operation::Loop(block(BlockType::Value(I32), vec![
+                Operation::I32Const(1),
+                Operation::BranchIf(0),
+                Operation::I32Const(2),
+            ])),
+            Operation::Drop,
+        ]), Ok(()));
+        // Branching back to the start doesn't take the loop's result with it
+        assert_eq!(check(vec![], vec![], vec![
+            Operation::Loop(block(BlockType::Value(I32), vec![
+                Operation::I32Const(0),
+                Operation::BranchIf(0),
+            ])),
+            Operation::Drop,
+        ]), Err(ValidationError::StackUnderflow));
+    }
+
+    #[test]
+    fn typed_blocks_take_their_parameters() {
+        assert_eq!(check(vec![], vec![I64], vec![
+            Operation::I32Const(1),
+            Operation::Block(block(BlockType::Type(0), vec![Operation::I64ExtendUI32])),
+        ]), Ok(()));
+        // A loop's label takes its parameters rather than its results
+        assert_eq!(check(vec![], vec![I64], vec![
+            Operation::I32Const(1),
+            Operation::Loop(block(BlockType::Type(0), vec![
+                Operation::I32Const(0),
+                Operation::BranchIf(0),
+                Operation::I64ExtendUI32,
+            ])),
+        ]), Ok(()));
+        assert_eq!(check(vec![], vec![I64], vec![
+            Operation::I32Const(1),
+            Operation::Loop(block(BlockType::Type(0), vec![
+                Operation::I64ExtendUI32,
+                Operation::I32Const(0),
+                Operation::BranchIf(0),
+            ])),
+        ]), Err(ValidationError::TypeMismatch { expected: I32, found: I64 }));
+        // Without an else the parameters are passed straight through
+        assert_eq!(check(vec![], vec![I64], vec![
+            Operation::I32Const(1),
+            Operation::I32Const(0),
+            Operation::If(block(BlockType::Type(0), vec![Operation::I64ExtendUI32])),
+        ]), Err(ValidationError::TypeMismatch { expected: I64, found: I32 }));
+        assert_eq!(check(vec![], vec![], vec![
+            Operation::Block(block(BlockType::Type(1), vec![])),
+        ]), Err(ValidationError::UndefinedType(1)));
+    }
+
+    #[test]
+    fn unreachable_code_is_polymorphic() {
+        assert_eq!(check(vec![], vec![I32], vec![
+            Operation::Unreachable,
+            Operation::I64Add,
+            Operation::I32WrapI64,
+        ]), Ok(()));
+        assert_eq!(check(vec![], vec![I32], vec![
+            Operation::I32Const(1),
+            Operation::Return,
+            Operation::F32Const(1.0),
+        ]), Err(ValidationError::TypeMismatch { expected: I32, found: F32 }));
+        assert_eq!(check(vec![], vec![F64], vec![
+            Operation::Unreachable,
+            Operation::Select,
+        ]), Ok(()));
+    }
+
+    #[test]
+    fn branch_table_targets_must_agree() {
+        assert_eq!(check(vec![], vec![I32], vec![
+            Operation::Block(block(BlockType::Empty, vec![
+                Operation::I32Const(1),
+                Operation::I32Const(0),
+                Operation::BranchTable(BranchTable { targets: vec![0, 1], default: 1 }),
+            ])),
+            Operation::I32Const(1),
+        ]), Err(ValidationError::BranchTableArityMismatch));
+    }
+
+    #[test]
+    fn indices_are_checked() {
+        assert_eq!(check(vec![I32], vec![], vec![Operation::GetLocal(2)]), Err(ValidationError::UndefinedLocal(2)));
+        assert_eq!(check(vec![], vec![], vec![Operation::GetGlobal(2)]), Err(ValidationError::UndefinedGlobal(2)));
+        assert_eq!(check(vec![], vec![], vec![Operation::Call(1)]), Err(ValidationError::UndefinedFunction(1)));
+        assert_eq!(check(vec![], vec![], vec![
+            Operation::I32Const(0),
+            Operation::CallIndirect(1, false),
+        ]), Err(ValidationError::UndefinedType(1)));
+        assert_eq!(check(vec![], vec![], vec![
+            Operation::I32Const(0),
+            Operation::SetGlobal(0),
+        ]), Err(ValidationError::ImmutableGlobal(0)));
+    }
+
+    #[test]
+    fn alignment_is_at_most_natural() {
+        assert_eq!(check(vec![], vec![I64], vec![
+            Operation::I32Const(0),
+            Operation::I64Load(memory(3)),
+        ]), Ok(()));
+        assert_eq!(check(vec![], vec![I32], vec![
+            Operation::I32Const(0),
+            Operation::I32Load16U(memory(2)),
+        ]), Err(ValidationError::InvalidAlignment(2)));
+    }
+}
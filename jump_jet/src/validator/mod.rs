@@ -0,0 +1,357 @@
+use std::collections::HashSet;
+
+use parse_tree::functions::FuncBody;
+use parse_tree::functions::FuncSignature;
+use parse_tree::language_types::ExternalKind;
+use parse_tree::language_types::GlobalType;
+use parse_tree::language_types::InitExpression;
+use parse_tree::language_types::ResizableLimits;
+use parse_tree::language_types::ValueType;
+use parse_tree::memory::MAX_PAGES;
+use parse_tree::types::TypeDefinition;
+use parse_tree::ParseModule;
+
+mod function;
+
+mod validation_error;
+pub use validator::validation_error::ValidationError;
+
+/// What function bodies are checked against, with every index space including imports.
+pub struct Context {
+    types: Vec<FuncSignature>,
+    functions: Vec<FuncSignature>,
+    tables: usize,
+    memories: usize,
+    globals: Vec<GlobalType>,
+}
+
+impl Context {
+    /// Gathers `module`'s index spaces, checking its types, tables and memories along the way.
+    pub fn new(module: &ParseModule) -> Result<Context, ValidationError> {
+        let types: Vec<FuncSignature> = module.types.iter().map(|definition| match *definition {
+            TypeDefinition::Func(ref signature) => signature.clone()
+        }).collect();
+        let mut functions = vec![];
+        for index in module.function_space() {
+            match types.get(index) {
+                Some(signature) => functions.push(signature.clone()),
+                None => return Err(ValidationError::UndefinedType(index))
+            }
+        }
+
+        let tables = module.table_space();
+        if tables.len() > 1 {
+            return Err(ValidationError::MultipleTables);
+        }
+        for limits in &tables {
+            check_limits(limits, ::std::u32::MAX as u64)?;
+        }
+        let memories = module.memory_space();
+        if memories.len() > 1 {
+            return Err(ValidationError::MultipleMemories);
+        }
+        for limits in &memories {
+            check_limits(limits, MAX_PAGES as u64)?;
+        }
+
+        Ok(Context {
+            types,
+            functions,
+            tables: tables.len(),
+            memories: memories.len(),
+            globals: module.global_space(),
+        })
+    }
+
+    /// Type checks the body of function `index`, for bodies that are decoded after the rest of the
+    /// module was validated.
+    pub fn validate_body(&self, index: usize, body: &FuncBody) -> Result<(), ValidationError> {
+        match self.functions.get(index) {
+            Some(signature) => function::validate(self, signature, body),
+            None => Err(ValidationError::UndefinedFunction(index))
+        }
+    }
+}
+
+/// Checks a parsed module is well formed before it's linked: every function body type checks, every
+/// index refers to something that exists, exports are unique and the start function takes and
+/// returns nothing. Bodies that were left encoded are checked with `Context::validate_body` once
+/// they're decoded.
+pub fn validate(module: &ParseModule) -> Result<(), ValidationError> {
+    let bodies = module.function_bodies.len() + module.encoded_bodies.len();
+    if module.function_signatures.len() != bodies {
+        return Err(ValidationError::FunctionCountMismatch {
+            signatures: module.function_signatures.len(),
+            bodies
+        });
+    }
+
+    let context = Context::new(module)?;
+
+    // Constant expressions can only read imported globals
+    let imported_globals = &context.globals[..context.globals.len() - module.globals.len()];
+    for global in &module.globals {
+        check_init_expression(&global.value, &global.constraints.content_type, imported_globals)?;
+    }
+    for segment in &module.elements {
+        if segment.index >= context.tables {
+            return Err(ValidationError::UndefinedTable(segment.index));
+        }
+        check_init_expression(&segment.offset, &ValueType::I32, imported_globals)?;
+        if let Some(index) = segment.elements.iter().find(|index| **index >= context.functions.len()) {
+            return Err(ValidationError::UndefinedFunction(*index));
+        }
+    }
+    for segment in &module.data {
+        if segment.index >= context.memories {
+            return Err(ValidationError::UndefinedMemory(segment.index));
+        }
+        check_init_expression(&segment.offset, &ValueType::I32, imported_globals)?;
+    }
+
+    let mut names = HashSet::new();
+    for export in &module.exports {
+        if !names.insert(&export.field) {
+            return Err(ValidationError::DuplicateExport(export.field.to_string()));
+        }
+        match export.kind {
+            ExternalKind::Function(index) if index >= context.functions.len() => return Err(ValidationError::UndefinedFunction(index)),
+            ExternalKind::Table(index) if index >= context.tables => return Err(ValidationError::UndefinedTable(index)),
+            ExternalKind::Memory(index) if index >= context.memories => return Err(ValidationError::UndefinedMemory(index)),
+            ExternalKind::Global(index) if index >= context.globals.len() => return Err(ValidationError::UndefinedGlobal(index)),
+            _ => {}
+        }
+    }
+
+    if let Some(start) = module.start_function {
+        match context.functions.get(start) {
+            Some(signature) => if !signature.parameters.is_empty() || !signature.returns.is_empty() {
+                return Err(ValidationError::InvalidStartFunction(start));
+            },
+            None => return Err(ValidationError::UndefinedFunction(start))
+        }
+    }
+
+    // Decoded bodies come after any that were left encoded
+    let first_decoded = context.functions.len() - module.function_bodies.len();
+    for (i, body) in module.function_bodies.iter().enumerate() {
+        let index = first_decoded + i;
+        if let Err(error) = function::validate(&context, &context.functions[index], body) {
+            return Err(ValidationError::InFunction {
+                index,
+                name: module.names.function(index).map(|name| name.to_string()),
+                error: Box::new(error)
+            });
+        }
+    }
+    Ok(())
+}
+
+fn check_limits(limits: &ResizableLimits, cap: u64) -> Result<(), ValidationError> {
+    let maximum = limits.maximum.unwrap_or(cap);
+    if limits.initial > maximum || maximum > cap {
+        return Err(ValidationError::InvalidLimits);
+    }
+    Ok(())
+}
+
+fn check_init_expression(expression: &InitExpression, expected: &ValueType, globals: &[GlobalType]) -> Result<(), ValidationError> {
+    let found = match *expression {
+        InitExpression::I32Const(_) => ValueType::I32,
+        InitExpression::I64Const(_) => ValueType::I64,
+        InitExpression::F32Const(_) => ValueType::F32,
+        InitExpression::F64Const(_) => ValueType::F64,
+        InitExpression::GetGlobal(index) => match globals.get(index) {
+            Some(global) if global.mutability => return Err(ValidationError::MutableGlobal(index)),
+            Some(global) => global.content_type.clone(),
+            None => return Err(ValidationError::UndefinedGlobal(index))
+        }
+    };
+    if found != *expected {
+        return Err(ValidationError::TypeMismatch {
+            expected: expected.clone(),
+            found
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parse_tree::builder::ModuleBuilder;
+    use parse_tree::globals::Global;
+    use parse_tree::language_types::ExportEntry;
+    use parse_tree::language_types::ImportEntry;
+    use parse_tree::language_types::ImportKind;
+    use parse_tree::language_types::Operation;
+    use parse_tree::memory::DataSegment;
+    use parse_tree::memory::Memory;
+
+    fn module() -> ParseModule<'static> {
+        let mut builder = ModuleBuilder::new();
+        builder.signature(vec![], vec![]);
+        builder.signature(vec![], vec![ValueType::I32]);
+        builder.build()
+    }
+
+    fn function(module: &mut ParseModule, signature: usize, code: Vec<Operation>) {
+        module.function_signatures.push(signature);
+        module.function_bodies.push(FuncBody {
+            locals: vec![],
+            code
+        });
+    }
+
+    fn export(module: &mut ParseModule, field: &str, kind: ExternalKind) {
+        module.exports.push(ExportEntry {
+            field: field.to_string().into(),
+            kind
+        });
+    }
+
+    #[test]
+    fn valid_modules_pass() {
+        let mut module = module();
+        module.imports.push(ImportEntry {
+            module: "env".into(),
+            field: "f".into(),
+            kind: ImportKind::Function(1)
+        });
+        function(&mut module, 1, vec![Operation::Call(0)]);
+        function(&mut module, 0, vec![]);
+        module.start_function = Some(2);
+        export(&mut module, "f", ExternalKind::Function(1));
+        export(&mut module, "g", ExternalKind::Function(2));
+        assert_eq!(validate(&module), Ok(()));
+    }
+
+    #[test]
+    fn bodies_are_type_checked() {
+        let mut module = module();
+        function(&mut module, 0, vec![]);
+        function(&mut module, 1, vec![Operation::I64Const(1)]);
+        module.names.functions.insert(1, "answer".to_string());
+        let error = validate(&module).unwrap_err();
+        assert_eq!(error, ValidationError::InFunction {
+            index: 1,
+            name: Some("answer".to_string()),
+            error: Box::new(ValidationError::TypeMismatch {
+                expected: ValueType::I32,
+                found: ValueType::I64
+            })
+        });
+        assert_eq!(error.to_string(), "in $answer: expected I32 but found I64");
+    }
+
+    #[test]
+    fn functions_need_bodies() {
+        let mut module = module();
+        module.function_signatures.push(0);
+        assert_eq!(validate(&module), Err(ValidationError::FunctionCountMismatch { signatures: 1, bodies: 0 }));
+    }
+
+    #[test]
+    fn exports_are_unique() {
+        let mut module = module();
+        function(&mut module, 0, vec![]);
+        export(&mut module, "f", ExternalKind::Function(0));
+        export(&mut module, "f", ExternalKind::Function(0));
+        assert_eq!(validate(&module), Err(ValidationError::DuplicateExport("f".to_string())));
+    }
+
+    #[test]
+    fn exports_must_exist() {
+        let mut module = module();
+        export(&mut module, "memory", ExternalKind::Memory(0));
+        assert_eq!(validate(&module), Err(ValidationError::UndefinedMemory(0)));
+    }
+
+    #[test]
+    fn start_function_takes_and_returns_nothing() {
+        let mut module = module();
+        function(&mut module, 1, vec![Operation::I32Const(0)]);
+        module.start_function = Some(0);
+        assert_eq!(validate(&module), Err(ValidationError::InvalidStartFunction(0)));
+        module.start_function = Some(1);
+        assert_eq!(validate(&module), Err(ValidationError::UndefinedFunction(1)));
+    }
+
+    #[test]
+    fn memories_are_limited() {
+        let mut module = module();
+        let memory = |initial, maximum| Memory {
+            limits: ResizableLimits {
+                initial,
+                maximum
+            },
+            values: vec![]
+        };
+        module.memories.push(memory(2, Some(1)));
+        assert_eq!(validate(&module), Err(ValidationError::InvalidLimits));
+        module.memories[0] = memory(1, Some(MAX_PAGES as u64 + 1));
+        assert_eq!(validate(&module), Err(ValidationError::InvalidLimits));
+        module.memories[0] = memory(1, None);
+        assert_eq!(validate(&module), Ok(()));
+        module.memories.push(memory(1, None));
+        assert_eq!(validate(&module), Err(ValidationError::MultipleMemories));
+    }
+
+    #[test]
+    fn globals_are_only_initialised_from_imported_globals() {
+        let mut module = module();
+        let global = |value| Global {
+            constraints: GlobalType {
+                content_type: ValueType::I32,
+                mutability: false
+            },
+            value
+        };
+        module.globals.push(global(InitExpression::I32Const(1)));
+        assert_eq!(validate(&module), Ok(()));
+        module.globals.push(global(InitExpression::GetGlobal(0)));
+        assert_eq!(validate(&module), Err(ValidationError::UndefinedGlobal(0)));
+        module.globals[1] = global(InitExpression::GetGlobal(3));
+        assert_eq!(validate(&module), Err(ValidationError::UndefinedGlobal(3)));
+        module.globals[1] = global(InitExpression::F32Const(1.0));
+        assert_eq!(validate(&module), Err(ValidationError::TypeMismatch {
+            expected: ValueType::I32,
+            found: ValueType::F32
+        }));
+    }
+
+    #[test]
+    fn init_expressions_only_read_immutable_globals() {
+        let global_type = |mutability| GlobalType {
+            content_type: ValueType::I32,
+            mutability
+        };
+        let mut module = module();
+        module.imports.push(ImportEntry {
+            module: "env".into(),
+            field: "g".into(),
+            kind: ImportKind::Global(global_type(false))
+        });
+        module.globals.push(Global {
+            constraints: global_type(true),
+            value: InitExpression::I32Const(0)
+        });
+        module.data.push(DataSegment {
+            index: 0,
+            offset: InitExpression::GetGlobal(0),
+            data: vec![].into()
+        });
+        module.memories.push(Memory {
+            limits: ResizableLimits {
+                initial: 1,
+                maximum: None
+            },
+            values: vec![]
+        });
+        assert_eq!(validate(&module), Ok(()));
+
+        module.imports[0].kind = ImportKind::Global(global_type(true));
+        module.globals[0].constraints = global_type(false);
+        assert_eq!(validate(&module), Err(ValidationError::MutableGlobal(0)));
+    }
+}
@@ -0,0 +1,68 @@
+use std::error::Error;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+
+use parse_tree::language_types::ValueType;
+
+/// The ways in which a module can be malformed beyond what parsing catches.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    FunctionCountMismatch { signatures: usize, bodies: usize },
+    UndefinedType(usize),
+    UndefinedFunction(usize),
+    UndefinedTable(usize),
+    UndefinedMemory(usize),
+    UndefinedGlobal(usize),
+    UndefinedLocal(usize),
+    UndefinedLabel(usize),
+    MultipleTables,
+    MultipleMemories,
+    InvalidLimits,
+    ImmutableGlobal(usize),
+    MutableGlobal(usize),
+    DuplicateExport(String),
+    InvalidStartFunction(usize),
+    InvalidAlignment(u32),
+    MisplacedElse,
+    TypeMismatch { expected: ValueType, found: ValueType },
+    StackUnderflow,
+    BranchTableArityMismatch,
+    ValuesLeftOnStack(usize),
+    InFunction { index: usize, name: Option<String>, error: Box<ValidationError> },
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            ValidationError::FunctionCountMismatch { signatures, bodies } =>
+                write!(f, "{} functions are declared but {} bodies are given", signatures, bodies),
+            ValidationError::UndefinedType(index) => write!(f, "undefined type {}", index),
+            ValidationError::UndefinedFunction(index) => write!(f, "undefined function {}", index),
+            ValidationError::UndefinedTable(index) => write!(f, "undefined table {}", index),
+            ValidationError::UndefinedMemory(index) => write!(f, "undefined memory {}", index),
+            ValidationError::UndefinedGlobal(index) => write!(f, "undefined global {}", index),
+            ValidationError::UndefinedLocal(index) => write!(f, "undefined local {}", index),
+            ValidationError::UndefinedLabel(depth) => write!(f, "branch depth {} is out of range", depth),
+            ValidationError::MultipleTables => write!(f, "only one table is allowed"),
+            ValidationError::MultipleMemories => write!(f, "only one memory is allowed"),
+            ValidationError::InvalidLimits => write!(f, "limits are out of range or larger than the maximum"),
+            ValidationError::ImmutableGlobal(index) => write!(f, "global {} is immutable", index),
+            ValidationError::MutableGlobal(index) => write!(f, "global {} is mutable, so constant expressions can't read it", index),
+            ValidationError::DuplicateExport(ref name) => write!(f, "{} is exported more than once", name),
+            ValidationError::InvalidStartFunction(index) => write!(f, "start function {} must take and return nothing", index),
+            ValidationError::InvalidAlignment(flags) => write!(f, "alignment 2^{} is larger than the access", flags),
+            ValidationError::MisplacedElse => write!(f, "else outside of an if"),
+            ValidationError::TypeMismatch { ref expected, ref found } => write!(f, "expected {:?} but found {:?}", expected, found),
+            ValidationError::StackUnderflow => write!(f, "operand stack underflow"),
+            ValidationError::BranchTableArityMismatch => write!(f, "branch table targets have different types"),
+            ValidationError::ValuesLeftOnStack(count) => write!(f, "{} values are left on the stack at the end of a block", count),
+            ValidationError::InFunction { index, ref name, ref error } => match *name {
+                Some(ref name) => write!(f, "in ${}: {}", name, error),
+                None => write!(f, "in function {}: {}", index, error),
+            },
+        }
+    }
+}
+
+impl Error for ValidationError {}
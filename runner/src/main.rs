@@ -4,7 +4,10 @@ extern crate env_logger;
 
 use std::collections::HashMap;
 use std::fs::File;
+use std::rc::Rc;
 
+use jump_jet::parse_tree::functions::FuncSignature;
+use jump_jet::parse_tree::language_types::ValueType;
 use jump_jet::runtime_tree::Import;
 use jump_jet::runtime::exports::GetExport;
 use jump_jet::runtime_tree::ValueTypeProvider;
@@ -12,9 +15,13 @@ use jump_jet::runtime_tree::ValueTypeProvider;
 fn build_imports() -> HashMap<String, HashMap<String, Import>> {
     let mut imports = HashMap::new();
     let mut imports_env = HashMap::new();
-    imports_env.insert("test".to_string(), Import::Function(Box::new(|a,b|{
+    let signature = FuncSignature {
+        parameters: vec![ValueType::I32],
+        returns: vec![ValueType::I32]
+    };
+    imports_env.insert("test".to_string(), Import::Function(signature, Rc::new(|a,b|{
         println!("being called");
-        vec![ValueTypeProvider::I32(16)]
+        Ok(vec![ValueTypeProvider::I32(16)])
     })));
     imports.insert("env".to_string(), imports_env);
     imports
@@ -27,5 +34,8 @@ fn main() {
     let module_template = jump_jet::instantiate(&mut File::open("out.wasm").unwrap(), imports).unwrap();
     let mut module_instance = module_template.instantiate().unwrap();
     let rets = module_instance.exports().call_fn("add", vec![ValueTypeProvider::I32(42), ValueTypeProvider::I32(123)]);
-    println!("rets {:#?}", rets);
+    match rets {
+        Ok(rets) => println!("rets {:#?}", rets),
+        Err(trap) => println!("trapped: {}", trap),
+    }
 }